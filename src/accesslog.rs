@@ -0,0 +1,195 @@
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use bytes::Bytes;
+use http_body::{Frame, SizeHint};
+use serde::Serialize;
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::error;
+
+use crate::{SharedState, audit, auth::LoggedIn};
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+// --- Log ---
+// One JSON line per request once its response has been sent (or abandoned), for
+// `--access-log`: a file, or standard output for `-`. Apart from the tracing output,
+// which is for debugging kiv rather than watching who uses it.
+pub struct AccessLog {
+    writer: mpsc::UnboundedSender<String>,
+}
+
+impl AccessLog {
+    pub async fn open(target: &Path) -> Result<Self, String> {
+        let writer = if target == Path::new("-") {
+            let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+            tokio::spawn(async move {
+                let mut out = tokio::io::stdout();
+                while let Some(line) = receiver.recv().await {
+                    if out.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            sender
+        } else {
+            audit::append_lines(target)
+                .await
+                .map_err(|e| format!("Failed to open access log {}: {}", target.display(), e))?
+        };
+        Ok(AccessLog { writer })
+    }
+}
+
+#[derive(Serialize)]
+struct Entry {
+    time: String,
+    id: String,
+    ip: IpAddr,
+    user: Option<String>,
+    method: String,
+    uri: String,
+    status: u16,
+    bytes: u64,
+    duration_ms: u64,
+    user_agent: Option<String>,
+}
+
+// Written when the response body is done with, whether sent in full or not.
+struct Pending {
+    entry: Entry,
+    started: Instant,
+    writer: mpsc::UnboundedSender<String>,
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        self.entry.duration_ms = self.started.elapsed().as_millis() as u64;
+        match serde_json::to_string(&self.entry) {
+            Ok(mut line) => {
+                line.push('\n');
+                let _ = self.writer.send(line);
+            }
+            Err(e) => error!("Failed to encode access log entry: {}", e),
+        }
+    }
+}
+
+// The response body, counting the bytes that go out.
+struct CountedBody {
+    inner: Body,
+    pending: Pending,
+}
+
+impl http_body::Body for CountedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, axum::Error>>> {
+        let this = self.get_mut();
+        let frame = Pin::new(&mut this.inner).poll_frame(cx);
+        if let Poll::Ready(Some(Ok(frame))) = &frame
+            && let Some(data) = frame.data_ref()
+        {
+            this.pending.entry.bytes += data.len() as u64;
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+// --- Request IDs ---
+// Sent back with every response as X-Request-Id, so a client's report can be matched to
+// the log. A trusted proxy's ID is kept, so one request can be followed through both
+// logs; anyone else gets a new one.
+fn request_id(state: &SharedState, peer: IpAddr, headers: &HeaderMap) -> String {
+    let forwarded = headers
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            (1..=128).contains(&id.len())
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+        });
+    match forwarded {
+        Some(id) if state.ip_filter.is_trusted(peer) => id.to_string(),
+        _ => uuid::Uuid::new_v4().simple().to_string()[..16].to_string(),
+    }
+}
+
+// --- Middleware ---
+// Outside everything that may turn a request away, so refusals are logged too.
+pub async fn log(
+    State(state): State<SharedState>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let id = request_id(&state, peer.ip(), request.headers());
+    let pending = state.access_log.as_ref().map(|log| Pending {
+        entry: Entry {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            id: id.clone(),
+            ip: state.ip_filter.client_ip(peer.ip(), request.headers()),
+            user: None,
+            method: request.method().to_string(),
+            uri: request
+                .uri()
+                .path_and_query()
+                .map_or_else(|| request.uri().path().to_string(), ToString::to_string),
+            status: 0,
+            bytes: 0,
+            duration_ms: 0,
+            user_agent: request
+                .headers()
+                .get(header::USER_AGENT)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+        },
+        started: Instant::now(),
+        writer: log.writer.clone(),
+    });
+
+    let mut response = next.run(request).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+    let Some(mut pending) = pending else {
+        return response;
+    };
+    pending.entry.status = response.status().as_u16();
+    pending.entry.user = response
+        .extensions()
+        .get::<LoggedIn>()
+        .map(|LoggedIn(name)| name.clone());
+    let (parts, body) = response.into_parts();
+    Response::from_parts(
+        parts,
+        Body::new(CountedBody {
+            inner: body,
+            pending,
+        }),
+    )
+}
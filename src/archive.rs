@@ -0,0 +1,266 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+use tokio_util::io::SyncIoBridge;
+use tracing::error;
+
+// Listings of huge archives are cut off here; single entries can still be downloaded by name.
+pub const MAX_LISTED_ENTRIES: usize = 5000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    SevenZip,
+}
+
+impl ArchiveFormat {
+    pub fn label(self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "ZIP",
+            ArchiveFormat::Tar => "TAR",
+            ArchiveFormat::TarGz => "TAR.GZ",
+            ArchiveFormat::SevenZip => "7z",
+        }
+    }
+}
+
+pub fn archive_format(path: &Path) -> Option<ArchiveFormat> {
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveFormat::Zip)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveFormat::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveFormat::TarGz)
+    } else if name.ends_with(".7z") {
+        Some(ArchiveFormat::SevenZip)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub size: u64,
+    // Not known for tar members or files inside solid 7z blocks.
+    pub compressed_size: Option<u64>,
+    pub is_dir: bool,
+}
+
+pub struct ArchiveListing {
+    pub entries: Vec<ArchiveEntry>,
+    pub truncated: bool,
+}
+
+// --- Listing ---
+// Reads only the archive's directory (zip, 7z) or member headers (tar); nothing is extracted.
+pub async fn list_entries(path: &Path, format: ArchiveFormat) -> Result<ArchiveListing, String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut entries = Vec::new();
+        let truncated = match format {
+            ArchiveFormat::Zip => list_zip(&path, &mut entries),
+            ArchiveFormat::Tar | ArchiveFormat::TarGz => {
+                list_tar(tar_reader(&path, format)?, &mut entries)
+            }
+            ArchiveFormat::SevenZip => list_7z(&path, &mut entries),
+        }?;
+        Ok(ArchiveListing { entries, truncated })
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| e.to_string())
+}
+
+fn tar_reader(path: &Path, format: ArchiveFormat) -> Result<Box<dyn Read>, String> {
+    let file = open(path)?;
+    Ok(match format {
+        ArchiveFormat::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+fn list_zip(path: &Path, entries: &mut Vec<ArchiveEntry>) -> Result<bool, String> {
+    let mut archive = zip::ZipArchive::new(open(path)?).map_err(|e| e.to_string())?;
+    for index in 0..archive.len().min(MAX_LISTED_ENTRIES) {
+        let file = archive.by_index_raw(index).map_err(|e| e.to_string())?;
+        entries.push(ArchiveEntry {
+            name: file
+                .name()
+                .map(|name| name.into_owned())
+                .map_err(|e| e.to_string())?,
+            size: file.size(),
+            compressed_size: Some(file.compressed_size()),
+            is_dir: file.is_dir(),
+        });
+    }
+    Ok(archive.len() > MAX_LISTED_ENTRIES)
+}
+
+fn list_tar(reader: impl Read, entries: &mut Vec<ArchiveEntry>) -> Result<bool, String> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        if entries.len() == MAX_LISTED_ENTRIES {
+            return Ok(true);
+        }
+        let entry = entry.map_err(|e| e.to_string())?;
+        let header = entry.header();
+        entries.push(ArchiveEntry {
+            name: tar_entry_name(&entry),
+            size: header.size().unwrap_or(0),
+            compressed_size: None,
+            is_dir: header.entry_type().is_dir(),
+        });
+    }
+    Ok(false)
+}
+
+fn list_7z(path: &Path, entries: &mut Vec<ArchiveEntry>) -> Result<bool, String> {
+    let archive = sevenz_rust2::Archive::open(path).map_err(|e| e.to_string())?;
+    for file in archive.files.iter().take(MAX_LISTED_ENTRIES) {
+        entries.push(ArchiveEntry {
+            name: file.name().to_string(),
+            size: file.size(),
+            compressed_size: (file.compressed_size > 0).then_some(file.compressed_size),
+            is_dir: file.is_directory(),
+        });
+    }
+    Ok(archive.files.len() > MAX_LISTED_ENTRIES)
+}
+
+fn tar_entry_name<R: Read>(entry: &tar::Entry<'_, R>) -> String {
+    entry
+        .path()
+        .map(|path| path.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_else(|_| String::from_utf8_lossy(&entry.path_bytes()).into_owned())
+}
+
+// Looks up one entry by its exact name within the archive.
+pub async fn find_entry(
+    path: &Path,
+    format: ArchiveFormat,
+    name: &str,
+) -> Result<Option<ArchiveEntry>, String> {
+    let name = name.to_string();
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(open(&path)?).map_err(|e| e.to_string())?;
+            let Some(index) = archive.index_for_name(&name) else {
+                return Ok(None);
+            };
+            let file = archive.by_index_raw(index).map_err(|e| e.to_string())?;
+            Ok(Some(ArchiveEntry {
+                name,
+                size: file.size(),
+                compressed_size: Some(file.compressed_size()),
+                is_dir: file.is_dir(),
+            }))
+        }
+        ArchiveFormat::Tar | ArchiveFormat::TarGz => {
+            let mut archive = tar::Archive::new(tar_reader(&path, format)?);
+            for entry in archive.entries().map_err(|e| e.to_string())? {
+                let entry = entry.map_err(|e| e.to_string())?;
+                if tar_entry_name(&entry) == name {
+                    return Ok(Some(ArchiveEntry {
+                        size: entry.header().size().unwrap_or(0),
+                        compressed_size: None,
+                        is_dir: entry.header().entry_type().is_dir(),
+                        name,
+                    }));
+                }
+            }
+            Ok(None)
+        }
+        ArchiveFormat::SevenZip => {
+            let archive = sevenz_rust2::Archive::open(&path).map_err(|e| e.to_string())?;
+            Ok(archive
+                .files
+                .iter()
+                .find(|file| file.name() == name)
+                .map(|file| ArchiveEntry {
+                    name: name.clone(),
+                    size: file.size(),
+                    compressed_size: (file.compressed_size > 0).then_some(file.compressed_size),
+                    is_dir: file.is_directory(),
+                }))
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// --- Single-entry extraction ---
+// Decompresses one entry on a blocking thread and pipes it into the returned reader, so
+// the entry is streamed to the client without touching the disk.
+pub fn stream_entry(
+    path: PathBuf,
+    format: ArchiveFormat,
+    name: String,
+) -> impl tokio::io::AsyncRead {
+    let (reader, writer) = tokio::io::duplex(1 << 16);
+    let mut writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = extract_entry(&path, format, &name, &mut writer) {
+            error!(
+                "Failed to extract '{}' from {}: {}",
+                name,
+                path.display(),
+                e
+            );
+        }
+        let _ = writer.shutdown();
+    });
+    reader
+}
+
+fn extract_entry(
+    path: &Path,
+    format: ArchiveFormat,
+    name: &str,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    match format {
+        ArchiveFormat::Zip => {
+            let mut archive = zip::ZipArchive::new(open(path)?).map_err(|e| e.to_string())?;
+            let mut file = archive.by_name(name).map_err(|e| e.to_string())?;
+            std::io::copy(&mut file, out).map_err(|e| e.to_string())?;
+        }
+        ArchiveFormat::Tar | ArchiveFormat::TarGz => {
+            let mut archive = tar::Archive::new(tar_reader(path, format)?);
+            for entry in archive.entries().map_err(|e| e.to_string())? {
+                let mut entry = entry.map_err(|e| e.to_string())?;
+                if tar_entry_name(&entry) == name {
+                    std::io::copy(&mut entry, out).map_err(|e| e.to_string())?;
+                    return Ok(());
+                }
+            }
+            return Err("entry not found".to_string());
+        }
+        ArchiveFormat::SevenZip => {
+            let mut reader =
+                sevenz_rust2::ArchiveReader::open(path, sevenz_rust2::Password::empty())
+                    .map_err(|e| e.to_string())?;
+            reader
+                .for_each_entries(|entry, data| {
+                    if entry.name() != name {
+                        return Ok(true);
+                    }
+                    std::io::copy(data, out)?;
+                    Ok(false)
+                })
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
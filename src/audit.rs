@@ -0,0 +1,273 @@
+use axum::{
+    body::{Body, to_bytes},
+    extract::{Request, State},
+    http::{HeaderMap, Method, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use tracing::error;
+use uuid::Uuid;
+
+use crate::{SharedState, auth, ipfilter::ClientIp, stats::Served};
+
+// Entries kept in memory for the admin page.
+const RECENT_EVENTS: usize = 500;
+// Forms are read for the paths they name; anything larger isn't one of kiv's forms.
+const MAX_FORM_BYTES: usize = 64 * 1024;
+
+// --- Events ---
+// One security-relevant action: who did what to which path, from where, and how it
+// ended. Written as a line of JSON each.
+#[derive(Clone, Serialize)]
+pub struct Event {
+    pub time: String,
+    pub action: &'static str,
+    pub actor: Option<String>,
+    pub ip: Option<IpAddr>,
+    pub path: String,
+    // "ok", "denied" or "failed".
+    pub result: &'static str,
+    pub status: u16,
+}
+
+impl Event {
+    pub fn new(
+        action: &'static str,
+        actor: Option<String>,
+        ip: Option<IpAddr>,
+        path: String,
+        status: StatusCode,
+    ) -> Self {
+        Event {
+            time: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+            action,
+            actor,
+            ip,
+            path,
+            result: match status.as_u16() {
+                200..=399 => "ok",
+                401 | 403 | 429 => "denied",
+                _ => "failed",
+            },
+            status: status.as_u16(),
+        }
+    }
+}
+
+// --- Log ---
+// Kept apart from the tracing output: appended to `--audit-log` (never rewritten) by a
+// writer task, in the order recorded, and the latest held in memory for /admin.
+pub struct AuditLog {
+    file: Option<PathBuf>,
+    writer: Option<mpsc::UnboundedSender<String>>,
+    recent: Mutex<VecDeque<Event>>,
+}
+
+impl AuditLog {
+    // Opens `file` for appending, creating it if needed. Without a file events are only
+    // kept in memory.
+    pub async fn open(file: Option<PathBuf>) -> Result<Self, String> {
+        let writer = match &file {
+            Some(path) => Some(
+                append_lines(path)
+                    .await
+                    .map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+        Ok(AuditLog {
+            file,
+            writer,
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_EVENTS)),
+        })
+    }
+
+    pub fn file(&self) -> Option<&PathBuf> {
+        self.file.as_ref()
+    }
+
+    pub fn record(&self, event: Event) {
+        if let Some(writer) = &self.writer {
+            match serde_json::to_string(&event) {
+                Ok(mut line) => {
+                    line.push('\n');
+                    let _ = writer.send(line);
+                }
+                Err(e) => error!("Failed to encode audit event: {}", e),
+            }
+        }
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() >= RECENT_EVENTS {
+            recent.pop_front();
+        }
+        recent.push_back(event);
+    }
+
+    // The latest events, newest first.
+    pub fn recent(&self, limit: usize) -> Vec<Event> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent.iter().rev().take(limit).cloned().collect()
+    }
+}
+
+// Appends the lines sent to it to `path`, creating it readable only by its owner. A
+// writer task keeps them in order without holding up the requests that send them.
+pub async fn append_lines(path: &Path) -> std::io::Result<mpsc::UnboundedSender<String>> {
+    let mut options = tokio::fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut out = options.open(path).await?;
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    let path = path.to_path_buf();
+    tokio::spawn(async move {
+        while let Some(line) = receiver.recv().await {
+            let written = match out.write_all(line.as_bytes()).await {
+                Ok(()) => out.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                error!("Failed to write {}: {}", path.display(), e);
+            }
+        }
+    });
+    Ok(sender)
+}
+
+// --- Subjects ---
+// Handlers that know better than the request what was acted on (the files an upload
+// stored, the share a link was made for) say so here for the request's event.
+tokio::task_local! {
+    static SUBJECT: RefCell<Option<String>>;
+}
+
+pub fn note(subject: String) {
+    let _ = SUBJECT.try_with(|slot| *slot.borrow_mut() = Some(subject));
+}
+
+// --- Middleware ---
+// The audited routes and what they are called in the log.
+fn action_for(method: &Method, path: &str, query: &str) -> Option<&'static str> {
+    if method == Method::POST {
+        return match path {
+            "/share" => Some("share-create"),
+            "/sign" => Some("sign"),
+            "/admin/shares/revoke" => Some("share-revoke"),
+            "/admin/users/reload" => Some("users-reload"),
+            "/upload" => Some("upload"),
+            "/copy" => Some("copy"),
+            "/versions/restore" => Some("restore"),
+            "/trash" => Some("trash"),
+            "/delete" => Some("delete"),
+            _ => None,
+        };
+    }
+    let download = path.starts_with("/direct-download/")
+        || path == "/signed"
+        || auth::capability_for(path, query) == Some(auth::Capability::Download);
+    download.then_some("download")
+}
+
+// The `path` (or `uuid`) values of a query string or form.
+fn named_paths(encoded: &[u8]) -> Vec<String> {
+    form_urlencoded::parse(encoded)
+        .filter(|(key, _)| key == "path" || key == "uuid")
+        .map(|(_, value)| value.into_owned())
+        .collect()
+}
+
+fn is_form(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"))
+}
+
+// Records the audited routes once their response is ready. Runs inside `auth::require`,
+// so it knows who is logged in; logins themselves are recorded there.
+pub async fn track(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let (method, path, query) = (
+        request.method().clone(),
+        request.uri().path().to_string(),
+        request.uri().query().unwrap_or("").to_string(),
+    );
+    let Some(action) = action_for(&method, &path, &query) else {
+        return next.run(request).await;
+    };
+    let ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| *ip);
+
+    let mut named = named_paths(query.as_bytes());
+    let request = if is_form(request.headers()) {
+        let declared = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok()?.parse::<usize>().ok());
+        if declared.is_some_and(|length| length > MAX_FORM_BYTES) {
+            return crate::error_response(StatusCode::PAYLOAD_TOO_LARGE, "Form too large.");
+        }
+        let (parts, body) = request.into_parts();
+        match to_bytes(body, MAX_FORM_BYTES).await {
+            Ok(bytes) => {
+                named.extend(named_paths(&bytes));
+                Request::from_parts(parts, Body::from(bytes))
+            }
+            // Too long without a length up front, or the client stopped sending it.
+            Err(_) => {
+                return crate::error_response(StatusCode::BAD_REQUEST, "Couldn't read the form.");
+            }
+        }
+    } else {
+        request
+    };
+    if let Some(uuid) = path
+        .strip_prefix("/direct-download/")
+        .and_then(|uuid| Uuid::parse_str(uuid).ok())
+        && let Some(share) = state.shares.get(&uuid)
+    {
+        named.push(format!(
+            "{} (share {})",
+            state.mounts.relative(&share.path),
+            uuid
+        ));
+    }
+
+    let (response, subject) = SUBJECT
+        .scope(RefCell::new(None), async {
+            let response = next.run(request).await;
+            (response, SUBJECT.with(|slot| slot.take()))
+        })
+        .await;
+
+    // Resumed downloads and seeks within one aren't separate actions.
+    let continued = response.status() == StatusCode::PARTIAL_CONTENT
+        && !response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|range| range.starts_with("bytes 0-"));
+    if !continued {
+        let served = response
+            .extensions()
+            .get::<Served>()
+            .map(|served| served.relative(&state).join(", "))
+            .filter(|served| !served.is_empty());
+        let path = subject
+            .or(served.filter(|_| named.is_empty()))
+            .unwrap_or_else(|| named.join(", "));
+        let event = Event::new(action, auth::current_user(), ip, path, response.status());
+        state.audit.record(event);
+    }
+    response
+}
@@ -0,0 +1,1170 @@
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier, password_hash::SaltString};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use dashmap::DashMap;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{info, warn};
+
+use crate::{
+    SharedState, audit, error_response, ipfilter::ClientIp, locks, ratelimit::too_many_requests,
+    secrets, sessions,
+};
+
+// Successful logins remembered so each request doesn't pay for a password hash; cleared
+// when it grows past this.
+const MAX_VERIFIED: usize = 1024;
+
+// --- Capabilities ---
+// What a request does to the path it names. Every route maps to one; see
+// `capability_for`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Capability {
+    // See folders and their listings, search and the tree.
+    Browse,
+    // Open files in the browser: previews, media, text, raw content. `/raw` hands over a
+    // file's whole contents inline, so Preview without Download only leaves out the save
+    // links and archives; it doesn't keep the bytes from anyone who can preview.
+    Preview,
+    // Save files as attachments and download folders and selections as archives.
+    Download,
+    // Create share links and signed links.
+    Share,
+    // Add or change files: uploads, copies, restoring versions, writing checksums.
+    Upload,
+    // Delete files or move them to the trash.
+    Delete,
+}
+
+const ALL_CAPABILITIES: [Capability; 6] = [
+    Capability::Browse,
+    Capability::Preview,
+    Capability::Download,
+    Capability::Share,
+    Capability::Upload,
+    Capability::Delete,
+];
+
+impl Capability {
+    // One name as written in the users file; `all` stands for every capability.
+    fn parse(name: &str) -> Option<Vec<Capability>> {
+        Some(match name.trim().to_ascii_lowercase().as_str() {
+            "all" => ALL_CAPABILITIES.to_vec(),
+            "browse" => vec![Capability::Browse],
+            "preview" => vec![Capability::Preview],
+            "download" => vec![Capability::Download],
+            "share" => vec![Capability::Share],
+            "upload" => vec![Capability::Upload],
+            "delete" => vec![Capability::Delete],
+            _ => return None,
+        })
+    }
+    pub fn name(self) -> &'static str {
+        match self {
+            Capability::Browse => "browse",
+            Capability::Preview => "preview",
+            Capability::Download => "download",
+            Capability::Share => "share",
+            Capability::Upload => "upload",
+            Capability::Delete => "delete",
+        }
+    }
+}
+
+// --- Users file ---
+// Accounts and what each may do where, as TOML:
+//
+//     [roles.family]
+//     allow = { "" = ["browse", "preview", "download"] }
+//
+//     [users.alice]
+//     password = "$argon2id$v=19$…"      # from `kiv --hash-password`
+//     roles = ["family"]
+//
+//     [users.bob]
+//     password_file = "bob.hash"          # the hash, kept out of this file
+//     roles = ["family"]
+//     admin = true                        # may use /admin
+//     allow = { "Photos" = ["share", "upload"] }
+//     deny = { "Photos/private" = ["all"] }
+//
+//     [users.carol]
+//     password_file = "carol.hash"
+//     home = "Family/carol"              # sees nothing outside this folder
+//     allow = { "" = ["all"] }
+//
+//     [guest]                             # visitors who don't log in
+//     allow = { "Public" = ["browse", "download"] }
+//
+// Paths are request paths ("" is everything). For a given path and capability the rule
+// on the longest matching path decides, deny winning over allow on the same path; with
+// no rule at all the answer is no. A relative `password_file` is in systemd's
+// credentials directory, or next to this file without one (see `secrets`). A top-level
+// `homes = "Family"` gives every user without a `home` of their own the folder of their
+// name in it; `home = ""` exempts one. Rules still apply inside a home. Without a `[guest]` section (or `--public`) visitors
+// who don't log in get nothing but share and signed links.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UsersFile {
+    homes: Option<String>,
+    #[serde(default)]
+    roles: HashMap<String, RulesConfig>,
+    #[serde(default)]
+    users: HashMap<String, UserConfig>,
+    guest: Option<RulesConfig>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct RulesConfig {
+    #[serde(default)]
+    allow: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    deny: HashMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserConfig {
+    password: Option<String>,
+    password_file: Option<PathBuf>,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    admin: bool,
+    home: Option<String>,
+    #[serde(default)]
+    allow: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    deny: HashMap<String, Vec<String>>,
+}
+
+struct Rule {
+    // Request path without leading or trailing slashes; empty for everything.
+    prefix: String,
+    allow: bool,
+    capabilities: Vec<Capability>,
+}
+
+impl Rule {
+    fn covers(&self, relative: &str) -> bool {
+        self.prefix.is_empty()
+            || relative
+                .strip_prefix(self.prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+pub struct User {
+    pub name: String,
+    pub roles: Vec<String>,
+    pub admin: bool,
+    // The folder the user is kept in, as a request path; `None` when not jailed.
+    pub home: Option<String>,
+    password_hash: String,
+    rules: Vec<Rule>,
+    // Stands for visitors who aren't logged in; see `Users::guest`.
+    guest: bool,
+}
+
+impl User {
+    // Whether `relative` is inside the user's home, or anywhere without one.
+    pub fn in_home(&self, relative: &str) -> bool {
+        self.home.as_deref().is_none_or(|home| {
+            normalize(relative)
+                .strip_prefix(home)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    // Whether `relative` is a folder on the way down to the user's home.
+    fn above_home(&self, relative: &str) -> bool {
+        let relative = normalize(relative);
+        self.home
+            .as_deref()
+            .is_some_and(|home| relative.is_empty() || home.starts_with(&format!("{}/", relative)))
+    }
+
+    // Whether any rule allows `capability` somewhere.
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.rules
+            .iter()
+            .any(|rule| rule.allow && rule.capabilities.contains(&capability))
+    }
+
+    pub fn can(&self, capability: Capability, relative: &str) -> bool {
+        let relative = normalize(relative);
+        let mut decision: Option<(usize, bool)> = None;
+        for rule in &self.rules {
+            if !rule.capabilities.contains(&capability) || !rule.covers(&relative) {
+                continue;
+            }
+            let depth = rule.prefix.len();
+            decision = match decision {
+                Some((best, allow)) if best == depth => Some((best, allow && rule.allow)),
+                Some((best, _)) if best > depth => decision,
+                _ => Some((depth, rule.allow)),
+            };
+        }
+        decision.is_some_and(|(_, allow)| allow)
+    }
+
+    // Whether the folder is shown and can be opened: it may be browsed, or something
+    // below it may, which has to be reachable.
+    pub fn can_traverse(&self, relative: &str) -> bool {
+        if self.can(Capability::Browse, relative) {
+            return true;
+        }
+        let relative = normalize(relative);
+        self.rules.iter().any(|rule| {
+            rule.allow
+                && rule.capabilities.contains(&Capability::Browse)
+                && (relative.is_empty() || rule.prefix.starts_with(&format!("{}/", relative)))
+                && self.can(Capability::Browse, &rule.prefix)
+        })
+    }
+}
+
+pub fn normalize(relative: &str) -> String {
+    let trimmed = relative.trim_matches('/');
+    if trimmed == "." {
+        String::new()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+fn parse_rules(
+    owner: &str,
+    allow: &HashMap<String, Vec<String>>,
+    deny: &HashMap<String, Vec<String>>,
+    into: &mut Vec<Rule>,
+) -> Result<(), String> {
+    for (rules, is_allow) in [(allow, true), (deny, false)] {
+        for (path, names) in rules {
+            let mut capabilities = Vec::new();
+            for name in names {
+                capabilities.extend(Capability::parse(name).ok_or_else(|| {
+                    format!("{}: unknown capability '{}' for '{}'", owner, name, path)
+                })?);
+            }
+            into.push(Rule {
+                prefix: normalize(path),
+                allow: is_allow,
+                capabilities,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Guests only ever look: sharing and uploading take an account, and so does changing
+// anything.
+const GUEST_CAPABILITIES: [Capability; 3] = [
+    Capability::Browse,
+    Capability::Preview,
+    Capability::Download,
+];
+
+struct Accounts {
+    users: HashMap<String, Arc<User>>,
+    guest: Option<Arc<User>>,
+}
+
+// `public` adds `--public`'s rule to the guest: browse, preview and download everywhere.
+async fn read_users(path: &Path, public: bool) -> Result<Accounts, String> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("Failed to read users file {}: {}", path.display(), e))?;
+    let file: UsersFile = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid users file {}: {}", path.display(), e))?;
+
+    let mut users = HashMap::new();
+    for (name, config) in file.users {
+        let password_hash = match (config.password, &config.password_file) {
+            (Some(hash), None) => hash,
+            (None, Some(password_file)) => secrets::read(&secrets::resolve(password_file, path))
+                .await
+                .map_err(|e| format!("User '{}' in {}: {}", name, path.display(), e))?,
+            _ => {
+                return Err(format!(
+                    "User '{}' in {}: give either password or password_file.",
+                    name,
+                    path.display()
+                ));
+            }
+        };
+        if PasswordHash::new(&password_hash).is_err() {
+            return Err(format!(
+                "User '{}' in {}: password must be a hash from `kiv --hash-password`.",
+                name,
+                path.display()
+            ));
+        }
+        let mut rules = Vec::new();
+        for role in &config.roles {
+            let role_rules = file.roles.get(role).ok_or_else(|| {
+                format!(
+                    "User '{}' in {}: unknown role '{}'",
+                    name,
+                    path.display(),
+                    role
+                )
+            })?;
+            parse_rules(
+                &format!("Role '{}'", role),
+                &role_rules.allow,
+                &role_rules.deny,
+                &mut rules,
+            )?;
+        }
+        parse_rules(
+            &format!("User '{}'", name),
+            &config.allow,
+            &config.deny,
+            &mut rules,
+        )?;
+        let user = User {
+            name: name.clone(),
+            roles: config.roles,
+            admin: config.admin,
+            home: config
+                .home
+                .or_else(|| {
+                    file.homes
+                        .as_ref()
+                        .map(|homes| format!("{}/{}", homes, name))
+                })
+                .map(|home| normalize(&home))
+                .filter(|home| !home.is_empty()),
+            password_hash,
+            rules,
+            guest: false,
+        };
+        users.insert(name, Arc::new(user));
+    }
+    if users.is_empty() {
+        return Err(format!("No users defined in {}.", path.display()));
+    }
+
+    let mut guest_rules = Vec::new();
+    if let Some(guest) = &file.guest {
+        parse_rules("Guest", &guest.allow, &guest.deny, &mut guest_rules)?;
+    }
+    if let Some(rule) = guest_rules.iter().find(|rule| {
+        rule.allow
+            && rule
+                .capabilities
+                .iter()
+                .any(|capability| !GUEST_CAPABILITIES.contains(capability))
+    }) {
+        return Err(format!(
+            "Guest in {}: '{}' may only allow browse, preview and download.",
+            path.display(),
+            rule.prefix
+        ));
+    }
+    if public {
+        guest_rules.push(Rule {
+            prefix: String::new(),
+            allow: true,
+            capabilities: GUEST_CAPABILITIES.to_vec(),
+        });
+    }
+    let guest = (file.guest.is_some() || public).then(|| {
+        Arc::new(User {
+            name: "guest".to_string(),
+            roles: Vec::new(),
+            admin: false,
+            home: None,
+            password_hash: String::new(),
+            rules: guest_rules,
+            guest: true,
+        })
+    });
+    Ok(Accounts { users, guest })
+}
+
+// The user name in an `Authorization: Basic` header, whether or not it exists.
+fn basic_name(value: &HeaderValue) -> Option<String> {
+    let encoded = value.to_str().ok()?.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    decoded.split_once(':').map(|(name, _)| name.to_string())
+}
+
+// --- Accounts ---
+pub struct Users {
+    file: PathBuf,
+    public: bool,
+    // Replaced as a whole when the file is reloaded.
+    accounts: RwLock<Accounts>,
+    // SHA-256 of an Authorization header that has already checked out → its user.
+    verified: DashMap<[u8; 32], Arc<User>>,
+}
+
+impl Users {
+    pub async fn load(path: &Path, public: bool) -> Result<Self, String> {
+        Ok(Users {
+            file: path.to_path_buf(),
+            public,
+            accounts: RwLock::new(read_users(path, public).await?),
+            verified: DashMap::new(),
+        })
+    }
+
+    // Picks up edits to the users file without a restart. On error the accounts in use
+    // stay as they were.
+    pub async fn reload(&self) -> Result<usize, String> {
+        let accounts = read_users(&self.file, self.public).await?;
+        let count = accounts.users.len();
+        *self.accounts.write().unwrap_or_else(|e| e.into_inner()) = accounts;
+        self.verified.clear();
+        info!("Reloaded {} accounts from {}", count, self.file.display());
+        Ok(count)
+    }
+
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+
+    // Every account, by name.
+    pub fn list(&self) -> Vec<Arc<User>> {
+        let mut users: Vec<Arc<User>> = self.read().users.values().cloned().collect();
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        users
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Accounts> {
+        self.accounts.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().users.len()
+    }
+
+    // What visitors who don't log in may do, from the `[guest]` section and `--public`;
+    // `None` when they may do nothing.
+    pub fn guest(&self) -> Option<Arc<User>> {
+        self.read().guest.clone()
+    }
+
+    // The user of an `Authorization` header whose password has already checked out.
+    fn verified(&self, value: &HeaderValue) -> Option<Arc<User>> {
+        let key: [u8; 32] = Sha256::digest(value.as_bytes()).into();
+        self.verified.get(&key).map(|user| user.clone())
+    }
+
+    // The user an `Authorization: Basic` header logs in as, if its password is right.
+    async fn authenticate(&self, value: &HeaderValue) -> Option<Arc<User>> {
+        if let Some(user) = self.verified(value) {
+            return Some(user);
+        }
+        let key: [u8; 32] = Sha256::digest(value.as_bytes()).into();
+        let encoded = value.to_str().ok()?.strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+        let (name, password) = decoded.split_once(':')?;
+        let user = self.check_password(name, password).await?;
+        if self.verified.len() >= MAX_VERIFIED {
+            self.verified.clear();
+        }
+        self.verified.insert(key, user.clone());
+        Some(user)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<User>> {
+        self.read().users.get(name).cloned()
+    }
+
+    // The user, if `password` is theirs.
+    pub async fn check_password(&self, name: &str, password: &str) -> Option<Arc<User>> {
+        let Some(user) = self.get(name) else {
+            info!("Login attempt for unknown user '{}'", name);
+            return None;
+        };
+        if !verify_password(&user.password_hash, password).await {
+            warn!("Wrong password for user '{}'", user.name);
+            return None;
+        }
+        Some(user)
+    }
+}
+
+// Whether `password` matches an argon2 hash from `hash_password`.
+pub async fn verify_password(password_hash: &str, password: &str) -> bool {
+    let (password_hash, password) = (password_hash.to_string(), password.to_string());
+    // Hashing is deliberately slow; keep it off the async workers.
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&password_hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .unwrap_or(false)
+}
+
+// An argon2id hash of `password`, for the users file.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    // 16 bytes from the operating system's random source, as for the signing key.
+    let salt =
+        SaltString::encode_b64(uuid::Uuid::new_v4().as_bytes()).map_err(|e| e.to_string())?;
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+// --- Current request ---
+// Who is making the request being handled and what it does, set by `require` for the
+// handler and anything it awaits, so path checks anywhere can consult it.
+#[derive(Clone)]
+struct Access {
+    user: Arc<User>,
+    capability: Capability,
+}
+
+#[derive(Clone)]
+enum Scope {
+    // A logged-in user or the guest, held to their rules.
+    User(Access),
+    // No accounts are configured, or the route carries its own proof of access (share
+    // and signed links). Folder passwords still apply.
+    Open,
+    // kiv's own background work, opted into with `system`: nothing is held back.
+    System,
+}
+
+tokio::task_local! {
+    static CURRENT: Scope;
+}
+
+// Path checks made outside any scope fail closed: work that isn't on behalf of a request
+// has to say so with `system`, and work that is has to carry the request's scope along
+// (`propagate`, `propagate_async`).
+fn decide(user: impl FnOnce(&Access) -> bool) -> bool {
+    CURRENT
+        .try_with(|scope| match scope {
+            Scope::User(access) => user(access),
+            Scope::Open | Scope::System => true,
+        })
+        .unwrap_or(false)
+}
+
+// Whether the current request may do what it does at `relative`, which also takes any
+// folder password over it to be unlocked.
+pub fn allows(relative: &str) -> bool {
+    locks::allows(relative)
+        && decide(|access| match access.capability {
+            Capability::Browse if access.user.above_home(relative) => true,
+            _ if !access.user.in_home(relative) => false,
+            Capability::Browse => access.user.can_traverse(relative),
+            capability => access.user.can(capability, relative),
+        })
+}
+
+// Whether the current user gets to see `relative` in listings, search results and the
+// tree; not the insides of folders still locked by a password.
+pub fn visible(relative: &str) -> bool {
+    locks::visible(relative)
+        && decide(|access| {
+            access.user.above_home(relative)
+                || (access.user.in_home(relative) && access.user.can_traverse(relative))
+        })
+}
+
+// The current request's user, if there is one.
+fn user() -> Option<Arc<User>> {
+    CURRENT
+        .try_with(|scope| match scope {
+            Scope::User(access) => Some(access.user.clone()),
+            Scope::Open | Scope::System => None,
+        })
+        .unwrap_or(None)
+}
+
+// The name of the user making the current request, if logged in.
+pub fn current_user() -> Option<String> {
+    user()
+        .filter(|user| !user.guest)
+        .map(|user| user.name.clone())
+}
+
+// The folder the current user is kept in, if any.
+pub fn home() -> Option<String> {
+    user().and_then(|user| user.home.clone())
+}
+
+// Whether `relative` is inside the current user's home, or anywhere without one.
+pub fn in_home(relative: &str) -> bool {
+    decide(|access| access.user.in_home(relative) || access.user.above_home(relative))
+}
+
+// Whether the current request comes from an administrator.
+pub fn is_admin() -> bool {
+    user().is_some_and(|user| user.admin)
+}
+
+// Whether the current request's user has one of `roles`, or is logged in at all when
+// `roles` is empty.
+pub fn has_role(roles: &[String]) -> bool {
+    user().is_some_and(|user| {
+        !user.guest && (roles.is_empty() || user.roles.iter().any(|role| roles.contains(role)))
+    })
+}
+
+// Whether the current work is kiv's own, run through `system`.
+pub fn is_system() -> bool {
+    CURRENT
+        .try_with(|scope| matches!(scope, Scope::System))
+        .unwrap_or(false)
+}
+
+// Runs kiv's own background work, which no user's rules or folder passwords hold back.
+pub async fn system<F: Future>(work: F) -> F::Output {
+    CURRENT.scope(Scope::System, work).await
+}
+
+// Carries the current request's user and unlocked folders into a closure run on a
+// blocking thread, where the task-locals aren't set.
+pub fn propagate<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let f = locks::propagate(f);
+    let scope = CURRENT.try_with(Scope::clone).ok();
+    move || match scope {
+        Some(scope) => CURRENT.sync_scope(scope, f),
+        None => f(),
+    }
+}
+
+// The same for work spawned onto a task of its own, such as a background job.
+pub fn propagate_async<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    let work = locks::propagate_async(work);
+    let scope = CURRENT.try_with(Scope::clone).ok();
+    async move {
+        match scope {
+            Some(scope) => CURRENT.scope(scope, work).await,
+            None => work.await,
+        }
+    }
+}
+
+// --- Middleware ---
+// The capability a route needs, or `None` for the routes anyone may use: share links,
+// signed links and static files, which carry their own proof of access, and logging in.
+pub fn capability_for(path: &str, query: &str) -> Option<Capability> {
+    if path == "/login"
+        || path == "/logout"
+        || path.starts_with("/share/")
+        || path.starts_with("/direct-download/")
+        || path == "/signed"
+        || path.starts_with("/static/")
+    {
+        return None;
+    }
+    // Decoded as the handler decodes it, so `download=%74rue` counts too. Anything but an
+    // explicit `false` does, so a query the handler would refuse asks for more, not less.
+    let attachment = form_urlencoded::parse(query.as_bytes())
+        .any(|(key, value)| key == "download" && value != "false");
+    Some(match path {
+        "/download-folder" | "/download-selection" | "/email-attachment" | "/archive-entry" => {
+            Capability::Download
+        }
+        "/media" if attachment => Capability::Download,
+        "/share" | "/sign" => Capability::Share,
+        "/upload" | "/copy" | "/versions/restore" | "/checksums/generate" => Capability::Upload,
+        // Favorites are shared by everyone and kept in a file under the root.
+        "/favorites/add" | "/favorites/remove" => Capability::Upload,
+        "/delete" | "/trash" => Capability::Delete,
+        "/preview"
+        | "/raw"
+        | "/media"
+        | "/image"
+        | "/poster"
+        | "/subtitles"
+        | "/tail"
+        | "/tail/events"
+        | "/structured-node"
+        | "/epub-resource"
+        | "/comic-page"
+        | "/direct-download-image"
+        | "/diff"
+        | "/diff/pick"
+        | "/hash"
+        | "/checksums"
+        | "/checksums/verify" => Capability::Preview,
+        // Every job takes at least Preview to start, so cancelling one does too.
+        _ if path.starts_with("/jobs/") && path.ends_with("/cancel") => Capability::Preview,
+        _ if path.ends_with("-preview") || path.starts_with("/hls/") => Capability::Preview,
+        _ => Capability::Browse,
+    })
+}
+
+// Asks for a login when accounts are configured, and runs the rest of the request as
+// that user. A session from the login page counts, as does HTTP Basic for scripts and
+// other clients without one. Visitors who don't log in run as the guest, if it may do
+// what they ask anywhere at all. Permissions on paths are checked where paths are
+// resolved.
+pub async fn require(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let Some(users) = &state.users else {
+        return CURRENT.scope(Scope::Open, next.run(request)).await;
+    };
+    let Some(capability) =
+        capability_for(request.uri().path(), request.uri().query().unwrap_or(""))
+    else {
+        return CURRENT.scope(Scope::Open, next.run(request)).await;
+    };
+    let session_user = sessions::token(request.headers())
+        .and_then(|token| state.sessions.touch(&token))
+        .and_then(|name| users.get(&name));
+    let credentials = request.headers().get(header::AUTHORIZATION).cloned();
+    let anonymous = credentials.is_none();
+    let user = match credentials {
+        _ if session_user.is_some() => session_user,
+        Some(value) => match users.verified(&value) {
+            Some(user) => Some(user),
+            None => {
+                let client = request
+                    .extensions()
+                    .get::<ClientIp>()
+                    .map(|ClientIp(client)| *client);
+                let (name, path) = (basic_name(&value), request.uri().path().to_string());
+                let login =
+                    |status| audit::Event::new("login", name.clone(), client, path.clone(), status);
+                // Each password checked counts against the client's attempts.
+                if let Some(client) = client
+                    && let Err(wait) = state.password_limiter.check(client)
+                {
+                    warn!("Too many login attempts from {}", client);
+                    state.audit.record(login(StatusCode::TOO_MANY_REQUESTS));
+                    return too_many_requests(wait, "Too many login attempts. Try again later.");
+                }
+                let user = users.authenticate(&value).await;
+                state.audit.record(login(if user.is_some() {
+                    StatusCode::OK
+                } else {
+                    StatusCode::UNAUTHORIZED
+                }));
+                user
+            }
+        },
+        None => None,
+    };
+    let Some(user) = user else {
+        if anonymous
+            && let Some(guest) = users.guest()
+            && guest.has_capability(capability)
+        {
+            return CURRENT
+                .scope(
+                    Scope::User(Access {
+                        user: guest,
+                        capability,
+                    }),
+                    next.run(request),
+                )
+                .await;
+        }
+        return login_required(&request);
+    };
+    let name = user.name.clone();
+    let mut response = CURRENT
+        .scope(Scope::User(Access { user, capability }), next.run(request))
+        .await
+        .into_response();
+    response.extensions_mut().insert(LoggedIn(name));
+    response
+}
+
+// Set on the response to a logged-in request, naming the user for the access log.
+#[derive(Clone)]
+pub struct LoggedIn(pub String);
+
+// Browsers are sent to the login page, coming back to where they were going afterwards;
+// htmx requests have htmx do the same for the whole page. Other clients are asked for
+// HTTP Basic credentials.
+fn login_required(request: &Request) -> Response {
+    let headers = request.headers();
+    let wants_page = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    if headers.contains_key("hx-request") {
+        let mut response = error_response(StatusCode::UNAUTHORIZED, "Please log in.");
+        response
+            .headers_mut()
+            .insert("hx-redirect", HeaderValue::from_static("/login"));
+        return response;
+    }
+    if wants_page && request.method() == Method::GET {
+        let next = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |path| path.as_str());
+        return Redirect::to(&format!("/login?next={}", urlencoding::encode(next))).into_response();
+    }
+    let mut response = error_response(StatusCode::UNAUTHORIZED, "Please log in.");
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        HeaderValue::from_static("Basic realm=\"kiv\", charset=\"UTF-8\""),
+    );
+    response
+}
+
+// --- Read-only mode ---
+// Refuses requests that would change files (`--read-only`) or hand out new links
+// (`--no-sharing`) before they reach a handler, whoever is logged in.
+pub async fn read_only(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    let refused = match capability_for(path, request.uri().query().unwrap_or("")) {
+        Some(Capability::Upload | Capability::Delete) if state.read_only => {
+            Some("This server is read-only.")
+        }
+        Some(Capability::Share) if !state.sharing => Some("Sharing is disabled on this server."),
+        _ => None,
+    };
+    if let Some(message) = refused {
+        info!("Refused {} {}: {}", request.method(), path, message);
+        return error_response(StatusCode::FORBIDDEN, message);
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "pw1", hashed by `kiv --hash-password`.
+    const HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$7vQJui+HSUK9lBHbRgQ83A$wIZBygBlvcgr0w60puINHO0gHGJYK0+C8i1cLWifZ7M";
+
+    async fn accounts(toml: &str) -> Result<Accounts, String> {
+        let path = std::env::temp_dir().join(format!("kiv-users-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, toml.replace("HASH", HASH)).unwrap();
+        let accounts = read_users(&path, false).await;
+        std::fs::remove_file(&path).unwrap();
+        accounts
+    }
+
+    async fn user(toml: &str, name: &str) -> Arc<User> {
+        accounts(toml).await.unwrap().users[name].clone()
+    }
+
+    fn as_user<R>(user: &Arc<User>, capability: Capability, f: impl FnOnce() -> R) -> R {
+        let access = Access {
+            user: user.clone(),
+            capability,
+        };
+        CURRENT.sync_scope(Scope::User(access), f)
+    }
+
+    const FAMILY: &str = r#"
+        [roles.family]
+        allow = { "" = ["browse", "preview"] }
+
+        [users.bob]
+        password = "HASH"
+        roles = ["family"]
+        allow = { "Photos" = ["share", "upload"], "Photos/private/ok" = ["upload"] }
+        deny = { "Photos/private" = ["all"] }
+    "#;
+
+    #[tokio::test]
+    async fn the_longest_matching_rule_decides() {
+        let bob = user(FAMILY, "bob").await;
+        assert!(bob.can(Capability::Browse, ""));
+        assert!(bob.can(Capability::Preview, "Music/a.mp3"));
+        assert!(bob.can(Capability::Upload, "Photos/2024"));
+        assert!(!bob.can(Capability::Upload, "Music"));
+        assert!(!bob.can(Capability::Browse, "Photos/private"));
+        assert!(!bob.can(Capability::Browse, "/Photos/private/x.jpg/"));
+        assert!(bob.can(Capability::Upload, "Photos/private/ok/x.jpg"));
+        // Rules cover whole path components only.
+        assert!(bob.can(Capability::Browse, "Photos/privateer"));
+        // No rule at all: no.
+        assert!(!bob.can(Capability::Delete, "Photos"));
+        assert!(!bob.can(Capability::Download, ""));
+    }
+
+    #[tokio::test]
+    async fn deny_wins_over_allow_on_the_same_path() {
+        let carol = user(
+            r#"
+            [users.carol]
+            password = "HASH"
+            allow = { "Docs" = ["all"] }
+            deny = { "Docs" = ["delete"] }
+            "#,
+            "carol",
+        )
+        .await;
+        assert!(carol.can(Capability::Upload, "Docs/a"));
+        assert!(!carol.can(Capability::Delete, "Docs/a"));
+    }
+
+    #[tokio::test]
+    async fn folders_above_what_may_be_browsed_can_be_traversed() {
+        let dave = user(
+            r#"
+            [users.dave]
+            password = "HASH"
+            allow = { "a/b/c" = ["browse"] }
+            "#,
+            "dave",
+        )
+        .await;
+        assert!(dave.can_traverse(""));
+        assert!(dave.can_traverse("a"));
+        assert!(dave.can_traverse("a/b"));
+        assert!(dave.can_traverse("a/b/c/d"));
+        assert!(!dave.can_traverse("a/x"));
+        assert!(!dave.can_traverse("a/b/cc"));
+        assert!(!dave.can(Capability::Browse, "a"));
+    }
+
+    const HOMES: &str = r#"
+        homes = "Family"
+
+        [users.alice]
+        password = "HASH"
+        allow = { "" = ["all"] }
+
+        [users.root]
+        password = "HASH"
+        home = ""
+        allow = { "" = ["all"] }
+
+        [users.erin]
+        password = "HASH"
+        home = "/Shared/erin/"
+        allow = { "" = ["all"] }
+    "#;
+
+    #[tokio::test]
+    async fn homes_keep_users_in_their_folder() {
+        let accounts = accounts(HOMES).await.unwrap();
+        let (alice, root, erin) = (
+            &accounts.users["alice"],
+            &accounts.users["root"],
+            &accounts.users["erin"],
+        );
+        assert_eq!(alice.home.as_deref(), Some("Family/alice"));
+        assert_eq!(root.home, None);
+        assert_eq!(erin.home.as_deref(), Some("Shared/erin"));
+
+        assert!(alice.in_home("Family/alice"));
+        assert!(alice.in_home("Family/alice/x.txt"));
+        assert!(!alice.in_home("Family/alicea"));
+        assert!(!alice.in_home("Family/bob/x.txt"));
+        assert!(!alice.in_home("Family"));
+        assert!(root.in_home("Family/bob"));
+
+        assert!(alice.above_home(""));
+        assert!(alice.above_home("Family"));
+        assert!(!alice.above_home("Family/alice"));
+        assert!(!alice.above_home("Fam"));
+        assert!(!root.above_home(""));
+    }
+
+    #[tokio::test]
+    async fn path_checks_follow_the_current_user() {
+        let accounts = accounts(HOMES).await.unwrap();
+        let alice = &accounts.users["alice"];
+        as_user(alice, Capability::Browse, || {
+            assert!(allows(""));
+            assert!(allows("Family"));
+            assert!(allows("Family/alice/x"));
+            assert!(!allows("Family/bob"));
+            assert!(!allows("Other"));
+            assert!(visible("Family"));
+            assert!(!visible("Family/bob"));
+            assert!(in_home("Family/alice/x"));
+            assert!(!in_home("Family/bob/x"));
+            assert_eq!(current_user().as_deref(), Some("alice"));
+            assert_eq!(home().as_deref(), Some("Family/alice"));
+            assert!(!is_admin());
+        });
+        // Anything but browsing stops at the home, even on the folders above it.
+        as_user(alice, Capability::Upload, || {
+            assert!(!allows("Family"));
+            assert!(allows("Family/alice/x"));
+        });
+    }
+
+    #[tokio::test]
+    async fn the_guest_is_nobody() {
+        let guest = accounts(
+            r#"
+            [users.bob]
+            password = "HASH"
+            [guest]
+            allow = { "Public" = ["browse", "download"] }
+            "#,
+        )
+        .await
+        .unwrap()
+        .guest
+        .unwrap();
+        as_user(&guest, Capability::Download, || {
+            assert!(allows("Public/a"));
+            assert!(!allows("Private/a"));
+            assert_eq!(current_user(), None);
+            assert!(!has_role(&[]));
+        });
+    }
+
+    #[tokio::test]
+    async fn the_guest_may_only_look() {
+        let error = accounts(
+            r#"
+            [users.bob]
+            password = "HASH"
+            [guest]
+            allow = { "Public" = ["upload"] }
+            "#,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(error.contains("may only allow browse, preview and download"));
+    }
+
+    #[tokio::test]
+    async fn users_files_are_checked() {
+        assert!(accounts("").await.is_err());
+        let unknown_role = accounts("[users.a]\npassword = \"HASH\"\nroles = [\"x\"]").await;
+        assert!(unknown_role.err().unwrap().contains("unknown role 'x'"));
+        let bad_capability =
+            accounts("[users.a]\npassword = \"HASH\"\nallow = { \"\" = [\"fly\"] }").await;
+        assert!(
+            bad_capability
+                .err()
+                .unwrap()
+                .contains("unknown capability 'fly'")
+        );
+        let plain_password = accounts("[users.a]\npassword = \"hunter2\"").await;
+        assert!(plain_password.err().unwrap().contains("must be a hash"));
+        let no_password = accounts("[users.a]\nadmin = true").await;
+        assert!(
+            no_password
+                .err()
+                .unwrap()
+                .contains("either password or password_file")
+        );
+    }
+
+    #[test]
+    fn checks_fail_closed_outside_any_scope() {
+        assert!(!allows("a"));
+        assert!(!visible("a"));
+        assert!(!in_home("a"));
+        assert!(!is_admin());
+        assert!(!is_system());
+        assert_eq!(current_user(), None);
+    }
+
+    #[test]
+    fn open_and_system_scopes_allow_everything() {
+        for scope in [Scope::Open, Scope::System] {
+            CURRENT.sync_scope(scope, || {
+                assert!(allows("a/b"));
+                assert!(visible("a/b"));
+                assert!(in_home("a/b"));
+                assert_eq!(current_user(), None);
+            });
+        }
+        CURRENT.sync_scope(Scope::System, || assert!(is_system()));
+        CURRENT.sync_scope(Scope::Open, || assert!(!is_system()));
+    }
+
+    #[tokio::test]
+    async fn scopes_are_carried_into_other_threads_and_tasks() {
+        let bob = user(FAMILY, "bob").await;
+        let access = Access {
+            user: bob,
+            capability: Capability::Browse,
+        };
+        CURRENT
+            .scope(Scope::User(access), async {
+                let checked = tokio::task::spawn_blocking(propagate(|| {
+                    (allows("Music"), allows("Photos/private"))
+                }))
+                .await
+                .unwrap();
+                assert_eq!(checked, (true, false));
+                let checked = tokio::spawn(propagate_async(async { current_user() }))
+                    .await
+                    .unwrap();
+                assert_eq!(checked.as_deref(), Some("bob"));
+                // Without carrying it along, nothing is allowed.
+                let checked = tokio::spawn(async { allows("Music") }).await.unwrap();
+                assert!(!checked);
+            })
+            .await;
+        assert!(system(async { allows("anything") }).await);
+    }
+
+    #[test]
+    fn routes_map_to_capabilities() {
+        for open in [
+            "/login",
+            "/logout",
+            "/share/abc",
+            "/direct-download/abc",
+            "/signed",
+            "/static/htmx.min.js",
+        ] {
+            assert_eq!(capability_for(open, ""), None, "{}", open);
+        }
+        let cases = [
+            ("/", "", Capability::Browse),
+            ("/browse", "path=a", Capability::Browse),
+            ("/jobs", "", Capability::Browse),
+            ("/jobs/1/cancel", "", Capability::Preview),
+            ("/raw", "path=a", Capability::Preview),
+            ("/raw", "path=a&download=true", Capability::Preview),
+            ("/media", "path=a", Capability::Preview),
+            ("/media", "path=a&download=false", Capability::Preview),
+            ("/media", "download=true&path=a", Capability::Download),
+            ("/media", "path=a&download=%74rue", Capability::Download),
+            ("/media", "path=a&download=1", Capability::Download),
+            ("/media", "path=download%3Dtrue", Capability::Preview),
+            ("/download-folder", "", Capability::Download),
+            ("/pdf-preview", "", Capability::Preview),
+            ("/hls/abc/index.m3u8", "", Capability::Preview),
+            ("/share", "", Capability::Share),
+            ("/sign", "", Capability::Share),
+            ("/upload", "", Capability::Upload),
+            ("/copy", "", Capability::Upload),
+            ("/favorites/add", "", Capability::Upload),
+            ("/favorites/remove", "", Capability::Upload),
+            ("/delete", "", Capability::Delete),
+            ("/trash", "", Capability::Delete),
+        ];
+        for (path, query, capability) in cases {
+            assert_eq!(
+                capability_for(path, query),
+                Some(capability),
+                "{}?{}",
+                path,
+                query
+            );
+        }
+    }
+
+    #[test]
+    fn normalizes_request_paths() {
+        assert_eq!(normalize("/a/b/"), "a/b");
+        assert_eq!(normalize("."), "");
+        assert_eq!(normalize("/"), "");
+        assert_eq!(normalize(""), "");
+    }
+}
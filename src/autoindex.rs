@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use maud::{DOCTYPE, Markup, html};
+
+// Names longer than this are cut short so the date and size columns still line up, as in
+// nginx's autoindex.
+const NAME_COLUMN: usize = 50;
+
+pub struct IndexEntry<'a> {
+    pub name: &'a str,
+    // The last segment of the entry's request path, which differs from `name` for names
+    // that aren't UTF-8.
+    pub segment: &'a str,
+    pub is_dir: bool,
+    pub modified: Option<DateTime<Utc>>,
+    pub size: Option<u64>,
+}
+
+// --- Rendering ---
+// A plain page with one relative link per entry and no scripts or styles, in the layout
+// nginx and Apache use, so mirroring tools (`wget -r`) and old browsers can walk the tree.
+// `display_path` starts and ends with `/`.
+pub fn render(display_path: &str, has_parent: bool, entries: &[IndexEntry]) -> Markup {
+    html! {
+        (DOCTYPE)
+        html {
+            head {
+                meta charset="utf-8";
+                title { "Index of " (display_path) }
+            }
+            body {
+                h1 { "Index of " (display_path) }
+                hr;
+                pre {
+                    @if has_parent {
+                        a href="../" { "../" } "\n"
+                    }
+                    @for entry in entries {
+                        @let label = label(entry);
+                        a href=(href(entry)) { (label) }
+                        (" ".repeat(NAME_COLUMN + 1 - label.chars().count()))
+                        (entry.modified.map_or("-".to_string(), |at| at.format("%d-%b-%Y %H:%M").to_string()))
+                        (format!("{:>20}", entry.size.map_or("-".to_string(), |size| size.to_string())))
+                        "\n"
+                    }
+                }
+                hr;
+            }
+        }
+    }
+}
+
+fn href(entry: &IndexEntry) -> String {
+    let encoded = urlencoding::encode(entry.segment);
+    if entry.is_dir {
+        format!("{}/", encoded)
+    } else {
+        encoded.into_owned()
+    }
+}
+
+fn label(entry: &IndexEntry) -> String {
+    let suffix = if entry.is_dir { "/" } else { "" };
+    let limit = NAME_COLUMN - suffix.len();
+    if entry.name.chars().count() > limit {
+        let short: String = entry.name.chars().take(limit - 3).collect();
+        format!("{}..>{}", short, suffix)
+    } else {
+        format!("{}{}", entry.name, suffix)
+    }
+}
@@ -0,0 +1,130 @@
+use bytes::Bytes;
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio_stream::{Stream, StreamExt};
+
+// Throughput is reported over windows of this length.
+const METER_WINDOW: Duration = Duration::from_secs(1);
+
+// --- Limiter ---
+// One token bucket shared by every download stream, refilled at the configured rate and
+// holding at most a second's worth, so a quiet server can't save up a burst. Streams take
+// what they send up front and wait off any debt, which keeps large chunks and many
+// concurrent streams from overshooting the cap.
+pub struct Bandwidth {
+    // Bytes per second; `None` sends as fast as the connection allows.
+    limit: Option<u64>,
+    bucket: Mutex<Bucket>,
+    meter: Mutex<Meter>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+struct Meter {
+    window_start: Instant,
+    window_bytes: u64,
+    // Bytes per second over the last full window.
+    rate: u64,
+    total: u64,
+}
+
+// Set once at startup, like the access policies: every file response streams through it.
+static BANDWIDTH: OnceLock<Bandwidth> = OnceLock::new();
+
+pub fn init(limit: Option<u64>) {
+    let _ = BANDWIDTH.set(Bandwidth::new(limit.filter(|limit| *limit > 0)));
+}
+
+pub fn get() -> &'static Bandwidth {
+    BANDWIDTH.get_or_init(|| Bandwidth::new(None))
+}
+
+impl Bandwidth {
+    fn new(limit: Option<u64>) -> Self {
+        let now = Instant::now();
+        Bandwidth {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.unwrap_or(0) as f64,
+                refilled_at: now,
+            }),
+            meter: Mutex::new(Meter {
+                window_start: now,
+                window_bytes: 0,
+                rate: 0,
+                total: 0,
+            }),
+        }
+    }
+
+    pub fn limit(&self) -> Option<u64> {
+        self.limit
+    }
+
+    // Bytes per second sent over the last second, across all downloads.
+    pub fn current_rate(&self) -> u64 {
+        let meter = self.meter.lock().unwrap_or_else(|e| e.into_inner());
+        // A window that ended a while ago means nothing has been sent since.
+        if meter.window_start.elapsed() > METER_WINDOW * 2 {
+            0
+        } else {
+            meter.rate
+        }
+    }
+
+    pub fn total_sent(&self) -> u64 {
+        self.meter.lock().unwrap_or_else(|e| e.into_inner()).total
+    }
+
+    async fn take(&self, bytes: usize) {
+        self.record(bytes as u64);
+        let Some(limit) = self.limit else {
+            return;
+        };
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.refilled_at).as_secs_f64() * limit as f64;
+            bucket.tokens = (bucket.tokens + refill).min(limit as f64) - bytes as f64;
+            bucket.refilled_at = now;
+            (bucket.tokens < 0.0).then(|| Duration::from_secs_f64(-bucket.tokens / limit as f64))
+        };
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn record(&self, bytes: u64) {
+        let mut meter = self.meter.lock().unwrap_or_else(|e| e.into_inner());
+        let elapsed = meter.window_start.elapsed();
+        if elapsed >= METER_WINDOW {
+            meter.rate = if elapsed >= METER_WINDOW * 2 {
+                0
+            } else {
+                (meter.window_bytes as f64 / elapsed.as_secs_f64()) as u64
+            };
+            meter.window_start = Instant::now();
+            meter.window_bytes = 0;
+        }
+        meter.window_bytes += bytes;
+        meter.total += bytes;
+    }
+}
+
+// Passes a response body through the shared limit, chunk by chunk.
+pub fn throttle<S>(stream: S) -> impl Stream<Item = std::io::Result<Bytes>> + Send + 'static
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Send + 'static,
+{
+    stream.then(|chunk| async move {
+        if let Ok(bytes) = &chunk {
+            get().take(bytes.len()).await;
+        }
+        chunk
+    })
+}
@@ -0,0 +1,339 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+use flate2::{Compression, write::GzEncoder};
+use std::{
+    fs::{File, Metadata},
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tokio_util::io::SyncIoBridge;
+use tracing::{error, warn};
+
+use crate::mounts::Mount;
+use zip::{AesMode, CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+// Files of these types are already compressed, so deflating them again only costs CPU.
+const STORED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "br", "bz2", "cbr", "cbz", "docx", "epub", "flac", "gif",
+    "gz", "heic", "jar", "jpeg", "jpg", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "odt", "ogg",
+    "opus", "png", "pptx", "rar", "tgz", "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
+
+fn compression_for(path: &Path) -> CompressionMethod {
+    let stored = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            STORED_EXTENSIONS
+                .iter()
+                .any(|stored| stored.eq_ignore_ascii_case(extension))
+        });
+    if stored {
+        CompressionMethod::Stored
+    } else {
+        CompressionMethod::Deflated
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BundleFormat {
+    Zip,
+    // Keeps Unix permissions, owners and symbolic links, which zip can't carry.
+    TarGz,
+}
+
+impl BundleFormat {
+    pub fn parse(name: Option<&str>) -> Self {
+        match name {
+            Some("tar.gz" | "tgz") => BundleFormat::TarGz,
+            _ => BundleFormat::Zip,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            BundleFormat::Zip => "zip",
+            BundleFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            BundleFormat::Zip => "application/zip",
+            BundleFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+// One file or folder to put in an archive, under `name`.
+pub struct BundleItem {
+    pub mount: Mount,
+    pub path: PathBuf,
+    pub name: String,
+}
+
+// --- Streaming ---
+// Writes the items, and everything listed inside the folders among them, as an archive on
+// a blocking thread and pipes it into the returned reader. Each file is read as it is
+// added, so memory stays bounded however large the folders are. With a password, zip
+// entries are AES-256 encrypted; tar has no encryption, so callers refuse that pairing.
+pub fn stream(
+    format: BundleFormat,
+    items: Vec<BundleItem>,
+    password: Option<String>,
+) -> impl tokio::io::AsyncRead {
+    let (reader, writer) = tokio::io::duplex(1 << 16);
+    let mut writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(crate::auth::propagate(move || {
+        let result = match format {
+            BundleFormat::Zip => write_zip(&items, password.as_deref(), &mut writer),
+            BundleFormat::TarGz => write_tar_gz(&items, &mut writer),
+        };
+        if let Err(e) = result {
+            let first = items.first().map(|item| item.path.display().to_string());
+            error!(
+                "Failed to archive {} ({} items): {}",
+                first.unwrap_or_default(),
+                items.len(),
+                e
+            );
+        }
+        let _ = writer.shutdown();
+    }));
+    reader
+}
+
+enum Entry {
+    Dir(Metadata),
+    File(File, Metadata),
+    // A link that stays inside the root, with what it points to relative to its folder.
+    Link(PathBuf, Metadata),
+}
+
+// Visits each item and, for folders, everything listed below them in name order,
+// folders before their contents, without following links. Unreadable files and folders
+// are left out.
+fn walk(
+    items: &[BundleItem],
+    mut visit: impl FnMut(String, &Path, Entry) -> Result<(), String>,
+) -> Result<(), String> {
+    for item in items {
+        if item.path.is_dir() {
+            walk_dir(&item.mount, &item.path, item.name.clone(), &mut visit)?;
+            continue;
+        }
+        let Ok(file) = File::open(&item.path) else {
+            warn!(
+                "Could not read {} while archiving; left out.",
+                item.path.display()
+            );
+            continue;
+        };
+        if let Ok(metadata) = file.metadata() {
+            visit(item.name.clone(), &item.path, Entry::File(file, metadata))?;
+        }
+    }
+    Ok(())
+}
+
+fn walk_dir(
+    mount: &Mount,
+    dir: &Path,
+    top: String,
+    visit: &mut impl FnMut(String, &Path, Entry) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut pending = vec![(dir.to_path_buf(), top)];
+    while let Some((dir, name)) = pending.pop() {
+        let metadata = std::fs::metadata(&dir).map_err(|e| e.to_string())?;
+        visit(format!("{}/", name), &dir, Entry::Dir(metadata))?;
+
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            warn!(
+                "Could not read {} while archiving; left out.",
+                dir.display()
+            );
+            continue;
+        };
+        let mut entries: Vec<_> = entries.flatten().map(|entry| entry.path()).collect();
+        entries.sort();
+        // Popped from the end, so pushed in reverse to come out in name order.
+        let mut subdirs = Vec::new();
+        for path in entries {
+            // What the user may not download themselves stays out of their archives too.
+            if !crate::policy::is_listed(&mount.dir, &path)
+                || !crate::policy::is_accessible(&mount.dir, &path)
+                || !crate::auth::allows(&mount.relative(&path))
+            {
+                continue;
+            }
+            let entry_name = format!(
+                "{}/{}",
+                name,
+                path.file_name().unwrap_or_default().to_string_lossy()
+            );
+            let Ok(link_metadata) = std::fs::symlink_metadata(&path) else {
+                continue;
+            };
+            if link_metadata.is_symlink() {
+                let target = std::fs::canonicalize(&path).ok().filter(|target| {
+                    target.starts_with(&mount.dir)
+                        && crate::policy::is_accessible(&mount.dir, target)
+                        && crate::auth::allows(&mount.relative(target))
+                });
+                if let (Some(target), Ok(dir)) = (target, std::fs::canonicalize(&dir)) {
+                    let link = relative_link(&dir, &target);
+                    visit(entry_name, &path, Entry::Link(link, link_metadata))?;
+                }
+                continue;
+            }
+            if link_metadata.is_dir() {
+                subdirs.push((path, entry_name));
+                continue;
+            }
+
+            let Ok(file) = File::open(&path) else {
+                warn!(
+                    "Could not read {} while archiving; left out.",
+                    path.display()
+                );
+                continue;
+            };
+            let Ok(metadata) = file.metadata() else {
+                continue;
+            };
+            if metadata.is_file() {
+                visit(entry_name, &path, Entry::File(file, metadata))?;
+            }
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    Ok(())
+}
+
+// Where `target` is as seen from `dir`, both canonical: links are stored this way rather
+// than as written, so none in an archive points at an absolute path on this server.
+fn relative_link(dir: &Path, target: &Path) -> PathBuf {
+    let dir: Vec<_> = dir.components().collect();
+    let target: Vec<_> = target.components().collect();
+    let shared = dir.iter().zip(&target).take_while(|(a, b)| a == b).count();
+    let mut link = PathBuf::new();
+    for _ in shared..dir.len() {
+        link.push("..");
+    }
+    link.extend(&target[shared..]);
+    if link.as_os_str().is_empty() {
+        link.push(".");
+    }
+    link
+}
+
+// --- Zip ---
+fn write_zip(
+    items: &[BundleItem],
+    password: Option<&str>,
+    out: &mut impl Write,
+) -> Result<(), String> {
+    let mut zip = ZipWriter::new_stream(out);
+    walk(items, |name, path, entry| {
+        let (mut file, metadata) = match entry {
+            Entry::Dir(metadata) => {
+                return zip
+                    .add_directory(name, options_for(&metadata))
+                    .map_err(|e| e.to_string());
+            }
+            Entry::File(file, metadata) => (file, metadata),
+            // Zip has no links: ones to files are stored as the file, ones to folders
+            // are left out so a loop can't make the archive endless.
+            Entry::Link(..) => match File::open(path) {
+                Ok(file) => match file.metadata() {
+                    Ok(metadata) if metadata.is_file() => (file, metadata),
+                    _ => return Ok(()),
+                },
+                Err(_) => return Ok(()),
+            },
+        };
+        let options = options_for(&metadata)
+            .compression_method(compression_for(path))
+            .large_file(metadata.len() >= u32::MAX as u64);
+        // Only contents are encrypted; names and sizes stay readable, as zip defines it.
+        let options = match password {
+            Some(password) => options.with_aes_encryption(AesMode::Aes256, password),
+            None => options,
+        };
+        zip.start_file(name, options).map_err(|e| e.to_string())?;
+        std::io::copy(&mut file, &mut zip).map_err(|e| e.to_string())?;
+        Ok(())
+    })?;
+    zip.finish().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Zip timestamps are in local time and can't go before 1980.
+fn options_for(metadata: &Metadata) -> SimpleFileOptions {
+    let mut options = SimpleFileOptions::default();
+    if let Some(modified) = metadata.modified().ok().and_then(|at| {
+        let at = DateTime::<Local>::from(at);
+        zip::DateTime::from_date_and_time(
+            u16::try_from(at.year()).ok()?,
+            at.month() as u8,
+            at.day() as u8,
+            at.hour() as u8,
+            at.minute() as u8,
+            at.second() as u8,
+        )
+        .ok()
+    }) {
+        options = options.last_modified_time(modified);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        options = options.unix_permissions(metadata.permissions().mode() & 0o777);
+    }
+    options
+}
+
+// --- Tar ---
+fn write_tar_gz(items: &[BundleItem], out: &mut impl Write) -> Result<(), String> {
+    let mut tar = tar::Builder::new(GzEncoder::new(out, Compression::default()));
+    walk(items, |name, _, entry| {
+        let mut header = tar::Header::new_gnu();
+        match entry {
+            Entry::Dir(metadata) => {
+                header.set_metadata(&metadata);
+                header.set_size(0);
+                tar.append_data(&mut header, name, std::io::empty())
+            }
+            Entry::File(file, metadata) => {
+                header.set_metadata(&metadata);
+                tar.append_data(&mut header, name, file)
+            }
+            Entry::Link(target, metadata) => {
+                header.set_metadata(&metadata);
+                header.set_size(0);
+                tar.append_link(&mut header, name, target)
+            }
+        }
+        .map_err(|e| e.to_string())
+    })?;
+    tar.into_inner()
+        .and_then(|gz| gz.finish())
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_are_stored_relative_to_their_folder() {
+        let link = |dir: &str, target: &str| relative_link(Path::new(dir), Path::new(target));
+        assert_eq!(link("/srv/a", "/srv/a/b.txt"), PathBuf::from("b.txt"));
+        assert_eq!(
+            link("/srv/a/b", "/srv/a/c/d.txt"),
+            PathBuf::from("../c/d.txt")
+        );
+        assert_eq!(link("/srv/a/b", "/srv/x.txt"), PathBuf::from("../../x.txt"));
+        assert_eq!(link("/srv/a", "/srv/a"), PathBuf::from("."));
+    }
+}
@@ -0,0 +1,130 @@
+use crate::SharedState;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::{error, info};
+
+// Derived files (resized images, …) live under `<root>/.kiv-cache/<kind>/<key>.<ext>`.
+// Keys cover the source's path, size and mtime, so editing a file simply leaves its old
+// entries to be evicted.
+pub const CACHE_DIR_NAME: &str = ".kiv-cache";
+const PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// --- Configuration ---
+pub struct CacheConfig {
+    pub dir: PathBuf,
+    // Once the cache grows past this, the least recently written entries are removed.
+    pub max_size: u64,
+}
+
+impl CacheConfig {
+    fn entry_path(&self, kind: &str, key: &str, extension: &str) -> PathBuf {
+        self.dir.join(kind).join(format!("{}.{}", key, extension))
+    }
+
+    // The cached file for `key`, if one was stored.
+    pub async fn get(&self, kind: &str, key: &str, extension: &str) -> Option<PathBuf> {
+        let path = self.entry_path(kind, key, extension);
+        tokio::fs::metadata(&path)
+            .await
+            .is_ok_and(|m| m.is_file())
+            .then_some(path)
+    }
+
+    // Stores `bytes` under `key`. Written to a temporary name first so concurrent
+    // readers never see a half-written file.
+    pub async fn put(
+        &self,
+        kind: &str,
+        key: &str,
+        extension: &str,
+        bytes: &[u8],
+    ) -> std::io::Result<PathBuf> {
+        let path = self.entry_path(kind, key, extension);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let temp_path = path.with_extension(format!("{}.tmp-{}", extension, uuid::Uuid::new_v4()));
+        tokio::fs::write(&temp_path, bytes).await?;
+        if let Err(e) = tokio::fs::rename(&temp_path, &path).await {
+            let _ = tokio::fs::remove_file(&temp_path).await;
+            return Err(e);
+        }
+        Ok(path)
+    }
+}
+
+// Cache key for something derived from `source` (e.g. "w=400").
+pub fn key(source: &Path, metadata: &Metadata, variant: &str) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |duration| duration.as_nanos());
+    let mut hasher = Sha256::new();
+    hasher.update(source.as_os_str().as_encoded_bytes());
+    hasher.update(format!("\0{}\0{}\0{}", metadata.len(), modified, variant));
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// --- Eviction ---
+pub async fn prune_loop(state: SharedState) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let dir = state.cache.dir.clone();
+        let max_size = state.cache.max_size;
+        match tokio::task::spawn_blocking(move || prune_once(&dir, max_size)).await {
+            Ok(0) => {}
+            Ok(removed) => info!("Cache prune removed {} file(s).", removed),
+            Err(e) => error!("Cache prune task failed: {}", e),
+        }
+    }
+}
+
+fn prune_once(dir: &Path, max_size: u64) -> usize {
+    let mut files: Vec<(PathBuf, u64, SystemTime)> = Vec::new();
+    let Ok(kinds) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    for kind in kinds.flatten() {
+        let Ok(entries) = std::fs::read_dir(kind.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if let Ok(metadata) = entry.metadata()
+                && metadata.is_file()
+            {
+                let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+                files.push((entry.path(), metadata.len(), modified));
+            }
+        }
+    }
+
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    if total <= max_size {
+        return 0;
+    }
+    files.sort_by_key(|(_, _, modified)| *modified);
+    let mut removed = 0;
+    for (path, size, _) in files {
+        if total <= max_size {
+            break;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(()) => {
+                total = total.saturating_sub(size);
+                removed += 1;
+            }
+            Err(e) => error!("Failed to remove cache file {}: {}", path.display(), e),
+        }
+    }
+    removed
+}
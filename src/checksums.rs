@@ -0,0 +1,310 @@
+use dashmap::DashMap;
+use sha2::{Digest, Sha256};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::sync::Semaphore;
+
+use crate::{
+    auth,
+    fileops::display_relative,
+    jobs::{Job, walk_tree},
+    mounts::Mount,
+    policy,
+};
+
+// Same name and line format as coreutils' `sha256sum`, so manifests can be checked with
+// `sha256sum -c SHA256SUMS` as well.
+pub const MANIFEST_NAME: &str = "SHA256SUMS";
+
+// --- Hashing ---
+pub async fn sha256_file(path: &Path) -> std::io::Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0u8; 1 << 20];
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// --- On-demand hashes ---
+// Files hashed at once for the listing's checksum column; the rest of the rows wait.
+const HASH_WORKERS: usize = 2;
+
+struct CachedHash {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: String,
+}
+
+// SHA-256 of single files for the checksum column, cached per (path, size, mtime) so
+// showing a listing again doesn't read every file again.
+pub struct FileHashes {
+    cache: DashMap<PathBuf, CachedHash>,
+    workers: Semaphore,
+}
+
+impl FileHashes {
+    pub fn new() -> Self {
+        FileHashes {
+            cache: DashMap::new(),
+            workers: Semaphore::new(HASH_WORKERS),
+        }
+    }
+
+    // The hash if the file has been hashed as it is now, without reading it.
+    pub fn cached(&self, path: &Path, metadata: &std::fs::Metadata) -> Option<String> {
+        let (modified, len) = (metadata.modified().ok(), metadata.len());
+        self.cache
+            .get(path)
+            .filter(|cached| cached.modified == modified && cached.len == len)
+            .map(|cached| cached.hash.clone())
+    }
+
+    pub async fn sha256(&self, path: &Path) -> std::io::Result<String> {
+        let metadata = tokio::fs::metadata(path).await?;
+        let (modified, len) = (metadata.modified().ok(), metadata.len());
+        let cached = || self.cached(path, &metadata);
+        if let Some(hash) = cached() {
+            return Ok(hash);
+        }
+        let _worker = self
+            .workers
+            .acquire()
+            .await
+            .map_err(std::io::Error::other)?;
+        // Another request for the same file may have finished while this one waited.
+        if let Some(hash) = cached() {
+            return Ok(hash);
+        }
+        let hash = sha256_file(path).await?;
+        self.cache.insert(
+            path.to_path_buf(),
+            CachedHash {
+                modified,
+                len,
+                hash: hash.clone(),
+            },
+        );
+        Ok(hash)
+    }
+}
+
+// --- Manifest generation ---
+pub async fn generate_manifest(
+    job: Arc<Job>,
+    mount: Mount,
+    dir: PathBuf,
+) -> Result<String, String> {
+    job.set_message("Scanning directory…");
+    let listing = walk_tree(dir.clone()).await;
+    for (path, e) in &listing.errors {
+        job.record_failure(format!("{}: {}", display_relative(&dir, path), e));
+    }
+
+    // Links are hashed only when they lead somewhere the user could open anyway.
+    let manifest_path = dir.join(MANIFEST_NAME);
+    let mut files: Vec<_> = listing
+        .files
+        .into_iter()
+        .filter(|file| file != &manifest_path && readable(&mount, file).is_some())
+        .collect();
+    files.sort();
+    job.set_total(files.len() as u64);
+    job.set_message(format!("Hashing {} file(s)…", files.len()));
+
+    let mut manifest = String::new();
+    let mut hashed = 0;
+    for file in &files {
+        if job.is_cancelled() {
+            return Ok("Cancelled; no manifest was written.".to_string());
+        }
+        let relative = display_relative(&dir, file);
+        if relative.contains('\n') {
+            job.record_failure(format!(
+                "{}: file names with newlines are skipped",
+                relative
+            ));
+        } else {
+            match sha256_file(file).await {
+                Ok(hash) => {
+                    manifest.push_str(&format!("{}  {}\n", hash, relative));
+                    hashed += 1;
+                }
+                Err(e) => job.record_failure(format!("{}: {}", relative, e)),
+            }
+        }
+        job.advance(1);
+    }
+
+    // Write next to the target and rename, so a half-written manifest never replaces a good one.
+    let temp_path = dir.join(format!(".{}.tmp", MANIFEST_NAME));
+    tokio::fs::write(&temp_path, manifest)
+        .await
+        .map_err(|e| format!("Could not write {}: {}", MANIFEST_NAME, e))?;
+    tokio::fs::rename(&temp_path, &manifest_path)
+        .await
+        .map_err(|e| format!("Could not write {}: {}", MANIFEST_NAME, e))?;
+
+    Ok(format!(
+        "Wrote {} with {} entr{}.",
+        MANIFEST_NAME,
+        hashed,
+        if hashed == 1 { "y" } else { "ies" }
+    ))
+}
+
+// --- Manifest verification ---
+pub async fn verify_manifest(job: Arc<Job>, mount: Mount, dir: PathBuf) -> Result<String, String> {
+    let manifest_path = dir.join(MANIFEST_NAME);
+    let manifest = tokio::fs::read_to_string(&manifest_path)
+        .await
+        .map_err(|e| format!("Could not read {}: {}", MANIFEST_NAME, e))?;
+
+    let entries: Vec<_> = manifest
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    job.set_total(entries.len() as u64);
+    job.set_message(format!("Verifying {} entr(ies)…", entries.len()));
+
+    let (mut ok, mut mismatched, mut missing, mut malformed) = (0, 0, 0, 0);
+    for line in entries {
+        if job.is_cancelled() {
+            return Ok(format!(
+                "Cancelled after {} verified, {} mismatched, {} missing.",
+                ok, mismatched, missing
+            ));
+        }
+        job.advance(1);
+
+        let Some((expected, name)) = parse_manifest_line(line) else {
+            malformed += 1;
+            job.record_failure(format!("MALFORMED: {}", line));
+            continue;
+        };
+        let Some(path) = readable(&mount, &dir.join(name)) else {
+            missing += 1;
+            job.record_failure(format!("MISSING: {}", name));
+            continue;
+        };
+        match sha256_file(&path).await {
+            Ok(actual) if actual.eq_ignore_ascii_case(expected) => ok += 1,
+            Ok(_) => {
+                mismatched += 1;
+                job.record_failure(format!("MISMATCH: {}", name));
+            }
+            Err(e) => {
+                missing += 1;
+                job.record_failure(format!("UNREADABLE: {}: {}", name, e));
+            }
+        }
+    }
+
+    let summary = format!(
+        "{} OK, {} mismatched, {} missing or unreadable{}.",
+        ok,
+        mismatched,
+        missing,
+        if malformed > 0 {
+            format!(", {} malformed line(s)", malformed)
+        } else {
+            String::new()
+        }
+    );
+    if mismatched + missing + malformed > 0 {
+        Err(format!("Verification failed: {}", summary))
+    } else {
+        Ok(format!("Verification passed: {}", summary))
+    }
+}
+
+// Accepts both `<hash>  <name>` (text mode) and `<hash> *<name>` (binary mode).
+fn parse_manifest_line(line: &str) -> Option<(&str, &str)> {
+    let (hash, rest) = line.split_once(' ')?;
+    let name = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+    let valid_hash = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+    (valid_hash && !name.is_empty()).then_some((hash, name))
+}
+
+// The file `path` leads to, if the current request could open it there. Manifest entries
+// are untrusted input, so this holds them to the same checks as a requested path: inside
+// the mount, allowed by the policies, and by the user's rules and folder passwords both
+// where the name is and where it leads.
+fn readable(mount: &Mount, path: &Path) -> Option<PathBuf> {
+    let canonical = path.canonicalize().ok()?;
+    (canonical.starts_with(&mount.dir)
+        && policy::is_accessible(&mount.dir, path)
+        && policy::is_accessible(&mount.dir, &canonical)
+        && auth::allows(&mount.relative(path))
+        && auth::allows(&mount.relative(&canonical))
+        && canonical.is_file())
+    .then_some(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    #[test]
+    fn parses_both_manifest_modes() {
+        assert_eq!(
+            parse_manifest_line(&format!("{}  a b.txt", HASH)),
+            Some((HASH, "a b.txt"))
+        );
+        assert_eq!(
+            parse_manifest_line(&format!("{} *bin/x", HASH)),
+            Some((HASH, "bin/x"))
+        );
+        assert_eq!(parse_manifest_line(&format!("{} x", HASH)), None);
+        assert_eq!(parse_manifest_line(&format!("{}  ", HASH)), None);
+        assert_eq!(parse_manifest_line(&format!("{}  x", &HASH[1..])), None);
+        assert_eq!(parse_manifest_line("zz  x"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn entries_stay_inside_the_mount() {
+        let dir = std::env::temp_dir().join(format!("kiv-checksums-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let mount = Mount {
+            name: String::new(),
+            dir: dir.canonicalize().unwrap(),
+        };
+        std::fs::write(mount.dir.join("sub/a.txt"), "test").unwrap();
+        std::os::unix::fs::symlink("a.txt", mount.dir.join("sub/inside")).unwrap();
+        std::os::unix::fs::symlink("/etc/hostname", mount.dir.join("sub/outside")).unwrap();
+        let sub = mount.dir.join("sub");
+
+        let (inside, outside, escaped, missing) = auth::system(async {
+            (
+                readable(&mount, &sub.join("inside")),
+                readable(&mount, &sub.join("outside")),
+                readable(&mount, &sub.join("../../etc/hostname")),
+                readable(&mount, &sub.join("missing")),
+            )
+        })
+        .await;
+        assert_eq!(inside, Some(mount.dir.join("sub/a.txt")));
+        assert_eq!((outside, escaped, missing), (None, None, None));
+        // Outside any request nothing is readable.
+        assert_eq!(readable(&mount, &sub.join("a.txt")), None);
+        assert_eq!(sha256_file(&sub.join("a.txt")).await.unwrap(), HASH);
+        std::fs::remove_dir_all(&mount.dir).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// Infected uploads are moved here instead of being deleted, so an admin can inspect them.
+pub const QUARANTINE_DIR_NAME: &str = ".kiv-quarantine";
+
+// clamd rejects INSTREAM chunks above its StreamMaxLength; 64 KiB is well below any sane limit.
+const CHUNK_SIZE: usize = 64 * 1024;
+const SCAN_TIMEOUT: Duration = Duration::from_secs(300);
+
+// --- Configuration ---
+// Accepts `unix:/path/to/clamd.ctl`, `tcp:host:port`, a bare absolute socket path,
+// or a bare `host:port`.
+#[derive(Clone, Debug)]
+pub enum ClamdAddr {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl FromStr for ClamdAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(ClamdAddr::Unix(PathBuf::from(path)))
+        } else if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(ClamdAddr::Tcp(addr.to_string()))
+        } else if s.starts_with('/') {
+            Ok(ClamdAddr::Unix(PathBuf::from(s)))
+        } else if s.contains(':') {
+            Ok(ClamdAddr::Tcp(s.to_string()))
+        } else {
+            Err(format!(
+                "expected unix:/path/to/socket or tcp:host:port, got '{}'",
+                s
+            ))
+        }
+    }
+}
+
+impl std::fmt::Display for ClamdAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClamdAddr::Unix(path) => write!(f, "unix:{}", path.display()),
+            ClamdAddr::Tcp(addr) => write!(f, "tcp:{}", addr),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScanVerdict {
+    Clean,
+    Infected(String),
+}
+
+// --- Scanning ---
+pub async fn scan_file(addr: &ClamdAddr, path: &Path) -> std::io::Result<ScanVerdict> {
+    match tokio::time::timeout(SCAN_TIMEOUT, scan_file_inner(addr, path)).await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "clamd scan timed out",
+        )),
+    }
+}
+
+async fn scan_file_inner(addr: &ClamdAddr, path: &Path) -> std::io::Result<ScanVerdict> {
+    let file = tokio::fs::File::open(path).await?;
+    match addr {
+        ClamdAddr::Tcp(addr) => {
+            let stream = tokio::net::TcpStream::connect(addr).await?;
+            instream(stream, file).await
+        }
+        #[cfg(unix)]
+        ClamdAddr::Unix(socket_path) => {
+            let stream = tokio::net::UnixStream::connect(socket_path).await?;
+            instream(stream, file).await
+        }
+        #[cfg(not(unix))]
+        ClamdAddr::Unix(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "unix sockets are not supported on this platform",
+        )),
+    }
+}
+
+// Speaks clamd's INSTREAM protocol: a command, then length-prefixed chunks terminated by a
+// zero-length chunk, answered with a single `stream: <result>` line.
+async fn instream<S, R>(mut stream: S, mut source: R) -> std::io::Result<ScanVerdict>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    R: AsyncRead + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    loop {
+        let read = source.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        stream.write_all(&buffer[..read]).await?;
+    }
+    stream.write_all(&0u32.to_be_bytes()).await?;
+    stream.flush().await?;
+
+    // Replies to `z`-prefixed commands are NUL-terminated; don't rely on clamd closing the socket.
+    let mut response = Vec::new();
+    let mut byte = [0u8; 1];
+    while stream.read(&mut byte).await? == 1 && byte[0] != 0 {
+        response.push(byte[0]);
+    }
+    parse_response(&response)
+}
+
+fn parse_response(response: &[u8]) -> std::io::Result<ScanVerdict> {
+    let text = String::from_utf8_lossy(response);
+    let text = text.trim_end_matches(['\0', '\n']).trim();
+    let result = text.strip_prefix("stream:").unwrap_or(text).trim();
+
+    if result == "OK" {
+        Ok(ScanVerdict::Clean)
+    } else if let Some(signature) = result.strip_suffix("FOUND") {
+        Ok(ScanVerdict::Infected(signature.trim().to_string()))
+    } else {
+        Err(std::io::Error::other(format!(
+            "unexpected clamd response: {}",
+            text
+        )))
+    }
+}
@@ -0,0 +1,100 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use std::{net::IpAddr, sync::Arc, time::Duration};
+use tokio::sync::Semaphore;
+use tokio_stream::StreamExt;
+use tracing::info;
+
+use crate::{SharedState, error_response, ipfilter::ClientIp};
+
+// Past this many clients, ones with nothing in flight are swept out on the next request.
+const SWEEP_THRESHOLD: usize = 1024;
+
+// --- Limits ---
+// How many downloads each client address may have streaming at once. Further requests
+// queue for a free slot, and are turned away once they have waited `queue_timeout`.
+pub struct ClientLimits {
+    per_client: Option<usize>,
+    queue_timeout: Duration,
+    slots: DashMap<IpAddr, Arc<Semaphore>>,
+}
+
+impl ClientLimits {
+    pub fn new(per_client: Option<usize>, queue_timeout: Duration) -> Self {
+        ClientLimits {
+            per_client: per_client.filter(|limit| *limit > 0),
+            queue_timeout,
+            slots: DashMap::new(),
+        }
+    }
+
+    pub fn per_client(&self) -> Option<usize> {
+        self.per_client
+    }
+
+    fn slots_for(&self, client: IpAddr, limit: usize) -> Arc<Semaphore> {
+        if self.slots.len() >= SWEEP_THRESHOLD {
+            self.slots.retain(|_, slots| {
+                Arc::strong_count(slots) > 1 || slots.available_permits() < limit
+            });
+        }
+        self.slots
+            .entry(client)
+            .or_insert_with(|| Arc::new(Semaphore::new(limit)))
+            .clone()
+    }
+}
+
+// --- Middleware ---
+// Holds one of the client's slots from the request until the last byte of the response
+// body has gone out (or the client hangs up), not just until the handler returns.
+pub async fn limit(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let limits = &state.client_limits;
+    let Some(limit) = limits.per_client else {
+        return next.run(request).await;
+    };
+
+    let slots = limits.slots_for(client, limit);
+    let permit = match slots.clone().try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            info!(
+                "{} has {} downloads running; queueing {}",
+                client,
+                limit,
+                request.uri().path()
+            );
+            match tokio::time::timeout(limits.queue_timeout, slots.acquire_owned()).await {
+                Ok(Ok(permit)) => permit,
+                _ => {
+                    let mut response = error_response(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Too many downloads at once from this address. Try again when one has finished.",
+                    );
+                    response
+                        .headers_mut()
+                        .insert(header::RETRY_AFTER, HeaderValue::from_static("30"));
+                    return response;
+                }
+            }
+        }
+    };
+
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _held = &permit;
+        chunk
+    });
+    (parts, Body::from_stream(body)).into_response()
+}
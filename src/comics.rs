@@ -0,0 +1,113 @@
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use crate::natsort::natural_cmp;
+
+// A single page is held in memory when it comes out of a RAR; bigger members are refused.
+const MAX_PAGE_BYTES: u64 = 64 * 1024 * 1024;
+
+// What the comic actually is inside. `.cbr` files are frequently zips with the wrong
+// extension (and vice versa), so this is decided by the file's magic bytes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ComicContainer {
+    Zip,
+    Rar,
+}
+
+pub fn is_comic_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("cbz") || ext.eq_ignore_ascii_case("cbr"))
+}
+
+fn detect_container(path: &Path) -> Result<ComicContainer, String> {
+    let mut magic = [0u8; 4];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .map_err(|e| e.to_string())?;
+    match &magic {
+        b"PK\x03\x04" => Ok(ComicContainer::Zip),
+        b"Rar!" => Ok(ComicContainer::Rar),
+        _ => Err("The file is neither a ZIP nor a RAR archive.".to_string()),
+    }
+}
+
+fn is_page_image(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    // macOS resource forks ride along in many zips and look like images by name.
+    !lower.starts_with("__macosx/")
+        && !lower
+            .rsplit('/')
+            .next()
+            .is_some_and(|file| file.starts_with('.'))
+        && [".jpg", ".jpeg", ".png", ".gif", ".webp", ".avif", ".bmp"]
+            .iter()
+            .any(|ext| lower.ends_with(ext))
+}
+
+// --- Pages ---
+// The archive's images in reading order. Only the directory (zip) or the headers (rar)
+// are read.
+pub async fn list_pages(path: &Path) -> Result<(ComicContainer, Vec<String>), String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let container = detect_container(&path)?;
+        let mut pages = match container {
+            ComicContainer::Zip => {
+                let file = File::open(&path).map_err(|e| e.to_string())?;
+                let archive =
+                    zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+                archive
+                    .file_names()
+                    .flatten()
+                    .filter(|name| is_page_image(name))
+                    .map(|name| name.into_owned())
+                    .collect::<Vec<_>>()
+            }
+            ComicContainer::Rar => {
+                let archive = unrar::Archive::new(&path)
+                    .open_for_listing()
+                    .map_err(|e| e.to_string())?;
+                let mut names = Vec::new();
+                for header in archive {
+                    let header = header.map_err(|e| e.to_string())?;
+                    let name = header.filename.to_string_lossy().replace('\\', "/");
+                    if header.is_file() && is_page_image(&name) {
+                        names.push(name);
+                    }
+                }
+                names
+            }
+        };
+        pages.sort_by(|a, b| natural_cmp(a, b));
+        Ok((container, pages))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// A RAR member, read into memory. Zip pages are streamed with `archive::stream_entry`.
+pub async fn read_rar_page(path: PathBuf, name: String) -> Result<Vec<u8>, String> {
+    tokio::task::spawn_blocking(move || {
+        let mut archive = unrar::Archive::new(&path)
+            .open_for_processing()
+            .map_err(|e| e.to_string())?;
+        while let Some(header) = archive.read_header().map_err(|e| e.to_string())? {
+            let entry = header.entry();
+            if entry.filename.to_string_lossy().replace('\\', "/") == name {
+                if entry.unpacked_size > MAX_PAGE_BYTES {
+                    return Err("The page is too large to display.".to_string());
+                }
+                let (data, _) = header.read().map_err(|e| e.to_string())?;
+                return Ok(data);
+            }
+            archive = header.skip().map_err(|e| e.to_string())?;
+        }
+        Err("entry not found".to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
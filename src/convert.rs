@@ -0,0 +1,102 @@
+use crate::{
+    cache::{self, CacheConfig},
+    ffmpeg, resize,
+};
+use image::{DynamicImage, RgbImage};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+const CACHE_KIND: &str = "converted";
+// Longest edge of a developed RAW; previews are resized further from this.
+const MAX_RAW_DIMENSION: usize = 4096;
+
+// Photo formats browsers can't display, which are converted to JPEG first.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SourceFormat {
+    // HEIC/HEIF from phones, decoded by ffmpeg.
+    Heif,
+    // Camera RAW, developed with imagepipe.
+    Raw,
+}
+
+pub fn source_format(path: &Path) -> Option<SourceFormat> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_str() {
+        "heic" | "heif" => Some(SourceFormat::Heif),
+        "dng" | "cr2" | "crw" | "nef" | "nrw" | "arw" | "srf" | "sr2" | "raf" | "orf" | "rw2"
+        | "pef" | "srw" | "3fr" | "erf" | "kdc" | "dcr" | "mrw" | "mef" | "mos" | "iiq" => {
+            Some(SourceFormat::Raw)
+        }
+        _ => None,
+    }
+}
+
+// --- Conversion ---
+// A JPEG rendition of `source`, converted on first request and cached by path, size and
+// mtime. It can be resized like any other image from there.
+pub async fn converted(
+    cache: &CacheConfig,
+    ffmpeg: &Path,
+    source: &Path,
+    format: SourceFormat,
+) -> Result<PathBuf, String> {
+    let metadata = tokio::fs::metadata(source)
+        .await
+        .map_err(|e| e.to_string())?;
+    let key = cache::key(source, &metadata, "display");
+    if let Some(path) = cache.get(CACHE_KIND, &key, "jpg").await {
+        return Ok(path);
+    }
+
+    let bytes = match format {
+        SourceFormat::Heif => convert_heif(ffmpeg, source).await?,
+        SourceFormat::Raw => {
+            let source = source.to_path_buf();
+            tokio::task::spawn_blocking(move || develop_raw(&source))
+                .await
+                .map_err(|e| e.to_string())??
+        }
+    };
+    if bytes.is_empty() {
+        return Err("The conversion produced no image.".to_string());
+    }
+    cache
+        .put(CACHE_KIND, &key, "jpg", &bytes)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Needs an ffmpeg recent enough (7.0+) to assemble the tiled images iPhones write.
+async fn convert_heif(ffmpeg: &Path, source: &Path) -> Result<Vec<u8>, String> {
+    let args: Vec<OsString> = vec![
+        "-i".into(),
+        source.into(),
+        "-frames:v".into(),
+        "1".into(),
+        "-f".into(),
+        "image2".into(),
+        "-c:v".into(),
+        "mjpeg".into(),
+        "-q:v".into(),
+        "3".into(),
+        "pipe:1".into(),
+    ];
+    ffmpeg::run(ffmpeg, args).await
+}
+
+fn develop_raw(source: &Path) -> Result<Vec<u8>, String> {
+    let developed = imagepipe::simple_decode_8bit(source, MAX_RAW_DIMENSION, MAX_RAW_DIMENSION)?;
+    let image = RgbImage::from_raw(
+        developed.width as u32,
+        developed.height as u32,
+        developed.data,
+    )
+    .ok_or_else(|| "The developed image has an unexpected size.".to_string())?;
+    resize::encode(&DynamicImage::ImageRgb8(image)).map(|(bytes, _)| bytes)
+}
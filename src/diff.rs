@@ -0,0 +1,176 @@
+use maud::{Markup, html};
+use similar::{ChangeTag, DiffOp, TextDiff};
+use std::{path::Path, time::Duration};
+
+// Files larger than this are not diffed; the comparison happens entirely in memory.
+pub const MAX_DIFF_FILE_SIZE: u64 = 4 * 1024 * 1024;
+// Lines of unchanged context shown around each change.
+const CONTEXT_LINES: usize = 3;
+// Pathological inputs fall back to a coarser (but still correct) diff after this long.
+const DIFF_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DiffMode {
+    Unified,
+    Split,
+}
+
+impl DiffMode {
+    pub fn from_param(value: Option<&str>) -> Self {
+        match value {
+            Some("split") => DiffMode::Split,
+            _ => DiffMode::Unified,
+        }
+    }
+
+    pub fn param(self) -> &'static str {
+        match self {
+            DiffMode::Unified => "unified",
+            DiffMode::Split => "split",
+        }
+    }
+}
+
+// Reads a file for diffing, refusing anything too large or that looks binary.
+pub async fn read_text(path: &Path) -> Result<String, String> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("Could not read {}: {}", display_name(path), e))?;
+    if metadata.len() > MAX_DIFF_FILE_SIZE {
+        return Err(format!(
+            "{} is too large to compare (limit {} MiB).",
+            display_name(path),
+            MAX_DIFF_FILE_SIZE / 1024 / 1024
+        ));
+    }
+    let bytes = tokio::fs::read(path)
+        .await
+        .map_err(|e| format!("Could not read {}: {}", display_name(path), e))?;
+    if bytes.contains(&0) {
+        return Err(format!(
+            "{} looks like a binary file and can't be compared as text.",
+            display_name(path)
+        ));
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+// --- Rendering ---
+pub fn render_diff(old: &str, new: &str, mode: DiffMode) -> Markup {
+    let diff = TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(old, new);
+    let groups = diff.grouped_ops(CONTEXT_LINES);
+
+    if groups.is_empty() {
+        return html! { p class="diff-identical" { "The files are identical." } };
+    }
+
+    let (mut added, mut removed) = (0, 0);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Insert => added += 1,
+            ChangeTag::Delete => removed += 1,
+            ChangeTag::Equal => {}
+        }
+    }
+
+    html! {
+        div class="diff-summary" {
+            span class="diff-added-count" { "+" (added) } " "
+            span class="diff-removed-count" { "−" (removed) }
+        }
+        table class={"diff-table diff-" (mode.param())} {
+            @for (index, group) in groups.iter().enumerate() {
+                @if index > 0 {
+                    tr class="diff-gap" { td colspan=(if mode == DiffMode::Split { 4 } else { 3 }) { "⋯" } }
+                }
+                @match mode {
+                    DiffMode::Unified => (unified_rows(&diff, group)),
+                    DiffMode::Split => (split_rows(&diff, group)),
+                }
+            }
+        }
+    }
+}
+
+fn unified_rows<'a>(diff: &TextDiff<'a, 'a, str>, group: &[DiffOp]) -> Markup {
+    html! {
+        @for op in group {
+            @for change in diff.iter_changes(op) {
+                @let (class, marker) = match change.tag() {
+                    ChangeTag::Equal => ("diff-equal", " "),
+                    ChangeTag::Insert => ("diff-insert", "+"),
+                    ChangeTag::Delete => ("diff-delete", "-"),
+                };
+                tr class=(class) {
+                    td class="diff-line-number" { (change.old_index().map(|i| (i + 1).to_string()).unwrap_or_default()) }
+                    td class="diff-line-number" { (change.new_index().map(|i| (i + 1).to_string()).unwrap_or_default()) }
+                    td class="diff-text" { span class="diff-marker" { (marker) } (trim_newline(change.value())) }
+                }
+            }
+        }
+    }
+}
+
+// Side by side: deletions and insertions of the same hunk are paired up row by row.
+fn split_rows<'a>(diff: &TextDiff<'a, 'a, str>, group: &[DiffOp]) -> Markup {
+    let old_line = |index: usize| trim_newline(diff.old_slice(index).unwrap_or_default());
+    let new_line = |index: usize| trim_newline(diff.new_slice(index).unwrap_or_default());
+    let mut rows: Vec<(Option<usize>, Option<usize>)> = Vec::new();
+    for op in group {
+        match *op {
+            DiffOp::Equal {
+                old_index,
+                new_index,
+                len,
+            } => rows.extend((0..len).map(|i| (Some(old_index + i), Some(new_index + i)))),
+            DiffOp::Delete {
+                old_index, old_len, ..
+            } => rows.extend((old_index..old_index + old_len).map(|i| (Some(i), None))),
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => rows.extend((new_index..new_index + new_len).map(|i| (None, Some(i)))),
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } => rows.extend((0..old_len.max(new_len)).map(|i| {
+                (
+                    (i < old_len).then_some(old_index + i),
+                    (i < new_len).then_some(new_index + i),
+                )
+            })),
+        }
+    }
+
+    html! {
+        @for (old, new) in rows {
+            @let changed = match (old, new) {
+                (Some(old), Some(new)) => old_line(old) != new_line(new),
+                _ => true,
+            };
+            tr class=(if changed { "diff-changed" } else { "diff-equal" }) {
+                td class="diff-line-number" { (old.map(|i| (i + 1).to_string()).unwrap_or_default()) }
+                td class={"diff-text" (if changed && old.is_some() { " diff-delete" } else { "" })} {
+                    (old.map(old_line).unwrap_or_default())
+                }
+                td class="diff-line-number" { (new.map(|i| (i + 1).to_string()).unwrap_or_default()) }
+                td class={"diff-text" (if changed && new.is_some() { " diff-insert" } else { "" })} {
+                    (new.map(new_line).unwrap_or_default())
+                }
+            }
+        }
+    }
+}
+
+fn trim_newline(line: &str) -> &str {
+    line.trim_end_matches(['\n', '\r'])
+}
@@ -0,0 +1,174 @@
+use dashmap::DashMap;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::sync::{Mutex, Semaphore};
+
+// A directory's mtime only changes with its direct entries, so a cached total is also
+// dropped after this long to pick up changes deeper down.
+const MAX_AGE: Duration = Duration::from_secs(10 * 60);
+// Directory walks running at once.
+const WALK_WORKERS: usize = 2;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DirSize {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+pub struct UsageEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub size: DirSize,
+}
+
+struct CachedSize {
+    modified: Option<SystemTime>,
+    computed_at: Instant,
+    size: DirSize,
+}
+
+// --- Cache ---
+// Recursive directory sizes, computed in the background and cached per (path, mtime).
+// Every directory visited during a walk is cached, so sizes of subdirectories are free
+// afterwards.
+pub struct DirSizes {
+    cache: Arc<DashMap<PathBuf, CachedSize>>,
+    // One walk per directory at a time; others asking for it wait for its result.
+    in_flight: DashMap<PathBuf, Arc<Mutex<()>>>,
+    workers: Semaphore,
+}
+
+impl DirSizes {
+    pub fn new() -> Self {
+        DirSizes {
+            cache: Arc::new(DashMap::new()),
+            in_flight: DashMap::new(),
+            workers: Semaphore::new(WALK_WORKERS),
+        }
+    }
+
+    // The cached size, if it was computed for this `modified` time and isn't too old.
+    pub fn cached(&self, dir: &Path, modified: Option<SystemTime>) -> Option<DirSize> {
+        self.cache
+            .get(dir)
+            .filter(|cached| cached.modified == modified && cached.computed_at.elapsed() < MAX_AGE)
+            .map(|cached| cached.size)
+    }
+
+    // `root_dir` is the mount `dir` lies in, which the listing policies are relative to.
+    pub async fn size(&self, root_dir: &Path, dir: &Path) -> DirSize {
+        let modified = tokio::fs::metadata(dir)
+            .await
+            .and_then(|m| m.modified())
+            .ok();
+        if let Some(size) = self.cached(dir, modified) {
+            return size;
+        }
+        let lock = self
+            .in_flight
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone();
+        let _guard = lock.lock().await;
+        if let Some(size) = self.cached(dir, modified) {
+            return size;
+        }
+
+        let _worker = self.workers.acquire().await;
+        let (root_dir, dir_path, cache) = (
+            root_dir.to_path_buf(),
+            dir.to_path_buf(),
+            self.cache.clone(),
+        );
+        let size = tokio::task::spawn_blocking(move || walk(&root_dir, &dir_path, &cache))
+            .await
+            .unwrap_or_default();
+        self.in_flight.remove(dir);
+        size
+    }
+
+    // --- Usage ---
+    // Everything directly in `dir` with its size, largest first. Folders count everything
+    // below them; the walk of `dir` caches those, so only the first call is slow.
+    pub async fn usage(&self, root_dir: &Path, dir: &Path) -> std::io::Result<Vec<UsageEntry>> {
+        self.size(root_dir, dir).await;
+        let mut entries = tokio::fs::read_dir(dir).await?;
+        let mut usage = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+            let name = crate::rawnames::display(&entry.file_name());
+            if !crate::policy::is_listed(root_dir, &path) {
+                continue;
+            }
+            let size = if file_type.is_dir() {
+                self.size(root_dir, &path).await
+            } else if file_type.is_file()
+                && let Ok(metadata) = entry.metadata().await
+            {
+                DirSize {
+                    bytes: metadata.len(),
+                    files: 1,
+                }
+            } else {
+                continue;
+            };
+            usage.push(UsageEntry {
+                name,
+                path,
+                is_dir: file_type.is_dir(),
+                size,
+            });
+        }
+        usage.sort_by(|a, b| {
+            b.size
+                .bytes
+                .cmp(&a.size.bytes)
+                .then_with(|| crate::natsort::natural_cmp(&a.name, &b.name))
+        });
+        Ok(usage)
+    }
+}
+
+// Adds up file sizes below `dir` without following symlinks, caching each directory's
+// total on the way back up. Unreadable entries are skipped.
+fn walk(root_dir: &Path, dir: &Path, cache: &DashMap<PathBuf, CachedSize>) -> DirSize {
+    let modified = std::fs::metadata(dir).and_then(|m| m.modified()).ok();
+    let mut size = DirSize::default();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if !crate::policy::is_listed(root_dir, &path) {
+                continue;
+            }
+            if file_type.is_dir() {
+                let child = walk(root_dir, &path, cache);
+                size.bytes += child.bytes;
+                size.files += child.files;
+            } else if file_type.is_file()
+                && let Ok(metadata) = entry.metadata()
+            {
+                size.bytes += metadata.len();
+                size.files += 1;
+            }
+        }
+    }
+    cache.insert(
+        dir.to_path_buf(),
+        CachedSize {
+            modified,
+            computed_at: Instant::now(),
+            size,
+        },
+    );
+    size
+}
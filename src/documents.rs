@@ -0,0 +1,194 @@
+use quick_xml::events::{BytesRef, BytesStart, Event};
+use std::{
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+// Extraction stops after this much text; enough to tell whether it's the right document.
+pub const MAX_EXTRACTED_CHARS: usize = 500_000;
+// Cap on the decompressed XML read from the container, against zip bombs.
+const MAX_XML_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DocumentFormat {
+    Docx,
+    Odt,
+}
+
+impl DocumentFormat {
+    pub fn label(self) -> &'static str {
+        match self {
+            DocumentFormat::Docx => "Word document",
+            DocumentFormat::Odt => "OpenDocument text",
+        }
+    }
+
+    // The zip member holding the body text.
+    fn content_member(self) -> &'static str {
+        match self {
+            DocumentFormat::Docx => "word/document.xml",
+            DocumentFormat::Odt => "content.xml",
+        }
+    }
+}
+
+pub fn document_format(path: &Path) -> Option<DocumentFormat> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "docx" => Some(DocumentFormat::Docx),
+        "odt" => Some(DocumentFormat::Odt),
+        _ => None,
+    }
+}
+
+pub struct Paragraph {
+    pub text: String,
+    pub is_heading: bool,
+}
+
+pub struct ExtractedText {
+    pub paragraphs: Vec<Paragraph>,
+    pub truncated: bool,
+}
+
+// --- Extraction ---
+// Both formats are zipped XML: paragraphs are `w:p` (docx) or `text:p`/`text:h` (odt),
+// and only their text runs are kept. Formatting, images and tables' layout are dropped.
+pub async fn extract_text(path: &Path, format: DocumentFormat) -> Result<ExtractedText, String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let file = File::open(&path).map_err(|e| e.to_string())?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+        let member = archive
+            .by_name(format.content_member())
+            .map_err(|_| "The document has no text content.".to_string())?;
+        parse_paragraphs(BufReader::new(member.take(MAX_XML_BYTES)), format)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn parse_paragraphs(
+    source: impl std::io::BufRead,
+    format: DocumentFormat,
+) -> Result<ExtractedText, String> {
+    let mut reader = quick_xml::Reader::from_reader(source);
+    let mut buf = Vec::new();
+    let mut extracted = ExtractedText {
+        paragraphs: Vec::new(),
+        truncated: false,
+    };
+    let mut total_chars = 0;
+    // The paragraph being built, and for docx whether we're inside a `w:t` text run.
+    let mut current: Option<Paragraph> = None;
+    let mut in_text_run = false;
+
+    loop {
+        let event = reader
+            .read_event_into(&mut buf)
+            .map_err(|e| format!("Malformed document XML: {}", e))?;
+        match event {
+            Event::Start(element) => match (format, element.local_name().as_ref()) {
+                (DocumentFormat::Docx, "p") | (DocumentFormat::Odt, "p") => {
+                    current = Some(Paragraph {
+                        text: String::new(),
+                        is_heading: false,
+                    });
+                }
+                (DocumentFormat::Odt, "h") => {
+                    current = Some(Paragraph {
+                        text: String::new(),
+                        is_heading: true,
+                    });
+                }
+                (DocumentFormat::Docx, "t") => in_text_run = true,
+                _ => {}
+            },
+            Event::Empty(element) => {
+                if let Some(paragraph) = current.as_mut() {
+                    apply_empty_element(paragraph, &element, format);
+                }
+            }
+            Event::Text(text) => {
+                if let Some(paragraph) = current.as_mut()
+                    && (format == DocumentFormat::Odt || in_text_run)
+                {
+                    paragraph.text.push_str(&text.xml10_content());
+                }
+            }
+            Event::GeneralRef(reference) => {
+                if let Some(paragraph) = current.as_mut()
+                    && (format == DocumentFormat::Odt || in_text_run)
+                {
+                    paragraph.text.extend(resolve_reference(&reference));
+                }
+            }
+            Event::End(element) => match (format, element.local_name().as_ref()) {
+                (DocumentFormat::Docx, "t") => in_text_run = false,
+                (DocumentFormat::Docx, "p") | (DocumentFormat::Odt, "p" | "h") => {
+                    if let Some(paragraph) = current.take() {
+                        total_chars += paragraph.text.len();
+                        extracted.paragraphs.push(paragraph);
+                        if total_chars > MAX_EXTRACTED_CHARS {
+                            extracted.truncated = true;
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Event::Eof => break,
+            _ => {}
+        }
+        buf.clear();
+    }
+    Ok(extracted)
+}
+
+// Character references and the predefined XML entities; anything else is dropped.
+pub fn resolve_reference(reference: &BytesRef) -> Option<char> {
+    match reference.resolve_char_ref() {
+        Ok(Some(c)) => Some(c),
+        _ => match reference.as_ref() {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => None,
+        },
+    }
+}
+
+// Tabs, line breaks, docx heading styles and odt's run-length spaces (`text:s`).
+fn apply_empty_element(paragraph: &mut Paragraph, element: &BytesStart, format: DocumentFormat) {
+    match (format, element.local_name().as_ref()) {
+        (_, "tab") => paragraph.text.push('\t'),
+        (DocumentFormat::Docx, "br" | "cr") | (DocumentFormat::Odt, "line-break") => {
+            paragraph.text.push('\n')
+        }
+        (DocumentFormat::Odt, "s") => {
+            let count = element
+                .attributes()
+                .flatten()
+                .find(|attribute| attribute.key.local_name().as_ref() == "c")
+                .and_then(|attribute| attribute.value.parse().ok())
+                .unwrap_or(1usize);
+            paragraph
+                .text
+                .extend(std::iter::repeat_n(' ', count.min(1000)));
+        }
+        (DocumentFormat::Docx, "pStyle") => {
+            paragraph.is_heading = element
+                .attributes()
+                .flatten()
+                .find(|attribute| attribute.key.local_name().as_ref() == "val")
+                .is_some_and(|attribute| {
+                    let style = attribute.value.to_lowercase();
+                    style.starts_with("heading") || style == "title"
+                });
+        }
+        _ => {}
+    }
+}
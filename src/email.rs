@@ -0,0 +1,135 @@
+use chrono::{DateTime, Local};
+use mail_parser::{Address, Message, MessageParser, MimeHeaders};
+use std::path::Path;
+
+// Messages are parsed in memory; anything larger is almost certainly not a plain export.
+pub const MAX_EMAIL_SIZE: u64 = 50 * 1024 * 1024;
+
+pub struct EmailAttachment {
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+}
+
+pub struct ParsedEmail {
+    pub subject: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub cc: Option<String>,
+    pub date: Option<String>,
+    pub text_body: Option<String>,
+    // Already sanitized; safe to embed in the page.
+    pub html_body: Option<String>,
+    pub attachments: Vec<EmailAttachment>,
+}
+
+pub fn is_email_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+}
+
+async fn read_message(path: &Path) -> Result<Vec<u8>, String> {
+    let metadata = tokio::fs::metadata(path).await.map_err(|e| e.to_string())?;
+    if metadata.len() > MAX_EMAIL_SIZE {
+        return Err(format!(
+            "The message is too large to preview (limit {} MiB).",
+            MAX_EMAIL_SIZE / 1024 / 1024
+        ));
+    }
+    tokio::fs::read(path).await.map_err(|e| e.to_string())
+}
+
+fn parse_message(bytes: &[u8]) -> Result<Message<'_>, String> {
+    MessageParser::default()
+        .parse(bytes)
+        .ok_or_else(|| "The file is not a valid email message.".to_string())
+}
+
+pub async fn parse(path: &Path) -> Result<ParsedEmail, String> {
+    let bytes = read_message(path).await?;
+    let message = parse_message(&bytes)?;
+
+    let html_body = message.body_html(0).map(|html| sanitize_html(&html));
+    // Prefer a real text/plain part; mail-parser otherwise derives one from the HTML.
+    let text_body = message
+        .text_bodies()
+        .next()
+        .filter(|part| part.is_content_type("text", "plain"))
+        .and_then(|_| message.body_text(0))
+        .map(|text| text.into_owned());
+
+    let attachments = message
+        .attachments()
+        .enumerate()
+        .map(|(index, part)| EmailAttachment {
+            name: attachment_name(part, index),
+            mime_type: part
+                .content_type()
+                .map(|ct| match ct.subtype() {
+                    Some(subtype) => format!("{}/{}", ct.ctype(), subtype),
+                    None => ct.ctype().to_string(),
+                })
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+            size: part.contents().len() as u64,
+        })
+        .collect();
+
+    Ok(ParsedEmail {
+        subject: message.subject().map(str::to_string),
+        from: message.from().map(format_addresses),
+        to: message.to().map(format_addresses),
+        cc: message.cc().map(format_addresses),
+        date: message.date().and_then(|date| {
+            DateTime::from_timestamp(date.to_timestamp(), 0).map(|at| {
+                at.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+        }),
+        text_body,
+        html_body,
+        attachments,
+    })
+}
+
+// The name and decoded bytes of the `index`th attachment, as listed by `parse`.
+pub async fn attachment(path: &Path, index: usize) -> Result<Option<(String, Vec<u8>)>, String> {
+    let bytes = read_message(path).await?;
+    let message = parse_message(&bytes)?;
+    Ok(message
+        .attachments()
+        .nth(index)
+        .map(|part| (attachment_name(part, index), part.contents().to_vec())))
+}
+
+fn attachment_name(part: &mail_parser::MessagePart, index: usize) -> String {
+    part.attachment_name()
+        .map(|name| name.rsplit(['/', '\\']).next().unwrap_or(name).trim())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("attachment-{}", index + 1))
+}
+
+fn format_addresses(address: &Address) -> String {
+    address
+        .iter()
+        .map(|addr| match (addr.name(), addr.address()) {
+            (Some(name), Some(email)) => format!("{} <{}>", name, email),
+            (Some(name), None) => name.to_string(),
+            (None, Some(email)) => email.to_string(),
+            (None, None) => String::new(),
+        })
+        .filter(|addr| !addr.is_empty())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// Strips scripts, styles, event handlers and the like. Images are dropped as well: remote
+// ones would tell the sender the message was opened, and inline `cid:` ones can't resolve.
+fn sanitize_html(html: &str) -> String {
+    ammonia::Builder::default()
+        .rm_tags(["img"])
+        .clean(html)
+        .to_string()
+}
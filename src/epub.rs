@@ -0,0 +1,300 @@
+use quick_xml::events::{BytesStart, Event};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+// Chapters (and the package files) larger than this aren't rendered.
+const MAX_DOCUMENT_BYTES: u64 = 16 * 1024 * 1024;
+
+pub struct Chapter {
+    // Zip member holding the chapter's XHTML.
+    pub member: String,
+    // From the book's table of contents, when it lists this chapter.
+    pub title: Option<String>,
+}
+
+pub struct EpubBook {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    // In reading order (the package's spine).
+    pub chapters: Vec<Chapter>,
+}
+
+pub fn is_epub_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"))
+}
+
+// --- Package ---
+// container.xml names the package (OPF) file; its manifest and spine give the chapters in
+// reading order, and the EPUB 3 nav document or EPUB 2 NCX gives their titles.
+pub async fn open_book(path: &Path) -> Result<EpubBook, String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || read_book(&path))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn read_book(path: &Path) -> Result<EpubBook, String> {
+    let mut archive = open(path)?;
+    let container = read_member(&mut archive, "META-INF/container.xml")?;
+    let package_path = find_package_path(&container)
+        .ok_or_else(|| "The book has no package document.".to_string())?;
+    let package = read_member(&mut archive, &package_path)?;
+    let package_dir = parent_dir(&package_path);
+
+    let mut reader = quick_xml::Reader::from_str(&package);
+    let mut book = EpubBook {
+        title: None,
+        author: None,
+        chapters: Vec::new(),
+    };
+    // Manifest id -> (member, media type, properties).
+    let mut manifest: HashMap<String, (String, String, String)> = HashMap::new();
+    let mut spine: Vec<String> = Vec::new();
+    let mut ncx_id = None;
+    // Which metadata element's text is being read, if any.
+    let (mut title, mut author) = (String::new(), String::new());
+    let mut text_target: Option<&str> = None;
+    loop {
+        match reader.read_event().map_err(|e| e.to_string())? {
+            Event::Start(element) | Event::Empty(element) => match element.local_name().as_ref() {
+                "title" if title.trim().is_empty() => text_target = Some("title"),
+                "creator" if author.trim().is_empty() => text_target = Some("creator"),
+                "item" => {
+                    if let (Some(id), Some(href)) =
+                        (attribute(&element, "id"), attribute(&element, "href"))
+                        && let Some(member) = resolve_href(&package_dir, &href)
+                    {
+                        manifest.insert(
+                            id,
+                            (
+                                member,
+                                attribute(&element, "media-type").unwrap_or_default(),
+                                attribute(&element, "properties").unwrap_or_default(),
+                            ),
+                        );
+                    }
+                }
+                "spine" => ncx_id = attribute(&element, "toc"),
+                "itemref" => {
+                    if attribute(&element, "linear").as_deref() != Some("no")
+                        && let Some(idref) = attribute(&element, "idref")
+                    {
+                        spine.push(idref);
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(text) => match text_target {
+                Some("title") => title.push_str(&text.xml10_content()),
+                Some("creator") => author.push_str(&text.xml10_content()),
+                _ => {}
+            },
+            Event::GeneralRef(reference) => match text_target {
+                Some("title") => title.extend(crate::documents::resolve_reference(&reference)),
+                Some("creator") => author.extend(crate::documents::resolve_reference(&reference)),
+                _ => {}
+            },
+            Event::End(_) => text_target = None,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    book.title = Some(title.trim().to_string()).filter(|title| !title.is_empty());
+    book.author = Some(author.trim().to_string()).filter(|author| !author.is_empty());
+
+    let toc_member = manifest
+        .values()
+        .find(|(_, _, properties)| properties.split_whitespace().any(|p| p == "nav"))
+        .or_else(|| ncx_id.as_ref().and_then(|id| manifest.get(id)))
+        .map(|(member, _, _)| member.clone());
+    let titles = match toc_member {
+        Some(member) => read_member(&mut archive, &member)
+            .map(|toc| toc_titles(&toc, &parent_dir(&member)))
+            .unwrap_or_default(),
+        None => HashMap::new(),
+    };
+
+    book.chapters = spine
+        .iter()
+        .filter_map(|idref| manifest.get(idref))
+        .filter(|(_, media_type, _)| media_type.contains("html"))
+        .map(|(member, _, _)| Chapter {
+            title: titles.get(member).cloned(),
+            member: member.clone(),
+        })
+        .collect();
+    if book.chapters.is_empty() {
+        return Err("The book has no readable chapters.".to_string());
+    }
+    Ok(book)
+}
+
+fn find_package_path(container: &str) -> Option<String> {
+    let mut reader = quick_xml::Reader::from_str(container);
+    loop {
+        match reader.read_event().ok()? {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name().as_ref() == "rootfile" =>
+            {
+                return attribute(&element, "full-path");
+            }
+            Event::Eof => return None,
+            _ => {}
+        }
+    }
+}
+
+// Chapter titles keyed by member: `<a href>` links in an EPUB 3 nav document, or the
+// `navLabel` text preceding each `<content src>` in an EPUB 2 NCX.
+fn toc_titles(toc: &str, toc_dir: &str) -> HashMap<String, String> {
+    let mut reader = quick_xml::Reader::from_str(toc);
+    let mut titles = HashMap::new();
+    let mut label = String::new();
+    let mut link: Option<String> = None;
+    while let Ok(event) = reader.read_event() {
+        match event {
+            Event::Start(element) | Event::Empty(element) => match element.local_name().as_ref() {
+                "a" => {
+                    label.clear();
+                    link = attribute(&element, "href");
+                }
+                "navLabel" => label.clear(),
+                "content" => {
+                    if let Some(member) =
+                        attribute(&element, "src").and_then(|src| resolve_href(toc_dir, &src))
+                    {
+                        titles
+                            .entry(member)
+                            .or_insert_with(|| label.trim().to_string());
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(text) => label.push_str(&text.xml10_content()),
+            Event::GeneralRef(reference) => {
+                label.extend(crate::documents::resolve_reference(&reference))
+            }
+            Event::End(element) if element.local_name().as_ref() == "a" => {
+                if let Some(member) = link.take().and_then(|href| resolve_href(toc_dir, &href)) {
+                    titles
+                        .entry(member)
+                        .or_insert_with(|| label.trim().to_string());
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    titles.retain(|_, title| !title.is_empty());
+    titles
+}
+
+// --- Chapters ---
+// The chapter's XHTML, sanitized. Images are pointed at `resource_url(member)` so they
+// are served out of the book; relative links are dropped since chapters aren't pages of
+// their own here, and navigation happens through the reader's controls instead.
+pub async fn chapter_html(
+    path: &Path,
+    member: &str,
+    resource_url: impl Fn(&str) -> String + Send + Sync + 'static,
+) -> Result<String, String> {
+    let path: PathBuf = path.to_path_buf();
+    let member = member.to_string();
+    tokio::task::spawn_blocking(move || {
+        let xhtml = read_member(&mut open(&path)?, &member)?;
+        let chapter_dir = parent_dir(&member);
+        let html = ammonia::Builder::default()
+            .clean_content_tags(HashSet::from(["script", "style", "title"]))
+            .attribute_filter(move |element, attribute, value| {
+                let is_relative = !value.contains(':') && !value.starts_with("//");
+                match (element, attribute) {
+                    ("img", "src") if is_relative => {
+                        resolve_href(&chapter_dir, value).map(|target| resource_url(&target).into())
+                    }
+                    ("a", "href") if is_relative => None,
+                    _ => Some(value.into()),
+                }
+            })
+            .clean(&xhtml)
+            .to_string();
+        Ok(html)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// --- Helpers ---
+fn open(path: &Path) -> Result<zip::ZipArchive<BufReader<File>>, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())
+}
+
+fn read_member(
+    archive: &mut zip::ZipArchive<BufReader<File>>,
+    name: &str,
+) -> Result<String, String> {
+    let member = archive
+        .by_name(name)
+        .map_err(|_| format!("'{}' is missing from the book.", name))?;
+    if member.size() > MAX_DOCUMENT_BYTES {
+        return Err(format!("'{}' is too large to display.", name));
+    }
+    let mut bytes = Vec::new();
+    member
+        .take(MAX_DOCUMENT_BYTES)
+        .read_to_end(&mut bytes)
+        .map_err(|e| e.to_string())?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+    element
+        .attributes()
+        .flatten()
+        .find(|attribute| attribute.key.local_name().as_ref() == name)
+        .and_then(|attribute| {
+            attribute
+                .normalized_value(quick_xml::XmlVersion::Implicit1_0)
+                .ok()
+                .map(|value| value.into_owned())
+        })
+}
+
+fn parent_dir(member: &str) -> String {
+    member
+        .rsplit_once('/')
+        .map(|(dir, _)| dir.to_string())
+        .unwrap_or_default()
+}
+
+// Resolves a (percent-encoded) href relative to `base_dir` into a zip member name.
+// Fragments are dropped; absolute URLs and paths escaping the book give `None`.
+fn resolve_href(base_dir: &str, href: &str) -> Option<String> {
+    let href = href.split(['#', '?']).next()?;
+    if href.is_empty() || href.contains(':') {
+        return None;
+    }
+    let href = urlencoding::decode(href).ok()?;
+    let mut segments: Vec<&str> = if href.starts_with('/') {
+        Vec::new()
+    } else {
+        base_dir.split('/').filter(|s| !s.is_empty()).collect()
+    };
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop()?;
+            }
+            segment => segments.push(segment),
+        }
+    }
+    Some(segments.join("/"))
+}
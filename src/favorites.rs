@@ -0,0 +1,74 @@
+use std::{io, path::PathBuf};
+use tokio::sync::Mutex;
+use tracing::error;
+
+// Pins live in `<root>/.kiv-state/favorites.json` as a JSON array of paths relative to the
+// root, in the order they were added.
+pub const FAVORITES_FILE_NAME: &str = "favorites.json";
+
+// --- Store ---
+pub struct Favorites {
+    file: PathBuf,
+    // Held across writes so two changes can't interleave on disk.
+    pins: Mutex<Vec<String>>,
+}
+
+impl Favorites {
+    // A missing file means no pins yet; an unreadable one is logged and treated the same,
+    // and is replaced on the next change.
+    pub async fn load(file: PathBuf) -> Self {
+        let pins = match tokio::fs::read(&file).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_else(|e| {
+                error!(
+                    "Ignoring unreadable favorites file {}: {}",
+                    file.display(),
+                    e
+                );
+                Vec::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                error!("Failed to read favorites file {}: {}", file.display(), e);
+                Vec::new()
+            }
+        };
+        Favorites {
+            file,
+            pins: Mutex::new(pins),
+        }
+    }
+
+    pub async fn list(&self) -> Vec<String> {
+        self.pins.lock().await.clone()
+    }
+
+    pub async fn add(&self, path: &str) -> io::Result<()> {
+        let mut pins = self.pins.lock().await;
+        if pins.iter().any(|pin| pin == path) {
+            return Ok(());
+        }
+        pins.push(path.to_string());
+        self.save(&pins).await
+    }
+
+    pub async fn remove(&self, path: &str) -> io::Result<()> {
+        let mut pins = self.pins.lock().await;
+        let before = pins.len();
+        pins.retain(|pin| pin != path);
+        if pins.len() == before {
+            return Ok(());
+        }
+        self.save(&pins).await
+    }
+
+    // Written to a temporary file first, so a crash never leaves half a list behind.
+    async fn save(&self, pins: &[String]) -> io::Result<()> {
+        if let Some(dir) = self.file.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let json = serde_json::to_vec_pretty(pins).map_err(io::Error::other)?;
+        let temp = self.file.with_extension("json.tmp");
+        tokio::fs::write(&temp, json).await?;
+        tokio::fs::rename(&temp, &self.file).await
+    }
+}
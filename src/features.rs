@@ -0,0 +1,39 @@
+use clap::ValueEnum;
+
+// --- Subsystems ---
+// Parts of kiv that `--disable` leaves out altogether: their routes aren't mounted and
+// their buttons aren't shown. Browsing and downloading are always there.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug, ValueEnum)]
+pub enum Feature {
+    // Every preview and viewer, thumbnails, media streaming and transcoding.
+    Preview,
+    // Share links and signed links, including ones created earlier.
+    Share,
+    // Uploads.
+    Upload,
+    // Deleting and the trash.
+    Delete,
+    // Searching and quick open.
+    Search,
+}
+
+pub struct Features {
+    disabled: Vec<Feature>,
+}
+
+impl Features {
+    pub fn new(disabled: &[Feature]) -> Self {
+        let mut disabled = disabled.to_vec();
+        disabled.sort_unstable();
+        disabled.dedup();
+        Features { disabled }
+    }
+
+    pub fn on(&self, feature: Feature) -> bool {
+        !self.disabled.contains(&feature)
+    }
+
+    pub fn disabled(&self) -> &[Feature] {
+        &self.disabled
+    }
+}
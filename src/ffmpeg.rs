@@ -0,0 +1,76 @@
+use std::{
+    ffi::OsStr,
+    path::Path,
+    process::Stdio,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use tokio::{
+    process::{Child, Command},
+    sync::Semaphore,
+};
+use tracing::error;
+
+const FFMPEG_TIMEOUT: Duration = Duration::from_secs(20);
+// Hovering down a folder of videos shouldn't start a dozen decoders at once.
+static FFMPEG_SLOTS: Semaphore = Semaphore::const_new(2);
+// Set once spawning ffmpeg fails because it isn't installed, so it isn't retried (and
+// logged) for every file.
+static FFMPEG_MISSING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_missing() -> bool {
+    FFMPEG_MISSING.load(Ordering::Relaxed)
+}
+
+// --- Running ffmpeg ---
+// Starts ffmpeg with `args`. The process is killed when the returned `Child` is dropped.
+pub fn spawn<I, S>(ffmpeg: &Path, args: I, stdout: Stdio) -> Result<Child, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    if is_missing() {
+        return Err("ffmpeg is not available.".to_string());
+    }
+    let child = Command::new(ffmpeg)
+        .args(["-nostdin", "-loglevel", "error"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(stdout)
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn();
+    match child {
+        Ok(child) => Ok(child),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if !FFMPEG_MISSING.swap(true, Ordering::Relaxed) {
+                error!(
+                    "'{}' was not found; video posters, HEIC previews and transcoding are disabled.",
+                    ffmpeg.display()
+                );
+            }
+            Err("ffmpeg is not available.".to_string())
+        }
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+// Runs ffmpeg with `args` (which should write to `pipe:1`) and returns its stdout.
+pub async fn run<I, S>(ffmpeg: &Path, args: I) -> Result<Vec<u8>, String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let _slot = FFMPEG_SLOTS.acquire().await.map_err(|e| e.to_string())?;
+    let child = spawn(ffmpeg, args, Stdio::piped())?;
+
+    let output = tokio::time::timeout(FFMPEG_TIMEOUT, child.wait_with_output())
+        .await
+        .map_err(|_| "ffmpeg timed out.".to_string())?
+        .map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("ffmpeg failed: {}", stderr.trim()));
+    }
+    Ok(output.stdout)
+}
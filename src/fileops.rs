@@ -0,0 +1,392 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    auth,
+    jobs::{Job, walk_tree},
+    mounts::Mount,
+    policy,
+};
+
+// --- Recursive copy ---
+// Copies a file or directory tree to `destination`, recording per-entry failures on the
+// job instead of aborting on the first one. Entries the policies or the user's rules
+// hold back, and everything below them, stay out of the copy; links are recreated as
+// links, and only when they lead somewhere the user could open anyway.
+pub async fn copy_tree(
+    job: Arc<Job>,
+    mount: Mount,
+    source: PathBuf,
+    destination: PathBuf,
+) -> Result<String, String> {
+    job.set_message("Scanning source…");
+    let listing = walk_tree(source.clone()).await;
+    for (path, e) in &listing.errors {
+        job.record_failure(format!("{}: {}", display_relative(&source, path), e));
+    }
+
+    let mut held_back: Vec<&Path> = Vec::new();
+    let mut dirs = Vec::new();
+    for dir in &listing.dirs {
+        if held_back.iter().any(|skipped| dir.starts_with(skipped)) {
+            continue;
+        }
+        if permitted(&mount, dir) {
+            dirs.push(dir);
+        } else {
+            held_back.push(dir);
+        }
+    }
+    let files: Vec<&PathBuf> = listing
+        .files
+        .iter()
+        .filter(|file| !held_back.iter().any(|skipped| file.starts_with(skipped)))
+        .filter(|file| permitted(&mount, file))
+        .collect();
+    job.set_total(files.len() as u64);
+    job.set_message(format!("Copying {} file(s)…", files.len()));
+
+    for dir in dirs {
+        let target = map_into(&source, dir, &destination);
+        if let Err(e) = tokio::fs::create_dir_all(&target).await {
+            if dir == &source {
+                return Err(format!("Could not create {}: {}", destination.display(), e));
+            }
+            job.record_failure(format!("{}: {}", display_relative(&source, dir), e));
+        }
+    }
+
+    let mut copied = 0;
+    for file in files {
+        if job.is_cancelled() {
+            return Ok(format!("Cancelled after copying {} file(s).", copied));
+        }
+        let target = map_into(&source, file, &destination);
+        let result = if file.is_symlink() {
+            copy_link(&mount, file, &target).await
+        } else {
+            tokio::fs::copy(file, &target).await.map(|_| ())
+        };
+        match result {
+            Ok(()) => copied += 1,
+            Err(e) => job.record_failure(format!("{}: {}", display_relative(&source, file), e)),
+        }
+        job.advance(1);
+    }
+
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    Ok(format!("Copied {} file(s) to '{}'.", copied, name))
+}
+
+// The copy sits next to the original, so the link's own text still leads to the same
+// place from there.
+async fn copy_link(mount: &Mount, link: &Path, target: &Path) -> std::io::Result<()> {
+    let leads_to = tokio::fs::canonicalize(link).await?;
+    if !leads_to.starts_with(&mount.dir) || !permitted(mount, &leads_to) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "link leads outside what can be copied",
+        ));
+    }
+    #[cfg(unix)]
+    return tokio::fs::symlink(tokio::fs::read_link(link).await?, target).await;
+    #[cfg(not(unix))]
+    tokio::fs::copy(leads_to, target).await.map(|_| ())
+}
+
+// Whether the current request may take `path` along, by the policies and the user's
+// rules and folder passwords.
+fn permitted(mount: &Mount, path: &Path) -> bool {
+    policy::is_accessible(&mount.dir, path) && auth::allows(&mount.relative(path))
+}
+
+// --- Recursive delete ---
+// Permanently removes a file or directory tree: files first, then directories deepest
+// first. Entries that can't be removed are reported and the rest of the tree still goes.
+// Nothing is removed when anything inside is one the user may not delete, or behind a
+// folder password that hasn't been given.
+pub async fn delete_tree(job: Arc<Job>, mount: Mount, target: PathBuf) -> Result<String, String> {
+    job.set_message("Scanning…");
+    let listing = walk_tree(target.clone()).await;
+    for (path, e) in &listing.errors {
+        job.record_failure(format!("{}: {}", display_relative(&target, path), e));
+    }
+    if let Some(held_back) = listing
+        .dirs
+        .iter()
+        .chain(&listing.files)
+        .find(|path| !auth::allows(&mount.relative(path)))
+    {
+        return Err(format!(
+            "Nothing was deleted: you may not delete '{}'.",
+            display_relative(&target, held_back)
+        ));
+    }
+    job.set_total((listing.files.len() + listing.dirs.len()) as u64);
+    job.set_message(format!(
+        "Deleting {} file(s) and {} folder(s)…",
+        listing.files.len(),
+        listing.dirs.len()
+    ));
+
+    let mut deleted_files = 0;
+    for file in &listing.files {
+        if job.is_cancelled() {
+            return Ok(format!(
+                "Cancelled after deleting {} file(s).",
+                deleted_files
+            ));
+        }
+        match tokio::fs::remove_file(file).await {
+            Ok(()) => deleted_files += 1,
+            Err(e) => job.record_failure(format!("{}: {}", display_relative(&target, file), e)),
+        }
+        job.advance(1);
+    }
+
+    let mut deleted_dirs = 0;
+    for dir in listing.dirs.iter().rev() {
+        if job.is_cancelled() {
+            return Ok(format!(
+                "Cancelled after deleting {} file(s) and {} folder(s).",
+                deleted_files, deleted_dirs
+            ));
+        }
+        match tokio::fs::remove_dir(dir).await {
+            Ok(()) => deleted_dirs += 1,
+            // A directory that still holds an undeletable entry has already been reported
+            // through that entry's failure.
+            Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {}
+            Err(e) => job.record_failure(format!("{}: {}", display_relative(&target, dir), e)),
+        }
+        job.advance(1);
+    }
+
+    let failures = job.status().failure_count;
+    let summary = format!(
+        "Deleted {} file(s) and {} folder(s).",
+        deleted_files, deleted_dirs
+    );
+    if failures > 0 {
+        Err(format!(
+            "{} {} entr(ies) could not be deleted.",
+            summary, failures
+        ))
+    } else {
+        Ok(summary)
+    }
+}
+
+// --- Moving across filesystems ---
+// Renames `source` to `destination`, falling back to copying and then removing the
+// original when the two are on different filesystems, as separate mounts may be.
+pub async fn move_path(source: &Path, destination: &Path) -> std::io::Result<()> {
+    match tokio::fs::rename(source, destination).await {
+        Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+            let (source, destination) = (source.to_path_buf(), destination.to_path_buf());
+            tokio::task::spawn_blocking(move || copy_then_remove(&source, &destination))
+                .await
+                .map_err(std::io::Error::other)?
+        }
+        result => result,
+    }
+}
+
+// Links are recreated as links rather than followed. The original is only removed once
+// everything below it has been copied.
+fn copy_then_remove(source: &Path, destination: &Path) -> std::io::Result<()> {
+    let metadata = std::fs::symlink_metadata(source)?;
+    if metadata.is_dir() {
+        std::fs::create_dir(destination)?;
+        for entry in std::fs::read_dir(source)? {
+            let entry = entry?;
+            copy_then_remove(&entry.path(), &destination.join(entry.file_name()))?;
+        }
+        return std::fs::remove_dir(source);
+    }
+    #[cfg(unix)]
+    if metadata.is_symlink() {
+        std::os::unix::fs::symlink(std::fs::read_link(source)?, destination)?;
+        return std::fs::remove_file(source);
+    }
+    std::fs::copy(source, destination)?;
+    std::fs::remove_file(source)
+}
+
+// Picks a sibling name like `report (copy).txt`, `report (copy 2).txt`, … that doesn't exist yet.
+pub async fn copy_destination(source: &Path) -> Option<PathBuf> {
+    let parent = source.parent()?;
+    let is_dir = tokio::fs::metadata(source).await.ok()?.is_dir();
+    let name = source.file_name()?.to_string_lossy().into_owned();
+    let (stem, extension) = match (is_dir, Path::new(&name).extension()) {
+        (false, Some(ext)) => (
+            Path::new(&name).file_stem()?.to_string_lossy().into_owned(),
+            format!(".{}", ext.to_string_lossy()),
+        ),
+        _ => (name.clone(), String::new()),
+    };
+
+    let mut counter = 1;
+    loop {
+        let suffix = if counter == 1 {
+            "copy".to_string()
+        } else {
+            format!("copy {}", counter)
+        };
+        let candidate = parent.join(format!("{} ({}){}", stem, suffix, extension));
+        if !tokio::fs::try_exists(&candidate).await.unwrap_or(true) {
+            return Some(candidate);
+        }
+        counter += 1;
+    }
+}
+
+fn map_into(source: &Path, path: &Path, destination: &Path) -> PathBuf {
+    match path.strip_prefix(source) {
+        Ok(relative) if !relative.as_os_str().is_empty() => destination.join(relative),
+        _ => destination.to_path_buf(),
+    }
+}
+
+pub fn display_relative(base: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(base).unwrap_or(path);
+    if relative.as_os_str().is_empty() {
+        base.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| base.display().to_string())
+    } else {
+        relative.to_string_lossy().replace('\\', "/")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jobs::{JobRegistry, JobState, JobStatus};
+
+    fn temp_mount() -> Mount {
+        let dir = std::env::temp_dir().join(format!("kiv-fileops-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Mount {
+            name: String::new(),
+            dir: dir.canonicalize().unwrap(),
+        }
+    }
+
+    async fn finished(job: &Job) -> JobStatus {
+        loop {
+            let status = job.status();
+            if status.state.is_finished() {
+                return status;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn copies_keep_links_inside_the_mount() {
+        let mount = temp_mount();
+        let source = mount.dir.join("album");
+        std::fs::create_dir_all(source.join("nested")).unwrap();
+        std::fs::write(source.join("a.txt"), "a").unwrap();
+        std::fs::write(source.join("nested/b.txt"), "b").unwrap();
+        std::os::unix::fs::symlink("a.txt", source.join("inside")).unwrap();
+        std::os::unix::fs::symlink("/etc/hostname", source.join("outside")).unwrap();
+        let destination = mount.dir.join("album (copy)");
+
+        let jobs = JobRegistry::new(1);
+        let (job_mount, job_source, job_destination) =
+            (mount.clone(), source.clone(), destination.clone());
+        let job = auth::system(async {
+            jobs.spawn("copy", String::new(), move |job| {
+                copy_tree(job, job_mount, job_source, job_destination)
+            })
+        })
+        .await;
+        let status = finished(&job).await;
+
+        assert_eq!(status.state, JobState::Completed);
+        assert_eq!(
+            std::fs::read_to_string(destination.join("a.txt")).unwrap(),
+            "a"
+        );
+        assert_eq!(
+            std::fs::read_to_string(destination.join("nested/b.txt")).unwrap(),
+            "b"
+        );
+        assert_eq!(
+            std::fs::read_link(destination.join("inside")).unwrap(),
+            Path::new("a.txt")
+        );
+        assert!(destination.join("outside").symlink_metadata().is_err());
+        assert_eq!(status.failure_count, 1);
+        std::fs::remove_dir_all(&mount.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn copies_and_deletes_outside_a_request_touch_nothing() {
+        let mount = temp_mount();
+        let source = mount.dir.join("folder");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(source.join("a.txt"), "a").unwrap();
+
+        // Started with no request scope at all, so every path check fails.
+        let jobs = JobRegistry::new(1);
+        let (job_mount, job_source) = (mount.clone(), source.clone());
+        let destination = mount.dir.join("folder (copy)");
+        let copy = jobs.spawn("copy", String::new(), move |job| {
+            copy_tree(job, job_mount, job_source, destination)
+        });
+        finished(&copy).await;
+        assert!(!mount.dir.join("folder (copy)/a.txt").exists());
+
+        let (job_mount, job_source) = (mount.clone(), source.clone());
+        let delete = jobs.spawn("delete", String::new(), move |job| {
+            delete_tree(job, job_mount, job_source)
+        });
+        let status = finished(&delete).await;
+        assert_eq!(status.state, JobState::Failed);
+        assert!(status.message.unwrap().starts_with("Nothing was deleted"));
+        assert!(source.join("a.txt").exists());
+        std::fs::remove_dir_all(&mount.dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn deletes_remove_the_whole_tree() {
+        let mount = temp_mount();
+        let target = mount.dir.join("folder");
+        std::fs::create_dir_all(target.join("nested")).unwrap();
+        std::fs::write(target.join("nested/a.txt"), "a").unwrap();
+
+        let jobs = JobRegistry::new(1);
+        let (job_mount, job_target) = (mount.clone(), target.clone());
+        let job = auth::system(async {
+            jobs.spawn("delete", String::new(), move |job| {
+                delete_tree(job, job_mount, job_target)
+            })
+        })
+        .await;
+        let status = finished(&job).await;
+        assert_eq!(status.state, JobState::Completed);
+        assert!(!target.exists());
+        std::fs::remove_dir_all(&mount.dir).unwrap();
+    }
+
+    #[test]
+    fn maps_paths_into_the_destination() {
+        let (source, destination) = (Path::new("/r/a"), Path::new("/r/a (copy)"));
+        assert_eq!(map_into(source, source, destination), destination);
+        assert_eq!(
+            map_into(source, Path::new("/r/a/b/c"), destination),
+            Path::new("/r/a (copy)/b/c")
+        );
+        assert_eq!(display_relative(source, Path::new("/r/a/b/c")), "b/c");
+        assert_eq!(display_relative(source, source), "a");
+    }
+}
@@ -0,0 +1,273 @@
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+};
+use tracing::info;
+
+use crate::{SharedState, error_response};
+
+// --- Networks ---
+// An address range in CIDR notation (`10.8.0.0/24`, `fd00::/8`); a bare address is a
+// range of one.
+#[derive(Clone, Copy, Debug)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("'{}' is not an IP address or CIDR range", s))?;
+        let addr = addr.to_canonical();
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("'{}' has an invalid prefix length", s))?,
+            None => max,
+        };
+        Ok(Network { addr, prefix })
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Network {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+// --- Filter ---
+// Who may reach the server at all. With an allow list only addresses on it get in; the
+// deny list shuts addresses out either way. Requests arriving through one of the trusted
+// proxies are judged by the address the proxy forwarded them for.
+pub struct IpFilter {
+    allow: Vec<Network>,
+    deny: Vec<Network>,
+    trusted_proxies: Vec<Network>,
+}
+
+// The address a request came from, after looking through trusted proxies. Set on every
+// request by `enforce`, for anything later that cares who the client is.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+// Whether the request reached the proxy in front of kiv over HTTPS, going by the proxy's
+// X-Forwarded-Proto. Set on every request by `enforce`; only trusted proxies are believed,
+// so a client talking to kiv directly can't claim it.
+#[derive(Clone, Copy, Debug)]
+pub struct ForwardedHttps(pub bool);
+
+impl IpFilter {
+    pub fn new(allow: Vec<Network>, deny: Vec<Network>, trusted_proxies: Vec<Network>) -> Self {
+        IpFilter {
+            allow,
+            deny,
+            trusted_proxies,
+        }
+    }
+
+    pub fn allowed(&self) -> &[Network] {
+        &self.allow
+    }
+
+    pub fn denied(&self) -> &[Network] {
+        &self.deny
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|proxy| proxy.contains(ip))
+    }
+
+    // The client behind `peer`. X-Forwarded-For is read right to left, since each proxy
+    // appends the address it was reached from; the first address not a trusted proxy is
+    // the client. Anything to the left of it came from the client and can't be believed.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = peer.to_canonical();
+        if !self.is_trusted(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        let values: Vec<_> = headers.get_all("x-forwarded-for").iter().collect();
+        for value in values.into_iter().rev() {
+            let Ok(value) = value.to_str() else {
+                return client;
+            };
+            for hop in value.rsplit(',') {
+                match hop.trim().parse::<IpAddr>() {
+                    Ok(ip) if self.is_trusted(ip) => client = ip.to_canonical(),
+                    Ok(ip) => return ip.to_canonical(),
+                    Err(_) => return client,
+                }
+            }
+        }
+        client
+    }
+
+    pub fn admits(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+// --- Middleware ---
+pub async fn enforce(
+    State(state): State<SharedState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let filter = &state.ip_filter;
+    let client = filter.client_ip(addr.ip(), request.headers());
+    if !filter.admits(client) {
+        info!(
+            "Refused {} {} from {}: address not allowed",
+            request.method(),
+            request.uri().path(),
+            client
+        );
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "This server can't be reached from your address.",
+        );
+    }
+    let https = filter.is_trusted(addr.ip().to_canonical())
+        && request
+            .headers()
+            .get("x-forwarded-proto")
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|proto| proto.eq_ignore_ascii_case("https"));
+    request.extensions_mut().insert(ClientIp(client));
+    request.extensions_mut().insert(ForwardedHttps(https));
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(s: &str) -> Network {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_networks() {
+        assert_eq!(network("10.8.0.0/24").to_string(), "10.8.0.0/24");
+        assert_eq!(network(" 192.168.1.5 ").to_string(), "192.168.1.5/32");
+        assert_eq!(network("fd00::/8").to_string(), "fd00::/8");
+        // IPv4-mapped IPv6 addresses are taken as the IPv4 address.
+        assert_eq!(network("::ffff:10.0.0.1").to_string(), "10.0.0.1/32");
+        assert!("10.0.0.0/33".parse::<Network>().is_err());
+        assert!("fd00::/129".parse::<Network>().is_err());
+        assert!("10.0.0.0/".parse::<Network>().is_err());
+        assert!("example.com".parse::<Network>().is_err());
+    }
+
+    #[test]
+    fn cidr_membership() {
+        let lan = network("10.8.0.0/24");
+        assert!(lan.contains(ip("10.8.0.1")));
+        assert!(lan.contains(ip("10.8.0.255")));
+        assert!(!lan.contains(ip("10.8.1.0")));
+        assert!(lan.contains(ip("::ffff:10.8.0.7")));
+        assert!(!lan.contains(ip("fd00::1")));
+        assert!(network("0.0.0.0/0").contains(ip("203.0.113.9")));
+        assert!(network("fd00::/8").contains(ip("fdab::1")));
+        assert!(!network("fd00::/8").contains(ip("fe80::1")));
+        assert!(network("::/0").contains(ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn allow_and_deny_lists() {
+        let filter = IpFilter::new(
+            vec![network("10.0.0.0/8")],
+            vec![network("10.0.0.66")],
+            Vec::new(),
+        );
+        assert!(filter.admits(ip("10.1.2.3")));
+        assert!(!filter.admits(ip("10.0.0.66")));
+        assert!(!filter.admits(ip("192.168.0.1")));
+        let open = IpFilter::new(Vec::new(), vec![network("192.0.2.0/24")], Vec::new());
+        assert!(open.admits(ip("198.51.100.1")));
+        assert!(!open.admits(ip("192.0.2.1")));
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let filter = IpFilter::new(Vec::new(), Vec::new(), vec![network("127.0.0.1")]);
+        let headers = forwarded_for(&["203.0.113.9"]);
+        assert_eq!(
+            filter.client_ip(ip("198.51.100.1"), &headers),
+            ip("198.51.100.1")
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_walked_from_the_right() {
+        let filter = IpFilter::new(
+            Vec::new(),
+            Vec::new(),
+            vec![network("127.0.0.1"), network("10.0.0.0/8")],
+        );
+        let peer = ip("127.0.0.1");
+        // Whatever the client wrote on the left can't be believed.
+        let headers = forwarded_for(&["1.2.3.4, 203.0.113.9, 10.0.0.2"]);
+        assert_eq!(filter.client_ip(peer, &headers), ip("203.0.113.9"));
+        // Proxies that add a header of their own rather than appending.
+        let headers = forwarded_for(&["1.2.3.4", "203.0.113.9", "10.0.0.2"]);
+        assert_eq!(filter.client_ip(peer, &headers), ip("203.0.113.9"));
+        // Only trusted proxies: the outermost of them is the client.
+        let headers = forwarded_for(&["10.0.0.3, 10.0.0.2"]);
+        assert_eq!(filter.client_ip(peer, &headers), ip("10.0.0.3"));
+        // A hop that isn't an address stops the walk at the last trusted one.
+        let headers = forwarded_for(&["203.0.113.9, unknown, 10.0.0.2"]);
+        assert_eq!(filter.client_ip(peer, &headers), ip("10.0.0.2"));
+        assert_eq!(filter.client_ip(peer, &HeaderMap::new()), peer);
+    }
+}
@@ -0,0 +1,75 @@
+use dashmap::DashMap;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+use tokio::task::JoinSet;
+
+// Folders counted at the same time for one listing.
+const COUNT_CONCURRENCY: usize = 16;
+// Counting stops here; the listing shows "10000+ items".
+pub const MAX_COUNTED: usize = 10_000;
+
+// --- Cache ---
+// How many entries each folder holds, cached per (path, mtime). Adding or removing an entry
+// changes the folder's mtime, so a cached count is never stale.
+pub struct ItemCounts {
+    cache: Arc<DashMap<PathBuf, (Option<SystemTime>, usize)>>,
+}
+
+impl ItemCounts {
+    pub fn new() -> Self {
+        ItemCounts {
+            cache: Arc::new(DashMap::new()),
+        }
+    }
+
+    // The number of listed entries in each of `dirs` (path and mtime), in the same order;
+    // `None` where a folder can't be read. All of them lie in the mount at `root_dir`.
+    pub async fn counts(
+        &self,
+        root_dir: &Path,
+        dirs: &[(PathBuf, Option<SystemTime>)],
+    ) -> Vec<Option<usize>> {
+        let mut counts = vec![None; dirs.len()];
+        let mut pending = Vec::new();
+        for (index, (dir, modified)) in dirs.iter().enumerate() {
+            match self.cache.get(dir) {
+                Some(cached) if cached.0 == *modified => counts[index] = Some(cached.1),
+                _ => pending.push(index),
+            }
+        }
+
+        let mut reads = JoinSet::new();
+        let mut pending = pending.into_iter();
+        loop {
+            while reads.len() < COUNT_CONCURRENCY
+                && let Some(index) = pending.next()
+            {
+                let (root_dir, dir) = (root_dir.to_path_buf(), dirs[index].0.clone());
+                reads.spawn_blocking(move || (index, count_entries(&root_dir, &dir)));
+            }
+            let Some(joined) = reads.join_next().await else {
+                break;
+            };
+            if let Ok((index, Some(count))) = joined {
+                let (dir, modified) = &dirs[index];
+                self.cache.insert(dir.clone(), (*modified, count));
+                counts[index] = Some(count);
+            }
+        }
+        counts
+    }
+}
+
+fn count_entries(root_dir: &Path, dir: &Path) -> Option<usize> {
+    let entries = std::fs::read_dir(dir).ok()?;
+    Some(
+        entries
+            .flatten()
+            .filter(|entry| crate::policy::is_listed(root_dir, &entry.path()))
+            .take(MAX_COUNTED)
+            .count(),
+    )
+}
@@ -0,0 +1,347 @@
+use chrono::prelude::*;
+use dashmap::DashMap;
+use maud::{Markup, html};
+use serde::Serialize;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use uuid::Uuid;
+
+// Finished jobs stay visible for this long before they are dropped from the registry.
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(60 * 60);
+// Only the first failures are kept; a job over a huge tree could otherwise hoard memory.
+const MAX_RECORDED_FAILURES: usize = 200;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(
+            self,
+            JobState::Completed | JobState::Failed | JobState::Cancelled
+        )
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            JobState::Queued => "Queued",
+            JobState::Running => "Running",
+            JobState::Completed => "Completed",
+            JobState::Failed => "Failed",
+            JobState::Cancelled => "Cancelled",
+        }
+    }
+}
+
+// A point-in-time copy of a job's progress, used for rendering.
+#[derive(Clone, Debug, Serialize)]
+pub struct JobStatus {
+    pub state: JobState,
+    pub done: u64,
+    pub total: Option<u64>,
+    pub message: Option<String>,
+    pub failures: Vec<String>,
+    pub failure_count: usize,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+pub struct Job {
+    pub id: Uuid,
+    pub kind: &'static str,
+    pub description: String,
+    pub created_at: DateTime<Utc>,
+    // The user who started it; `None` without accounts, or for the guest.
+    owner: Option<String>,
+    status: Mutex<JobStatus>,
+    cancel: CancellationToken,
+}
+
+impl Job {
+    pub fn status(&self) -> JobStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    pub fn set_total(&self, total: u64) {
+        self.status.lock().unwrap().total = Some(total);
+    }
+
+    pub fn advance(&self, amount: u64) {
+        self.status.lock().unwrap().done += amount;
+    }
+
+    pub fn set_message(&self, message: impl Into<String>) {
+        self.status.lock().unwrap().message = Some(message.into());
+    }
+
+    pub fn record_failure(&self, failure: impl Into<String>) {
+        let mut status = self.status.lock().unwrap();
+        status.failure_count += 1;
+        if status.failures.len() < MAX_RECORDED_FAILURES {
+            status.failures.push(failure.into());
+        }
+    }
+
+    // Whether the current request may see and cancel the job: only its owner may, or an
+    // administrator.
+    fn belongs_to_caller(&self) -> bool {
+        crate::auth::is_admin() || self.owner == crate::auth::current_user()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    fn finish(&self, state: JobState, message: Option<String>) {
+        let mut status = self.status.lock().unwrap();
+        status.state = state;
+        if message.is_some() {
+            status.message = message;
+        }
+        status.finished_at = Some(Utc::now());
+    }
+}
+
+// --- Registry ---
+pub struct JobRegistry {
+    jobs: DashMap<Uuid, Arc<Job>>,
+    slots: Arc<Semaphore>,
+}
+
+impl JobRegistry {
+    pub fn new(max_concurrent: usize) -> Self {
+        JobRegistry {
+            jobs: DashMap::new(),
+            slots: Arc::new(Semaphore::new(max_concurrent.max(1))),
+        }
+    }
+
+    // The job, if it is the current request's to see.
+    pub fn get(&self, id: &Uuid) -> Option<Arc<Job>> {
+        self.jobs
+            .get(id)
+            .map(|job| job.value().clone())
+            .filter(|job| job.belongs_to_caller())
+    }
+
+    // The current request's jobs, newest first.
+    pub fn list(&self) -> Vec<Arc<Job>> {
+        let mut jobs: Vec<_> = self
+            .jobs
+            .iter()
+            .map(|job| job.value().clone())
+            .filter(|job| job.belongs_to_caller())
+            .collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.created_at));
+        jobs
+    }
+
+    // Queues `work` to run once a job slot is free. The closure's `Ok` value becomes the
+    // job's final message; cancellation is detected through `Job::is_cancelled`. The job
+    // runs as the request that queued it, so path checks hold it to the same rules.
+    pub fn spawn<F, Fut>(&self, kind: &'static str, description: String, work: F) -> Arc<Job>
+    where
+        F: FnOnce(Arc<Job>) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<String, String>> + Send + 'static,
+    {
+        self.prune_finished();
+
+        let job = Arc::new(Job {
+            id: Uuid::new_v4(),
+            kind,
+            description,
+            created_at: Utc::now(),
+            owner: crate::auth::current_user(),
+            status: Mutex::new(JobStatus {
+                state: JobState::Queued,
+                done: 0,
+                total: None,
+                message: None,
+                failures: Vec::new(),
+                failure_count: 0,
+                finished_at: None,
+            }),
+            cancel: CancellationToken::new(),
+        });
+        self.jobs.insert(job.id, job.clone());
+        info!("Queued {} job {}: {}", job.kind, job.id, job.description);
+
+        let slots = self.slots.clone();
+        let task_job = job.clone();
+        tokio::spawn(crate::auth::propagate_async(async move {
+            let _permit = tokio::select! {
+                permit = slots.acquire_owned() => match permit {
+                    Ok(permit) => permit,
+                    Err(_) => return,
+                },
+                _ = task_job.cancel.cancelled() => {
+                    task_job.finish(JobState::Cancelled, Some("Cancelled before it started.".to_string()));
+                    return;
+                }
+            };
+
+            task_job.status.lock().unwrap().state = JobState::Running;
+            let result = work(task_job.clone()).await;
+
+            let (state, message) = match result {
+                _ if task_job.is_cancelled() => (JobState::Cancelled, None),
+                Ok(message) => (JobState::Completed, Some(message)),
+                Err(message) => {
+                    error!("{} job {} failed: {}", task_job.kind, task_job.id, message);
+                    (JobState::Failed, Some(message))
+                }
+            };
+            info!(
+                "{} job {} finished: {}",
+                task_job.kind,
+                task_job.id,
+                state.label()
+            );
+            task_job.finish(state, message);
+        }));
+
+        job
+    }
+
+    fn prune_finished(&self) {
+        let now = Utc::now();
+        self.jobs.retain(|_, job| {
+            job.status().finished_at.is_none_or(|finished_at| {
+                (now - finished_at).to_std().unwrap_or_default() < FINISHED_JOB_RETENTION
+            })
+        });
+    }
+}
+
+// --- Tree walking shared by job implementations ---
+#[derive(Default)]
+pub struct TreeListing {
+    // Directories in pre-order (parents before children).
+    pub dirs: Vec<PathBuf>,
+    pub files: Vec<PathBuf>,
+    pub errors: Vec<(PathBuf, std::io::Error)>,
+}
+
+// Walks `root` without following symlinks; symlinks are reported as files.
+pub async fn walk_tree(root: PathBuf) -> TreeListing {
+    tokio::task::spawn_blocking(move || {
+        let mut listing = TreeListing::default();
+        walk_into(&root, &mut listing);
+        listing
+    })
+    .await
+    .unwrap_or_default()
+}
+
+fn walk_into(path: &Path, listing: &mut TreeListing) {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            listing.errors.push((path.to_path_buf(), e));
+            return;
+        }
+    };
+    if !metadata.is_dir() {
+        listing.files.push(path.to_path_buf());
+        return;
+    }
+
+    listing.dirs.push(path.to_path_buf());
+    let reader = match std::fs::read_dir(path) {
+        Ok(reader) => reader,
+        Err(e) => {
+            listing.errors.push((path.to_path_buf(), e));
+            return;
+        }
+    };
+    for entry in reader {
+        match entry {
+            Ok(entry) => walk_into(&entry.path(), listing),
+            Err(e) => listing.errors.push((path.to_path_buf(), e)),
+        }
+    }
+}
+
+// --- Rendering ---
+// A self-refreshing status card; it stops polling once the job has finished.
+pub fn render_job(job: &Job) -> Markup {
+    let status = job.status();
+    let card_id = format!("job-{}", job.id);
+    let poll_url = format!("/jobs/{}", job.id);
+    let cancel_url = format!("/jobs/{}/cancel", job.id);
+    let percent = status
+        .total
+        .filter(|total| *total > 0)
+        .map(|total| (status.done as f64 / total as f64 * 100.0).min(100.0));
+
+    html! {
+        @if status.state.is_finished() {
+            div #(card_id) class={"job-card job-" (format!("{:?}", status.state).to_lowercase())} {
+                (job_body(job, &status, percent))
+                button class="job-dismiss" type="button"
+                       onclick={"document.getElementById('" (card_id) "').remove();"} { "Dismiss" }
+            }
+        } @else {
+            div #(card_id) class="job-card job-active"
+                hx-get=(poll_url)
+                hx-trigger="every 1s"
+                hx-swap="outerHTML" {
+                (job_body(job, &status, percent))
+                button class="job-cancel" type="button"
+                       hx-post=(cancel_url)
+                       hx-target={"#" (card_id)}
+                       hx-swap="outerHTML" { "Cancel" }
+            }
+        }
+    }
+}
+
+fn job_body(job: &Job, status: &JobStatus, percent: Option<f64>) -> Markup {
+    html! {
+        div class="job-title" {
+            strong { (job.kind) } " — " (job.description)
+        }
+        div class="job-state" {
+            (status.state.label())
+            @if let Some(total) = status.total {
+                " · " (status.done) " / " (total)
+            } @else if status.done > 0 {
+                " · " (status.done)
+            }
+        }
+        @if let Some(percent) = percent {
+            progress max="100" value=(format!("{:.0}", percent)) {}
+        }
+        @if let Some(message) = &status.message {
+            div class="job-message" { (message) }
+        }
+        @if status.failure_count > 0 {
+            details class="job-failures" {
+                summary { (status.failure_count) " failure(s)" }
+                ul {
+                    @for failure in &status.failures { li { (failure) } }
+                    @if status.failure_count > status.failures.len() {
+                        li { "… and " (status.failure_count - status.failures.len()) " more" }
+                    }
+                }
+            }
+        }
+    }
+}
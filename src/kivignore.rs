@@ -0,0 +1,125 @@
+use dashmap::DashMap;
+use ignore::{
+    Match,
+    gitignore::{Gitignore, GitignoreBuilder},
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::warn;
+
+// A gitignore-style file whose patterns hide entries of its folder and everything below.
+pub const IGNORE_FILE_NAME: &str = ".kivignore";
+// How long a folder's `.kivignore` (or its absence) is trusted before it is looked at again.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+struct DirRules {
+    checked_at: Instant,
+    modified: Option<SystemTime>,
+    rules: Option<Arc<Gitignore>>,
+}
+
+// --- Rules ---
+// `--ignore` patterns apply everywhere below each mount; a folder's `.kivignore` adds to
+// them and, as with `.gitignore`, rules closer to a path win, so `!pattern` in a
+// subfolder can bring back something ignored further up.
+pub struct IgnoreRules {
+    // The `--ignore` patterns anchored at each mount's directory.
+    global: Vec<(PathBuf, Gitignore)>,
+    per_dir: DashMap<PathBuf, DirRules>,
+}
+
+impl IgnoreRules {
+    pub fn new(root_dirs: &[PathBuf], patterns: &[String]) -> Result<Self, String> {
+        let mut global = Vec::new();
+        for root_dir in root_dirs {
+            let mut builder = GitignoreBuilder::new(root_dir);
+            for pattern in patterns {
+                builder
+                    .add_line(None, pattern)
+                    .map_err(|e| format!("Invalid --ignore pattern '{}': {}", pattern, e))?;
+            }
+            global.push((
+                root_dir.clone(),
+                builder.build().map_err(|e| e.to_string())?,
+            ));
+        }
+        Ok(IgnoreRules {
+            global,
+            per_dir: DashMap::new(),
+        })
+    }
+
+    // Whether `path`, or a folder it is in below `root_dir`, matches an ignore rule.
+    pub fn is_ignored(&self, root_dir: &Path, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root_dir) else {
+            return false;
+        };
+        // Only looked up once a rule needs it.
+        let mut known_is_dir = None;
+        let mut is_dir = || *known_is_dir.get_or_insert_with(|| path.is_dir());
+
+        let mut dirs = vec![root_dir.to_path_buf()];
+        if let Some(parent) = relative.parent() {
+            for component in parent.components() {
+                let next = dirs[dirs.len() - 1].join(component);
+                dirs.push(next);
+            }
+        }
+        for dir in dirs.iter().rev() {
+            if let Some(rules) = self.rules_for(dir) {
+                match rules.matched_path_or_any_parents(path, is_dir()) {
+                    Match::Ignore(_) => return true,
+                    Match::Whitelist(_) => return false,
+                    Match::None => {}
+                }
+            }
+        }
+        self.global
+            .iter()
+            .find(|(dir, _)| dir == root_dir)
+            .is_some_and(|(_, global)| {
+                !global.is_empty()
+                    && global
+                        .matched_path_or_any_parents(path, is_dir())
+                        .is_ignore()
+            })
+    }
+
+    // The parsed `.kivignore` of `dir`, re-read when it has changed.
+    fn rules_for(&self, dir: &Path) -> Option<Arc<Gitignore>> {
+        if let Some(cached) = self.per_dir.get(dir)
+            && cached.checked_at.elapsed() < RECHECK_INTERVAL
+        {
+            return cached.rules.clone();
+        }
+
+        let file = dir.join(IGNORE_FILE_NAME);
+        let modified = std::fs::metadata(&file).and_then(|m| m.modified()).ok();
+        if let Some(mut cached) = self.per_dir.get_mut(dir)
+            && cached.modified == modified
+        {
+            cached.checked_at = Instant::now();
+            return cached.rules.clone();
+        }
+
+        let rules = modified.and_then(|_| {
+            let (rules, error) = Gitignore::new(&file);
+            if let Some(e) = error {
+                warn!("Problem in {}: {}", file.display(), e);
+            }
+            (!rules.is_empty()).then(|| Arc::new(rules))
+        });
+        self.per_dir.insert(
+            dir.to_path_buf(),
+            DirRules {
+                checked_at: Instant::now(),
+                modified,
+                rules: rules.clone(),
+            },
+        );
+        rules
+    }
+}
@@ -0,0 +1,56 @@
+use serde::Deserialize;
+use std::{collections::HashMap, path::Path};
+use tracing::warn;
+
+// Notes about a folder and its entries, kept in the folder itself:
+//
+//     description = "Final renders from the March delivery."
+//
+//     [files]
+//     "hero.png" = "Approved by the client"
+//     "drafts" = "Superseded; kept for reference"
+pub const META_FILE_NAME: &str = ".kivmeta.toml";
+// Larger files are ignored rather than parsed on every listing.
+const MAX_META_SIZE: u64 = 256 * 1024;
+
+#[derive(Deserialize, Default, Debug)]
+pub struct FolderMeta {
+    #[serde(default, alias = "notes")]
+    pub description: Option<String>,
+    // Entry name → annotation shown next to it.
+    #[serde(default)]
+    pub files: HashMap<String, String>,
+}
+
+impl FolderMeta {
+    // A folder's annotation may be keyed with or without a trailing slash.
+    pub fn annotation(&self, name: &str, is_dir: bool) -> Option<String> {
+        self.files
+            .get(name)
+            .or_else(|| {
+                is_dir
+                    .then(|| self.files.get(&format!("{}/", name)))
+                    .flatten()
+            })
+            .map(|text| text.trim().to_string())
+            .filter(|text| !text.is_empty())
+    }
+}
+
+// --- Reading ---
+// The folder's notes, or `None` when it has none. A file that can't be read or parsed is
+// logged and treated as missing, so a typo never breaks the listing.
+pub async fn read(dir: &Path) -> Option<FolderMeta> {
+    let path = dir.join(META_FILE_NAME);
+    let metadata = tokio::fs::metadata(&path).await.ok()?;
+    if !metadata.is_file() || metadata.len() > MAX_META_SIZE {
+        return None;
+    }
+    let contents = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| warn!("Failed to read {}: {}", path.display(), e))
+        .ok()?;
+    toml::from_str(&contents)
+        .map_err(|e| warn!("Ignoring {}: {}", path.display(), e))
+        .ok()
+}
@@ -0,0 +1,123 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::{
+    rt::{TokioIo, TokioTimer},
+    service::TowerToHyperService,
+};
+use std::{io, net::SocketAddr, time::Duration};
+use tokio::net::TcpListener;
+use tower::ServiceExt;
+use tower_http::timeout::TimeoutBody;
+use tracing::{debug, error, info};
+
+use crate::{SharedState, auth, error_response};
+
+// The smallest header buffer hyper accepts.
+pub const MIN_HEADER_SIZE: usize = 8 * 1024;
+
+// --- Connections ---
+// What a single connection may cost before it is dropped: how long the client may take
+// to send a request's headers (and, between requests, to start the next one), how large
+// those headers may be, and how long its request body may stall.
+pub struct ConnectionLimits {
+    pub header_timeout: Option<Duration>,
+    pub max_header_size: usize,
+    pub body_idle_timeout: Option<Duration>,
+}
+
+// Serves `app` like `axum::serve`, which has no settings for any of this.
+pub async fn serve(listener: TcpListener, app: Router, limits: ConnectionLimits) {
+    let mut builder = http1::Builder::new();
+    builder
+        .timer(TokioTimer::new())
+        .header_read_timeout(limits.header_timeout)
+        .max_buf_size(limits.max_header_size);
+    let body_idle_timeout = limits.body_idle_timeout;
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            // The client gave up before it was accepted; nothing to do.
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::ConnectionAborted
+                        | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                continue;
+            }
+            // Usually out of file descriptors; give connections a moment to close.
+            Err(e) => {
+                error!("Failed to accept a connection: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let service = app.clone().map_request(move |request: Request<Incoming>| {
+            let mut request = request.map(|body| match body_idle_timeout {
+                Some(timeout) => Body::new(TimeoutBody::new(timeout, body)),
+                None => Body::new(body),
+            });
+            request
+                .extensions_mut()
+                .insert(ConnectInfo::<SocketAddr>(peer));
+            request
+        });
+        let connection = builder
+            .serve_connection(TokioIo::new(stream), TowerToHyperService::new(service))
+            .with_upgrades();
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("Connection from {} ended: {}", peer, e);
+            }
+        });
+    }
+}
+
+// --- Middleware ---
+// Stops requests that take longer than `--request-timeout` to answer. Uploads, which
+// take as long as the file does, and downloads, which may wait their turn in the
+// download queue, aren't timed; a stalled upload still runs into `--body-timeout`.
+pub async fn request_timeout(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(timeout) = state.request_timeout else {
+        return next.run(request).await;
+    };
+    let path = request.uri().path().to_string();
+    let exempt = path == "/upload"
+        || path.starts_with("/direct-download/")
+        || path == "/signed"
+        || auth::capability_for(&path, request.uri().query().unwrap_or(""))
+            == Some(auth::Capability::Download);
+    if exempt {
+        return next.run(request).await;
+    }
+    let method = request.method().clone();
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            info!(
+                "Stopped {} {} after {} seconds",
+                method,
+                path,
+                timeout.as_secs()
+            );
+            error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "This took too long and was stopped.",
+            )
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use dashmap::DashMap;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+
+// A folder's mtime only changes with its entries being added, removed or renamed, so a
+// cached listing is also dropped after this long to pick up files changing in place.
+const MAX_AGE: Duration = Duration::from_secs(5);
+// Past this many folders, expired listings are swept out on the next insert.
+const SWEEP_THRESHOLD: usize = 256;
+
+struct CachedListing<T> {
+    modified: Option<SystemTime>,
+    stored_at: Instant,
+    listing: T,
+}
+
+// --- Cache ---
+// Recently read folder listings keyed by (path, mtime), so refreshing a page or many
+// people opening the same folder don't stat every entry again. Open listings' watchers
+// drop entries as soon as their folder changes.
+pub struct ListingCache<T> {
+    entries: DashMap<PathBuf, CachedListing<T>>,
+}
+
+impl<T: Clone> ListingCache<T> {
+    pub fn new() -> Self {
+        ListingCache {
+            entries: DashMap::new(),
+        }
+    }
+
+    pub fn get(&self, dir: &Path, modified: Option<SystemTime>) -> Option<T> {
+        self.entries
+            .get(dir)
+            .filter(|cached| cached.modified == modified && cached.stored_at.elapsed() < MAX_AGE)
+            .map(|cached| cached.listing.clone())
+    }
+
+    pub fn insert(&self, dir: &Path, modified: Option<SystemTime>, listing: T) {
+        if self.entries.len() >= SWEEP_THRESHOLD {
+            self.entries
+                .retain(|_, cached| cached.stored_at.elapsed() < MAX_AGE);
+        }
+        self.entries.insert(
+            dir.to_path_buf(),
+            CachedListing {
+                modified,
+                stored_at: Instant::now(),
+                listing,
+            },
+        );
+    }
+
+    pub fn invalidate(&self, dir: &Path) {
+        self.entries.remove(dir);
+    }
+}
@@ -0,0 +1,437 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{SharedState, auth, secrets, sessions, signing::Signer};
+
+pub const UNLOCK_COOKIE: &str = "kiv_unlocked";
+
+// --- Folder passwords ---
+// Folders that open only with a password, from a TOML file of request paths and hashes:
+//
+//     "Photos/private" = "$argon2id$v=19$…"   # from `kiv --hash-password`
+//     "Taxes" = { password_file = "taxes.hash" }
+//
+// A `password_file` holds the hash instead, found as in the users file (see `secrets`).
+// A password covers its folder and everything below; under two locked folders both have
+// to be unlocked. Browsers remember what they unlocked until they are closed.
+pub struct FolderLocks {
+    locks: Vec<Lock>,
+}
+
+struct Lock {
+    // Request path without leading or trailing slashes.
+    prefix: String,
+    password_hash: String,
+    // Stands for this folder and password in the unlock cookie, so changing the password
+    // locks the folder again for everyone.
+    key: String,
+}
+
+impl Lock {
+    fn covers(&self, relative: &str) -> bool {
+        relative
+            .strip_prefix(self.prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PasswordConfig {
+    Hash(String),
+    File { password_file: PathBuf },
+}
+
+impl FolderLocks {
+    pub async fn load(path: &Path) -> Result<Self, String> {
+        let contents = tokio::fs::read_to_string(path)
+            .await
+            .map_err(|e| format!("Failed to read folder passwords {}: {}", path.display(), e))?;
+        let file: HashMap<String, PasswordConfig> = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid folder passwords file {}: {}", path.display(), e))?;
+        let mut locks = Vec::new();
+        for (folder, password) in file {
+            let password_hash = match password {
+                PasswordConfig::Hash(hash) => hash,
+                PasswordConfig::File { password_file } => {
+                    secrets::read(&secrets::resolve(&password_file, path))
+                        .await
+                        .map_err(|e| format!("Folder '{}' in {}: {}", folder, path.display(), e))?
+                }
+            };
+            let prefix = auth::normalize(&folder);
+            if prefix.is_empty() {
+                return Err(format!(
+                    "{}: lock folders below the root, not the root itself; use --users for that.",
+                    path.display()
+                ));
+            }
+            if argon2::PasswordHash::new(&password_hash).is_err() {
+                return Err(format!(
+                    "Folder '{}' in {}: password must be a hash from `kiv --hash-password`.",
+                    folder,
+                    path.display()
+                ));
+            }
+            let digest = Sha256::digest(format!("{}\0{}", prefix, password_hash));
+            let key = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+            locks.push(Lock {
+                prefix,
+                password_hash,
+                key,
+            });
+        }
+        // Outermost first, so the first locked folder on a path is the one asked about.
+        locks.sort_by_key(|lock| lock.prefix.len());
+        Ok(FolderLocks { locks })
+    }
+
+    pub fn len(&self) -> usize {
+        self.locks.len()
+    }
+
+    // The locked folder `relative` is in that hasn't been unlocked, if any. Outside a
+    // request nothing counts as unlocked, unless the work is kiv's own.
+    pub fn locked(&self, relative: &str) -> Option<&str> {
+        if auth::is_system() {
+            return None;
+        }
+        let relative = auth::normalize(relative);
+        let unlocked = UNLOCKED.try_with(Arc::clone).unwrap_or_default();
+        self.locks
+            .iter()
+            .find(|lock| lock.covers(&relative) && !unlocked.contains(&lock.key))
+            .map(|lock| lock.prefix.as_str())
+    }
+
+    // Checks `password` for the folder, returning its cookie key when it is right.
+    pub async fn unlock(&self, folder: &str, password: &str) -> Option<String> {
+        let folder = auth::normalize(folder);
+        let lock = self.locks.iter().find(|lock| lock.prefix == folder)?;
+        auth::verify_password(&lock.password_hash, password)
+            .await
+            .then(|| lock.key.clone())
+    }
+}
+
+// --- Current request ---
+// The folders the current request's browser has unlocked, set by `scope`.
+tokio::task_local! {
+    static UNLOCKED: Arc<HashSet<String>>;
+}
+
+static LOCKS: std::sync::OnceLock<Option<FolderLocks>> = std::sync::OnceLock::new();
+
+// Set once at startup, like the policies: path checks everywhere consult it.
+pub fn init(locks: Option<FolderLocks>) {
+    let _ = LOCKS.set(locks);
+}
+
+pub fn get() -> Option<&'static FolderLocks> {
+    LOCKS.get().and_then(Option::as_ref)
+}
+
+// Whether `relative` may be opened by the current request.
+pub fn allows(relative: &str) -> bool {
+    get().is_none_or(|locks| locks.locked(relative).is_none())
+}
+
+// Whether `relative` shows up for the current request: a locked folder itself is listed,
+// so it can be unlocked, but nothing inside it is.
+pub fn visible(relative: &str) -> bool {
+    let Some(locks) = get() else {
+        return true;
+    };
+    let relative = auth::normalize(relative);
+    locks
+        .locked(&relative)
+        .is_none_or(|folder| folder == relative)
+}
+
+// Carries the current request's unlocked folders into a closure run on a blocking thread.
+pub fn propagate<R>(f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+    let unlocked = UNLOCKED.try_with(Arc::clone).ok();
+    move || match unlocked {
+        Some(unlocked) => UNLOCKED.sync_scope(unlocked, f),
+        None => f(),
+    }
+}
+
+// The same for work spawned onto a task of its own.
+pub fn propagate_async<F: Future>(work: F) -> impl Future<Output = F::Output> {
+    let unlocked = UNLOCKED.try_with(Arc::clone).ok();
+    async move {
+        match unlocked {
+            Some(unlocked) => UNLOCKED.scope(unlocked, work).await,
+            None => work.await,
+        }
+    }
+}
+
+// --- Cookie ---
+// Unlocks last this long at most, however long the browser stays open.
+const UNLOCK_LIFETIME_SECS: i64 = 12 * 60 * 60;
+
+// The keys of the unlocked folders, signed so they can't be made up, together with when
+// they run out and the login session they were given in: a cookie copied to another
+// browser, or kept past a logout, unlocks nothing.
+pub fn unlocked_keys(headers: &HeaderMap, signer: &Signer) -> HashSet<String> {
+    let value = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == UNLOCK_COOKIE)
+        .map(|(_, value)| value);
+    value
+        .and_then(|value| verified_keys(value, &session_tag(headers), signer))
+        .unwrap_or_default()
+}
+
+fn verified_keys(value: &str, session: &str, signer: &Signer) -> Option<HashSet<String>> {
+    let (payload, signature) = value.rsplit_once('|')?;
+    if !signer.verify(payload, signature) {
+        return None;
+    }
+    let mut fields = payload.split('|');
+    let (keys, expires, bound_to) = (fields.next()?, fields.next()?, fields.next()?);
+    let expires: i64 = expires.parse().ok()?;
+    if fields.next().is_some() || bound_to != session || chrono::Utc::now().timestamp() > expires {
+        return None;
+    }
+    Some(
+        keys.split('.')
+            .filter(|key| !key.is_empty())
+            .map(str::to_string)
+            .collect(),
+    )
+}
+
+// Stands for the browser's login session, if it has one, without the token itself.
+fn session_tag(headers: &HeaderMap) -> String {
+    sessions::token(headers)
+        .map(|token| {
+            Sha256::digest(token.as_bytes())[..8]
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn cookie_value(keys: &HashSet<String>, session: &str, expires: i64, signer: &Signer) -> String {
+    let mut keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+    keys.sort_unstable();
+    let payload = format!("{}|{}|{}", keys.join("."), expires, session);
+    format!("{}|{}", payload, signer.sign(&payload))
+}
+
+// No Max-Age: the folders also lock again when the browser is closed. `headers` are the
+// request's, for its session.
+pub fn set_cookie(
+    keys: &HashSet<String>,
+    headers: &HeaderMap,
+    signer: &Signer,
+    secure: bool,
+) -> HeaderValue {
+    let expires = chrono::Utc::now().timestamp() + UNLOCK_LIFETIME_SECS;
+    let cookie = format!(
+        "{}={}; Path=/; SameSite=Lax; HttpOnly{}",
+        UNLOCK_COOKIE,
+        cookie_value(keys, &session_tag(headers), expires, signer),
+        if secure { "; Secure" } else { "" }
+    );
+    HeaderValue::from_str(&cookie).expect("unlock cookies are plain ASCII")
+}
+
+// --- Middleware ---
+// Marks a response refused because of a folder password; see `scope`.
+#[derive(Clone)]
+pub struct Locked;
+
+// Runs the request with the folders its browser has unlocked. htmx doesn't swap in error
+// responses, so a refusal it asked for comes back as 200: it carries the unlock form.
+pub async fn scope(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    if get().is_none() {
+        return next.run(request).await;
+    }
+    let unlocked = Arc::new(unlocked_keys(request.headers(), &state.signer));
+    let htmx = request.headers().contains_key("hx-request");
+    let mut response = UNLOCKED.scope(unlocked, next.run(request)).await;
+    if htmx && response.extensions().get::<Locked>().is_some() {
+        *response.status_mut() = StatusCode::OK;
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "pw1", hashed by `kiv --hash-password`.
+    const HASH: &str = "$argon2id$v=19$m=19456,t=2,p=1$7vQJui+HSUK9lBHbRgQ83A$wIZBygBlvcgr0w60puINHO0gHGJYK0+C8i1cLWifZ7M";
+
+    async fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kiv-{}-{}", uuid::Uuid::new_v4(), name));
+        tokio::fs::write(&path, contents).await.unwrap();
+        path
+    }
+
+    async fn folder_locks(toml: &str) -> Result<FolderLocks, String> {
+        let path = temp_file("locks.toml", &toml.replace("HASH", HASH)).await;
+        let locks = FolderLocks::load(&path).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        locks
+    }
+
+    async fn signer(byte: u8) -> Signer {
+        let key: String = std::iter::repeat_n(format!("{:02x}", byte), 32).collect();
+        let path = temp_file("signing.key", &key).await;
+        let signer = Signer::load(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        signer
+    }
+
+    fn unlocked(keys: &[&String]) -> Arc<HashSet<String>> {
+        Arc::new(keys.iter().map(|key| key.to_string()).collect())
+    }
+
+    const LOCKS: &str = r#"
+        "Taxes" = "HASH"
+        "/Photos/private/" = "HASH"
+        "Photos/private/deeper" = "HASH"
+    "#;
+
+    #[tokio::test]
+    async fn passwords_cover_folders_and_everything_below() {
+        let locks = folder_locks(LOCKS).await.unwrap();
+        let taxes = locks.unlock("Taxes", "pw1").await.unwrap();
+        let private = locks.unlock("Photos/private", "pw1").await.unwrap();
+        assert!(locks.unlock("Taxes", "wrong").await.is_none());
+        assert!(locks.unlock("Elsewhere", "pw1").await.is_none());
+
+        UNLOCKED.sync_scope(unlocked(&[]), || {
+            assert_eq!(locks.locked("Taxes"), Some("Taxes"));
+            assert_eq!(locks.locked("Taxes/2024/a.pdf"), Some("Taxes"));
+            assert_eq!(locks.locked("Taxes2024"), None);
+            assert_eq!(
+                locks.locked("Photos/private/deeper/x"),
+                Some("Photos/private")
+            );
+            assert_eq!(locks.locked("Photos"), None);
+        });
+        UNLOCKED.sync_scope(unlocked(&[&taxes, &private]), || {
+            assert_eq!(locks.locked("Taxes/2024/a.pdf"), None);
+            assert_eq!(locks.locked("Photos/private/a.jpg"), None);
+            // Nested folders have to be unlocked each.
+            assert_eq!(
+                locks.locked("Photos/private/deeper/x"),
+                Some("Photos/private/deeper")
+            );
+        });
+    }
+
+    #[tokio::test]
+    async fn everything_is_locked_outside_a_request_but_for_kivs_own_work() {
+        let locks = folder_locks(LOCKS).await.unwrap();
+        assert_eq!(locks.locked("Taxes/a.pdf"), Some("Taxes"));
+        assert_eq!(locks.locked("Photos/a.jpg"), None);
+        let locked = auth::system(async { locks.locked("Taxes/a.pdf").map(str::to_string) }).await;
+        assert_eq!(locked, None);
+    }
+
+    #[tokio::test]
+    async fn lock_files_are_checked() {
+        let root = folder_locks(r#""/" = "HASH""#).await;
+        assert!(root.err().unwrap().contains("not the root itself"));
+        let plain = folder_locks(r#""Taxes" = "hunter2""#).await;
+        assert!(plain.err().unwrap().contains("must be a hash"));
+        assert!(folder_locks("Taxes = [1]").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn keys_change_with_the_password() {
+        let before = folder_locks(LOCKS).await.unwrap();
+        let other = "$argon2id$v=19$m=19456,t=2,p=1$c2FsdHNhbHRzYWx0$Ua1Kr6fAi3JTMzl2QpkyAVyOaTpSqVdATCp0YFIcXzA";
+        let after = folder_locks(&LOCKS.replacen("HASH", other, 1))
+            .await
+            .unwrap();
+        assert_ne!(before.locks[0].key, after.locks[0].key);
+        assert_eq!(before.locks[1].key, after.locks[1].key);
+    }
+
+    fn keys(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn cookies_hold_signed_keys() {
+        let signer = signer(1).await;
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let value = cookie_value(&keys(&["aa", "bb"]), "s1", expires, &signer);
+        assert_eq!(
+            verified_keys(&value, "s1", &signer),
+            Some(keys(&["aa", "bb"]))
+        );
+        // Altered keys, another server's key, or no signature at all.
+        let altered = value.replacen("aa", "cc", 1);
+        assert_eq!(verified_keys(&altered, "s1", &signer), None);
+        assert_eq!(verified_keys(&value, "s1", &self::signer(2).await), None);
+        assert_eq!(verified_keys("aa.bb", "s1", &signer), None);
+    }
+
+    #[tokio::test]
+    async fn cookies_expire_and_stay_with_their_session() {
+        let signer = signer(1).await;
+        let now = chrono::Utc::now().timestamp();
+        let value = cookie_value(&keys(&["aa"]), "s1", now + 60, &signer);
+        assert_eq!(verified_keys(&value, "s2", &signer), None);
+        assert_eq!(verified_keys(&value, "", &signer), None);
+        let expired = cookie_value(&keys(&["aa"]), "s1", now - 1, &signer);
+        assert_eq!(verified_keys(&expired, "s1", &signer), None);
+        // Without a session the cookie is bound to having none.
+        let sessionless = cookie_value(&keys(&["aa"]), "", now + 60, &signer);
+        assert_eq!(
+            verified_keys(&sessionless, "", &signer),
+            Some(keys(&["aa"]))
+        );
+        assert_eq!(verified_keys(&sessionless, "s1", &signer), None);
+    }
+
+    #[tokio::test]
+    async fn cookies_are_read_from_the_request() {
+        let signer = signer(1).await;
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("kiv_session=token"),
+        );
+        let set = set_cookie(&keys(&["aa"]), &headers, &signer, true);
+        let set = set.to_str().unwrap();
+        assert!(set.ends_with("; Path=/; SameSite=Lax; HttpOnly; Secure"));
+        let value = set.split(';').next().unwrap();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("kiv_session=token; {}", value)).unwrap(),
+        );
+        assert_eq!(unlocked_keys(&headers, &signer), keys(&["aa"]));
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("kiv_session=other; {}", value)).unwrap(),
+        );
+        assert!(unlocked_keys(&headers, &signer).is_empty());
+    }
+}
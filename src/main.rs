@@ -1,8 +1,11 @@
 use axum::{
     Router,
-    extract::{Form, Path as AxumPath, Query, State}, // Host is no longer needed here or implicitly
+    extract::{DefaultBodyLimit, Extension, Form, Multipart, Path as AxumPath, Query, State}, // Host is no longer needed here or implicitly
     http::{HeaderMap, HeaderValue, StatusCode, header},
-    response::{IntoResponse, Response},
+    response::{
+        IntoResponse, Redirect, Response,
+        sse::{KeepAlive, Sse},
+    },
     routing::{get, post},
 };
 // ... (other imports remain the same)
@@ -14,14 +17,16 @@ use maud::{DOCTYPE, Markup, PreEscaped, html};
 use serde::{Deserialize, Serialize};
 use std::{
     fs::Metadata,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
-use tokio::fs;
+use tokio::{fs, io::AsyncWriteExt};
 use tokio_util::io::ReaderStream;
 use tower_http::{
     cors::{Any, CorsLayer},
+    limit::RequestBodyLimitLayer,
     services::ServeDir,
     trace::TraceLayer,
 };
@@ -29,6 +34,74 @@ use tracing::{Level, error, info};
 use tracing_subscriber::FmtSubscriber;
 use uuid::Uuid;
 
+use features::Feature;
+use ipfilter::{ClientIp, ForwardedHttps};
+
+mod accesslog;
+mod archive;
+mod audit;
+mod auth;
+mod autoindex;
+mod bandwidth;
+mod bundle;
+mod cache;
+mod checksums;
+mod clamav;
+mod clientlimit;
+mod comics;
+mod convert;
+mod diff;
+mod dirsize;
+mod documents;
+mod email;
+mod epub;
+mod favorites;
+mod features;
+mod ffmpeg;
+mod fileops;
+mod ipfilter;
+mod itemcount;
+mod jobs;
+mod kivignore;
+mod kivmeta;
+mod limits;
+mod listcache;
+mod locks;
+mod markdown;
+mod media;
+mod mounts;
+mod namefilter;
+mod natsort;
+mod ownership;
+mod policy;
+mod poster;
+mod prefs;
+mod queue;
+mod quickopen;
+mod ratelimit;
+mod rawnames;
+mod resize;
+mod search;
+mod secrets;
+mod security;
+mod serve;
+mod sessions;
+mod signing;
+mod sniff;
+mod stats;
+mod structured;
+mod subtitles;
+#[cfg(feature = "data-preview")]
+mod tabular;
+mod tail;
+mod text;
+mod torrent;
+mod transcode;
+mod trash;
+mod tree;
+mod versions;
+mod watch;
+
 // --- Configuration --- (remains the same)
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -37,21 +110,330 @@ struct Args {
     root_dir: PathBuf,
     #[arg(short, long, value_name = "ADDR", default_value = "127.0.0.1:3001")]
     bind_addr: SocketAddr,
+    /// Days to keep trashed items before purging them (0 keeps them forever)
+    #[arg(long, value_name = "DAYS", default_value_t = 30)]
+    trash_retention_days: u64,
+    /// Maximum total size of the trash in MiB; oldest items are purged first
+    #[arg(long, value_name = "MIB")]
+    trash_max_size: Option<u64>,
+    /// How often the trash purge runs, in minutes
+    #[arg(long, value_name = "MINUTES", default_value_t = 60)]
+    trash_purge_interval: u64,
+    /// Number of previous versions to keep when a file is overwritten (0 disables versioning)
+    #[arg(long, value_name = "COUNT", default_value_t = 0)]
+    keep_versions: usize,
+    /// clamd address used to scan uploads (unix:/path/to/clamd.ctl or tcp:host:port)
+    #[arg(long, value_name = "ADDR")]
+    clamd: Option<clamav::ClamdAddr>,
+    /// Refuse uploads with this extension, or of this kind by their contents: "executable"
+    /// (Linux, Windows and macOS programs) or "script" (files starting with #!)
+    #[arg(long, value_name = "TYPE")]
+    deny_upload_type: Vec<String>,
+    /// Accept uploads whose contents don't match their extension (disguised programs are
+    /// still refused)
+    #[arg(long)]
+    skip_upload_type_check: bool,
+    /// Store uploads whose contents don't match their extension under the right extension
+    /// instead of refusing them
+    #[arg(long)]
+    fix_upload_extensions: bool,
+    /// Run as an anonymous drop zone: visitors can only upload into this directory
+    /// (relative to the root) and cannot browse, preview, or download anything. Not
+    /// with --users
+    #[arg(long, value_name = "DIR")]
+    drop_zone: Option<PathBuf>,
+    /// Maximum number of background jobs (copies, deletes, checksums, …) running at once
+    #[arg(long, value_name = "COUNT", default_value_t = 2)]
+    max_jobs: usize,
+    /// How much of a text file is shown per preview page, in KiB
+    #[arg(long, value_name = "KIB", default_value_t = 256)]
+    preview_chunk_size: u64,
+    /// Don't show GPS coordinates from photos' EXIF data in image previews
+    #[arg(long)]
+    hide_exif_gps: bool,
+    /// Maximum size of the cache of resized images and other derived files, in MiB
+    #[arg(long, value_name = "MIB", default_value_t = 1024)]
+    cache_max_size: u64,
+    /// ffmpeg executable used for video poster frames and HEIC conversion
+    #[arg(long, value_name = "PATH", default_value = "ffmpeg")]
+    ffmpeg: PathBuf,
+    /// Transcode videos the browser can't play to HLS with ffmpeg
+    #[arg(long)]
+    transcode: bool,
+    /// Maximum number of videos being transcoded at once
+    #[arg(long, value_name = "COUNT", default_value_t = 2)]
+    transcode_max_sessions: usize,
+    /// Show the total size of each folder in listings, computed in the background
+    #[arg(long)]
+    dir_sizes: bool,
+    /// Dotfiles and dot-folders: hide and refuse them (deny), treat them like any other
+    /// file (show), or list them but refuse to open them (list-only)
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = policy::HiddenPolicy::Show)]
+    hidden: policy::HiddenPolicy,
+    /// Symlinks: hide and refuse them (deny), follow them when they stay inside the root
+    /// (follow), or list them as links with their target (show)
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = policy::SymlinkPolicy::Follow)]
+    symlinks: policy::SymlinkPolicy,
+    /// Gitignore-style pattern for entries to hide from listings, search and sharing, on top
+    /// of each folder's .kivignore (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    ignore: Vec<String>,
+    /// Serve a directory as a named mount, shown at the top level next to the others
+    /// (name=/path, repeatable); the root directory then only holds kiv's own data
+    #[arg(long = "root", value_name = "NAME=PATH")]
+    mounts: Vec<mounts::MountArg>,
+    /// Send visitors of / to the plain, script-free index at /index/ (always available) for
+    /// mirroring tools and old browsers
+    #[arg(long)]
+    autoindex: bool,
+    /// Cap on the combined rate of all downloads and streamed files, in KiB/s
+    #[arg(long, value_name = "KIB")]
+    bandwidth_limit: Option<u64>,
+    /// Size of the chunks files are read and sent in, in KiB (16 to 16384); larger values
+    /// help on 10GbE and faster links
+    #[arg(long, value_name = "KIB", default_value_t = serve::DEFAULT_STREAM_BUFFER_KIB)]
+    stream_buffer_size: usize,
+    /// Downloads one client address may have running at once; more wait in line
+    #[arg(long, value_name = "COUNT")]
+    max_downloads_per_client: Option<usize>,
+    /// Downloads the whole server streams at once; more wait in line, with their place
+    /// shown on share landing pages
+    #[arg(long, value_name = "SLOTS")]
+    download_queue: Option<usize>,
+    /// TOML file of user accounts, roles and per-path permissions; when given, every
+    /// page asks for a login, apart from what its [guest] section allows (and --public)
+    #[arg(long, value_name = "FILE")]
+    users: Option<PathBuf>,
+    /// With --users, let visitors who aren't logged in browse, preview and download
+    /// everywhere, as if the users file's [guest] section allowed it; sharing, uploading
+    /// and everything else still take a login
+    #[arg(long)]
+    public: bool,
+    /// Only users with this role may create share and signed links (repeatable)
+    #[arg(long = "share-role", value_name = "ROLE")]
+    share_roles: Vec<String>,
+    /// Only users with this role may upload (repeatable)
+    #[arg(long = "upload-role", value_name = "ROLE")]
+    upload_roles: Vec<String>,
+    /// Minutes a login session may go unused before it ends
+    #[arg(long, value_name = "MINUTES", default_value_t = 60)]
+    session_idle_minutes: u64,
+    /// TOML file of folders that open only with a password (folder path = hash from
+    /// --hash-password); works with or without --users
+    #[arg(long, value_name = "FILE")]
+    folder_passwords: Option<PathBuf>,
+    /// Read a password from standard input, print its hash for the users file and exit
+    #[arg(long)]
+    hash_password: bool,
+    /// Sign cookies and links with the keys in this file (one per line in hex, the first
+    /// signing) instead of kiv's own; under systemd a "signing-key" credential is used
+    /// when this isn't given
+    #[arg(long, value_name = "FILE")]
+    signing_key_file: Option<PathBuf>,
+    /// Print a new random signing key in hex and exit
+    #[arg(long)]
+    generate_signing_key: bool,
+    /// Start signing cookies and links with a new key and exit; what the current key
+    /// signed stays valid until the rotation after this one. Takes effect when kiv is
+    /// next started
+    #[arg(long)]
+    rotate_signing_key: bool,
+    /// Never change the served files: uploads, copies, deletes, the trash, restoring
+    /// versions, checksum manifests and favorites are all refused. Thumbnails and other
+    /// conversions are still cached in .kiv-cache, and without an existing key cookies
+    /// and signed links only last until a restart
+    #[arg(long)]
+    read_only: bool,
+    /// Refuse to create share links and signed URLs; existing shares keep working
+    #[arg(long)]
+    no_sharing: bool,
+    /// Leave a part of kiv out entirely, routes and buttons both (repeatable)
+    #[arg(long, value_enum, value_name = "FEATURE")]
+    disable: Vec<Feature>,
+    /// Only let in clients from this address or CIDR range, e.g. a VPN's 10.8.0.0/24
+    /// (repeatable)
+    #[arg(long = "allow-ip", value_name = "CIDR")]
+    allow_ips: Vec<ipfilter::Network>,
+    /// Turn away clients from this address or CIDR range, even if allowed (repeatable)
+    #[arg(long = "deny-ip", value_name = "CIDR")]
+    deny_ips: Vec<ipfilter::Network>,
+    /// Reverse proxy (address or CIDR range) whose X-Forwarded-For and X-Forwarded-Proto
+    /// are believed; clients are then told apart by the address it forwards for (repeatable)
+    #[arg(long = "trusted-proxy", value_name = "CIDR")]
+    trusted_proxies: Vec<ipfilter::Network>,
+    /// Requests per second each client address may make on average, downloads and static
+    /// files aside; past it requests are refused with 429 Too Many Requests
+    #[arg(long, value_name = "PER_SECOND")]
+    rate_limit: Option<f64>,
+    /// Requests a client may make at once before --rate-limit applies
+    #[arg(long, value_name = "COUNT", default_value_t = 50)]
+    rate_limit_burst: u32,
+    /// Password checks per minute each client address may make, for logins (0 disables
+    /// the limit); a few more are allowed at once
+    #[arg(long, value_name = "PER_MINUTE", default_value_t = 10.0)]
+    password_attempts: f64,
+    /// Content-Security-Policy for kiv's pages, replacing the built-in one ("off" sends
+    /// none); files opened in the browser keep their own sandboxing policy
+    #[arg(long, value_name = "POLICY")]
+    csp: Option<String>,
+    /// Add a source to a directive of the Content-Security-Policy, for custom static
+    /// assets (e.g. script-src=https://cdn.example.com, repeatable)
+    #[arg(long = "csp-allow", value_name = "DIRECTIVE=SOURCE")]
+    csp_allow: Vec<security::CspSource>,
+    /// Send Strict-Transport-Security with this max-age, in seconds; only when kiv is
+    /// reached over HTTPS
+    #[arg(long, value_name = "SECONDS")]
+    hsts: Option<u64>,
+    /// Append a JSON line for every login, share, download, upload and delete to this
+    /// file, apart from the server log
+    #[arg(long, value_name = "FILE")]
+    audit_log: Option<PathBuf>,
+    /// Write a JSON line for every request (method, path, status, bytes, duration, client,
+    /// user and request ID) to this file, or to standard output for "-"
+    #[arg(long, value_name = "FILE")]
+    access_log: Option<PathBuf>,
+    /// Largest upload accepted, in MiB; larger ones are refused with 413 Payload Too Large
+    #[arg(long, value_name = "MIB")]
+    max_upload_size: Option<u64>,
+    /// Largest body accepted for forms and any other request but uploads, in KiB
+    #[arg(long, value_name = "KIB", default_value_t = 2048)]
+    max_body_size: u64,
+    /// Largest request line and headers accepted, in KiB (at least 8)
+    #[arg(long, value_name = "KIB", default_value_t = 64)]
+    max_header_size: u64,
+    /// Seconds a client may take to send a request's headers, or to start its next request
+    /// on an open connection (0 disables)
+    #[arg(long, value_name = "SECONDS", default_value_t = 30)]
+    header_timeout: u64,
+    /// Seconds a request body (an upload, a form) may stall before the request is dropped
+    /// (0 disables)
+    #[arg(long, value_name = "SECONDS", default_value_t = 60)]
+    body_timeout: u64,
+    /// Seconds a request may take to be answered before it is stopped; uploads and
+    /// downloads aren't timed
+    #[arg(long, value_name = "SECONDS")]
+    request_timeout: Option<u64>,
+    /// How long a download waits in line (for its client's slots or the download queue)
+    /// before being refused
+    #[arg(long, value_name = "SECONDS", default_value_t = 120)]
+    download_queue_timeout: u64,
 }
 
 // --- State --- (remains the same)
 type SharedState = Arc<AppState>;
-type ShareMap = DashMap<Uuid, PathBuf>;
+type ShareMap = DashMap<Uuid, Share>;
+
+struct Share {
+    path: PathBuf,
+    // Served to open in the browser (PDFs, images, video) rather than as a download.
+    inline: bool,
+    // The landing page also offers a .torrent with the share as its web seed.
+    torrent: bool,
+    // Goes ahead of other downloads waiting in the download queue.
+    high_priority: bool,
+    created: DateTime<Local>,
+    // Who made it, when logged in.
+    created_by: Option<String>,
+}
 
 struct AppState {
+    // Home of kiv's own directories (trash, versions, cache, …), and with no named mounts
+    // also the single directory being served.
     root_dir: PathBuf,
+    mounts: mounts::Mounts,
     shares: ShareMap,
+    trash: trash::TrashConfig,
+    versions: versions::VersionsConfig,
+    clamd: Option<clamav::ClamdAddr>,
+    upload_types: sniff::UploadTypes,
+    drop_zone: Option<DropZone>,
+    jobs: jobs::JobRegistry,
+    preview_chunk_size: u64,
+    show_exif_gps: bool,
+    cache: cache::CacheConfig,
+    ffmpeg: PathBuf,
+    transcoder: Option<transcode::Transcoder>,
+    dir_sizes: dirsize::DirSizes,
+    show_dir_sizes: bool,
+    item_counts: itemcount::ItemCounts,
+    listings: listcache::ListingCache<(Vec<DirEntryInfo>, Vec<DirEntryInfo>)>,
+    file_hashes: checksums::FileHashes,
+    torrents: torrent::Torrents,
+    client_limits: clientlimit::ClientLimits,
+    // Accounts from `--users`; `None` serves everyone without a login.
+    users: Option<auth::Users>,
+    sessions: sessions::Sessions,
+    // Who may create links and upload, beyond their rights on the path: members of one
+    // of these roles, or anyone when empty. See `may_create`.
+    share_roles: Vec<String>,
+    upload_roles: Vec<String>,
+    // `--read-only` and the opposite of `--no-sharing`, enforced by `auth::read_only`.
+    read_only: bool,
+    sharing: bool,
+    // What `--disable` left out; see `features`.
+    features: features::Features,
+    ip_filter: ipfilter::IpFilter,
+    security_headers: security::SecurityHeaders,
+    audit: audit::AuditLog,
+    access_log: Option<accesslog::AccessLog>,
+    request_limiter: ratelimit::RateLimiter,
+    password_limiter: ratelimit::RateLimiter,
+    // Wrong `/unlock` passwords per client and per locked folder.
+    unlock_clients: ratelimit::Lockout<IpAddr>,
+    unlock_folders: ratelimit::Lockout<String>,
+    // `--request-timeout`, enforced by `limits::request_timeout`.
+    request_timeout: Option<Duration>,
+    download_queue: Arc<queue::DownloadQueue>,
+    download_stats: stats::DownloadStats,
+    favorites: favorites::Favorites,
+    quick_open: quickopen::PathIndex,
+    signer: signing::Signer,
 }
 
+struct DropZone {
+    relative_path: PathBuf,
+    full_path: PathBuf,
+}
+
+// Lines shown above a linked line, so it isn't the very first thing on the page.
+const LINE_LINK_CONTEXT: u64 = 5;
+
+// Uploads are staged here before being moved into place, so a half-received file
+// never shows up in a listing.
+const UPLOADS_DIR_NAME: &str = ".kiv-uploads";
+
+// Server-side state that outlives a restart, such as pinned favorites.
+const STATE_DIR_NAME: &str = ".kiv-state";
+
+// Directories directly under the root that kiv uses for its own bookkeeping.
+// They are never listed and cannot be browsed or served.
+const INTERNAL_DIRS: &[&str] = &[
+    trash::TRASH_DIR_NAME,
+    versions::VERSIONS_DIR_NAME,
+    UPLOADS_DIR_NAME,
+    clamav::QUARANTINE_DIR_NAME,
+    cache::CACHE_DIR_NAME,
+    STATE_DIR_NAME,
+];
+
 // --- Request Payloads --- (remains the same)
 #[derive(Deserialize, Debug)]
 struct BrowseQuery {
     path: Option<String>,
+    // `grid` shows thumbnails; anything else is the plain list.
+    view: Option<String>,
+    // Show each file's SHA-256, computed as the rows load.
+    #[serde(default)]
+    checksums: bool,
+    // `txt` asks for the plain-text listing, as `Accept: text/plain` does.
+    format: Option<String>,
+    // Only entries whose names match: a substring, or a glob such as `*.jpg`.
+    filter: Option<String>,
+    // Listing preferences to change and remember; see `prefs::PrefChanges`.
+    sort: Option<String>,
+    order: Option<String>,
+    folders: Option<String>,
+    hidden: Option<String>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -59,25 +441,237 @@ struct SharePayload {
     path: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct ShareOptions {
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    inline: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    torrent: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    priority: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SignedDownloadQuery {
+    path: String,
+    // Seconds since the epoch.
+    expires: i64,
+    sig: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct DownloadQuery {
+    // Overrides the share's own choice between opening in the browser and downloading.
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    inline: Option<bool>,
+}
+
+// Query flags as people type them into URLs: `1`, `true`, `yes` or `on`, and their
+// opposites.
+fn deserialize_flag<'de, D>(deserializer: D) -> Result<Option<bool>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let value = String::deserialize(deserializer)?;
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(Some(true)),
+        "0" | "false" | "no" | "off" | "" => Ok(Some(false)),
+        _ => Err(serde::de::Error::custom(format!("not a flag: {}", value))),
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct PathPayload {
+    path: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct FavoritePayload {
+    path: String,
+    // Listing to show afterwards; the folder holding the item by default.
+    back: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RestoreVersionPayload {
+    path: String,
+    version: String,
+}
+
 #[derive(Deserialize, Debug)]
 struct PreviewQuery {
     path: String,
 }
 
+#[derive(Deserialize, Debug)]
+struct FolderDownloadQuery {
+    path: String,
+    // `zip` (the default) or `tar.gz`.
+    format: Option<String>,
+    // Encrypts the zip. Only taken from a POSTed form, so it stays out of URLs and logs.
+    password: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RootQuery {
+    preview: Option<String>,
+    line: Option<String>,
+    lang: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TextPreviewQuery {
+    path: String,
+    // Show the page starting at this byte offset…
+    offset: Option<u64>,
+    // …or the page ending at this one (used for "previous" and "jump to end").
+    before: Option<u64>,
+    // Line or range to highlight, e.g. `42` or `42-50`; the page is chosen to contain it.
+    line: Option<String>,
+    // Highlighting language chosen by the user, overriding `detect_language`.
+    lang: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DiffQuery {
+    // The older side; with `version`, the file whose stored version is compared to it.
+    left: String,
+    right: Option<String>,
+    version: Option<String>,
+    mode: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TailQuery {
+    path: String,
+    // Byte offset to start following from; defaults to the current end of the file.
+    from: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ArchiveEntryQuery {
+    path: String,
+    entry: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct EpubQuery {
+    path: String,
+    // Index into the book's reading order; the first chapter by default.
+    chapter: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EpubResourceQuery {
+    path: String,
+    item: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct StructuredNodeQuery {
+    path: String,
+    // JSON pointer to the node whose children are listed; the document root when empty.
+    #[serde(default)]
+    pointer: String,
+    offset: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SearchQuery {
+    q: String,
+    // Folder to search under; the whole root when absent.
+    path: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct QuickOpenQuery {
+    #[serde(default)]
+    q: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct TreeQuery {
+    path: Option<String>,
+    // Levels of folders to include; deeper ones are fetched as they are expanded.
+    depth: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ImageQuery {
+    path: String,
+    // Maximum width in pixels; the original is served when absent.
+    w: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ComicQuery {
+    path: String,
+    // Zero-based page index; the first page by default.
+    page: Option<usize>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmailAttachmentQuery {
+    path: String,
+    index: usize,
+}
+
+#[derive(Deserialize, Debug)]
+struct MediaQuery {
+    path: String,
+    // Serve as an attachment instead of inline (the PDF viewer's download fallback).
+    #[serde(default)]
+    download: bool,
+}
+
 // --- Response Data --- (remains the same)
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Clone, Debug)]
 struct DirEntryInfo {
     name: String,
     path: String,
     is_dir: bool,
     size: Option<String>,
     modified: Option<String>,
+    // The same two, raw, for JSON listings.
+    size_bytes: Option<u64>,
+    modified_at: Option<DateTime<Utc>>,
+    // Set for symlinks with `--symlinks show`: where the link points, as written.
+    link_target: Option<String>,
+    // Whether it can be opened; false for links that are broken or lead outside the root.
+    openable: bool,
+    // Entries in a folder, up to `itemcount::MAX_COUNTED`.
+    item_count: Option<usize>,
+    // Permission bits, owner and group; Unix only.
+    ownership: Option<ownership::Ownership>,
+    // Note from the folder's `.kivmeta.toml`.
+    annotation: Option<String>,
+    // Its metadata couldn't be read for lack of permission; only the name is known.
+    access_denied: bool,
 }
 
 // --- Main Application --- (remains the same, including router setup)
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
+    if args.hash_password {
+        let mut password = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut password) {
+            eprintln!("Error: Failed to read the password: {}", e);
+            std::process::exit(1);
+        }
+        match auth::hash_password(password.trim_end_matches(['\r', '\n'])) {
+            Ok(hash) => println!("{}", hash),
+            Err(e) => {
+                eprintln!("Error: Failed to hash the password: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if args.generate_signing_key {
+        println!("{}", signing::generate_key());
+        return;
+    }
 
     let subscriber = FmtSubscriber::builder()
         .with_max_level(Level::INFO)
@@ -113,28 +707,610 @@ async fn main() {
         std::process::exit(1);
     }
 
-    info!("Serving files from: {}", absolute_root_dir.display());
+    // A key file kiv doesn't manage, given or passed by systemd; without one kiv keeps its
+    // own in the state directory.
+    let signing_key_file = args.signing_key_file.clone().or_else(|| {
+        secrets::credentials_dir()
+            .map(|dir| dir.join(secrets::SIGNING_KEY_CREDENTIAL))
+            .filter(|file| file.is_file())
+    });
+    if args.rotate_signing_key {
+        let key_file = signing_key_file.clone().unwrap_or_else(|| {
+            absolute_root_dir
+                .join(STATE_DIR_NAME)
+                .join(signing::SECRET_FILE_NAME)
+        });
+        match signing::rotate(&key_file).await {
+            Ok(message) => println!("{}", message),
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let signer = match &signing_key_file {
+        Some(file) => match signing::Signer::load(file).await {
+            Ok(signer) => signer,
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => {
+            // Read-only mode doesn't create the key, only reads one left by earlier runs.
+            signing::Signer::load_or_create(
+                absolute_root_dir
+                    .join(STATE_DIR_NAME)
+                    .join(signing::SECRET_FILE_NAME),
+                !args.read_only,
+            )
+            .await
+        }
+    };
+
+    let mounts = if args.mounts.is_empty() {
+        mounts::Mounts::single(absolute_root_dir.clone())
+    } else {
+        match resolve_mounts(&args.mounts).await {
+            Ok(mounts) => mounts::Mounts::named(mounts),
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        }
+    };
+    let mount_dirs: Vec<PathBuf> = mounts.list().iter().map(|m| m.dir.clone()).collect();
+
+    match kivignore::IgnoreRules::new(&mount_dirs, &args.ignore) {
+        Ok(ignored) => policy::init(args.hidden, args.symlinks, ignored),
+        Err(message) => {
+            error!("{} Exiting.", message);
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+    }
+    bandwidth::init(args.bandwidth_limit.map(|kib| kib * 1024));
+    let stream_buffer = serve::init_stream_buffer(args.stream_buffer_size);
+    if stream_buffer != args.stream_buffer_size * 1024 {
+        info!(
+            "Stream buffer size adjusted to {}.",
+            format_size(stream_buffer as u64, BINARY)
+        );
+    }
+    if let Some(limit) = bandwidth::get().limit() {
+        info!(
+            "Downloads are limited to {}/s in total.",
+            format_size(limit, BINARY)
+        );
+    }
+
+    let users = match &args.users {
+        Some(file) => match auth::Users::load(file, args.public).await {
+            Ok(users) => {
+                info!(
+                    "Logins required; {} accounts loaded from {}",
+                    users.len(),
+                    file.display()
+                );
+                Some(users)
+            }
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    match &args.folder_passwords {
+        Some(file) => match locks::FolderLocks::load(file).await {
+            Ok(folder_locks) => {
+                info!(
+                    "{} folders locked by passwords from {}",
+                    folder_locks.len(),
+                    file.display()
+                );
+                locks::init(Some(folder_locks));
+            }
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => locks::init(None),
+    }
+
+    if users.is_none()
+        && (args.public || !args.share_roles.is_empty() || !args.upload_roles.is_empty())
+    {
+        error!("--public, --share-role and --upload-role need --users. Exiting.");
+        eprintln!("Error: --public, --share-role and --upload-role need --users.");
+        std::process::exit(1);
+    }
+    if args.read_only && args.drop_zone.is_some() {
+        error!("--read-only and --drop-zone can't be used together. Exiting.");
+        eprintln!("Error: --read-only and --drop-zone can't be used together.");
+        std::process::exit(1);
+    }
+    // The drop zone is for visitors who don't log in, and has no login page.
+    if users.is_some() && args.drop_zone.is_some() {
+        error!("--users and --drop-zone can't be used together. Exiting.");
+        eprintln!("Error: --users and --drop-zone can't be used together.");
+        std::process::exit(1);
+    }
+    if args.disable.contains(&Feature::Upload) && args.drop_zone.is_some() {
+        error!("--disable upload and --drop-zone can't be used together. Exiting.");
+        eprintln!("Error: --disable upload and --drop-zone can't be used together.");
+        std::process::exit(1);
+    }
+    let max_header_size = (args.max_header_size as usize).saturating_mul(1024);
+    if max_header_size < limits::MIN_HEADER_SIZE {
+        error!("--max-header-size must be at least 8 KiB. Exiting.");
+        eprintln!("Error: --max-header-size must be at least 8 KiB.");
+        std::process::exit(1);
+    }
+    let csp = match args.csp.as_deref() {
+        Some("off") => None,
+        Some(policy) => Some(security::extend_csp(policy, &args.csp_allow)),
+        None => Some(security::extend_csp(security::DEFAULT_CSP, &args.csp_allow)),
+    };
+    let security_headers = match security::SecurityHeaders::new(csp.as_deref(), args.hsts) {
+        Ok(security_headers) => security_headers,
+        Err(message) => {
+            error!("{} Exiting.", message);
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+    };
+
+    let audit = match audit::AuditLog::open(args.audit_log.clone()).await {
+        Ok(audit) => audit,
+        Err(message) => {
+            error!("{} Exiting.", message);
+            eprintln!("Error: {}", message);
+            std::process::exit(1);
+        }
+    };
+    if let Some(file) = audit.file() {
+        info!("Writing the audit log to {}", file.display());
+    }
+    let access_log = match &args.access_log {
+        Some(target) => match accesslog::AccessLog::open(target).await {
+            Ok(access_log) => Some(access_log),
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Checked as kiv's own setup, not as any user.
+    let drop_zone = match &args.drop_zone {
+        Some(dir) => match auth::system(prepare_drop_zone(&mounts, dir)).await {
+            Ok(drop_zone) => Some(drop_zone),
+            Err(message) => {
+                error!("{} Exiting.", message);
+                eprintln!("Error: {}", message);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if mounts.is_named() {
+        for mount in mounts.list() {
+            info!("Serving {} from: {}", mount.name, mount.dir.display());
+        }
+    } else {
+        info!("Serving files from: {}", absolute_root_dir.display());
+    }
     info!("Listening on: {}", args.bind_addr);
+    if let Some(clamd) = &args.clamd {
+        info!("Scanning uploads with clamd at {}", clamd);
+    }
+    if args.read_only {
+        info!("Read-only mode: uploads, deletes and other changes are refused");
+    }
+    if args.no_sharing {
+        info!("Sharing is disabled: no new share links or signed URLs");
+    }
+    for network in &args.allow_ips {
+        info!("Allowing clients from {}", network);
+    }
+    for network in &args.deny_ips {
+        info!("Denying clients from {}", network);
+    }
+    for network in &args.trusted_proxies {
+        info!("Trusting X-Forwarded-For from {}", network);
+    }
+    if let Some(rate) = args.rate_limit {
+        info!(
+            "Limiting each client to {} requests/s (bursts of {})",
+            rate, args.rate_limit_burst
+        );
+    }
 
     let shared_state = Arc::new(AppState {
         root_dir: absolute_root_dir.clone(),
+        mounts,
         shares: DashMap::new(),
+        trash: trash::TrashConfig {
+            dir: absolute_root_dir.join(trash::TRASH_DIR_NAME),
+            retention: (args.trash_retention_days > 0)
+                .then(|| Duration::from_secs(args.trash_retention_days * 24 * 60 * 60)),
+            max_size: args.trash_max_size.map(|mib| mib * 1024 * 1024),
+            purge_interval: Duration::from_secs(args.trash_purge_interval.max(1) * 60),
+        },
+        versions: versions::VersionsConfig {
+            dir: absolute_root_dir.join(versions::VERSIONS_DIR_NAME),
+            keep: args.keep_versions,
+        },
+        clamd: args.clamd.clone(),
+        upload_types: sniff::UploadTypes::new(
+            &args.deny_upload_type,
+            !args.skip_upload_type_check,
+            args.fix_upload_extensions,
+        ),
+        drop_zone,
+        jobs: jobs::JobRegistry::new(args.max_jobs),
+        preview_chunk_size: args.preview_chunk_size.max(1) * 1024,
+        show_exif_gps: !args.hide_exif_gps,
+        cache: cache::CacheConfig {
+            dir: absolute_root_dir.join(cache::CACHE_DIR_NAME),
+            max_size: args.cache_max_size * 1024 * 1024,
+        },
+        ffmpeg: args.ffmpeg.clone(),
+        transcoder: args.transcode.then(|| {
+            transcode::Transcoder::new(
+                &absolute_root_dir.join(cache::CACHE_DIR_NAME),
+                args.ffmpeg.clone(),
+                args.transcode_max_sessions,
+            )
+        }),
+        dir_sizes: dirsize::DirSizes::new(),
+        show_dir_sizes: args.dir_sizes,
+        item_counts: itemcount::ItemCounts::new(),
+        listings: listcache::ListingCache::new(),
+        file_hashes: checksums::FileHashes::new(),
+        torrents: torrent::Torrents::new(),
+        download_stats: stats::DownloadStats::new(),
+        users,
+        sessions: sessions::Sessions::new(Duration::from_secs(
+            args.session_idle_minutes.max(1) * 60,
+        )),
+        share_roles: args.share_roles,
+        upload_roles: args.upload_roles,
+        read_only: args.read_only,
+        features: features::Features::new(&args.disable),
+        sharing: !args.no_sharing,
+        ip_filter: ipfilter::IpFilter::new(
+            args.allow_ips.clone(),
+            args.deny_ips.clone(),
+            args.trusted_proxies.clone(),
+        ),
+        security_headers,
+        audit,
+        access_log,
+        request_limiter: ratelimit::RateLimiter::new(args.rate_limit, args.rate_limit_burst),
+        request_timeout: args.request_timeout.map(Duration::from_secs),
+        password_limiter: ratelimit::RateLimiter::new(
+            Some(args.password_attempts / 60.0),
+            ratelimit::PASSWORD_ATTEMPTS_BURST,
+        ),
+        unlock_clients: ratelimit::Lockout::new(ratelimit::UNLOCK_CLIENT_LOCKOUT),
+        unlock_folders: ratelimit::Lockout::new(ratelimit::UNLOCK_FOLDER_LOCKOUT),
+        client_limits: clientlimit::ClientLimits::new(
+            args.max_downloads_per_client,
+            Duration::from_secs(args.download_queue_timeout),
+        ),
+        download_queue: Arc::new(queue::DownloadQueue::new(
+            args.download_queue,
+            Duration::from_secs(args.download_queue_timeout),
+        )),
+        favorites: favorites::Favorites::load(
+            absolute_root_dir
+                .join(STATE_DIR_NAME)
+                .join(favorites::FAVORITES_FILE_NAME),
+        )
+        .await,
+        quick_open: quickopen::PathIndex::new(),
+        signer,
     });
 
+    // Housekeeping is kiv's own work, not done on behalf of any request.
+    tokio::spawn(auth::system(trash::purge_loop(shared_state.clone())));
+    tokio::spawn(auth::system(cache::prune_loop(shared_state.clone())));
+    tokio::spawn(auth::system(transcode::cleanup_loop(shared_state.clone())));
+    if shared_state.features.on(Feature::Search) {
+        tokio::spawn(auth::system(quickopen::maintain_loop(shared_state.clone())));
+    }
+
+    // HEAD is answered by every GET route with the same headers and no body, which is what
+    // download managers and link checkers use to probe a share.
+    let compression = serve::compression_layer();
+    // On every route that streams a file's bytes, inline or not, so none of them gets
+    // around the download limits.
+    let client_limit =
+        axum::middleware::from_fn_with_state(shared_state.clone(), clientlimit::limit);
+    let track_downloads = axum::middleware::from_fn_with_state(shared_state.clone(), stats::track);
+    let download_queue = axum::middleware::from_fn_with_state(shared_state.clone(), queue::limit);
     let cors = CorsLayer::new()
-        .allow_methods([http::Method::GET, http::Method::POST])
+        .allow_methods([http::Method::GET, http::Method::HEAD, http::Method::POST])
+        // Conditional and resumed downloads from other origins.
+        .allow_headers([
+            header::RANGE,
+            header::IF_RANGE,
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+        ])
+        .expose_headers([
+            header::CONTENT_LENGTH,
+            header::CONTENT_DISPOSITION,
+            header::CONTENT_RANGE,
+            header::ACCEPT_RANGES,
+            header::ETAG,
+            header::LAST_MODIFIED,
+            serve::REPR_DIGEST,
+            serve::X_CHECKSUM_SHA256,
+            accesslog::REQUEST_ID,
+        ])
         .allow_origin(Any);
 
-    let app = Router::new()
-        .route("/", get(root_handler))
-        .route("/browse", get(browse_handler))
-        .route("/preview", get(preview_handler))
-        .route("/image-preview", get(image_preview_handler))
-        .route("/direct-download-image", get(direct_image_handler))
-        .route("/share", post(share_handler)) // This handler is modified
-        .route("/share/{uuid}", get(share_landing_handler))
-        .route("/direct-download/{uuid}", get(download_handler))
+    // Uploads are exempt from `--max-body-size`, which limits everything else read whole.
+    let upload = post(upload_handler).layer(DefaultBodyLimit::disable());
+    let upload = match args.max_upload_size {
+        Some(mib) => upload.layer(RequestBodyLimitLayer::new(
+            (mib as usize).saturating_mul(1024 * 1024),
+        )),
+        None => upload,
+    };
+
+    // In drop-zone mode only the upload page and endpoint are mounted, so there is
+    // nothing else for anonymous visitors to reach.
+    let app = if let Some(drop_zone) = &shared_state.drop_zone {
+        info!(
+            "Drop-zone mode: accepting uploads into {} only",
+            drop_zone.full_path.display()
+        );
+        Router::new()
+            .route("/", get(drop_zone_handler))
+            .route("/upload", upload)
+    } else {
+        let router = if args.autoindex {
+            Router::new().route("/", get(|| async { Redirect::to("/index/") }))
+        } else {
+            Router::new().route("/", get(root_handler))
+        };
+        let mut router = router
+            .route("/index", get(|| async { Redirect::permanent("/index/") }))
+            .route("/index/", get(autoindex_root_handler))
+            .route("/index/{*path}", get(autoindex_handler))
+            .route("/browse", get(browse_negotiated_handler))
+            .route("/api/v1/browse", get(browse_json_handler))
+            .route("/browse/events", get(browse_events_handler))
+            .route("/tree", get(tree_handler))
+            .route("/dir-size", get(dir_size_handler))
+            .route("/usage", get(usage_handler))
+            .route("/hash", get(hash_handler))
+            .route(
+                "/raw",
+                get(raw_handler)
+                    .layer(compression.clone())
+                    .layer(axum::middleware::map_response(serve::tag_encoded_response))
+                    .layer(track_downloads.clone())
+                    .layer(download_queue.clone())
+                    .layer(client_limit.clone()),
+            )
+            .route(
+                "/download-folder",
+                get(download_folder_handler)
+                    .post(encrypted_folder_handler)
+                    .layer(track_downloads.clone())
+                    .layer(download_queue.clone())
+                    .layer(client_limit.clone()),
+            )
+            .route(
+                "/download-selection",
+                post(download_selection_handler)
+                    .layer(track_downloads.clone())
+                    .layer(download_queue.clone())
+                    .layer(client_limit.clone()),
+            )
+            .route("/favorites/add", post(add_favorite_handler))
+            .route("/favorites/remove", post(remove_favorite_handler))
+            .route("/versions", get(versions_handler))
+            .route("/versions/restore", post(restore_version_handler))
+            .route("/copy", post(copy_handler))
+            .route("/diff", get(diff_handler))
+            .route("/diff/pick", get(diff_pick_handler))
+            .route("/checksums", get(checksum_manifest_handler))
+            .route("/checksums/generate", post(generate_checksums_handler))
+            .route("/checksums/verify", post(verify_checksums_handler))
+            .route("/jobs", get(jobs_handler))
+            .route("/stats", get(stats_handler))
+            .route("/metrics", get(metrics_handler))
+            .route("/login", get(login_page_handler).post(login_handler))
+            .route("/logout", post(logout_handler))
+            .route("/unlock", post(unlock_handler))
+            .route("/admin", get(admin_handler))
+            .route("/admin/transfers", get(admin_transfers_handler))
+            .route("/admin/audit", get(admin_audit_handler))
+            .route("/admin/users/reload", post(admin_reload_users_handler))
+            .route("/jobs/{id}", get(job_status_handler))
+            .route("/jobs/{id}/cancel", post(cancel_job_handler));
+
+        // Subsystems turned off with `--disable` aren't mounted at all.
+        let features = &shared_state.features;
+        if features.on(Feature::Preview) {
+            router = router
+                .route(
+                    "/preview",
+                    get(preview_handler)
+                        .layer(compression.clone())
+                        .layer(axum::middleware::map_response(serve::tag_encoded_response)),
+                )
+                .route("/image-preview", get(image_preview_handler))
+                .route(
+                    "/direct-download-image",
+                    get(direct_image_handler)
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route("/image", get(image_handler))
+                .route("/poster", get(poster_handler))
+                .route("/video-preview", get(video_preview_handler))
+                .route("/audio-preview", get(audio_preview_handler))
+                .route("/pdf-preview", get(pdf_preview_handler))
+                .route("/structured-preview", get(structured_preview_handler))
+                .route("/structured-node", get(structured_node_handler))
+                .route("/tail", get(tail_handler))
+                .route("/tail/events", get(tail_events_handler))
+                .route("/archive-preview", get(archive_preview_handler))
+                .route("/document-preview", get(document_preview_handler))
+                .route("/font-preview", get(font_preview_handler))
+                .route("/email-preview", get(email_preview_handler))
+                .route("/epub-preview", get(epub_preview_handler))
+                .route(
+                    "/epub-resource",
+                    get(epub_resource_handler)
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route("/gallery", get(gallery_handler))
+                .route("/comic-preview", get(comic_preview_handler))
+                .route(
+                    "/comic-page",
+                    get(comic_page_handler)
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route(
+                    "/email-attachment",
+                    get(email_attachment_handler)
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route(
+                    "/archive-entry",
+                    get(archive_entry_handler)
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route(
+                    "/media",
+                    get(media_handler)
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route("/subtitles", get(subtitles_handler))
+                .route("/hls/start", get(hls_start_handler))
+                .route("/hls/{session}/{file}", get(hls_file_handler));
+            #[cfg(feature = "data-preview")]
+            {
+                router = router.route("/data-preview", get(data_preview_handler));
+            }
+        }
+        if features.on(Feature::Search) {
+            router = router
+                .route("/search", get(search_handler))
+                .route("/quickopen", get(quick_open_handler));
+        }
+        if features.on(Feature::Share) {
+            router = router
+                .route("/share", post(share_handler)) // This handler is modified
+                .route("/share/{uuid}", get(share_landing_handler))
+                .route("/share/{uuid}/poster", get(share_poster_handler))
+                .route("/share/{uuid}/sha256", get(share_sha256_handler))
+                .route("/share/{uuid}/torrent", get(share_torrent_handler))
+                .route("/share/{uuid}/queue", get(share_queue_handler))
+                .route("/admin/shares/revoke", post(admin_revoke_share_handler))
+                .route("/sign", post(sign_handler))
+                .route(
+                    "/signed",
+                    get(signed_download_handler)
+                        .layer(compression.clone())
+                        .layer(axum::middleware::map_response(serve::tag_encoded_response))
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                )
+                .route(
+                    "/direct-download/{uuid}",
+                    get(download_handler)
+                        .layer(compression.clone())
+                        .layer(axum::middleware::map_response(serve::tag_encoded_response))
+                        .layer(track_downloads.clone())
+                        .layer(download_queue.clone())
+                        .layer(client_limit.clone()),
+                );
+        }
+        if features.on(Feature::Upload) {
+            router = router.route("/upload", upload);
+        }
+        if features.on(Feature::Delete) {
+            router = router
+                .route("/trash", post(trash_handler))
+                .route("/delete", post(delete_handler));
+        }
+        router
+    };
+
+    let app = app
         .nest_service("/static", ServeDir::new("static"))
+        .layer(DefaultBodyLimit::max(
+            (args.max_body_size as usize).saturating_mul(1024),
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            limits::request_timeout,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            auth::read_only,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            audit::track,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            locks::scope,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            auth::require,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            ratelimit::limit,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            ipfilter::enforce,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            security::add_headers,
+        ))
+        .layer(axum::middleware::from_fn_with_state(
+            shared_state.clone(),
+            accesslog::log,
+        ))
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         .with_state(shared_state);
@@ -147,15 +1323,47 @@ async fn main() {
             std::process::exit(1);
         }
     };
-    if let Err(e) = axum::serve(listener, app).await {
-        error!("Server error: {}", e);
-        eprintln!("Server error: {}", e);
-        std::process::exit(1);
-    }
+    let seconds = |seconds: u64| (seconds > 0).then(|| Duration::from_secs(seconds));
+    limits::serve(
+        listener,
+        app,
+        limits::ConnectionLimits {
+            header_timeout: seconds(args.header_timeout),
+            max_header_size,
+            body_idle_timeout: seconds(args.body_timeout),
+        },
+    )
+    .await;
 }
 
 // --- root_handler --- (remains the same)
-async fn root_handler() -> Markup {
+async fn root_handler(State(state): State<SharedState>, Query(query): Query<RootQuery>) -> Markup {
+    // `/?preview=<path>&line=<n>` opens straight into a file preview (line deep links).
+    let initial_url = match query
+        .preview
+        .as_ref()
+        .filter(|_| state.features.on(Feature::Preview))
+    {
+        Some(path) => {
+            let mut url = format!("/preview?path={}", urlencoding::encode(path));
+            if let Some(line) = &query.line {
+                url.push_str(&format!("&line={}", urlencoding::encode(line)));
+            }
+            if let Some(lang) = &query.lang {
+                url.push_str(&format!("&lang={}", urlencoding::encode(lang)));
+            }
+            url
+        }
+        // Jailed users start in their own folder.
+        None => match auth::home() {
+            Some(home) => format!("/browse?path={}", urlencoding::encode(&home)),
+            None => "/browse?path=.".to_string(),
+        },
+    };
+    let can_share = state.sharing
+        && state.features.on(Feature::Share)
+        && may_create(&state, &state.share_roles);
+
     html! {
         (DOCTYPE)
         html lang="en" {
@@ -167,10 +1375,22 @@ async fn root_handler() -> Markup {
                 link rel="stylesheet" href="https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.11.1/styles/default.min.css";
                 script src="/static/htmx.min.js" {}
                 script src="https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.11.1/highlight.min.js" {}
+                // Languages offered in the preview's language picker that the common bundle lacks.
+                @for language in HIGHLIGHT_EXTRA_LANGUAGES {
+                    script src=(format!("https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.11.1/languages/{}.min.js", language)) {}
+                }
                 script { (PreEscaped("hljs.highlightAll();")) }
                 script src="/static/context_menu.js" defer {}
                 script src="/static/copy_link.js" defer {}
                 script src="/static/image_hover.js" defer {}
+                script src="/static/log_tail.js" defer {}
+                script src="/static/line_links.js" defer {}
+                script src="/static/gallery.js" defer {}
+                script src="/static/hls_player.js" defer {}
+                script src="/static/tree.js" defer {}
+                script src="/static/view_toggle.js" defer {}
+                script src="/static/live_listing.js" defer {}
+                script src="/static/quick_open.js" defer {}
                 script {
                     (PreEscaped("
                         // Highlight syntax when HTMX swaps content
@@ -187,19 +1407,73 @@ async fn root_handler() -> Markup {
                 }
             }
             body {
-                h1 { "File Browser" }
-                div #file-browser
-                    hx-get="/browse?path=."
-                    hx-trigger="load"
-                    hx-target="#file-browser"
-                    hx-swap="innerHTML" {
-                    div #current-path-container { "Loading path..." }
-                    div #file-list-container { "Loading files..." }
+                h1 {
+                    "File Browser"
+                    @if state.features.on(Feature::Search) {
+                        button #quick-open-button type="button" title="Go to a file by name (Ctrl+P)" { "🔎 Go to file" }
+                    }
+                }
+                @if state.features.on(Feature::Search) {
+                    div #quick-open {
+                        div class="quick-open-dialog" {
+                            input #quick-open-input type="search" name="q" placeholder="Type part of a path…" autocomplete="off"
+                                hx-get="/quickopen"
+                                hx-trigger="input changed delay:100ms"
+                                hx-target="#quick-open-results"
+                                hx-swap="innerHTML";
+                            div #quick-open-results {}
+                        }
+                    }
+                }
+                div #main-layout {
+                    nav #tree-sidebar {
+                        a class="tree-link tree-root" data-path="."
+                          hx-get="/browse?path=."
+                          hx-target="#file-browser"
+                          hx-swap="innerHTML" { "🏠 /" }
+                        ul #dir-tree hx-get="/tree?path=.&depth=1" hx-trigger="load" hx-swap="innerHTML" {
+                            li class="tree-loading" { "Loading…" }
+                        }
+                    }
+                    div #file-browser
+                        hx-get=(initial_url)
+                        hx-trigger="load"
+                        hx-target="#file-browser"
+                        hx-swap="innerHTML" {
+                        div #current-path-container { "Loading path..." }
+                        div #file-list-container { "Loading files..." }
+                    }
+                }
+                div #jobs-panel {
+                    button #show-jobs
+                        hx-get="/jobs"
+                        hx-target="#jobs-area"
+                        hx-swap="innerHTML"
+                        { "🧰 Background Jobs" }
+                    div #jobs-area {}
+                    button #show-stats
+                        hx-get="/stats"
+                        hx-target="#stats-area"
+                        hx-swap="innerHTML"
+                        { "📈 Stats" }
+                    div #stats-area {}
+                    @if auth::is_admin() {
+                        a #show-admin href="/admin" { "🛠️ Admin" }
+                    }
+                    @if let Some(user) = auth::current_user() {
+                        form #logout-form method="post" action="/logout" {
+                            span { "👤 " (user) }
+                            button type="submit" { "Log Out" }
+                        }
+                    } @else if state.users.is_some() {
+                        a #show-login href="/login" { "🔐 Log In" }
+                    }
                 }
                 div #share-result-area {}
                 div #context-menu {
                     ul {
-                        li #context-share-target {
+                        // Kept when sharing is off, just hidden: context_menu.js expects it.
+                        li #context-share-target hidden[!can_share] {
                             span #context-share-button-wrapper {
                                 button #context-share
                                     hx-post="/share"
@@ -209,732 +1483,6103 @@ async fn root_handler() -> Markup {
                                     { "🔗 Share File" }
                            }
                         }
-                    }
-                }
-            }
-        }
-    }
-}
-
-// --- browse_handler --- (remains the same)
-async fn browse_handler(
-    State(state): State<SharedState>,
-    Query(query): Query<BrowseQuery>,
-) -> Result<Markup, Response> {
-    let requested_path_str = query.path.unwrap_or_else(|| ".".to_string());
-    let sanitized_req_path = sanitize_path(&requested_path_str);
-    let full_path = resolve_and_validate_path(&state.root_dir, &sanitized_req_path)?;
-
-    if !full_path.is_dir() {
-        error!("Browse attempt on non-directory: {}", full_path.display());
-        return Err(error_response(
-            StatusCode::BAD_REQUEST,
-            "Requested path is not a directory.",
-        ));
-    }
-
-    let mut entries = match fs::read_dir(&full_path).await {
-        Ok(reader) => reader,
-        Err(e) => {
-            error!("Failed to read directory {}: {}", full_path.display(), e);
-            return Err(error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Error reading directory contents.",
-            ));
+                        @if can_share {
+                            li #context-share-inline-target {
+                                button #context-share-inline .context-action data-files-only
+                                    hx-post="/share?inline=1"
+                                    hx-trigger="click"
+                                    hx-target="#share-result-area"
+                                    hx-swap="innerHTML"
+                                    title="The link opens the file in the browser instead of downloading it"
+                                    { "🌐 Share to Open in Browser" }
+                            }
+                            li #context-share-torrent-target {
+                                button #context-share-torrent .context-action data-files-only
+                                    hx-post="/share?torrent=1"
+                                    hx-trigger="click"
+                                    hx-target="#share-result-area"
+                                    hx-swap="innerHTML"
+                                    title="The landing page also offers a .torrent, for fetching large files over BitTorrent"
+                                    { "🧲 Share as Torrent" }
+                            }
+                        }
+                        @if can_share && state.download_queue.enabled() {
+                            li #context-share-priority-target {
+                                button #context-share-priority .context-action data-files-only
+                                    hx-post="/share?priority=1"
+                                    hx-trigger="click"
+                                    hx-target="#share-result-area"
+                                    hx-swap="innerHTML"
+                                    title="Downloads through this link go ahead of others waiting in the download queue"
+                                    { "⚡ Share with Priority" }
+                            }
+                        }
+                        li #context-versions-target {
+                            button #context-versions .context-action data-files-only
+                                hx-get="/versions"
+                                hx-trigger="click"
+                                hx-target="#file-browser"
+                                hx-swap="innerHTML"
+                                { "🕘 Versions" }
+                        }
+                        li #context-compare-target {
+                            button #context-compare .context-action data-files-only
+                                hx-get="/diff/pick"
+                                hx-trigger="click"
+                                hx-target="#file-browser"
+                                hx-swap="innerHTML"
+                                { "⚖️ Compare…" }
+                        }
+                        @if !state.read_only {
+                            li #context-pin-target {
+                                button #context-pin .context-action data-unpinned-only
+                                    hx-post="/favorites/add"
+                                    hx-trigger="click"
+                                    hx-target="#file-browser"
+                                    hx-swap="innerHTML"
+                                    { "⭐ Add to Favorites" }
+                            }
+                            li #context-unpin-target {
+                                button #context-unpin .context-action data-pinned-only
+                                    hx-post="/favorites/remove"
+                                    hx-trigger="click"
+                                    hx-target="#file-browser"
+                                    hx-swap="innerHTML"
+                                    { "☆ Remove from Favorites" }
+                            }
+                            li #context-copy-target {
+                                button #context-copy .context-action
+                                    hx-post="/copy"
+                                    hx-trigger="click"
+                                    hx-target="#jobs-area"
+                                    hx-swap="afterbegin"
+                                    { "📋 Duplicate" }
+                            }
+                            li #context-checksums-target {
+                                button #context-checksums .context-action data-dirs-only
+                                    hx-post="/checksums/generate"
+                                    hx-trigger="click"
+                                    hx-target="#jobs-area"
+                                    hx-swap="afterbegin"
+                                    { "🔐 Generate SHA256SUMS" }
+                            }
+                        }
+                        li #context-verify-target {
+                            button #context-verify .context-action data-dirs-only
+                                hx-post="/checksums/verify"
+                                hx-trigger="click"
+                                hx-target="#jobs-area"
+                                hx-swap="afterbegin"
+                                { "✅ Verify SHA256SUMS" }
+                        }
+                        @if !state.read_only && state.features.on(Feature::Delete) {
+                            li #context-trash-target {
+                                button #context-trash .context-action
+                                    hx-post="/trash"
+                                    hx-trigger="click"
+                                    hx-target="#file-browser"
+                                    hx-swap="innerHTML"
+                                    hx-confirm="Move this item to the trash?"
+                                    { "🗑️ Move to Trash" }
+                            }
+                            li #context-delete-target {
+                                button #context-delete .context-action
+                                    hx-post="/delete"
+                                    hx-trigger="click"
+                                    hx-target="#jobs-area"
+                                    hx-swap="afterbegin"
+                                    hx-confirm="Permanently delete this item? This cannot be undone."
+                                    { "❌ Delete Permanently" }
+                            }
+                        }
+                    }
+                }
+            }
         }
-    };
-
-    let mut dir_items = Vec::new();
-    let mut file_items = Vec::new();
+    }
+}
 
-    while let Ok(Some(entry)) = entries.next_entry().await {
-        let entry_path = entry.path();
-        let name = match entry.file_name().into_string() {
-            Ok(n) => n,
-            Err(_) => {
-                error!(
-                    "Skipping entry with non-UTF8 filename in {}",
-                    full_path.display()
-                );
-                continue;
+// --- drop_zone_handler ---
+async fn drop_zone_handler() -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                title { "Upload Files" }
+                link rel="stylesheet" href="/static/styles.css";
+                script src="/static/htmx.min.js" {}
             }
-        };
-
-        let relative_path = entry_path
-            .strip_prefix(&state.root_dir)
-            .unwrap()
-            .to_string_lossy()
-            .replace('\\', "/");
-
-        match entry.metadata().await {
-            Ok(metadata) => {
-                let is_dir = metadata.is_dir();
-                let (size, modified) = get_metadata_strings(&metadata);
-
-                let item = DirEntryInfo {
-                    name,
-                    path: relative_path,
-                    is_dir,
-                    size,
-                    modified,
-                };
-
-                if is_dir {
-                    dir_items.push(item);
-                } else {
-                    file_items.push(item);
+            body {
+                div class="download-card drop-zone" {
+                    div class="file-header" {
+                        div class="file-icon" { "📥" }
+                        div class="file-title" { h1 { "Upload Files" } }
+                    }
+                    form #drop-zone-form
+                        hx-post="/upload"
+                        hx-encoding="multipart/form-data"
+                        hx-target="#drop-zone-result"
+                        hx-swap="innerHTML" {
+                        input type="file" name="file" multiple required;
+                        button type="submit" class="download-button" { "Upload" }
+                    }
+                    div #drop-zone-result {}
+                    div class="footer" {
+                        "Files you upload here are delivered privately. You will not be able to see other submissions."
+                    }
                 }
             }
-            Err(e) => {
-                error!("Failed to get metadata for {}: {}", entry_path.display(), e);
-                continue;
-            }
         }
     }
+}
 
-    dir_items.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
-    file_items.sort_by(|a, b| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+// --- login_handler ---
+#[derive(Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
 
-    let current_display_path = if sanitized_req_path == Path::new(".") {
-        "/".to_string()
-    } else {
-        format!(
-            "/{}",
-            sanitized_req_path.to_string_lossy().replace('\\', "/")
-        )
-    };
+#[derive(Deserialize)]
+struct LoginForm {
+    user: String,
+    password: String,
+    next: Option<String>,
+}
 
-    Ok(html! {
-        div #current-path-container {
-            div #current-path { "Current: " (current_display_path) }
+// Only paths on this server are returned to after logging in.
+fn login_next(next: Option<&str>) -> &str {
+    match next {
+        Some(next)
+            if next.starts_with('/') && !next.starts_with("//") && !next.starts_with("/\\") =>
+        {
+            next
         }
-        div #file-list-container {
-            ul #file-list {
-                @if sanitized_req_path != Path::new(".") {
-                    @let parent_rel_path = sanitized_req_path.parent().map(|p| p.to_string_lossy().replace('\\', "/")).unwrap_or_else(|| ".".to_string());
-                    @let parent_url_encoded = urlencoding::encode(&parent_rel_path);
-                    @let hx_get_value_up = format!("/browse?path={}", parent_url_encoded);
-                    li hx-get=(hx_get_value_up) hx-target="#file-browser" hx-swap="innerHTML" style="cursor: pointer;" {
-                        span class="icon" { "⬆️" }
-                        span { ".." }
+        _ => "/",
+    }
+}
+
+// Cookies are marked Secure when the server is known to be reached over HTTPS.
+fn secure_cookies(state: &AppState, ForwardedHttps(https): ForwardedHttps) -> bool {
+    state.security_headers.hsts() || https
+}
+
+fn login_page(next: &str, user: &str, error: Option<&str>) -> Markup {
+    html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                title { "Log In" }
+                link rel="stylesheet" href="/static/styles.css";
+            }
+            body {
+                div class="download-card login" {
+                    div class="file-header" {
+                        div class="file-icon" { "🔐" }
+                        div class="file-title" { h1 { "Log In" } }
                     }
-                }
-                @for item in &dir_items {
-                    @let path_url_encoded = urlencoding::encode(&item.path);
-                    @let hx_get_value_dir = format!("/browse?path={}", path_url_encoded);
-                    li data-path=(item.path) data-is-dir="true" hx-get=(hx_get_value_dir) hx-target="#file-browser" hx-swap="innerHTML" style="cursor: pointer;" {
-                       div {
-                           span class="icon" { "📁" }
-                           span { (item.name) }
-                        }
-                       div class="file-info" { (item.modified.as_deref().unwrap_or("")) }
-                   }
-                }
-                @for item in &file_items {
-                    @let item_id_base = item.path.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
-                    @let li_id = format!("file-item-{}", item_id_base);
-                    @let placeholder_id = format!("share-placeholder-{}", item_id_base);
-                    @let full_file_path = state.root_dir.join(&item.path);
-                    @let is_previewable = is_previewable_file(&full_file_path);
-
-                    @if is_previewable {
-                        @let encoded_path = urlencoding::encode(&item.path);
-                        @let is_image = is_image_file(&full_file_path);
-                        @let preview_url = if is_image {
-                            format!("/image-preview?path={}", encoded_path)
-                        } else {
-                            format!("/preview?path={}", encoded_path)
-                        };
-                        @if is_image {
-                            li #(li_id) data-path=(item.path) data-is-dir="false" data-image-url=(format!("/direct-download-image?path={}", encoded_path))
-                               hx-get=(preview_url)
-                               hx-target="#file-browser"
-                               hx-swap="innerHTML"
-                               style="cursor: pointer;" {
-                                div {
-                                    span class="icon" { "🖼️" }
-                                    span { (item.name) }
-                                }
-                                div class="file-info" {
-                                    @if let Some(size) = &item.size { span { (size) " " } }
-                                    @if let Some(modified) = &item.modified { span { (modified) } }
-                                }
-                            }
-                        } @else {
-                            li #(li_id) data-path=(item.path) data-is-dir="false"
-                               hx-get=(preview_url)
-                               hx-target="#file-browser"
-                               hx-swap="innerHTML"
-                               style="cursor: pointer;" {
-                                div {
-                                    span class="icon" { "📄" }
-                                    span { (item.name) }
-                                }
-                                div class="file-info" {
-                                    @if let Some(size) = &item.size { span { (size) " " } }
-                                    @if let Some(modified) = &item.modified { span { (modified) } }
-                                }
-                            }
-                        }
-                    } @else {
-                        li #(li_id) data-path=(item.path) data-is-dir="false" {
-                            div {
-                                span class="icon" { "📄" }
-                                span { (item.name) }
-                            }
-                            div class="file-info" {
-                                @if let Some(size) = &item.size { span { (size) " " } }
-                                @if let Some(modified) = &item.modified { span { (modified) } }
-                            }
-                        }
+                    form method="post" action="/login" {
+                        input type="hidden" name="next" value=(next);
+                        input type="text" name="user" value=(user) placeholder="User"
+                            required autofocus[user.is_empty()] autocomplete="username";
+                        input type="password" name="password" placeholder="Password"
+                            required autofocus[!user.is_empty()] autocomplete="current-password";
+                        button type="submit" class="download-button" { "Log In" }
+                    }
+                    @if let Some(error) = error {
+                        div class="login-error" { (error) }
                     }
-                    div #(placeholder_id) class="share-link-placeholder" {}
                 }
             }
         }
-    })
+    }
 }
 
-// --- preview_handler ---
-async fn preview_handler(
+async fn login_page_handler(
     State(state): State<SharedState>,
-    Query(query): Query<PreviewQuery>,
-) -> Result<Markup, Response> {
-    let sanitized_req_path = sanitize_path(&query.path);
-    let full_path = resolve_and_validate_path(&state.root_dir, &sanitized_req_path)?;
+    Query(query): Query<LoginQuery>,
+) -> Response {
+    if state.users.is_none() {
+        return Redirect::to("/").into_response();
+    }
+    login_page(login_next(query.next.as_deref()), "", None).into_response()
+}
 
-    if !full_path.is_file() {
-        error!("Preview attempt on non-file: {}", full_path.display());
-        return Err(error_response(
-            StatusCode::BAD_REQUEST,
-            "Preview is only supported for files.",
-        ));
+async fn login_handler(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    Extension(forwarded): Extension<ForwardedHttps>,
+    Form(form): Form<LoginForm>,
+) -> Response {
+    let Some(users) = &state.users else {
+        return Redirect::to("/").into_response();
+    };
+    let next = login_next(form.next.as_deref());
+    let event = |status| {
+        audit::Event::new(
+            "login",
+            Some(form.user.clone()),
+            Some(client),
+            "/login".to_string(),
+            status,
+        )
+    };
+    if let Err(wait) = state.password_limiter.check(client) {
+        info!("Too many password attempts from {}", client);
+        state.audit.record(event(StatusCode::TOO_MANY_REQUESTS));
+        let mut response = (
+            StatusCode::TOO_MANY_REQUESTS,
+            login_page(
+                next,
+                &form.user,
+                Some("Too many attempts. Try again in a minute."),
+            ),
+        )
+            .into_response();
+        if let Ok(value) = HeaderValue::from_str(&wait.as_secs().max(1).to_string()) {
+            response.headers_mut().insert(header::RETRY_AFTER, value);
+        }
+        return response;
     }
+    let Some(user) = users.check_password(&form.user, &form.password).await else {
+        state.audit.record(event(StatusCode::UNAUTHORIZED));
+        return (
+            StatusCode::UNAUTHORIZED,
+            login_page(next, &form.user, Some("Wrong user or password.")),
+        )
+            .into_response();
+    };
+    state.audit.record(event(StatusCode::SEE_OTHER));
+    info!("{} logged in from {}", user.name, client);
+    let token = state.sessions.create(&user.name);
+    let mut response = Redirect::to(next).into_response();
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        sessions::set_cookie(&token, secure_cookies(&state, forwarded)),
+    );
+    response
+}
 
-    // Check if file is previewable
-    if !is_previewable_file(&full_path) {
-        return Err(error_response(
-            StatusCode::BAD_REQUEST,
-            "File type not supported for preview.",
+async fn logout_handler(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    Extension(forwarded): Extension<ForwardedHttps>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(user) = sessions::token(&headers).and_then(|token| state.sessions.remove(&token)) {
+        state.audit.record(audit::Event::new(
+            "logout",
+            Some(user),
+            Some(client),
+            "/logout".to_string(),
+            StatusCode::SEE_OTHER,
         ));
     }
+    let mut response = Redirect::to("/login").into_response();
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        sessions::clear_cookie(secure_cookies(&state, forwarded)),
+    );
+    response
+}
 
-    // Read file content
-    let content = match tokio::fs::read_to_string(&full_path).await {
-        Ok(content) => content,
-        Err(e) => {
-            error!(
-                "Failed to read file for preview {}: {}",
-                full_path.display(),
-                e
-            );
-            return Err(error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Could not read file content.",
-            ));
+// --- unlock_handler ---
+// Asked for in place of anything inside a locked folder; see `locks`.
+fn locked_response(folder: &str, error: Option<&str>) -> Response {
+    let status = if error.is_some() {
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::FORBIDDEN
+    };
+    let markup = html! {
+        div class="unlock" {
+            h2 { "🔒 " (folder) " is locked" }
+            p { "Enter the folder's password to open it." }
+            form method="post" action="/unlock"
+                hx-post="/unlock" hx-target="closest .unlock" hx-swap="outerHTML" {
+                input type="hidden" name="path" value=(folder);
+                input type="password" name="password" placeholder="Password"
+                    required autofocus autocomplete="current-password";
+                button type="submit" { "Unlock" }
+            }
+            @if let Some(error) = error {
+                p class="unlock-error" { (error) }
+            }
         }
     };
+    let mut response = (status, markup).into_response();
+    response.extensions_mut().insert(locks::Locked);
+    response
+}
 
-    let filename = full_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("Unknown file")
-        .to_string();
-
-    let language = detect_language(&full_path);
+#[derive(Deserialize)]
+struct UnlockForm {
+    path: String,
+    password: String,
+}
 
-    // Get the parent directory for the back button
-    let parent_path = sanitized_req_path
-        .parent()
-        .map(|p| p.to_string_lossy().replace('\\', "/"))
-        .unwrap_or_else(|| ".".to_string());
-    let encoded_parent_path = urlencoding::encode(&parent_path);
-    let back_url = format!("/browse?path={}", encoded_parent_path);
+async fn unlock_handler(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    Extension(forwarded): Extension<ForwardedHttps>,
+    headers: HeaderMap,
+    Form(form): Form<UnlockForm>,
+) -> Response {
+    let Some(folder_locks) = locks::get() else {
+        return error_response(StatusCode::NOT_FOUND, "No folders are locked.");
+    };
+    let folder = auth::normalize(&form.path);
+    let event = |status| {
+        audit::Event::new(
+            "unlock",
+            auth::current_user(),
+            Some(client),
+            folder.clone(),
+            status,
+        )
+    };
+    if let Err(wait) = state.password_limiter.check(client) {
+        info!("Too many password attempts from {}", client);
+        state.audit.record(event(StatusCode::TOO_MANY_REQUESTS));
+        return ratelimit::too_many_requests(wait, "Too many password attempts. Try again later.");
+    }
+    let client_key = ratelimit::client_key(client);
+    if let Err(wait) = state
+        .unlock_clients
+        .check(&client_key)
+        .and_then(|()| state.unlock_folders.check(&folder))
+    {
+        info!("Locked out of '{}' from {}", folder, client);
+        state.audit.record(event(StatusCode::TOO_MANY_REQUESTS));
+        return ratelimit::too_many_requests(wait, "Too many wrong passwords. Try again later.");
+    }
+    let Some(key) = folder_locks.unlock(&folder, &form.password).await else {
+        info!(
+            "Wrong password for locked folder '{}' from {}",
+            folder, client
+        );
+        state.unlock_clients.failed(&client_key);
+        state.unlock_folders.failed(&folder);
+        state.audit.record(event(StatusCode::UNAUTHORIZED));
+        return locked_response(&folder, Some("Wrong password."));
+    };
+    state.unlock_clients.succeeded(&client_key);
+    state.unlock_folders.succeeded(&folder);
+    state.audit.record(event(StatusCode::OK));
+    info!("Folder '{}' unlocked by {}", folder, client);
 
-    Ok(html! {
-        div class="preview-container" {
-            div class="preview-header" {
-                h1 { "File Preview: " (filename) }
-                div class="preview-actions" {
-                    button hx-get=(back_url)
-                           hx-target="#file-browser"
-                           hx-swap="innerHTML"
-                           class="close-button" { "Back to Files" }
-                }
-            }
-            div class="preview-content" {
-                pre {
-                    code class=(format!("language-{}", language)) {
-                        (content)
-                    }
-                }
+    let mut keys = locks::unlocked_keys(&headers, &state.signer);
+    keys.insert(key);
+    let cookie = locks::set_cookie(
+        &keys,
+        &headers,
+        &state.signer,
+        secure_cookies(&state, forwarded),
+    );
+    let mut response = if headers.contains_key("hx-request") {
+        html! {
+            div hx-get=(format!("/browse?path={}", urlencoding::encode(&folder)))
+                hx-trigger="load" hx-target="#file-browser" hx-swap="innerHTML" {
+                "Unlocked."
             }
         }
-        script {
-            (PreEscaped(&format!("
-                console.log('Preview content loaded for language: {}');
-                console.log('hljs available:', typeof hljs !== 'undefined');
-                if (typeof hljs !== 'undefined') {{
-                    console.log('Calling hljs.highlightAll() from preview');
-                    hljs.highlightAll();
-                }}
-            ", language)))
-        }
-    })
+        .into_response()
+    } else {
+        Redirect::to("/").into_response()
+    };
+    response.headers_mut().insert(header::SET_COOKIE, cookie);
+    response
 }
 
-// --- image_preview_handler ---
-async fn image_preview_handler(
+// --- browse_negotiated_handler ---
+// `/browse` answers with JSON or plain text for clients that ask for it and with the HTML
+// listing otherwise.
+async fn browse_negotiated_handler(
     State(state): State<SharedState>,
-    Query(query): Query<PreviewQuery>,
-) -> Result<Markup, Response> {
-    let sanitized_req_path = sanitize_path(&query.path);
-    let full_path = resolve_and_validate_path(&state.root_dir, &sanitized_req_path)?;
+    headers: HeaderMap,
+    Query(query): Query<BrowseQuery>,
+) -> Response {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    if query.format.as_deref() == Some("txt") || accept.contains("text/plain") {
+        browse_text_handler(state, query).await
+    } else if accept.contains("application/json") {
+        browse_json_handler(State(state), Query(query)).await
+    } else {
+        // Preferences passed in the query are remembered for the next listing.
+        let mut prefs = prefs::ListingPrefs::from_headers(&headers, &state.signer);
+        let changed = prefs.apply(&prefs::PrefChanges {
+            sort: query.sort.as_deref(),
+            order: query.order.as_deref(),
+            folders: query.folders.as_deref(),
+            hidden: query.hidden.as_deref(),
+            view: query.view.as_deref(),
+        });
+        let cookie = changed.then(|| prefs.set_cookie(&state.signer));
+        let mut response = match browse_page(state, query, prefs).await {
+            Ok(page) => page.into_response(),
+            Err(response) => response,
+        };
+        if let Some(cookie) = cookie {
+            response.headers_mut().append(header::SET_COOKIE, cookie);
+        }
+        response
+    }
+}
 
-    if !full_path.is_file() {
-        error!("Image preview attempt on non-file: {}", full_path.display());
-        return Err(error_response(
-            StatusCode::BAD_REQUEST,
-            "Preview is only supported for files.",
+// --- browse_json_handler ---
+// The listing as JSON, for scripts: `/api/v1/browse`, or `/browse` with
+// `Accept: application/json`.
+async fn browse_json_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<BrowseQuery>,
+) -> Response {
+    let sanitized_req_path = sanitize_path(query.path.as_deref().unwrap_or("."));
+    let (dir_items, file_items) = match read_listing(&state, &sanitized_req_path).await {
+        Ok(listing) => listing,
+        // The HTML error fragments mean nothing to a script; keep just the status.
+        Err(response) => {
+            let status = response.status();
+            let error = status.canonical_reason().unwrap_or("Error");
+            return (status, axum::Json(serde_json::json!({ "error": error }))).into_response();
+        }
+    };
+    let (dir_items, file_items) = filter_listing((dir_items, file_items), query.filter.as_deref());
+    let path = rawnames::encode(&sanitized_req_path);
+    let mut entries = dir_items;
+    entries.extend(file_items);
+    axum::Json(serde_json::json!({ "path": path, "entries": entries })).into_response()
+}
+
+// --- autoindex_handler ---
+// `/index/<path>/`: the folder as a classic autoindex page, and files below it as
+// themselves, so relative links work the way mirroring tools expect.
+async fn autoindex_root_handler(State(state): State<SharedState>, headers: HeaderMap) -> Response {
+    autoindex_handler(State(state), AxumPath(String::new()), headers).await
+}
+
+async fn autoindex_handler(
+    State(state): State<SharedState>,
+    AxumPath(path): AxumPath<String>,
+    headers: HeaderMap,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&path);
+    let is_top = state.mounts.is_top(&sanitized_req_path);
+    if !is_top {
+        let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+            Ok(path) => path,
+            Err(response) => return response,
+        };
+        if full_path.is_file() {
+            return inline_file_response(&full_path, &headers).await;
+        }
+    }
+    // Links on the page are relative to the folder, so its URL has to end in a slash.
+    if !path.is_empty() && !path.ends_with('/') {
+        let segments: Vec<_> = path.split('/').map(urlencoding::encode).collect();
+        return Redirect::permanent(&format!("/index/{}/", segments.join("/"))).into_response();
+    }
+
+    let (dir_items, file_items) = match read_listing(&state, &sanitized_req_path).await {
+        Ok(listing) => listing,
+        Err(response) => return response,
+    };
+    let entries: Vec<autoindex::IndexEntry> = dir_items
+        .iter()
+        .chain(&file_items)
+        .filter(|item| item.openable)
+        .map(|item| autoindex::IndexEntry {
+            name: &item.name,
+            segment: item.path.rsplit('/').next().unwrap_or(&item.path),
+            is_dir: item.is_dir,
+            modified: item.modified_at,
+            size: item.size_bytes.filter(|_| !item.is_dir),
+        })
+        .collect();
+    let has_parent = sanitized_req_path != Path::new(".");
+    let display_path = if has_parent {
+        format!(
+            "/{}/",
+            sanitized_req_path.to_string_lossy().replace('\\', "/")
+        )
+    } else {
+        "/".to_string()
+    };
+    autoindex::render(&display_path, has_parent, &entries).into_response()
+}
+
+// --- browse_text_handler ---
+// One line per entry, `modified  size  name`, folders first and marked with a trailing
+// `/`, for reading with curl and piping into other tools. The name comes last so that
+// spaces in it don't shift the columns.
+async fn browse_text_handler(state: SharedState, query: BrowseQuery) -> Response {
+    let sanitized_req_path = sanitize_path(query.path.as_deref().unwrap_or("."));
+    let text_response = |status: StatusCode, body: String| {
+        (
+            status,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            body,
+        )
+            .into_response()
+    };
+    let (dir_items, file_items) = match read_listing(&state, &sanitized_req_path).await {
+        Ok(listing) => listing,
+        Err(response) => {
+            let status = response.status();
+            let error = status.canonical_reason().unwrap_or("Error");
+            return text_response(status, format!("Error: {}\n", error));
+        }
+    };
+    let (dir_items, file_items) = filter_listing((dir_items, file_items), query.filter.as_deref());
+
+    let items: Vec<&DirEntryInfo> = dir_items.iter().chain(&file_items).collect();
+    let size_width = items
+        .iter()
+        .map(|item| item.size.as_deref().map_or(1, |size| size.chars().count()))
+        .max()
+        .unwrap_or(1);
+    let mut body = String::new();
+    for item in items {
+        // A name with a newline in it would otherwise read as two entries.
+        let name: String = item
+            .name
+            .chars()
+            .map(|c| if c.is_control() { '?' } else { c })
+            .collect();
+        body.push_str(&format!(
+            "{:<16}  {:>width$}  {}{}\n",
+            item.modified.as_deref().unwrap_or("-"),
+            item.size.as_deref().unwrap_or("-"),
+            name,
+            if item.is_dir { "/" } else { "" },
+            width = size_width
         ));
     }
+    text_response(StatusCode::OK, body)
+}
 
-    // Check if file is an image
-    if !is_image_file(&full_path) {
+// --- browse_events_handler ---
+// SSE stream telling an open listing to refresh itself when the folder changes.
+async fn browse_events_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<BrowseQuery>,
+) -> Result<Response, Response> {
+    let sanitized_req_path = sanitize_path(query.path.as_deref().unwrap_or("."));
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if !full_path.is_dir() {
         return Err(error_response(
             StatusCode::BAD_REQUEST,
-            "File type not supported for image preview.",
+            "Requested path is not a directory.",
         ));
     }
 
-    let filename = full_path
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("Unknown file")
-        .to_string();
+    // The listing is about to be fetched again, so it mustn't come from the cache.
+    let changes = tokio_stream::StreamExt::map(watch::changes(full_path.clone()), move |event| {
+        state.listings.invalidate(&full_path);
+        event
+    });
+    Ok(Sse::new(changes)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
 
-    // Get the parent directory for the back button
-    let parent_path = sanitized_req_path
-        .parent()
-        .map(|p| p.to_string_lossy().replace('\\', "/"))
-        .unwrap_or_else(|| ".".to_string());
-    let encoded_parent_path = urlencoding::encode(&parent_path);
-    let back_url = format!("/browse?path={}", encoded_parent_path);
+// --- browse_handler ---
+// The listing re-rendered after an action, with the browser's stored preferences.
+async fn browse_handler(
+    state: SharedState,
+    headers: &HeaderMap,
+    query: BrowseQuery,
+) -> Result<Markup, Response> {
+    let prefs = prefs::ListingPrefs::from_headers(headers, &state.signer);
+    browse_page(state, query, prefs)
+        .await
+        .map(ListingPage::into_markup)
+}
 
-    // Create the image URL for display
-    let encoded_image_path = urlencoding::encode(&query.path);
-    let image_url = format!("/direct-download-image?path={}", encoded_image_path);
+// Listings with more entries than this are streamed to the client a chunk of rows at a
+// time, instead of being rendered into one string first.
+const STREAMED_LISTING_ROWS: usize = 2000;
+// Rows rendered per chunk of a streamed listing.
+const LISTING_CHUNK_ROWS: usize = 250;
 
-    Ok(html! {
-        div class="preview-container image-preview" {
-            div class="preview-header" {
-                h1 { "Image Preview: " (filename) }
-                div class="preview-actions" {
-                    button hx-get=(back_url)
+// A folder's listing page, split around its rows so that a huge folder can be rendered a
+// piece at a time. `head` opens the list that `tail` closes.
+struct ListingPage {
+    head: Markup,
+    rows: ListingRows,
+    tail: Markup,
+}
+
+struct ListingRows {
+    state: SharedState,
+    // Folders and files in the order they are shown, by the browser's preferences.
+    items: Vec<DirEntryInfo>,
+    grid: bool,
+    show_checksums: bool,
+    pins: Vec<String>,
+}
+
+impl ListingPage {
+    fn into_markup(self) -> Markup {
+        html! {
+            (self.head)
+            (self.rows.render(0..self.rows.len()))
+            (self.tail)
+        }
+    }
+
+    fn into_response(self) -> Response {
+        if self.rows.len() <= STREAMED_LISTING_ROWS {
+            return self.into_markup().into_response();
+        }
+        let ListingPage { head, rows, tail } = self;
+        let total = rows.len();
+        // Each chunk is only rendered once the previous one has been sent.
+        let chunks = (0..total)
+            .step_by(LISTING_CHUNK_ROWS)
+            .map(move |start| rows.render(start..(start + LISTING_CHUNK_ROWS).min(total)));
+        let body = std::iter::once(head)
+            .chain(chunks)
+            .chain(std::iter::once(tail))
+            .map(|markup| Ok::<_, std::convert::Infallible>(markup.into_string()));
+        (
+            [(header::CONTENT_TYPE, "text/html; charset=utf-8")],
+            axum::body::Body::from_stream(tokio_stream::iter(body)),
+        )
+            .into_response()
+    }
+}
+
+async fn browse_page(
+    state: SharedState,
+    query: BrowseQuery,
+    prefs: prefs::ListingPrefs,
+) -> Result<ListingPage, Response> {
+    let requested_path_str = query.path.unwrap_or_else(|| ".".to_string());
+    let sanitized_req_path = sanitize_path(&requested_path_str);
+    let (dir_items, file_items) = read_listing(&state, &sanitized_req_path).await?;
+
+    let current_display_path = if sanitized_req_path == Path::new(".") {
+        "/".to_string()
+    } else {
+        format!(
+            "/{}",
+            sanitized_req_path.to_string_lossy().replace('\\', "/")
+        )
+    };
+
+    let current_rel_path = rawnames::encode(&sanitized_req_path);
+    // The list of mounts isn't a real folder: nothing can be uploaded to it, and it never
+    // changes while the server runs.
+    let is_top = state.mounts.is_top(&sanitized_req_path);
+    let has_images = file_items
+        .iter()
+        .any(|item| is_image_file(Path::new(&item.name)));
+    let grid = prefs.grid;
+    let show_checksums = query.checksums;
+    let pins = state.favorites.list().await;
+    // Shown at the top of the root listing: each pin the viewer may open and whether it is
+    // a folder, or `None` when it no longer exists. Pins the viewer can't open are left out.
+    let favorites: Vec<(&String, Option<bool>)> = if sanitized_req_path == Path::new(".") {
+        pins.iter()
+            .filter_map(
+                |pin| match resolve_and_validate_path(&state.mounts, Path::new(pin)) {
+                    Ok(full_path) => Some((pin, Some(full_path.is_dir()))),
+                    Err(_) if pin_missing(&state.mounts, Path::new(pin)) => Some((pin, None)),
+                    Err(_) => None,
+                },
+            )
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let description = if is_top {
+        None
+    } else {
+        match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+            Ok(full_path) => kivmeta::read(&full_path)
+                .await
+                .and_then(|meta| meta.description)
+                .map(|text| text.trim().to_string())
+                .filter(|text| !text.is_empty()),
+            Err(_) => None,
+        }
+    };
+    let readme = match markdown::find_readme(
+        file_items
+            .iter()
+            .filter(|item| item.openable)
+            .map(|item| item.name.as_str()),
+    ) {
+        Some(name) => read_readme(&state, &sanitized_req_path, name).await,
+        None => None,
+    };
+    let filter = query.filter.as_deref().map(str::trim).unwrap_or("");
+    let filtered = !filter.is_empty();
+    let (mut dir_items, mut file_items) = filter_listing((dir_items, file_items), Some(filter));
+    // The toggle is offered wherever there is something for it to do.
+    let has_hidden = dir_items
+        .iter()
+        .chain(&file_items)
+        .any(|item| item.name.starts_with('.'));
+    if !prefs.show_hidden {
+        dir_items.retain(|item| !item.name.starts_with('.'));
+        file_items.retain(|item| !item.name.starts_with('.'));
+    }
+    let pref_url = |params: &str| {
+        let mut url = format!(
+            "/browse?path={}&checksums={}&{}",
+            urlencoding::encode(&current_rel_path),
+            show_checksums,
+            params
+        );
+        if filtered {
+            url.push_str(&format!("&filter={}", urlencoding::encode(filter)));
+        }
+        url
+    };
+    // Choosing the current sort again reverses it.
+    let sort_url = |key: prefs::SortKey| {
+        let descending = prefs.sort == key && !prefs.descending;
+        pref_url(&format!(
+            "sort={}&order={}",
+            key.as_str(),
+            if descending { "desc" } else { "asc" }
+        ))
+    };
+    let view_url = |view: &str, checksums: bool| {
+        let mut url = format!(
+            "/browse?path={}&view={}&checksums={}",
+            urlencoding::encode(&current_rel_path),
+            view,
+            checksums
+        );
+        if filtered {
+            url.push_str(&format!("&filter={}", urlencoding::encode(filter)));
+        }
+        url
+    };
+    let current_view = if grid { "grid" } else { "list" };
+
+    let head = html! {
+        div #current-path-container data-path=(current_rel_path)
+            data-events-url=[(!is_top).then(|| format!("/browse/events?path={}", urlencoding::encode(&current_rel_path)))] {
+            div #current-path {
+                "Current: " (current_display_path)
+                @if has_images && state.features.on(Feature::Preview) {
+                    button class="gallery-button"
+                           hx-get=(format!("/gallery?path={}", urlencoding::encode(&current_rel_path)))
                            hx-target="#file-browser"
-                           hx-swap="innerHTML"
-                           class="close-button" { "Back to Files" }
+                           hx-swap="innerHTML" { "🖼️ Gallery" }
+                }
+                @if !is_top {
+                    button class="gallery-button"
+                           hx-get=(format!("/usage?path={}", urlencoding::encode(&current_rel_path)))
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML" { "📊 Usage" }
+                    a class="gallery-button" download
+                      href=(format!("/download-folder?path={}", urlencoding::encode(&current_rel_path))) { "📦 Download folder" }
+                    a class="gallery-button folder-download-alt" download title="Download the folder as tar.gz, keeping permissions and links"
+                      href=(format!("/download-folder?path={}&format=tar.gz", urlencoding::encode(&current_rel_path))) { "tar.gz" }
+                    form class="encrypted-download" method="post" action="/download-folder" {
+                        input type="hidden" name="path" value=(current_rel_path);
+                        input type="password" name="password" placeholder="Password" required autocomplete="new-password";
+                        button type="submit" class="gallery-button"
+                               title="Download the folder as an AES-256 encrypted zip; file names stay visible" { "🔒 Encrypted zip" }
+                    }
+                }
+                span class="view-toggle" {
+                    button class=[(!grid).then_some("active")] data-view="list"
+                           hx-get=(view_url("list", show_checksums)) hx-target="#file-browser" hx-swap="innerHTML" { "☰ List" }
+                    button class=[grid.then_some("active")] data-view="grid"
+                           hx-get=(view_url("grid", show_checksums)) hx-target="#file-browser" hx-swap="innerHTML" { "▦ Grid" }
+                    @if !file_items.is_empty() {
+                        button class=[show_checksums.then_some("active")] data-checksums=(!show_checksums)
+                               title="Show the SHA-256 of each file"
+                               hx-get=(view_url(current_view, !show_checksums)) hx-target="#file-browser" hx-swap="innerHTML" { "#️⃣ Checksums" }
+                    }
+                }
+                span class="view-toggle sort-controls" {
+                    "Sort: "
+                    @for (key, label) in [(prefs::SortKey::Name, "Name"), (prefs::SortKey::Modified, "Modified"), (prefs::SortKey::Size, "Size")] {
+                        button class=[(prefs.sort == key).then_some("active")]
+                               hx-get=(sort_url(key)) hx-target="#file-browser" hx-swap="innerHTML" {
+                            (label)
+                            @if prefs.sort == key { @if prefs.descending { " ▼" } @else { " ▲" } }
+                        }
+                    }
+                    button class=[prefs.folders_first.then_some("active")]
+                           title="List folders before files"
+                           hx-get=(pref_url(if prefs.folders_first { "folders=mixed" } else { "folders=first" }))
+                           hx-target="#file-browser" hx-swap="innerHTML" { "📁 Folders first" }
+                    @if has_hidden || !prefs.show_hidden {
+                        button class=[prefs.show_hidden.then_some("active")]
+                               title="Show files and folders whose names start with a dot"
+                               hx-get=(pref_url(if prefs.show_hidden { "hidden=hide" } else { "hidden=show" }))
+                               hx-target="#file-browser" hx-swap="innerHTML" { "👁️ Hidden" }
+                    }
                 }
             }
-            div class="image-preview-content" {
-                img src=(image_url) alt=(filename) class="preview-image" {}
+            @if state.features.on(Feature::Search) {
+                (search_form(&current_rel_path, ""))
+            }
+            form #selection-form method="post" action="/download-selection" {
+                button type="submit" title="Download the ticked files and folders as one archive" { "📦 Download selected" }
+                select name="format" {
+                    option value="zip" { "zip" }
+                    option value="tar.gz" { "tar.gz" }
+                }
+                input type="password" name="password" placeholder="Zip password (optional)"
+                      autocomplete="new-password" title="Encrypts the zip with AES-256; file names stay visible";
+            }
+            form #filter-form onsubmit="return false;" {
+                input type="hidden" name="path" value=(current_rel_path);
+                input type="hidden" name="view" value=(current_view);
+                input type="hidden" name="checksums" value=(show_checksums);
+                input #filter-input type="search" name="filter" value=(filter)
+                    placeholder="Filter by name or *.glob…" autocomplete="off"
+                    hx-get="/browse"
+                    hx-include="#filter-form"
+                    hx-trigger="input changed delay:300ms, search"
+                    hx-target="#file-list-container"
+                    hx-select="#file-list-container"
+                    hx-swap="outerHTML";
+            }
+            @if !is_top
+                && !state.read_only
+                && state.features.on(Feature::Upload)
+                && may_create(&state, &state.upload_roles)
+            {
+                form #upload-form
+                    hx-post="/upload"
+                    hx-encoding="multipart/form-data"
+                    hx-target="#file-browser"
+                    hx-swap="innerHTML" {
+                    input type="hidden" name="path" value=(current_rel_path);
+                    input type="file" name="file" multiple required;
+                    button type="submit" { "⬆️ Upload" }
+                }
+            }
+        }
+        @if let Some(description) = &description {
+            div class="folder-description" { (description) }
+        }
+        @if !favorites.is_empty() {
+            div #favorites {
+                h2 { "⭐ Favorites" }
+                ul #favorites-list {
+                    @for (pin, kind) in &favorites {
+                        @let encoded_pin = urlencoding::encode(pin);
+                        @let name = pin.rsplit('/').next().unwrap_or(pin);
+                        @let vals = serde_json::json!({ "path": pin, "back": "." }).to_string();
+                        li class=[kind.is_none().then_some("missing")] title=(pin) {
+                            @match kind {
+                                Some(true) => a hx-get=(format!("/browse?path={}", encoded_pin))
+                                              hx-target="#file-browser" hx-swap="innerHTML" { "📁 " (name) },
+                                Some(false) => {
+                                    @let kind = state.mounts.join(Path::new(pin)).and_then(|path| preview_kind(&path));
+                                    @let parent = pin.rsplit_once('/').map_or(".", |(parent, _)| parent);
+                                    @let url = preview_url(&state, kind, &encoded_pin)
+                                        .unwrap_or_else(|| format!("/browse?path={}", urlencoding::encode(parent)));
+                                    a hx-get=(url) hx-target="#file-browser" hx-swap="innerHTML" {
+                                        (kind.map_or("📄", PreviewKind::icon)) " " (name)
+                                    }
+                                },
+                                None => span { (name) " (missing)" },
+                            }
+                            @if !state.read_only {
+                                button class="unpin-button"
+                                       hx-post="/favorites/remove"
+                                       hx-vals=(vals)
+                                       hx-target="#file-browser"
+                                       hx-swap="innerHTML"
+                                       title="Remove from Favorites" { "✕" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        (PreEscaped(if grid {
+            r#"<div id="file-list-container"><ul id="file-list" class="grid-view">"#
+        } else {
+            r#"<div id="file-list-container"><ul id="file-list">"#
+        }))
+        @if sanitized_req_path != Path::new(".") {
+            @let parent_rel_path = sanitized_req_path.parent().map(rawnames::encode).unwrap_or_else(|| ".".to_string());
+            @let parent_url_encoded = urlencoding::encode(&parent_rel_path);
+            @let hx_get_value_up = format!("/browse?path={}", parent_url_encoded);
+            li hx-get=(hx_get_value_up) hx-target="#file-browser" hx-swap="innerHTML" style="cursor: pointer;" {
+                @if grid { div class="thumb" { span class="thumb-icon" { "⬆️" } } }
+                span class="icon" { "⬆️" }
+                span { ".." }
+            }
+        }
+        @if filtered && dir_items.is_empty() && file_items.is_empty() {
+            li class="filter-empty" { "Nothing here matches “" (filter) "”." }
+        }
+    };
+    let tail = html! {
+        (PreEscaped("</ul></div>"))
+        @if let Some((name, contents)) = &readme {
+            div #readme {
+                div class="readme-header" { "📖 " (name) }
+                (contents)
             }
         }
+    };
+
+    Ok(ListingPage {
+        head,
+        rows: ListingRows {
+            state: state.clone(),
+            items: sort_listing(dir_items, file_items, &prefs),
+            grid,
+            show_checksums,
+            pins,
+        },
+        tail,
     })
 }
 
-// --- direct_image_handler ---
-async fn direct_image_handler(
-    State(state): State<SharedState>,
-    Query(query): Query<PreviewQuery>,
-) -> Response {
-    let sanitized_req_path = sanitize_path(&query.path);
-    let full_path = match resolve_and_validate_path(&state.root_dir, &sanitized_req_path) {
-        Ok(path) => path,
-        Err(response) => return response,
+impl ListingRows {
+    fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    // Rows `range` of the listing, in the order it is shown.
+    fn render(&self, range: std::ops::Range<usize>) -> Markup {
+        let items = &self.items[range];
+        let is_pinned = |path: &str| self.pins.iter().any(|pin| pin == path);
+        html! {
+            @for item in items {
+                @if item.is_dir {
+                    @let path_url_encoded = urlencoding::encode(&item.path);
+                    @let hx_get_value_dir = item.openable.then(|| format!("/browse?path={}", path_url_encoded));
+                    li data-path=(item.path) data-is-dir="true" data-pinned=[is_pinned(&item.path).then_some("true")]
+                       class=[item.access_denied.then_some("access-denied")]
+                       hx-get=[hx_get_value_dir.as_ref()]
+                       hx-target=[hx_get_value_dir.as_ref().map(|_| "#file-browser")]
+                       hx-swap=[hx_get_value_dir.as_ref().map(|_| "innerHTML")]
+                       style=[hx_get_value_dir.as_ref().map(|_| "cursor: pointer;")] {
+                       @if self.grid { div class="thumb" { span class="thumb-icon" { "📁" } } }
+                       div {
+                           (select_box(item))
+                           span class="icon" { "📁" }
+                           span { (item.name) }
+                           @if is_pinned(&item.path) { span class="pinned" title="In Favorites" { " ⭐" } }
+                           (link_target(item))
+                        }
+                       div class="file-info" {
+                           (access_denied_marker(item))
+                           @if let Some(count) = item.item_count {
+                               span class="item-count" {
+                                   (count) @if count >= itemcount::MAX_COUNTED { "+" }
+                                   @if count == 1 { " item" } @else { " items" } " · "
+                               }
+                           }
+                           (ownership_info(item))
+                           @if let Some(size) = &item.size {
+                               span { (size) " " }
+                           } @else if self.state.show_dir_sizes && item.openable {
+                               span class="dir-size-pending"
+                                    hx-get=(format!("/dir-size?path={}", path_url_encoded))
+                                    hx-trigger="load"
+                                    hx-target="this"
+                                    hx-swap="outerHTML" { span class="spinner" {} " " }
+                           }
+                           (item.modified.as_deref().unwrap_or(""))
+                       }
+                       (annotation(item))
+                   }
+                } @else {
+                    @let item_id_base = item.path.replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+                    @let li_id = format!("file-item-{}", item_id_base);
+                    @let placeholder_id = format!("share-placeholder-{}", item_id_base);
+                    @let encoded_path = urlencoding::encode(&item.path);
+                    @let kind = self.state.mounts.join(Path::new(&item.path)).and_then(|path| preview_kind(&path));
+                    @let preview_url = preview_url(&self.state, kind.filter(|_| item.openable), &encoded_path);
+                    @let image_url = match kind.filter(|_| item.openable && self.state.features.on(Feature::Preview)) {
+                        Some(PreviewKind::Image) => Some(format!("/image?path={}&w=400", encoded_path)),
+                        Some(PreviewKind::Video) => Some(format!("/poster?path={}", encoded_path)),
+                        _ => None,
+                    };
+
+                    li #(li_id) data-path=(item.path) data-is-dir="false" data-pinned=[is_pinned(&item.path).then_some("true")] data-image-url=[image_url.as_ref()]
+                       class=[item.access_denied.then_some("access-denied")]
+                       hx-get=[preview_url.as_ref()]
+                       hx-target=[preview_url.as_ref().map(|_| "#file-browser")]
+                       hx-swap=[preview_url.as_ref().map(|_| "innerHTML")]
+                       style=[preview_url.as_ref().map(|_| "cursor: pointer;")] {
+                        // Same URLs as the hover preview, so both share one cached thumbnail.
+                        @if self.grid {
+                            div class="thumb" {
+                                @if let Some(url) = &image_url {
+                                    img src=(url) alt="" loading="lazy" onerror="this.remove()";
+                                } @else {
+                                    span class="thumb-icon" { (kind.map_or("📄", PreviewKind::icon)) }
+                                }
+                            }
+                        }
+                        div {
+                            (select_box(item))
+                            span class="icon" { (kind.map_or("📄", PreviewKind::icon)) }
+                            span { (item.name) }
+                            @if is_pinned(&item.path) { span class="pinned" title="In Favorites" { " ⭐" } }
+                            (link_target(item))
+                        }
+                        div class="file-info" {
+                            (access_denied_marker(item))
+                            (ownership_info(item))
+                            @if let Some(size) = &item.size { span { (size) " " } }
+                            @if let Some(modified) = &item.modified { span { (modified) } }
+                        }
+                        (annotation(item))
+                        @if self.show_checksums && !self.grid && item.openable {
+                            span class="checksum-pending"
+                                 hx-get=(format!("/hash?path={}", encoded_path))
+                                 hx-trigger="load"
+                                 hx-target="this"
+                                 hx-swap="outerHTML" { span class="spinner" {} " SHA-256…" }
+                        }
+                    }
+                    div #(placeholder_id) class="share-link-placeholder" {}
+                }
+            }
+        }
+    }
+}
+
+// Ticks the entry for `#selection-form`. Clicks stop here so they don't also open it.
+fn select_box(item: &DirEntryInfo) -> Markup {
+    html! {
+        @if item.openable && !item.access_denied {
+            input type="checkbox" class="select-item" name="path" value=(item.path)
+                form="selection-form" title="Select for download"
+                onclick="event.stopPropagation()";
+        }
+    }
+}
+
+// Folders and files as one list, in the order the preferences ask for. Ties, and entries
+// without the size or date being sorted by, fall back to the name.
+fn sort_listing(
+    dir_items: Vec<DirEntryInfo>,
+    file_items: Vec<DirEntryInfo>,
+    prefs: &prefs::ListingPrefs,
+) -> Vec<DirEntryInfo> {
+    let compare = |a: &DirEntryInfo, b: &DirEntryInfo| {
+        let by_key = match prefs.sort {
+            prefs::SortKey::Name => std::cmp::Ordering::Equal,
+            prefs::SortKey::Modified => a.modified_at.cmp(&b.modified_at),
+            prefs::SortKey::Size => a.size_bytes.cmp(&b.size_bytes),
+        };
+        let ordering = by_key.then_with(|| natsort::natural_cmp(&a.name, &b.name));
+        if prefs.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
     };
+    let mut items = dir_items;
+    if prefs.folders_first {
+        let mut files = file_items;
+        items.sort_by(compare);
+        files.sort_by(compare);
+        items.extend(files);
+    } else {
+        items.extend(file_items);
+        items.sort_by(compare);
+    }
+    items
+}
 
-    if !full_path.is_file() {
-        error!("Direct image attempt on non-file: {}", full_path.display());
-        return error_response(
-            StatusCode::BAD_REQUEST,
-            "Direct image access is only supported for files.",
-        );
+// Drops the entries whose names don't match `filter=`.
+fn filter_listing(
+    (mut dir_items, mut file_items): (Vec<DirEntryInfo>, Vec<DirEntryInfo>),
+    filter: Option<&str>,
+) -> (Vec<DirEntryInfo>, Vec<DirEntryInfo>) {
+    if let Some(filter) = filter.and_then(namefilter::NameFilter::parse) {
+        dir_items.retain(|item| filter.matches(&item.name));
+        file_items.retain(|item| filter.matches(&item.name));
     }
+    (dir_items, file_items)
+}
 
-    // Check if file is an image
-    if !is_image_file(&full_path) {
-        return error_response(
+// The entries of a folder, folders first, each group in natural order by name.
+async fn read_listing(
+    state: &AppState,
+    sanitized_req_path: &Path,
+) -> Result<(Vec<DirEntryInfo>, Vec<DirEntryInfo>), Response> {
+    if state.mounts.is_top(sanitized_req_path) {
+        return Ok(visible_entries((
+            read_mounts_listing(state).await,
+            Vec::new(),
+        )));
+    }
+    let full_path = resolve_and_validate_path(&state.mounts, sanitized_req_path)?;
+    let mount = state.mounts.root_of(&full_path);
+    if !full_path.is_dir() {
+        error!("Browse attempt on non-directory: {}", full_path.display());
+        return Err(error_response(
             StatusCode::BAD_REQUEST,
-            "File type not supported for direct image access.",
-        );
+            "Requested path is not a directory.",
+        ));
     }
 
-    match tokio::fs::File::open(&full_path).await {
-        Ok(file) => {
-            let mime_type = mime_guess::from_path(&full_path)
-                .first_or_octet_stream()
-                .to_string();
+    let modified = fs::metadata(&full_path)
+        .await
+        .and_then(|m| m.modified())
+        .ok();
+    if let Some(listing) = state.listings.get(&full_path, modified) {
+        return Ok(visible_entries(listing));
+    }
+    let listing = read_entries(state, mount, &full_path).await?;
+    state.listings.insert(&full_path, modified, listing.clone());
+    Ok(visible_entries(listing))
+}
 
-            let stream = ReaderStream::with_capacity(file, 1 << 18); // 256KiB buffer
-            let body = axum::body::Body::from_stream(stream);
+// Leaves out what the logged-in user may not see. Done after the cache, which is shared
+// by everyone.
+fn visible_entries(
+    (mut dir_items, mut file_items): (Vec<DirEntryInfo>, Vec<DirEntryInfo>),
+) -> (Vec<DirEntryInfo>, Vec<DirEntryInfo>) {
+    dir_items.retain(|item| auth::visible(&item.path));
+    file_items.retain(|item| auth::visible(&item.path));
+    (dir_items, file_items)
+}
 
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                header::CONTENT_TYPE,
-                HeaderValue::from_str(&mime_type)
-                    .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+// Reads and stats every listed entry of `full_path`, which lies in `mount`.
+async fn read_entries(
+    state: &AppState,
+    mount: &mounts::Mount,
+    full_path: &Path,
+) -> Result<(Vec<DirEntryInfo>, Vec<DirEntryInfo>), Response> {
+    let mut entries = match fs::read_dir(full_path).await {
+        Ok(reader) => reader,
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            error!(
+                "Permission denied reading directory {}",
+                full_path.display()
             );
-
-            (StatusCode::OK, headers, body).into_response()
+            return Err(access_denied_page(&mount.relative(full_path)));
         }
         Err(e) => {
-            error!(
-                "Failed to open image file for direct access {}: {}",
-                full_path.display(),
-                e
-            );
-            error_response(
+            error!("Failed to read directory {}: {}", full_path.display(), e);
+            return Err(error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Could not read image file.",
-            )
+                "Error reading directory contents.",
+            ));
         }
-    }
-}
+    };
 
-// --- MODIFIED share_handler ---
-async fn share_handler(
-    State(state): State<SharedState>, // App state
-    // Host(hostname): Host, // Removed: We no longer extract the hostname
-    Form(payload): Form<SharePayload>, // Form data (path)
-) -> Result<Markup, Response> {
-    info!("Share requested for path: {}", payload.path);
-    // info!("Request received via host: {}", hostname); // Removed
+    let meta = kivmeta::read(full_path).await;
+    let mut dir_items = Vec::new();
+    let mut file_items = Vec::new();
+    // Folders whose entries are counted: their index into `dir_items`, path and mtime.
+    let mut counted_dirs = Vec::new();
+    let mut counted_paths = Vec::new();
 
-    let sanitized_req_path = sanitize_path(&payload.path);
-    let full_path = resolve_and_validate_path(&state.root_dir, &sanitized_req_path)?;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let entry_path = entry.path();
+        if !policy::is_listed(&mount.dir, &entry_path) {
+            continue;
+        }
+        let name = rawnames::display(&entry.file_name());
 
-    if !full_path.is_file() {
-        error!("Share attempt on non-file: {}", full_path.display());
-        return Err(error_response(
-            StatusCode::BAD_REQUEST,
-            "Sharing is only supported for files.",
-        ));
-    }
+        let relative_path = mount.relative(&entry_path);
 
-    let uuid = Uuid::new_v4();
-    state.shares.insert(uuid, full_path.clone());
-    info!(
-        "Created share entry for UUID {} pointing to {}",
-        uuid,
-        full_path.display()
+        // Links are listed as what they point to. Unless they are shown as links, ones that
+        // are broken or lead outside the mount are left out.
+        let is_symlink = entry.file_type().await.is_ok_and(|t| t.is_symlink());
+        let openable = !is_symlink
+            || fs::canonicalize(&entry_path).await.is_ok_and(|target| {
+                target.starts_with(&mount.dir) && policy::is_accessible(&mount.dir, &target)
+            });
+        let show_link = is_symlink && policy::symlink_policy() == policy::SymlinkPolicy::Show;
+        if !openable && !show_link {
+            continue;
+        }
+        let link_target = if show_link {
+            fs::read_link(&entry_path)
+                .await
+                .ok()
+                .map(|target| target.to_string_lossy().into_owned())
+        } else {
+            None
+        };
+        let metadata = match fs::metadata(&entry_path).await {
+            Err(_) if show_link => entry.metadata().await,
+            metadata => metadata,
+        };
+
+        match metadata {
+            Ok(metadata) => {
+                let is_dir = metadata.is_dir();
+                let (mut size, modified) = get_metadata_strings(&metadata);
+                let mut size_bytes = metadata.is_file().then_some(metadata.len());
+                // Folder sizes already worked out are shown straight away; the rest are
+                // fetched from /dir-size once the listing is on the page.
+                if is_dir && state.show_dir_sizes {
+                    size_bytes = state
+                        .dir_sizes
+                        .cached(&entry_path, metadata.modified().ok())
+                        .map(|dir_size| dir_size.bytes);
+                    size = size_bytes.map(|bytes| format_size(bytes, BINARY));
+                }
+
+                let annotation = meta
+                    .as_ref()
+                    .and_then(|meta| meta.annotation(&name, is_dir));
+                let item = DirEntryInfo {
+                    name,
+                    path: relative_path,
+                    is_dir,
+                    size,
+                    modified,
+                    size_bytes,
+                    modified_at: metadata.modified().ok().map(DateTime::<Utc>::from),
+                    link_target,
+                    openable,
+                    item_count: None,
+                    ownership: ownership::of(&metadata),
+                    annotation,
+                    access_denied: false,
+                };
+
+                if is_dir {
+                    if openable {
+                        counted_dirs.push(dir_items.len());
+                        counted_paths.push((entry_path, metadata.modified().ok()));
+                    }
+                    dir_items.push(item);
+                } else {
+                    file_items.push(item);
+                }
+            }
+            // Listed greyed out rather than left out, so the file doesn't seem to vanish.
+            Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+                let is_dir = entry.file_type().await.is_ok_and(|t| t.is_dir());
+                let item = DirEntryInfo {
+                    name,
+                    path: relative_path,
+                    is_dir,
+                    size: None,
+                    modified: None,
+                    size_bytes: None,
+                    modified_at: None,
+                    link_target,
+                    openable: false,
+                    item_count: None,
+                    ownership: None,
+                    annotation: None,
+                    access_denied: true,
+                };
+                if is_dir {
+                    dir_items.push(item);
+                } else {
+                    file_items.push(item);
+                }
+            }
+            Err(e) => {
+                error!("Failed to get metadata for {}: {}", entry_path.display(), e);
+                continue;
+            }
+        }
+    }
+
+    let counts = state.item_counts.counts(&mount.dir, &counted_paths).await;
+    for (index, count) in counted_dirs.into_iter().zip(counts) {
+        dir_items[index].item_count = count;
+    }
+
+    dir_items.sort_by(|a, b| natsort::natural_cmp(&a.name, &b.name));
+    file_items.sort_by(|a, b| natsort::natural_cmp(&a.name, &b.name));
+    Ok((dir_items, file_items))
+}
+
+// Shown instead of the listing of a folder kiv may not read.
+fn access_denied_page(rel_path: &str) -> Response {
+    let parent = Path::new(rel_path)
+        .parent()
+        .map(rawnames::encode)
+        .filter(|p| !p.is_empty())
+        .unwrap_or_else(|| ".".to_string());
+    let markup = html! {
+        div #current-path-container data-path=(rel_path) {
+            div #current-path {
+                "Current: /" (rel_path)
+                button class="gallery-button"
+                       hx-get=(format!("/browse?path={}", urlencoding::encode(&parent)))
+                       hx-target="#file-browser"
+                       hx-swap="innerHTML" { "Back to parent folder" }
+            }
+        }
+        div #file-list-container {
+            div class="access-denied-page" {
+                h2 { "🔒 Access denied" }
+                p { "The server isn't allowed to read this folder. Its permissions have to be changed on the machine kiv runs on before it can be browsed." }
+            }
+        }
+    };
+    (StatusCode::FORBIDDEN, markup).into_response()
+}
+
+// The virtual top level with named mounts: one folder per mount, in the order given.
+async fn read_mounts_listing(state: &AppState) -> Vec<DirEntryInfo> {
+    let mut items = Vec::new();
+    for mount in state.mounts.list() {
+        let metadata = fs::metadata(&mount.dir).await.ok();
+        let modified_time = metadata.as_ref().and_then(|m| m.modified().ok());
+        let mut size_bytes = None;
+        if state.show_dir_sizes {
+            size_bytes = state
+                .dir_sizes
+                .cached(&mount.dir, modified_time)
+                .map(|dir_size| dir_size.bytes);
+        }
+        let item_count = state
+            .item_counts
+            .counts(&mount.dir, &[(mount.dir.clone(), modified_time)])
+            .await
+            .pop()
+            .flatten();
+        items.push(DirEntryInfo {
+            name: mount.name.clone(),
+            path: mount.name.clone(),
+            is_dir: true,
+            size: size_bytes.map(|bytes| format_size(bytes, BINARY)),
+            modified: metadata.as_ref().and_then(|m| get_metadata_strings(m).1),
+            size_bytes,
+            modified_at: modified_time.map(DateTime::<Utc>::from),
+            link_target: None,
+            openable: true,
+            item_count,
+            ownership: metadata.as_ref().and_then(ownership::of),
+            annotation: None,
+            access_denied: false,
+        });
+    }
+    items
+}
+
+// The folder's README, rendered for showing under the listing. Markdown goes through
+// the Markdown renderer; anything else is shown as plain text.
+async fn read_readme(
+    state: &AppState,
+    sanitized_req_path: &Path,
+    name: &str,
+) -> Option<(String, Markup)> {
+    let full_path = resolve_and_validate_path(&state.mounts, sanitized_req_path)
+        .ok()?
+        .join(name);
+    let metadata = fs::metadata(&full_path).await.ok()?;
+    if metadata.len() > markdown::MAX_README_SIZE {
+        return None;
+    }
+    let bytes = fs::read(&full_path)
+        .await
+        .map_err(|e| error!("Failed to read README {}: {}", full_path.display(), e))
+        .ok()?;
+    let contents = String::from_utf8_lossy(&bytes);
+    let body = if markdown::is_markdown_file(name) {
+        let base_dir = rawnames::encode(sanitized_req_path);
+        let contents = contents.into_owned();
+        let html = tokio::task::spawn_blocking(move || markdown::to_html(&contents, &base_dir))
+            .await
+            .ok()?;
+        html! { div class="markdown-body" { (PreEscaped(html)) } }
+    } else {
+        html! { pre class="readme-text" { (contents) } }
+    };
+    Some((name.to_string(), body))
+}
+
+// --- tree_handler ---
+// Folders below `path` for the sidebar, as `<li>` items.
+async fn tree_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<TreeQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(query.path.as_deref().unwrap_or("."));
+    let depth = query.depth.unwrap_or(1);
+    let nodes = if state.mounts.is_top(&sanitized_req_path) {
+        tree::read_mounts(state.mounts.list(), depth).await
+    } else {
+        let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+        if !full_path.is_dir() {
+            return Err(error_response(StatusCode::BAD_REQUEST, "Not a directory."));
+        }
+        tree::read_tree(state.mounts.root_of(&full_path), &full_path, depth).await
+    }
+    .map_err(|e| {
+        error!(
+            "Failed to read tree under {}: {}",
+            sanitized_req_path.display(),
+            e
+        );
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error reading directory contents.",
+        )
+    })?;
+    Ok(tree::render_tree(&nodes))
+}
+
+// --- dir_size_handler ---
+// The total size of a folder for its row in the listing; waits for the walk if the size
+// isn't cached yet.
+async fn dir_size_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if !full_path.is_dir() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Requested path is not a directory.",
+        ));
+    }
+
+    let size = state
+        .dir_sizes
+        .size(&state.mounts.root_of(&full_path).dir, &full_path)
+        .await;
+    Ok(html! {
+        span title=(format!("{} file{}", size.files, if size.files == 1 { "" } else { "s" })) {
+            (format_size(size.bytes, BINARY)) " "
+        }
+    })
+}
+
+// --- hash_handler ---
+// The SHA-256 of a file for its row in the listing when checksums are shown.
+async fn hash_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if !full_path.is_file() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Checksums are only computed for files.",
+        ));
+    }
+
+    Ok(match state.file_hashes.sha256(&full_path).await {
+        Ok(hash) => html! { code class="checksum" title="SHA-256" { (hash) } },
+        Err(e) => {
+            error!("Failed to hash {}: {}", full_path.display(), e);
+            html! { span class="checksum checksum-error" { "Checksum unavailable" } }
+        }
+    })
+}
+
+// --- usage_handler ---
+// Where the space in a folder goes: its files and subfolders, largest first, each with a
+// bar showing its share of the total.
+async fn usage_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<BrowseQuery>,
+) -> Result<Markup, Response> {
+    const USAGE_ROWS: usize = 200;
+
+    let sanitized_req_path = sanitize_path(query.path.as_deref().unwrap_or("."));
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if !full_path.is_dir() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Requested path is not a directory.",
+        ));
+    }
+
+    let mount = state.mounts.root_of(&full_path);
+    let mut usage = state
+        .dir_sizes
+        .usage(&mount.dir, &full_path)
+        .await
+        .map_err(|e| {
+            error!("Failed to read directory {}: {}", full_path.display(), e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error reading directory contents.",
+            )
+        })?;
+    usage.retain(|entry| auth::visible(&mount.relative(&entry.path)));
+    let total: u64 = usage.iter().map(|entry| entry.size.bytes).sum();
+    let total_files: u64 = usage.iter().map(|entry| entry.size.files).sum();
+    let rest = usage.get(USAGE_ROWS..).unwrap_or_default();
+    let rest_bytes: u64 = rest.iter().map(|entry| entry.size.bytes).sum();
+    let percent = |bytes: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            bytes as f64 * 100.0 / total as f64
+        }
+    };
+
+    let current_rel_path = rawnames::encode(&sanitized_req_path);
+    let display_path = if sanitized_req_path == Path::new(".") {
+        "/".to_string()
+    } else {
+        format!("/{}", current_rel_path)
+    };
+    let back_url = format!("/browse?path={}", urlencoding::encode(&current_rel_path));
+
+    Ok(html! {
+        div class="preview-container usage" {
+            div class="preview-header" {
+                h1 { "Disk usage: " (display_path) }
+                div class="preview-actions" {
+                    @if sanitized_req_path != Path::new(".") {
+                        @let parent = sanitized_req_path.parent().map(rawnames::encode).unwrap_or_else(|| ".".to_string());
+                        button hx-get=(format!("/usage?path={}", urlencoding::encode(&parent)))
+                               hx-target="#file-browser"
+                               hx-swap="innerHTML"
+                               class="close-button" { "⬆️ Up" }
+                    }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            p class="usage-total" {
+                strong { (format_size(total, BINARY)) }
+                " in " (total_files) @if total_files == 1 { " file" } @else { " files" }
+            }
+            @if usage.is_empty() {
+                p { em { "This folder is empty." } }
+            } @else {
+                ul class="usage-list" {
+                    @for entry in usage.iter().take(USAGE_ROWS) {
+                        @let share = percent(entry.size.bytes);
+                        @let row = html! {
+                            span class="usage-name" { @if entry.is_dir { "📁 " } @else { "📄 " } (entry.name) }
+                            span class="usage-bar" { span class="usage-fill" style=(format!("width: {:.1}%", share)) {} }
+                            span class="usage-size" { (format_size(entry.size.bytes, BINARY)) }
+                            span class="usage-percent" { (format!("{:.1}%", share)) }
+                        };
+                        @if entry.is_dir {
+                            li class="usage-dir"
+                               hx-get=(format!("/usage?path={}", urlencoding::encode(&state.mounts.relative(&entry.path))))
+                               hx-target="#file-browser"
+                               hx-swap="innerHTML" { (row) }
+                        } @else {
+                            li { (row) }
+                        }
+                    }
+                    @if !rest.is_empty() {
+                        li class="usage-rest" {
+                            span class="usage-name" { (rest.len()) " more items" }
+                            span class="usage-bar" { span class="usage-fill" style=(format!("width: {:.1}%", percent(rest_bytes))) {} }
+                            span class="usage-size" { (format_size(rest_bytes, BINARY)) }
+                            span class="usage-percent" { (format!("{:.1}%", percent(rest_bytes))) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// "→ target" after the name of a link shown with `--symlinks show`.
+fn link_target(item: &DirEntryInfo) -> Markup {
+    html! {
+        @if let Some(target) = &item.link_target {
+            span class="link-target" title=[(!item.openable).then_some("Broken, or outside the served folder")] {
+                " → " (target)
+            }
+        }
+    }
+}
+
+// Mode, owner and group at the start of a row's details, where the platform has them.
+fn ownership_info(item: &DirEntryInfo) -> Markup {
+    html! {
+        @if let Some(ownership) = &item.ownership {
+            span class="ownership" title=(format!("Owner {}, group {}", ownership.owner, ownership.group)) {
+                code class="mode" { (ownership.mode) }
+                " " (ownership.owner) " " (ownership.group) " · "
+            }
+        }
+    }
+}
+
+fn access_denied_marker(item: &DirEntryInfo) -> Markup {
+    html! {
+        @if item.access_denied {
+            span class="denied-marker" title="The server isn't allowed to read this entry" { "🔒 access denied" }
+        }
+    }
+}
+
+fn annotation(item: &DirEntryInfo) -> Markup {
+    html! {
+        @if let Some(annotation) = &item.annotation {
+            span class="annotation" title=(annotation) { (annotation) }
+        }
+    }
+}
+
+fn search_form(scope: &str, query: &str) -> Markup {
+    html! {
+        form #search-form hx-get="/search" hx-target="#file-browser" hx-swap="innerHTML" {
+            input type="hidden" name="path" value=(scope);
+            input type="search" name="q" value=(query) placeholder="Search this folder…" required;
+            button type="submit" { "🔍 Search" }
+        }
+    }
+}
+
+// --- search_handler ---
+// Recursive name search below a folder, rendered like a listing.
+async fn search_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(query.path.as_deref().unwrap_or("."));
+    let results = if state.mounts.is_top(&sanitized_req_path) {
+        search::search_mounts(state.mounts.list(), &query.q).await
+    } else {
+        let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+        if !full_path.is_dir() {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "Search is only supported in directories.",
+            ));
+        }
+        search::search(state.mounts.root_of(&full_path), &full_path, &query.q).await
+    };
+    let scope = rawnames::encode(&sanitized_req_path);
+    let scope_display = if sanitized_req_path == Path::new(".") {
+        "/".to_string()
+    } else {
+        format!("/{}", scope)
+    };
+    let back_url = format!("/browse?path={}", urlencoding::encode(&scope));
+
+    Ok(html! {
+        div #current-path-container data-path=(scope) {
+            div #current-path {
+                "Search: “" (query.q) "” in " (scope_display)
+                button class="gallery-button"
+                       hx-get=(back_url)
+                       hx-target="#file-browser"
+                       hx-swap="innerHTML" { "Back to folder" }
+            }
+            (search_form(&scope, &query.q))
+        }
+        div #file-list-container {
+            @if results.truncated {
+                div class="preview-banner" {
+                    "Showing the first " (results.hits.len()) " matches; refine the search to see more."
+                }
+            }
+            @if results.hits.is_empty() {
+                p class="search-empty" { "No files or folders match." }
+            }
+            ul #file-list {
+                @for hit in &results.hits {
+                    @let encoded_path = urlencoding::encode(&hit.path);
+                    @let parent = hit.path.rsplit_once('/').map_or("/", |(parent, _)| parent);
+                    @if hit.is_dir {
+                        li data-path=(hit.path) data-is-dir="true"
+                           hx-get=(format!("/browse?path={}", encoded_path))
+                           hx-target="#file-browser" hx-swap="innerHTML" style="cursor: pointer;" {
+                            div {
+                                span class="icon" { "📁" }
+                                span { (hit.name) }
+                            }
+                            div class="file-info search-hit-path" { (parent) }
+                        }
+                    } @else {
+                        @let kind = state.mounts.join(Path::new(&hit.path)).and_then(|path| preview_kind(&path));
+                        @let preview_url = preview_url(&state, kind, &encoded_path);
+                        li data-path=(hit.path) data-is-dir="false"
+                           hx-get=[preview_url.as_ref()]
+                           hx-target=[preview_url.as_ref().map(|_| "#file-browser")]
+                           hx-swap=[preview_url.as_ref().map(|_| "innerHTML")]
+                           style=[preview_url.as_ref().map(|_| "cursor: pointer;")] {
+                            div {
+                                span class="icon" { (kind.map_or("📄", PreviewKind::icon)) }
+                                span { (hit.name) }
+                            }
+                            div class="file-info search-hit-path" { (parent) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- quick_open_handler ---
+// Ranked fuzzy matches over every indexed path, for the Ctrl+P dialog.
+async fn quick_open_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<QuickOpenQuery>,
+) -> Markup {
+    if !state.quick_open.is_ready() {
+        return html! { p class="quick-open-note" { "Still indexing files…" } };
+    }
+    let task_state = state.clone();
+    let mut hits = tokio::task::spawn_blocking(move || {
+        task_state.quick_open.find(&query.q, quickopen::MAX_MATCHES)
+    })
+    .await
+    .unwrap_or_default();
+    hits.retain(|hit| auth::visible(&hit.path));
+
+    html! {
+        @if hits.is_empty() {
+            p class="quick-open-note" { "No matches." }
+        }
+        ul #quick-open-list {
+            @for hit in &hits {
+                @let encoded_path = urlencoding::encode(&hit.path);
+                @let url = if hit.is_dir {
+                    format!("/browse?path={}", encoded_path)
+                } else {
+                    let kind = state.mounts.join(Path::new(&hit.path)).and_then(|path| preview_kind(&path));
+                    let parent = hit.path.rsplit_once('/').map_or(".", |(parent, _)| parent);
+                    preview_url(&state, kind, &encoded_path)
+                        .unwrap_or_else(|| format!("/browse?path={}", urlencoding::encode(parent)))
+                };
+                li class="quick-open-hit" hx-get=(url) hx-target="#file-browser" hx-swap="innerHTML" {
+                    span class="icon" { (if hit.is_dir { "📁" } else { "📄" }) }
+                    span class="quick-open-path" {
+                        @for (i, c) in hit.path.chars().enumerate() {
+                            @if hit.positions.binary_search(&i).is_ok() {
+                                mark { (c) }
+                            } @else {
+                                (c)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// --- preview_handler ---
+async fn preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<TextPreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() {
+        error!("Preview attempt on non-file: {}", full_path.display());
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Preview is only supported for files.",
+        ));
+    }
+
+    // Check if file is previewable
+    if !is_text_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for preview.",
+        ));
+    }
+
+    let read_error = |e: std::io::Error| {
+        error!(
+            "Failed to read file for preview {}: {}",
+            full_path.display(),
+            e
+        );
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not read file content.",
+        )
+    };
+
+    // Read only one page of the file; large logs would otherwise be loaded whole. A
+    // requested line range picks the page that starts a few lines above it.
+    let highlight = query.line.as_deref().and_then(text::parse_line_range);
+    let anchor = match (query.before, query.offset, highlight) {
+        (Some(before), _, _) => text::ChunkAnchor::EndAt(before),
+        (None, Some(offset), _) => text::ChunkAnchor::StartAt(offset),
+        (None, None, Some((first, _))) => {
+            let context_start = first.saturating_sub(LINE_LINK_CONTEXT).max(1);
+            let offset = text::line_start(&full_path, context_start)
+                .await
+                .map_err(read_error)?;
+            text::ChunkAnchor::StartAt(offset.unwrap_or(0))
+        }
+        (None, None, None) => text::ChunkAnchor::StartAt(0),
+    };
+    let chunk = text::read_chunk(&full_path, anchor, state.preview_chunk_size)
+        .await
+        .map_err(read_error)?;
+    let first_line = text::line_number_at(&full_path, chunk.start)
+        .await
+        .map_err(read_error)?;
+    let line_count = chunk.text.lines().count() as u64;
+    // Highlight overlay position, in lines from the top of the page.
+    let highlight_lines = first_line
+        .zip(highlight)
+        .and_then(|(first_line, (start, end))| {
+            let last_line = first_line + line_count.max(1) - 1;
+            (start <= last_line && end >= first_line).then(|| {
+                let (start, end) = (start.max(first_line), end.min(last_line));
+                (start - first_line, end - start + 1)
+            })
+        });
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let detected_language = detect_language(&full_path);
+    let chosen_language = query
+        .lang
+        .as_deref()
+        .filter(|lang| HIGHLIGHT_LANGUAGES.iter().any(|(value, _)| value == lang));
+    let language = chosen_language.unwrap_or(&detected_language).to_string();
+    let lang_param = chosen_language
+        .map(|lang| format!("&lang={}", lang))
+        .unwrap_or_default();
+
+    // Get the parent directory for the back button
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let encoded_parent_path = urlencoding::encode(&parent_path);
+    let back_url = format!("/browse?path={}", encoded_parent_path);
+    let page_url = |param: &str, value: u64| {
+        format!(
+            "/preview?path={}&{}={}{}",
+            urlencoding::encode(&query.path),
+            param,
+            value,
+            lang_param
+        )
+    };
+
+    Ok(html! {
+        div class="preview-container" {
+            div class="preview-header" {
+                h1 { "File Preview: " (filename) }
+                div class="preview-actions" {
+                    // Re-renders the same page with another language.
+                    form class="language-picker" hx-get="/preview" hx-trigger="change"
+                         hx-target="#file-browser" hx-swap="innerHTML" {
+                        input type="hidden" name="path" value=(query.path);
+                        input type="hidden" name="offset" value=(chunk.start);
+                        @if let Some(line) = &query.line {
+                            input type="hidden" name="line" value=(line);
+                        }
+                        label {
+                            "Language "
+                            select name="lang" {
+                                option value="" selected[chosen_language.is_none()] {
+                                    "Auto (" (detected_language) ")"
+                                }
+                                @for (value, label) in HIGHLIGHT_LANGUAGES {
+                                    option value=(value) selected[chosen_language == Some(value)] { (label) }
+                                }
+                            }
+                        }
+                    }
+                    @if structured::structured_format(&full_path).is_some() {
+                        button hx-get=(format!("/structured-preview?path={}", urlencoding::encode(&query.path)))
+                               hx-target="#file-browser"
+                               hx-swap="innerHTML"
+                               class="close-button" { "Tree view" }
+                    }
+                    button hx-get=(format!("/tail?path={}", urlencoding::encode(&query.path)))
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Follow" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            @if !chunk.is_complete() {
+                div class="preview-banner" {
+                    "Large file: showing "
+                    (format_size(chunk.start, BINARY)) " – " (format_size(chunk.end, BINARY))
+                    " of " (format_size(chunk.file_len, BINARY)) "."
+                }
+                div class="preview-paging" {
+                    button hx-get=(page_url("offset", 0))
+                           hx-target="#file-browser" hx-swap="innerHTML"
+                           disabled[!chunk.has_previous()] { "⏮ Start" }
+                    button hx-get=(page_url("before", chunk.start))
+                           hx-target="#file-browser" hx-swap="innerHTML"
+                           disabled[!chunk.has_previous()] { "◀ Previous" }
+                    button hx-get=(page_url("offset", chunk.end))
+                           hx-target="#file-browser" hx-swap="innerHTML"
+                           disabled[!chunk.has_next()] { "Next ▶" }
+                    button hx-get=(page_url("before", chunk.file_len))
+                           hx-target="#file-browser" hx-swap="innerHTML"
+                           disabled[!chunk.has_next()] { "Jump to end ⏭" }
+                }
+            }
+            div class="preview-content" {
+                div class="code-with-lines" data-preview-path=(query.path) data-preview-lang=[chosen_language] {
+                    @if let Some(first_line) = first_line {
+                        pre class="line-numbers" {
+                            @for number in first_line..first_line + line_count.max(1) {
+                                a id={"L" (number)} href=(format!("/?preview={}&line={}{}", urlencoding::encode(&query.path), number, lang_param)) { (number) }
+                                "\n"
+                            }
+                        }
+                    }
+                    @if let Some((offset, count)) = highlight_lines {
+                        div class="line-highlight"
+                            style=(format!("--hl-start: {}; --hl-count: {};", offset, count)) {}
+                    }
+                    pre {
+                        code class=(format!("language-{}", language)) {
+                            (chunk.text)
+                        }
+                    }
+                }
+            }
+        }
+        script {
+            (PreEscaped(&format!("
+                console.log('Preview content loaded for language: {}');
+                console.log('hljs available:', typeof hljs !== 'undefined');
+                if (typeof hljs !== 'undefined') {{
+                    console.log('Calling hljs.highlightAll() from preview');
+                    hljs.highlightAll();
+                }}
+            ", language)))
+        }
+    })
+}
+
+// --- structured_preview_handler ---
+// JSON and YAML as a collapsible tree. Large arrays and objects are paged, and deep
+// parts of big documents are loaded from `/structured-node` as they are expanded.
+async fn structured_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    let Some(format) = structured::structured_format(&full_path).filter(|_| full_path.is_file())
+    else {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Tree view is only available for JSON and YAML files.",
+        ));
+    };
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let text_url = format!("/preview?path={}", encoded_path);
+    let node_url = |pointer: &str, offset: usize| {
+        format!(
+            "/structured-node?path={}&pointer={}&offset={}",
+            encoded_path,
+            urlencoding::encode(pointer),
+            offset
+        )
+    };
+
+    let document = structured::parse(&full_path, format).await;
+
+    Ok(html! {
+        div class="preview-container structured-preview" {
+            div class="preview-header" {
+                h1 { "Tree View: " (filename) }
+                div class="preview-actions" {
+                    button hx-get=(text_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Text view" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            @match &document {
+                Ok(value) if value.is_object() || value.is_array() => {
+                    ul class="json-tree" {
+                        (structured::render_children(value, "", 0, &node_url))
+                    }
+                }
+                Ok(value) => {
+                    div class="json-tree" { (structured::scalar(value)) }
+                }
+                Err(e) => {
+                    div class="preview-banner" { "Could not parse the file: " (e) }
+                }
+            }
+        }
+    })
+}
+
+// --- structured_node_handler ---
+async fn structured_node_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<StructuredNodeQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    let Some(format) = structured::structured_format(&full_path).filter(|_| full_path.is_file())
+    else {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Tree view is only available for JSON and YAML files.",
+        ));
+    };
+    let document = structured::parse(&full_path, format)
+        .await
+        .map_err(|e| error_response(StatusCode::UNPROCESSABLE_ENTITY, &e))?;
+    let node = document.pointer(&query.pointer).ok_or_else(|| {
+        error_response(
+            StatusCode::NOT_FOUND,
+            "That part of the document no longer exists.",
+        )
+    })?;
+
+    let encoded_path = urlencoding::encode(&query.path);
+    let node_url = |pointer: &str, offset: usize| {
+        format!(
+            "/structured-node?path={}&pointer={}&offset={}",
+            encoded_path,
+            urlencoding::encode(pointer),
+            offset
+        )
+    };
+    Ok(structured::render_children(
+        node,
+        &query.pointer,
+        query.offset.unwrap_or(0),
+        &node_url,
+    ))
+}
+
+// --- tail_handler ---
+// "tail -f" view: the end of the file, then everything appended to it, live.
+async fn tail_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !is_text_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for following.",
+        ));
+    }
+
+    let chunk = text::read_chunk(
+        &full_path,
+        text::ChunkAnchor::EndAt(u64::MAX),
+        state.preview_chunk_size,
+    )
+    .await
+    .map_err(|e| {
+        error!(
+            "Failed to read file for tail {}: {}",
+            full_path.display(),
+            e
+        );
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not read file content.",
+        )
+    })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+    let encoded_path = urlencoding::encode(&query.path);
+    let preview_url = format!("/preview?path={}", encoded_path);
+    let events_url = format!("/tail/events?path={}", encoded_path);
+
+    Ok(html! {
+        div class="preview-container tail-preview" {
+            div class="preview-header" {
+                h1 { "Following: " (filename) }
+                div class="preview-actions" {
+                    button type="button" class="close-button tail-toggle" { "Pause" }
+                    button hx-get=(preview_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Stop" }
+                }
+            }
+            div class="tail-status" { "Connecting…" }
+            div class="preview-content tail-output" data-tail-url=(events_url) data-tail-offset=(chunk.end) {
+                pre { (chunk.text) }
+            }
+        }
+    })
+}
+
+// --- tail_events_handler ---
+async fn tail_events_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<TailQuery>,
+    headers: HeaderMap,
+) -> Result<Response, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !is_text_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for following.",
+        ));
+    }
+
+    // A reconnecting EventSource reports the offset of the last event it received.
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    let from = match last_event_id.or(query.from) {
+        Some(from) => from,
+        None => tokio::fs::metadata(&full_path)
+            .await
+            .map(|metadata| metadata.len())
+            .unwrap_or(0),
+    };
+
+    Ok(Sse::new(tail::follow(full_path, from))
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+// --- image_preview_handler ---
+async fn image_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() {
+        error!("Image preview attempt on non-file: {}", full_path.display());
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Preview is only supported for files.",
+        ));
+    }
+
+    // Check if file is an image
+    if !is_image_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for image preview.",
+        ));
+    }
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    // Get the parent directory for the back button
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let encoded_parent_path = urlencoding::encode(&parent_path);
+    let back_url = format!("/browse?path={}", encoded_parent_path);
+
+    // Create the image URL for display
+    let encoded_image_path = urlencoding::encode(&query.path);
+    // A screen-sized rendition; the original can be opened separately.
+    let image_url = format!("/image?path={}&w=1920", encoded_image_path);
+    // Formats the browser can't show open as their full-size JPEG conversion instead.
+    let original_url = match convert::source_format(&full_path) {
+        Some(_) => format!("/image?path={}", encoded_image_path),
+        None => format!("/raw?path={}", encoded_image_path),
+    };
+
+    let exif = media::image_metadata(&full_path, state.show_exif_gps).await;
+
+    Ok(html! {
+        div class="preview-container image-preview" {
+            div class="preview-header" {
+                h1 { "Image Preview: " (filename) }
+                div class="preview-actions" {
+                    a href=(original_url) class="download-button" target="_blank" { "Full size" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="image-preview-content" {
+                img src=(image_url) alt=(filename) class="preview-image" {}
+            }
+            @if !exif.is_empty() {
+                div class="file-meta exif-meta" {
+                    @if let Some(camera) = &exif.camera { div { strong { "Camera:" } (camera) } }
+                    @if let Some(lens) = &exif.lens { div { strong { "Lens:" } (lens) } }
+                    @if let Some(taken_at) = &exif.taken_at { div { strong { "Taken:" } (taken_at) } }
+                    @let exposure: Vec<&str> = [&exif.exposure_time, &exif.aperture, &exif.focal_length]
+                        .into_iter()
+                        .flatten()
+                        .map(String::as_str)
+                        .collect();
+                    @if !exposure.is_empty() { div { strong { "Exposure:" } (exposure.join(", ")) } }
+                    @if let Some(iso) = &exif.iso { div { strong { "ISO:" } (iso) } }
+                    @if let Some((latitude, longitude)) = exif.gps {
+                        div {
+                            strong { "Location:" }
+                            a href=(format!("https://www.openstreetmap.org/?mlat={0:.6}&mlon={1:.6}#map=15/{0:.6}/{1:.6}", latitude, longitude))
+                              target="_blank" rel="noopener noreferrer" {
+                                (format!("{:.5}, {:.5}", latitude, longitude))
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- video_preview_handler ---
+async fn video_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !is_video_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for video preview.",
+        ));
+    }
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let media_url = format!("/media?path={}", urlencoding::encode(&query.path));
+    let poster_url = format!("/poster?path={}", urlencoding::encode(&query.path));
+    let mime_type = mime_guess::from_path(&full_path)
+        .first_or_octet_stream()
+        .to_string();
+    let sidecars = subtitles::sidecars(&full_path).await;
+    let hls_url = state
+        .transcoder
+        .as_ref()
+        .map(|_| format!("/hls/start?path={}", urlencoding::encode(&query.path)));
+    let parent_dir = sanitized_req_path.parent().unwrap_or(Path::new(""));
+
+    Ok(html! {
+        div class="preview-container video-preview" {
+            div class="preview-header" {
+                h1 { "Video Preview: " (filename) }
+                div class="preview-actions" {
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="media-preview-content" {
+                video controls preload="metadata" poster=(poster_url) class="preview-video"
+                      data-hls-src=[hls_url.as_ref()] {
+                    source src=(media_url) type=(mime_type);
+                    @for (index, sidecar) in sidecars.iter().enumerate() {
+                        @let relative_path = parent_dir
+                            .join(sidecar.path.file_name().unwrap_or_default())
+                            .to_string_lossy()
+                            .replace('\\', "/");
+                        track kind="subtitles"
+                              src=(format!("/subtitles?path={}", urlencoding::encode(&relative_path)))
+                              label=(sidecar.label)
+                              srclang=[sidecar.language.as_deref()]
+                              default[index == 0];
+                    }
+                    "Your browser cannot play this video."
+                }
+                // Played automatically when the browser rejects the original, or by hand
+                // when it "plays" with a black picture.
+                @if hls_url.is_some() {
+                    div class="transcode-controls" {
+                        button type="button" class="transcode-button" { "Play transcoded" }
+                        span class="transcode-status" {}
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- audio_preview_handler ---
+async fn audio_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !is_audio_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for audio preview.",
+        ));
+    }
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let media_url = format!("/media?path={}", urlencoding::encode(&query.path));
+    let mime_type = mime_guess::from_path(&full_path)
+        .first_or_octet_stream()
+        .to_string();
+    let metadata = media::audio_metadata(&full_path).await;
+
+    Ok(html! {
+        div class="preview-container audio-preview" {
+            div class="preview-header" {
+                h1 { "Audio Preview: " (filename) }
+                div class="preview-actions" {
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="media-preview-content" {
+                div class="file-meta audio-meta" {
+                    div { strong { "Title:" } (metadata.title.as_deref().unwrap_or(&filename)) }
+                    @if let Some(artist) = &metadata.artist { div { strong { "Artist:" } (artist) } }
+                    @if let Some(album) = &metadata.album { div { strong { "Album:" } (album) } }
+                    @if let Some(duration) = metadata.duration {
+                        div { strong { "Duration:" } (media::format_duration(duration)) }
+                    }
+                    @if let Some(bitrate) = metadata.bitrate_kbps {
+                        div {
+                            strong { "Quality:" } (bitrate) " kbps"
+                            @if let Some(rate) = metadata.sample_rate { ", " (rate) " Hz" }
+                        }
+                    }
+                }
+                audio controls preload="metadata" class="preview-audio" {
+                    source src=(media_url) type=(mime_type);
+                    "Your browser cannot play this audio file."
+                }
+            }
+        }
+    })
+}
+
+// --- pdf_preview_handler ---
+async fn pdf_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !is_pdf_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for PDF preview.",
+        ));
+    }
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let media_url = format!("/media?path={}", encoded_path);
+    let download_url = format!("/media?path={}&download=true", encoded_path);
+
+    Ok(html! {
+        div class="preview-container pdf-preview" {
+            div class="preview-header" {
+                h1 { "PDF Preview: " (filename) }
+                div class="preview-actions" {
+                    a href=(download_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="pdf-preview-content" {
+                // Browsers without a built-in PDF viewer render the fallback content instead.
+                object data=(media_url) type="application/pdf" class="preview-pdf" {
+                    div class="pdf-fallback" {
+                        p { "This browser cannot display PDFs inline." }
+                        a href=(download_url) class="download-button" download=(filename) { "Download PDF" }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- font_preview_handler ---
+const FONT_SPECIMEN_SIZES: [u32; 7] = [12, 16, 20, 28, 36, 48, 72];
+const FONT_SAMPLE_SENTENCE: &str = "The quick brown fox jumps over the lazy dog.";
+
+async fn font_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !is_font_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for font preview.",
+        ));
+    }
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let raw_url = format!("/raw?path={}", encoded_path);
+
+    // The font is loaded straight from /raw under a family name derived from the path.
+    // Both only contain URL-safe characters, so they can go into the stylesheet verbatim.
+    let family = format!(
+        "kiv-specimen-{}",
+        query
+            .path
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    );
+    let font_face = format!(
+        "@font-face {{ font-family: \"{family}\"; src: url(\"{raw_url}\"); font-display: block; }}\n\
+         .font-specimen {{ font-family: \"{family}\", sans-serif; }}"
+    );
+
+    Ok(html! {
+        div class="preview-container font-preview" {
+            style { (PreEscaped(font_face)) }
+            div class="preview-header" {
+                h1 { "Font Preview: " (filename) }
+                div class="preview-actions" {
+                    a href=(raw_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="preview-content font-specimen" {
+                section class="font-alphabet" {
+                    p { "ABCDEFGHIJKLMNOPQRSTUVWXYZ" }
+                    p { "abcdefghijklmnopqrstuvwxyz" }
+                    p { "0123456789 !?&@#$%*()[]{}.,;:'\"/\\-+=" }
+                }
+                section class="font-sizes" {
+                    @for size in FONT_SPECIMEN_SIZES {
+                        div class="font-size-row" {
+                            span class="font-size-label" { (size) "px" }
+                            // Editable so recipients can try their own text.
+                            p style=(format!("font-size: {}px", size)) contenteditable="true" spellcheck="false" {
+                                (FONT_SAMPLE_SENTENCE)
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- email_preview_handler ---
+async fn email_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !email::is_email_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for email preview.",
+        ));
+    }
+
+    let message = email::parse(&full_path).await.map_err(|e| {
+        error!("Failed to parse email {}: {}", full_path.display(), e);
+        error_response(StatusCode::UNPROCESSABLE_ENTITY, &e)
+    })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let download_url = format!("/raw?path={}", encoded_path);
+
+    let header_rows = [
+        ("From", &message.from),
+        ("To", &message.to),
+        ("Cc", &message.cc),
+        ("Date", &message.date),
+    ];
+
+    Ok(html! {
+        div class="preview-container email-preview" {
+            div class="preview-header" {
+                h1 { "Email Preview: " (filename) }
+                div class="preview-actions" {
+                    a href=(download_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="preview-content email-content" {
+                h2 class="email-subject" { (message.subject.as_deref().unwrap_or("(no subject)")) }
+                table class="email-headers" {
+                    @for (label, value) in header_rows {
+                        @if let Some(value) = value {
+                            tr { th { (label) } td { (value) } }
+                        }
+                    }
+                }
+                @if !message.attachments.is_empty() {
+                    div class="email-attachments" {
+                        h3 { "Attachments (" (message.attachments.len()) ")" }
+                        ul {
+                            @for (index, attachment) in message.attachments.iter().enumerate() {
+                                li {
+                                    a href=(format!("/email-attachment?path={}&index={}", encoded_path, index))
+                                      download=(attachment.name) { "📎 " (attachment.name) }
+                                    span class="email-attachment-meta" {
+                                        " " (attachment.mime_type) ", "
+                                        (format_size(attachment.size, BINARY))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                @if let Some(html_body) = &message.html_body {
+                    // Sanitized by email::parse; scripts, styles and images are removed.
+                    div class="email-body email-html" { (PreEscaped(html_body)) }
+                    @if let Some(text_body) = &message.text_body {
+                        details class="email-plain-text" {
+                            summary { "Plain-text version" }
+                            pre class="email-body" { (text_body) }
+                        }
+                    }
+                } @else if let Some(text_body) = &message.text_body {
+                    pre class="email-body email-text" { (text_body) }
+                } @else {
+                    p { em { "This message has no text body." } }
+                }
+            }
+        }
+    })
+}
+
+// --- epub_preview_handler ---
+// One chapter at a time, with previous/next buttons and a chapter picker.
+async fn epub_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<EpubQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !epub::is_epub_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for EPUB preview.",
+        ));
+    }
+
+    let book = epub::open_book(&full_path).await.map_err(|e| {
+        error!("Failed to open EPUB {}: {}", full_path.display(), e);
+        error_response(StatusCode::UNPROCESSABLE_ENTITY, &e)
+    })?;
+    let index = query.chapter.unwrap_or(0);
+    let Some(chapter) = book.chapters.get(index) else {
+        return Err(error_response(StatusCode::NOT_FOUND, "No such chapter."));
+    };
+
+    let resource_path = query.path.clone();
+    let chapter_html = epub::chapter_html(&full_path, &chapter.member, move |item| {
+        format!(
+            "/epub-resource?path={}&item={}",
+            urlencoding::encode(&resource_path),
+            urlencoding::encode(item)
+        )
+    })
+    .await
+    .map_err(|e| {
+        error!("Failed to render EPUB chapter {}: {}", chapter.member, e);
+        error_response(StatusCode::UNPROCESSABLE_ENTITY, &e)
+    })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let download_url = format!("/raw?path={}", encoded_path);
+    let chapter_url =
+        |index: usize| format!("/epub-preview?path={}&chapter={}", encoded_path, index);
+    let chapter_label = |index: usize, chapter: &epub::Chapter| {
+        chapter
+            .title
+            .clone()
+            .unwrap_or_else(|| format!("Section {}", index + 1))
+    };
+    let has_previous = index > 0;
+    let has_next = index + 1 < book.chapters.len();
+
+    let chapter_nav = html! {
+        div class="epub-nav" {
+            button hx-get=(chapter_url(index.saturating_sub(1)))
+                   hx-target="#file-browser" hx-swap="innerHTML show:top"
+                   disabled[!has_previous] { "◀ Previous" }
+            form hx-get="/epub-preview" hx-trigger="change"
+                 hx-target="#file-browser" hx-swap="innerHTML show:top" {
+                input type="hidden" name="path" value=(query.path);
+                select name="chapter" {
+                    @for (i, entry) in book.chapters.iter().enumerate() {
+                        option value=(i) selected[i == index] { (chapter_label(i, entry)) }
+                    }
+                }
+            }
+            button hx-get=(chapter_url(index + 1))
+                   hx-target="#file-browser" hx-swap="innerHTML show:top"
+                   disabled[!has_next] { "Next ▶" }
+        }
+    };
+
+    Ok(html! {
+        div class="preview-container epub-preview" {
+            div class="preview-header" {
+                h1 { "Book: " (book.title.as_deref().unwrap_or(&filename)) }
+                div class="preview-actions" {
+                    a href=(download_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            @if let Some(author) = &book.author {
+                p class="epub-author" { "by " (author) }
+            }
+            (chapter_nav)
+            // Sanitized by epub::chapter_html.
+            article class="epub-chapter" { (PreEscaped(chapter_html)) }
+            (chapter_nav)
+        }
+    })
+}
+
+// --- epub_resource_handler ---
+// Images and other files referenced by a chapter, served out of the book inline.
+async fn epub_resource_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<EpubResourceQuery>,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !epub::is_epub_file(&full_path) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for EPUB preview.",
+        );
+    }
+
+    let format = archive::ArchiveFormat::Zip;
+    let entry = match archive::find_entry(&full_path, format, &query.item).await {
+        Ok(Some(entry)) if !entry.is_dir => entry,
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "No such file in the book."),
+        Err(e) => {
+            error!("Failed to read EPUB {}: {}", full_path.display(), e);
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Could not read the book's contents.",
+            );
+        }
+    };
+
+    let mime_type = mime_guess::from_path(&entry.name)
+        .first_or_octet_stream()
+        .to_string();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&mime_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(entry.size));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    if serve::is_scriptable(&mime_type) {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(serve::SANDBOX_CSP),
+        );
+    }
+
+    let reader = archive::stream_entry(full_path, format, entry.name);
+    let body = axum::body::Body::from_stream(ReaderStream::new(reader));
+    (StatusCode::OK, headers, body).into_response()
+}
+
+// --- gallery_handler ---
+// Thumbnail grid of a directory's images; gallery.js turns it into a slideshow.
+async fn gallery_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<BrowseQuery>,
+) -> Result<Markup, Response> {
+    let requested_path_str = query.path.unwrap_or_else(|| ".".to_string());
+    let sanitized_req_path = sanitize_path(&requested_path_str);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_dir() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Requested path is not a directory.",
+        ));
+    }
+
+    let mut entries = fs::read_dir(&full_path).await.map_err(|e| {
+        error!("Failed to read directory {}: {}", full_path.display(), e);
+        error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error reading directory contents.",
+        )
+    })?;
+    let mount = state.mounts.root_of(&full_path);
+    let mut images = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let entry_path = entry.path();
+        if !policy::is_listed(&mount.dir, &entry_path)
+            || !auth::visible(&mount.relative(&entry_path))
+            || !is_image_file(&entry_path)
+            || !entry.file_type().await.is_ok_and(|t| t.is_file())
+        {
+            continue;
+        }
+        let name = rawnames::display(&entry.file_name());
+        images.push((name, mount.relative(&entry_path)));
+    }
+    images.sort_by(|(a, _), (b, _)| natsort::natural_cmp(a, b));
+
+    let current_rel_path = rawnames::encode(&sanitized_req_path);
+    let display_path = if sanitized_req_path == Path::new(".") {
+        "/".to_string()
+    } else {
+        format!("/{}", current_rel_path)
+    };
+    let back_url = format!("/browse?path={}", urlencoding::encode(&current_rel_path));
+
+    Ok(html! {
+        div class="preview-container gallery" {
+            div class="preview-header" {
+                h1 { "Gallery: " (display_path) }
+                div class="preview-actions" {
+                    @if !images.is_empty() {
+                        button class="close-button gallery-slideshow-start" { "▶ Slideshow" }
+                    }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            @if images.is_empty() {
+                p { em { "This folder contains no images." } }
+            } @else {
+                div class="gallery-grid" {
+                    @for (index, (name, path)) in images.iter().enumerate() {
+                        @let encoded_path = urlencoding::encode(path);
+                        figure class="gallery-item"
+                               data-index=(index)
+                               data-name=(name)
+                               data-full-src=(format!("/image?path={}&w=2560", encoded_path))
+                               data-preview-url=(format!("/image-preview?path={}", encoded_path)) {
+                            img src=(format!("/image?path={}&w=400", encoded_path))
+                                alt=(name) loading="lazy" decoding="async";
+                            figcaption { (name) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- comic_preview_handler ---
+// Page-by-page viewer; each page is pulled out of the archive on request.
+async fn comic_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<ComicQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() || !comics::is_comic_file(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for comic preview.",
+        ));
+    }
+
+    let (_, pages) = comics::list_pages(&full_path).await.map_err(|e| {
+        error!("Failed to list comic {}: {}", full_path.display(), e);
+        error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Could not read the comic archive.",
+        )
+    })?;
+    if pages.is_empty() {
+        return Err(error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The archive contains no images.",
+        ));
+    }
+    let index = query.page.unwrap_or(0).min(pages.len() - 1);
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let download_url = format!("/raw?path={}", encoded_path);
+    let viewer_url = |page: usize| format!("/comic-preview?path={}&page={}", encoded_path, page);
+    let page_url = |page: usize| format!("/comic-page?path={}&page={}", encoded_path, page);
+    let has_previous = index > 0;
+    let has_next = index + 1 < pages.len();
+
+    Ok(html! {
+        div class="preview-container comic-preview" {
+            div class="preview-header" {
+                h1 { "Comic: " (filename) }
+                div class="preview-actions" {
+                    a href=(download_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="comic-nav" {
+                button hx-get=(viewer_url(0))
+                       hx-target="#file-browser" hx-swap="innerHTML"
+                       disabled[!has_previous] { "⏮" }
+                button class="comic-previous" hx-get=(viewer_url(index.saturating_sub(1)))
+                       hx-target="#file-browser" hx-swap="innerHTML"
+                       disabled[!has_previous] { "◀ Previous" }
+                form hx-get="/comic-preview" hx-trigger="change"
+                     hx-target="#file-browser" hx-swap="innerHTML" {
+                    input type="hidden" name="path" value=(query.path);
+                    select name="page" {
+                        @for page in 0..pages.len() {
+                            option value=(page) selected[page == index] { "Page " (page + 1) }
+                        }
+                    }
+                    " of " (pages.len())
+                }
+                button class="comic-next" hx-get=(viewer_url(index + 1))
+                       hx-target="#file-browser" hx-swap="innerHTML"
+                       disabled[!has_next] { "Next ▶" }
+                button hx-get=(viewer_url(pages.len() - 1))
+                       hx-target="#file-browser" hx-swap="innerHTML"
+                       disabled[!has_next] { "⏭" }
+            }
+            // Clicking the right half of the page turns forward, the left half back.
+            div class="comic-page" {
+                img src=(page_url(index)) alt=(format!("Page {}", index + 1));
+                @if has_previous {
+                    div class="comic-turn comic-turn-back"
+                        hx-get=(viewer_url(index - 1)) hx-target="#file-browser" hx-swap="innerHTML" {}
+                }
+                @if has_next {
+                    div class="comic-turn comic-turn-forward"
+                        hx-get=(viewer_url(index + 1)) hx-target="#file-browser" hx-swap="innerHTML" {}
+                    link rel="prefetch" href=(page_url(index + 1));
+                }
+            }
+        }
+    })
+}
+
+// --- comic_page_handler ---
+async fn comic_page_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<ComicQuery>,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !comics::is_comic_file(&full_path) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for comic preview.",
+        );
+    }
+
+    let (container, pages) = match comics::list_pages(&full_path).await {
+        Ok(listing) => listing,
+        Err(e) => {
+            error!("Failed to list comic {}: {}", full_path.display(), e);
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Could not read the comic archive.",
+            );
+        }
+    };
+    let Some(name) = pages.into_iter().nth(query.page.unwrap_or(0)) else {
+        return error_response(StatusCode::NOT_FOUND, "No such page.");
+    };
+
+    let mime_type = mime_guess::from_path(&name)
+        .first_or_octet_stream()
+        .to_string();
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&mime_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    match container {
+        comics::ComicContainer::Zip => {
+            let reader = archive::stream_entry(full_path, archive::ArchiveFormat::Zip, name);
+            let body = axum::body::Body::from_stream(ReaderStream::new(reader));
+            (StatusCode::OK, headers, body).into_response()
+        }
+        comics::ComicContainer::Rar => match comics::read_rar_page(full_path, name).await {
+            Ok(data) => (StatusCode::OK, headers, data).into_response(),
+            Err(e) => {
+                error!("Failed to extract comic page: {}", e);
+                error_response(StatusCode::UNPROCESSABLE_ENTITY, "Could not read the page.")
+            }
+        },
+    }
+}
+
+// --- email_attachment_handler ---
+async fn email_attachment_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<EmailAttachmentQuery>,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !email::is_email_file(&full_path) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for email preview.",
+        );
+    }
+
+    let (filename, contents) = match email::attachment(&full_path, query.index).await {
+        Ok(Some(attachment)) => attachment,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "No such attachment."),
+        Err(e) => {
+            error!("Failed to parse email {}: {}", full_path.display(), e);
+            return error_response(StatusCode::UNPROCESSABLE_ENTITY, &e);
+        }
+    };
+    let mime_type = mime_guess::from_path(&filename)
+        .first_or_octet_stream()
+        .to_string();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&mime_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        serve::disposition(false, &filename),
+    );
+    (StatusCode::OK, headers, contents).into_response()
+}
+
+// --- data_preview_handler ---
+#[cfg(feature = "data-preview")]
+async fn data_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    let Some(format) = tabular::data_format(&full_path).filter(|_| full_path.is_file()) else {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for data preview.",
+        ));
+    };
+
+    let table = tabular::preview(&full_path, format).await.map_err(|e| {
+        error!("Failed to read data file {}: {}", full_path.display(), e);
+        error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Could not read the data file.",
+        )
+    })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let download_url = format!("/raw?path={}", urlencoding::encode(&query.path));
+
+    Ok(html! {
+        div class="preview-container data-preview" {
+            div class="preview-header" {
+                h1 { "Data Preview: " (filename) }
+                div class="preview-actions" {
+                    a href=(download_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="file-meta data-meta" {
+                div { strong { "Format:" } (format.label()) }
+                div { strong { "Columns:" } (table.columns.len()) }
+                div {
+                    strong { "Rows:" }
+                    @match table.total_rows {
+                        Some(total) => { (total) },
+                        None => { "at least " (table.rows.len()) },
+                    }
+                }
+            }
+            @if table.total_rows.is_none_or(|total| total > table.rows.len() as u64) {
+                div class="preview-banner" {
+                    "Showing the first " (table.rows.len()) " rows."
+                }
+            }
+            details class="data-schema" {
+                summary { "Schema" }
+                table class="data-table" {
+                    thead { tr { th { "Column" } th { "Type" } th { "Nullable" } } }
+                    tbody {
+                        @for column in &table.columns {
+                            tr {
+                                td { (column.name) }
+                                td { code { (column.data_type) } }
+                                td { (if column.nullable { "yes" } else { "no" }) }
+                            }
+                        }
+                    }
+                }
+            }
+            div class="data-table-wrapper" {
+                table class="data-table" {
+                    thead {
+                        tr {
+                            @for column in &table.columns {
+                                th title=(column.data_type) { (column.name) }
+                            }
+                        }
+                    }
+                    tbody {
+                        @for row in &table.rows {
+                            tr {
+                                @for cell in row {
+                                    td { (cell) }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- archive_preview_handler ---
+async fn archive_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    let Some(format) = archive::archive_format(&full_path).filter(|_| full_path.is_file()) else {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for archive preview.",
+        ));
+    };
+
+    let listing = archive::list_entries(&full_path, format)
+        .await
+        .map_err(|e| {
+            error!("Failed to list archive {}: {}", full_path.display(), e);
+            error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Could not read the archive's contents.",
+            )
+        })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let encoded_path = urlencoding::encode(&query.path);
+    let total_size: u64 = listing.entries.iter().map(|entry| entry.size).sum();
+
+    Ok(html! {
+        div class="preview-container archive-preview" {
+            div class="preview-header" {
+                h1 { "Archive Preview: " (filename) }
+                div class="preview-actions" {
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="file-meta" {
+                div { strong { "Format:" } (format.label()) }
+                div { strong { "Entries:" } (listing.entries.len()) }
+                div { strong { "Unpacked:" } (format_size(total_size, BINARY)) }
+            }
+            @if listing.truncated {
+                div class="preview-banner" {
+                    "Only the first " (archive::MAX_LISTED_ENTRIES) " entries are shown."
+                }
+            }
+            div class="preview-content" {
+                table class="archive-entries" {
+                    thead {
+                        tr { th { "Name" } th { "Size" } th { "Compressed" } }
+                    }
+                    tbody {
+                        @for entry in &listing.entries {
+                            tr {
+                                td {
+                                    @if entry.is_dir {
+                                        span class="icon" { "📁" } (entry.name)
+                                    } @else {
+                                        a href=(format!("/archive-entry?path={}&entry={}", encoded_path, urlencoding::encode(&entry.name))) {
+                                            (entry.name)
+                                        }
+                                    }
+                                }
+                                td { @if !entry.is_dir { (format_size(entry.size, BINARY)) } }
+                                td {
+                                    @if let Some(compressed) = entry.compressed_size.filter(|_| !entry.is_dir) {
+                                        (format_size(compressed, BINARY))
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+// --- document_preview_handler ---
+async fn document_preview_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    let Some(format) = documents::document_format(&full_path).filter(|_| full_path.is_file())
+    else {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for document preview.",
+        ));
+    };
+
+    let extracted = documents::extract_text(&full_path, format)
+        .await
+        .map_err(|e| {
+            error!("Failed to extract text from {}: {}", full_path.display(), e);
+            error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Could not read the document's text.",
+            )
+        })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let download_url = format!("/raw?path={}", urlencoding::encode(&query.path));
+
+    Ok(html! {
+        div class="preview-container document-preview" {
+            div class="preview-header" {
+                h1 { "Document Preview: " (filename) }
+                div class="preview-actions" {
+                    a href=(download_url) class="download-button" download=(filename) { "Download" }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="preview-banner" {
+                (format.label()) " — text only; formatting, images and layout are not shown."
+                @if extracted.truncated { " The document is long, so only the beginning is shown." }
+            }
+            div class="preview-content document-text" {
+                @for paragraph in extracted.paragraphs.iter().filter(|p| !p.text.trim().is_empty()) {
+                    @if paragraph.is_heading {
+                        h3 { (paragraph.text) }
+                    } @else {
+                        p { (paragraph.text) }
+                    }
+                }
+                @if extracted.paragraphs.iter().all(|p| p.text.trim().is_empty()) {
+                    p { em { "This document contains no text." } }
+                }
+            }
+        }
+    })
+}
+
+// --- archive_entry_handler ---
+// Streams a single archive member to the client, decompressing on the fly.
+async fn archive_entry_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<ArchiveEntryQuery>,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    let Some(format) = archive::archive_format(&full_path).filter(|_| full_path.is_file()) else {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for archive preview.",
+        );
+    };
+
+    let entry = match archive::find_entry(&full_path, format, &query.entry).await {
+        Ok(Some(entry)) if !entry.is_dir => entry,
+        Ok(_) => return error_response(StatusCode::NOT_FOUND, "No such file in the archive."),
+        Err(e) => {
+            error!("Failed to read archive {}: {}", full_path.display(), e);
+            return error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Could not read the archive's contents.",
+            );
+        }
+    };
+
+    let filename = entry
+        .name
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download")
+        .to_string();
+    let mime_type = mime_guess::from_path(&filename)
+        .first_or_octet_stream()
+        .to_string();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&mime_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(entry.size));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        serve::disposition(false, &filename),
+    );
+
+    let reader = archive::stream_entry(full_path, format, entry.name);
+    let body = axum::body::Body::from_stream(bandwidth::throttle(ReaderStream::new(reader)));
+    (StatusCode::OK, headers, body).into_response()
+}
+
+// --- media_handler ---
+// Inline, seekable (Range-aware) delivery for the media and PDF preview viewers.
+async fn media_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<MediaQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+
+    if !full_path.is_file()
+        || !(is_video_file(&full_path) || is_audio_file(&full_path) || is_pdf_file(&full_path))
+    {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for inline playback.",
+        );
+    }
+
+    let disposition = if query.download {
+        let filename = full_path
+            .file_name()
+            .map(rawnames::display)
+            .unwrap_or_default();
+        serve::disposition(false, &filename)
+    } else {
+        HeaderValue::from_static("inline")
+    };
+    let mut extra_headers = HeaderMap::new();
+    extra_headers.insert(header::CONTENT_DISPOSITION, disposition);
+    serve::file_response(&full_path, &headers, extra_headers).await
+}
+
+// --- subtitles_handler ---
+// Subtitle sidecars as WebVTT, the only format `<track>` accepts.
+async fn subtitles_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !subtitles::is_subtitle_file(&full_path) {
+        return error_response(StatusCode::BAD_REQUEST, "Not a subtitle file.");
+    }
+    match tokio::fs::metadata(&full_path).await {
+        Ok(metadata) if metadata.len() > subtitles::MAX_SUBTITLE_SIZE => {
+            return error_response(StatusCode::PAYLOAD_TOO_LARGE, "Subtitle file is too large.");
+        }
+        Ok(_) => {}
+        Err(e) => {
+            error!("Failed to get metadata for {}: {}", full_path.display(), e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file.");
+        }
+    }
+    let bytes = match tokio::fs::read(&full_path).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            error!("Failed to read subtitles {}: {}", full_path.display(), e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file.");
+        }
+    };
+    let is_srt = full_path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("srt"));
+    let vtt = subtitles::to_vtt(&String::from_utf8_lossy(&bytes), is_srt);
+    ([(header::CONTENT_TYPE, "text/vtt; charset=utf-8")], vtt).into_response()
+}
+
+// --- hls_start_handler ---
+// Starts (or joins) a transcoding session and redirects to its playlist, so segment URLs
+// in the playlist resolve relative to the session.
+async fn hls_start_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Response {
+    let Some(transcoder) = &state.transcoder else {
+        return error_response(StatusCode::NOT_FOUND, "Transcoding is not enabled.");
+    };
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !is_video_file(&full_path) {
+        return error_response(StatusCode::BAD_REQUEST, "Not a video file.");
+    }
+    match transcoder.start(&full_path, &query.path).await {
+        Ok(session) => Redirect::to(&format!("/hls/{}/index.m3u8", session)).into_response(),
+        Err(transcode::StartError::Busy) => error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many videos are being transcoded right now. Try again later.",
+        ),
+        Err(transcode::StartError::Failed(e)) => {
+            error!("Failed to start transcoding {}: {}", full_path.display(), e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not start transcoding.",
+            )
+        }
+    }
+}
+
+// --- hls_file_handler ---
+async fn hls_file_handler(
+    State(state): State<SharedState>,
+    AxumPath((session, file)): AxumPath<(String, String)>,
+) -> Response {
+    let Some(transcoder) = &state.transcoder else {
+        return error_response(StatusCode::NOT_FOUND, "Transcoding is not enabled.");
+    };
+    // The video is held to the same checks as when the session started, in case the
+    // user's rules or the file changed since.
+    let path = match transcoder.path(&session) {
+        Ok(path) => path,
+        Err(e) => return error_response(StatusCode::NOT_FOUND, &e),
+    };
+    if let Err(response) = resolve_and_validate_path(&state.mounts, &sanitize_path(&path)) {
+        return response;
+    }
+    // The playlist grows while ffmpeg runs; finished segments never change.
+    if file == "index.m3u8" {
+        return match transcoder.playlist(&session).await {
+            Ok(playlist) => (
+                [
+                    (header::CONTENT_TYPE, "application/vnd.apple.mpegurl"),
+                    (header::CACHE_CONTROL, "no-store"),
+                ],
+                playlist,
+            )
+                .into_response(),
+            Err(e) => error_response(StatusCode::NOT_FOUND, &e),
+        };
+    }
+    match transcoder.segment(&session, &file).await {
+        Ok(bytes) => (
+            [
+                (header::CONTENT_TYPE, "video/mp2t"),
+                (header::CACHE_CONTROL, "private, max-age=3600"),
+            ],
+            bytes,
+        )
+            .into_response(),
+        Err(e) => error_response(StatusCode::NOT_FOUND, &e),
+    }
+}
+
+// --- raw_handler ---
+// The bytes of any browsable file, served inline with its MIME type and Range support,
+// for tools and embeds that shouldn't need a share link.
+async fn raw_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+
+    if !full_path.is_file() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Raw access is only supported for files.",
+        );
+    }
+
+    inline_file_response(&full_path, &headers).await
+}
+
+// Serves a file for display in the browser, sandboxing types that could run script.
+async fn inline_file_response(full_path: &Path, headers: &HeaderMap) -> Response {
+    let mime_type = mime_guess::from_path(full_path)
+        .first_or_octet_stream()
+        .to_string();
+    let mut extra_headers = HeaderMap::new();
+    extra_headers.insert(
+        header::CONTENT_DISPOSITION,
+        HeaderValue::from_static("inline"),
+    );
+    if serve::is_scriptable(&mime_type) {
+        extra_headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(serve::SANDBOX_CSP),
+        );
+    }
+    serve::file_response(full_path, headers, extra_headers).await
+}
+
+// --- image_handler ---
+// Images scaled down to `w` pixels wide for thumbnails and previews, cached on disk.
+// Anything that can't be resized falls back to the original.
+async fn image_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<ImageQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !is_image_file(&full_path) {
+        return error_response(StatusCode::BAD_REQUEST, "Not an image file.");
+    }
+
+    // HEIC and RAW files are served (and resized) from a JPEG conversion.
+    let (source_path, is_converted) = match convert::source_format(&full_path) {
+        Some(format) => {
+            match convert::converted(&state.cache, &state.ffmpeg, &full_path, format).await {
+                Ok(converted_path) => (converted_path, true),
+                Err(e) => {
+                    error!("Failed to convert {}: {}", full_path.display(), e);
+                    return error_response(
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        "This image could not be converted for display.",
+                    );
+                }
+            }
+        }
+        None => (full_path.clone(), false),
+    };
+
+    let Some(width) = query.w.filter(|_| resize::is_resizable(&source_path)) else {
+        return image_file_response(&source_path, is_converted, &headers).await;
+    };
+    match resize::resized(&state.cache, &source_path, width).await {
+        Ok(resize::Resized::Cached(resized_path)) => {
+            image_file_response(&resized_path, true, &headers).await
+        }
+        Ok(resize::Resized::Original) => {
+            image_file_response(&source_path, is_converted, &headers).await
+        }
+        Err(e) => {
+            error!("Failed to resize {}: {}", source_path.display(), e);
+            image_file_response(&source_path, is_converted, &headers).await
+        }
+    }
+}
+
+// Files from the cache can be kept by the browser for a while; originals are served as
+// `/raw` would.
+async fn image_file_response(path: &Path, is_cached: bool, headers: &HeaderMap) -> Response {
+    if !is_cached {
+        return inline_file_response(path, headers).await;
+    }
+    let mut extra_headers = HeaderMap::new();
+    extra_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, max-age=3600"),
+    );
+    serve::file_response(path, headers, extra_headers).await
+}
+
+// --- poster_handler ---
+async fn poster_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !is_video_file(&full_path) {
+        return error_response(StatusCode::BAD_REQUEST, "Not a video file.");
+    }
+    poster_response(&state, &full_path, &headers).await
+}
+
+async fn poster_response(state: &AppState, video_path: &Path, headers: &HeaderMap) -> Response {
+    match poster::poster(&state.cache, &state.ffmpeg, video_path).await {
+        Ok(poster_path) => {
+            let mut extra_headers = HeaderMap::new();
+            extra_headers.insert(
+                header::CACHE_CONTROL,
+                HeaderValue::from_static("private, max-age=3600"),
+            );
+            serve::file_response(&poster_path, headers, extra_headers).await
+        }
+        Err(()) => error_response(StatusCode::NOT_FOUND, "No poster frame available."),
+    }
+}
+
+// --- direct_image_handler ---
+async fn direct_image_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+
+    if !full_path.is_file() {
+        error!("Direct image attempt on non-file: {}", full_path.display());
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Direct image access is only supported for files.",
+        );
+    }
+
+    // Check if file is an image
+    if !is_image_file(&full_path) {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "File type not supported for direct image access.",
+        );
+    }
+
+    // Inline like /raw, with SVGs sandboxed, and ranges and validators as for any file.
+    inline_file_response(&full_path, &headers).await
+}
+
+// --- Creating links and uploads ---
+// Creating links and uploading take a login when there are accounts, even under
+// `--public`, and one of `roles` (`--share-role`, `--upload-role`) when any are given.
+fn may_create(state: &AppState, roles: &[String]) -> bool {
+    state.users.is_none() || auth::has_role(roles)
+}
+
+#[allow(clippy::result_large_err)]
+fn require_creator(state: &AppState, roles: &[String], action: &str) -> Result<(), Response> {
+    if may_create(state, roles) {
+        return Ok(());
+    }
+    Err(match auth::current_user() {
+        Some(user) => {
+            info!("Refused to let {} {}: not in {:?}", user, action, roles);
+            error_response(
+                StatusCode::FORBIDDEN,
+                &format!("Your account isn't allowed to {}.", action),
+            )
+        }
+        None => error_response(StatusCode::UNAUTHORIZED, &format!("Log in to {}.", action)),
+    })
+}
+
+// --- MODIFIED share_handler ---
+async fn share_handler(
+    State(state): State<SharedState>, // App state
+    // Host(hostname): Host, // Removed: We no longer extract the hostname
+    Query(options): Query<ShareOptions>,
+    Form(payload): Form<SharePayload>, // Form data (path)
+) -> Result<Markup, Response> {
+    info!("Share requested for path: {}", payload.path);
+    require_creator(&state, &state.share_roles, "create share links")?;
+    // info!("Request received via host: {}", hostname); // Removed
+
+    let sanitized_req_path = sanitize_path(&payload.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if !full_path.is_file() {
+        error!("Share attempt on non-file: {}", full_path.display());
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Sharing is only supported for files.",
+        ));
+    }
+    if !policy::is_listed(&state.mounts.root_of(&full_path).dir, &full_path) {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "This file is excluded from sharing.",
+        ));
+    }
+
+    let uuid = Uuid::new_v4();
+    state.shares.insert(
+        uuid,
+        Share {
+            path: full_path.clone(),
+            inline: options.inline.unwrap_or(false),
+            torrent: options.torrent.unwrap_or(false),
+            high_priority: options.priority.unwrap_or(false),
+            created: Local::now(),
+            created_by: auth::current_user(),
+        },
+    );
+    info!(
+        "Created share entry for UUID {} pointing to {}",
+        uuid,
+        full_path.display()
+    );
+    audit::note(format!(
+        "{} (share {})",
+        state.mounts.relative(&full_path),
+        uuid
+    ));
+    // Hashed now so downloads and the landing page can show the digest without waiting.
+    let hash_state = state.clone();
+    let hash_path = full_path.clone();
+    tokio::spawn(async move {
+        if let Err(e) = hash_state.file_hashes.sha256(&hash_path).await {
+            error!("Failed to hash shared file {}: {}", hash_path.display(), e);
+        }
+    });
+    if options.torrent.unwrap_or(false) {
+        let torrent_state = state.clone();
+        let torrent_path = full_path.clone();
+        tokio::spawn(async move {
+            if let Err(e) = torrent_state.torrents.prepare(&torrent_path).await {
+                error!(
+                    "Failed to hash pieces of shared file {}: {}",
+                    torrent_path.display(),
+                    e
+                );
+            }
+        });
+    }
+
+    // --- Construct RELATIVE URL path to the landing page ---
+    // The link will be relative to the current domain, e.g., "/share/uuid-goes-here"
+    let share_link_path = format!("/share/{}", uuid);
+    info!("Relative share link path generated: {}", share_link_path);
+    // --- End Construct URL ---
+
+    // --- Determine Target Placeholder ID (same as before) ---
+    let item_id_base = payload
+        .path
+        .replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
+    let target_placeholder_id = format!("share-placeholder-{}", item_id_base);
+    let input_id = format!("share-link-input-{}", uuid);
+
+    // --- Create OOB Swap Response Targeting Placeholder ---
+    Ok(html! {
+        // 1. Default Swap Content (targets #context-share-button-wrapper)
+        { "" } // Clears the "Share File" button from the context menu span
+
+        // 2. Out-of-Band Swap Content - The Share Link Box
+        div class="share-link-inline-box"
+            hx-swap-oob={"innerHTML:#"(target_placeholder_id)} // Target the specific placeholder
+            {
+            span { "Share Link:" }
+            div style="display: flex; align-items: center; gap: 10px;" {
+                input type="text"
+                      id=(input_id)
+                      value=(share_link_path) // Use the relative path initially
+                      readonly;
+                button class="copy-button"
+                       data-copy-target={"#"(input_id)}
+                       type="button" { "Copy" }
+                button class="close-inline-share"
+                        type="button"
+                        onclick={"document.getElementById('"(target_placeholder_id)"').innerHTML = '';"}
+                        { (PreEscaped("×")) } // Close button (cross icon)
+            }
+        }
+        script {
+            (PreEscaped(&format!("
+                // Convert relative URL to absolute URL in the input field
+                (function() {{
+                    const input = document.getElementById('{}');
+                    if (input && input.value.startsWith('/')) {{
+                        const protocol = window.location.protocol;
+                        const host = window.location.host;
+                        input.value = protocol + '//' + host + input.value;
+                    }}
+                }})();
+            ", input_id)))
+        }
+    })
+}
+
+// --- share_landing_handler --- (remains the same)
+async fn share_landing_handler(
+    State(state): State<SharedState>,
+    AxumPath(uuid): AxumPath<Uuid>,
+) -> Response {
+    info!("Share landing page requested for UUID: {}", uuid);
+
+    let (path_to_serve, inline, torrent) = match state.shares.get(&uuid) {
+        Some(share) => (share.path.clone(), share.inline, share.torrent),
+        None => {
+            info!("Share link not found: {}", uuid);
+            return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
+        }
+    };
+
+    info!("Showing landing page for: {}", path_to_serve.display());
+
+    match path_to_serve.canonicalize() {
+        Ok(canonical_path_now) => {
+            if state.mounts.containing(&canonical_path_now).is_none() {
+                error!(
+                    "Shared path {} resolved to {} outside the served directories for landing page (UUID: {}).",
+                    path_to_serve.display(),
+                    canonical_path_now.display(),
+                    uuid
+                );
+                return error_response(StatusCode::FORBIDDEN, "Access denied.");
+            }
+            if !canonical_path_now.is_file() {
+                error!(
+                    "Shared path {} is no longer a file (UUID: {}).",
+                    canonical_path_now.display(),
+                    uuid
+                );
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    "Shared item is no longer accessible as a file.",
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "Failed to validate path {} for landing page (UUID: {}): {}",
+                path_to_serve.display(),
+                uuid,
+                e
+            );
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return error_response(StatusCode::NOT_FOUND, "Shared file not found.");
+            } else {
+                return error_response(StatusCode::FORBIDDEN, "Cannot access shared file.");
+            }
+        }
+    }
+
+    let metadata = match tokio::fs::metadata(&path_to_serve).await {
+        Ok(meta) => meta,
+        Err(e) => {
+            error!(
+                "Failed to get metadata for shared file {}: {}",
+                path_to_serve.display(),
+                e
+            );
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not read file information.",
+            );
+        }
+    };
+
+    let filename = path_to_serve
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+
+    let extension = path_to_serve
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    let file_icon = match extension.as_str() {
+        "pdf" => "📄",
+        "doc" | "docx" => "📝",
+        "xls" | "xlsx" => "📊",
+        "ppt" | "pptx" => "📑",
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "svg" => "🖼️",
+        "mp3" | "wav" | "flac" | "ogg" => "🎵",
+        "mp4" | "avi" | "mov" | "mkv" | "webm" => "🎬",
+        "zip" | "rar" | "7z" | "tar" | "gz" => "🗄️",
+        "txt" | "md" | "rst" => "📄",
+        "html" | "htm" | "css" | "js" => "🌐",
+        "exe" | "msi" | "dmg" | "app" => "📦",
+        _ => "📄",
+    };
+
+    let (size, modified) = get_metadata_strings(&metadata);
+    let mime_type = mime_guess::from_path(&path_to_serve)
+        .first_or_octet_stream()
+        .to_string();
+    let is_video = is_video_file(&path_to_serve);
+    let digest = state.file_hashes.cached(&path_to_serve, &metadata);
+
+    let markup = html! {
+        (DOCTYPE)
+        html lang="en" {
+            head {
+                meta charset="UTF-8";
+                meta name="viewport" content="width=device-width, initial-scale=1.0";
+                title { "Download " (filename) }
+                link rel="stylesheet" href="/static/styles.css"; // Relative path for CSS
+                script src="/static/htmx.min.js" {}
+            }
+            body {
+                div class="download-card" {
+                    div class="file-header" {
+                        div class="file-icon" { (file_icon) }
+                        div class="file-title" { h1 { (filename) } }
+                    }
+                    @if is_video {
+                        img class="share-poster" src={"/share/"(uuid)"/poster"} alt=(filename)
+                            onerror="this.remove()";
+                    }
+                    div class="file-meta" {
+                        @if let Some(size_str) = &size { div { strong { "Size:" } (size_str) } }
+                        @if let Some(mod_str) = &modified { div { strong { "Modified:" } (mod_str) } }
+                        div { strong { "Type:" } (mime_type) }
+                        div class="share-digest" {
+                            strong { "SHA-256:" }
+                            @match &digest {
+                                Some(hash) => code { (hash) },
+                                None => span hx-get={"/share/"(uuid)"/sha256"} hx-trigger="load" hx-swap="outerHTML" {
+                                    "Computing…"
+                                },
+                            }
+                        }
+                    }
+                    // The download link is also relative
+                    @if inline {
+                        a href={"/direct-download/"(uuid)} class="download-button" { "Open File" }
+                        a href={"/direct-download/"(uuid)"?inline=0"} class="download-button secondary" { "Download File" }
+                    } @else {
+                        a href={"/direct-download/"(uuid)} class="download-button" { "Download File" }
+                    }
+                    @if torrent {
+                        a href={"/share/"(uuid)"/torrent"} class="download-button secondary"
+                            title="Fetch over BitTorrent, with this server as a seed" { "🧲 Download .torrent" }
+                    }
+                    @if state.download_queue.enabled() {
+                        div class="queue-status" hx-get={"/share/"(uuid)"/queue"} hx-trigger="load, every 2s" {}
+                    }
+                    div class="footer" {
+                        "This file has been shared with you securely. Click the Download button to save it to your device."
+                    }
+                }
+            }
+        }
+    };
+    markup.into_response()
+}
+
+// --- share_poster_handler ---
+async fn share_poster_handler(
+    State(state): State<SharedState>,
+    AxumPath(uuid): AxumPath<Uuid>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(path_to_serve) = state.shares.get(&uuid).map(|share| share.path.clone()) else {
+        return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
+    };
+    match path_to_serve.canonicalize() {
+        Ok(canonical_path_now)
+            if state.mounts.containing(&canonical_path_now).is_some()
+                && canonical_path_now.is_file()
+                && is_video_file(&canonical_path_now) =>
+        {
+            poster_response(&state, &canonical_path_now, &headers).await
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "No poster frame available."),
+    }
+}
+
+// --- share_sha256_handler ---
+// The shared file's SHA-256 for the landing page, once it has been computed.
+async fn share_sha256_handler(
+    State(state): State<SharedState>,
+    AxumPath(uuid): AxumPath<Uuid>,
+) -> Response {
+    let Some(path_to_serve) = state.shares.get(&uuid).map(|share| share.path.clone()) else {
+        return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
+    };
+    match path_to_serve.canonicalize() {
+        Ok(canonical_path_now)
+            if state.mounts.containing(&canonical_path_now).is_some()
+                && canonical_path_now.is_file() =>
+        {
+            match state.file_hashes.sha256(&canonical_path_now).await {
+                Ok(hash) => html! { code { (hash) } }.into_response(),
+                Err(e) => {
+                    error!("Failed to hash {}: {}", canonical_path_now.display(), e);
+                    html! { span { "Unavailable" } }.into_response()
+                }
+            }
+        }
+        _ => error_response(StatusCode::NOT_FOUND, "Shared file not found."),
+    }
+}
+
+// --- share_queue_handler ---
+// The visitor's place in the download queue, polled by the landing page while they wait
+// for their download to start.
+async fn share_queue_handler(
+    State(state): State<SharedState>,
+    AxumPath(uuid): AxumPath<Uuid>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+) -> Markup {
+    let queue = &state.download_queue;
+    let (running, waiting) = queue.counts();
+    let busy = waiting > 0 || queue.slots().is_some_and(|slots| running >= slots);
+    match queue.position(client, &format!("/direct-download/{}", uuid)) {
+        Some((position, total)) => html! {
+            "⏳ Your download is waiting for a free slot: " strong { (position) } " of " (total) " in line. It starts by itself."
+        },
+        None if busy => {
+            html! { "The server is busy; a new download may wait a while before it starts." }
+        }
+        None => html! {},
+    }
+}
+
+// --- share_torrent_handler ---
+// A .torrent for the shared file whose web seed is the share's direct-download link, so
+// BitTorrent clients can fetch and resume from kiv even with no other peers.
+async fn share_torrent_handler(
+    State(state): State<SharedState>,
+    Extension(ForwardedHttps(https)): Extension<ForwardedHttps>,
+    AxumPath(uuid): AxumPath<Uuid>,
+    headers: HeaderMap,
+) -> Response {
+    let Some(path_to_serve) = state
+        .shares
+        .get(&uuid)
+        .filter(|share| share.torrent)
+        .map(|share| share.path.clone())
+    else {
+        return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
+    };
+    let canonical_path_now = match path_to_serve.canonicalize() {
+        Ok(path) if state.mounts.containing(&path).is_some() && path.is_file() => path,
+        _ => return error_response(StatusCode::NOT_FOUND, "Shared file not found."),
+    };
+    let Some(host) = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return error_response(StatusCode::BAD_REQUEST, "Missing Host header.");
+    };
+    // Behind a TLS-terminating proxy the seed has to point at the proxy's https address.
+    let scheme = if https { "https" } else { "http" };
+    let web_seed = format!("{}://{}/direct-download/{}", scheme, host, uuid);
+
+    let filename = canonical_path_now
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "download".to_string());
+    match state
+        .torrents
+        .build(&canonical_path_now, &filename, &web_seed)
+        .await
+    {
+        Ok(torrent) => (
+            [
+                (
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/x-bittorrent"),
+                ),
+                (
+                    header::CONTENT_DISPOSITION,
+                    serve::disposition(false, &format!("{}.torrent", filename)),
+                ),
+            ],
+            torrent,
+        )
+            .into_response(),
+        Err(e) => {
+            error!(
+                "Failed to build torrent for {}: {}",
+                canonical_path_now.display(),
+                e
+            );
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not create the torrent.",
+            )
+        }
+    }
+}
+
+// --- download_folder_handler ---
+// The folder and everything listed in it as a zip or tar.gz, built while it is sent.
+async fn download_folder_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<FolderDownloadQuery>,
+) -> Response {
+    if query.password.is_some() {
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "Send the password in a form, not in the link.",
+        );
+    }
+    folder_download(&state, query)
+}
+
+// The same with the password from the folder's "Encrypted zip" form.
+async fn encrypted_folder_handler(
+    State(state): State<SharedState>,
+    Form(query): Form<FolderDownloadQuery>,
+) -> Response {
+    folder_download(&state, query)
+}
+
+fn folder_download(state: &SharedState, query: FolderDownloadQuery) -> Response {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path) {
+        Ok(path) => path,
+        Err(response) => return response,
+    };
+    if !full_path.is_dir() {
+        return error_response(StatusCode::BAD_REQUEST, "Not a folder.");
+    }
+
+    let mount = state.mounts.root_of(&full_path);
+    let name = if full_path == mount.dir && !mount.name.is_empty() {
+        mount.name.clone()
+    } else {
+        full_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "folder".to_string())
+    };
+    let format = bundle::BundleFormat::parse(query.format.as_deref());
+    let password = match archive_password(format, query.password) {
+        Ok(password) => password,
+        Err(response) => return response,
+    };
+    info!(
+        "Archiving folder for download as {}: {}",
+        format.extension(),
+        full_path.display()
+    );
+    let item = bundle::BundleItem {
+        mount: mount.clone(),
+        path: full_path,
+        name: name.clone(),
+    };
+    bundle_response(format, vec![item], &name, password)
+}
+
+// --- download_selection_handler ---
+// Several files and folders, picked with the listing's checkboxes, as one archive. Each
+// is stored under its own name; names that repeat get a number.
+async fn download_selection_handler(
+    State(state): State<SharedState>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Response {
+    let mut format = bundle::BundleFormat::Zip;
+    let mut password = None;
+    let mut items: Vec<bundle::BundleItem> = Vec::new();
+    for (key, value) in fields {
+        match key.as_str() {
+            "format" => format = bundle::BundleFormat::parse(Some(&value)),
+            "password" => password = Some(value),
+            "path" => {
+                let sanitized_req_path = sanitize_path(&value);
+                let full_path = match resolve_and_validate_path(&state.mounts, &sanitized_req_path)
+                {
+                    Ok(path) => path,
+                    Err(response) => return response,
+                };
+                if items.iter().any(|item| item.path == full_path) {
+                    continue;
+                }
+                let mount = state.mounts.root_of(&full_path);
+                let base = full_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| mount.name.clone());
+                let mut name = base.clone();
+                let mut n = 1;
+                while items.iter().any(|item| item.name == name) {
+                    n += 1;
+                    name = format!("{} ({})", base, n);
+                }
+                items.push(bundle::BundleItem {
+                    mount: mount.clone(),
+                    path: full_path,
+                    name,
+                });
+            }
+            _ => {}
+        }
+    }
+    if items.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "Nothing selected.");
+    }
+    let password = match archive_password(format, password) {
+        Ok(password) => password,
+        Err(response) => return response,
+    };
+
+    info!(
+        "Archiving {} selected items for download as {}",
+        items.len(),
+        format.extension()
+    );
+    bundle_response(format, items, "selection", password)
+}
+
+// An empty password field means no encryption; tar.gz can't be encrypted at all.
+#[allow(clippy::result_large_err)]
+fn archive_password(
+    format: bundle::BundleFormat,
+    password: Option<String>,
+) -> Result<Option<String>, Response> {
+    match password.filter(|password| !password.is_empty()) {
+        Some(_) if format != bundle::BundleFormat::Zip => Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Only zip downloads can be password-protected.",
+        )),
+        password => Ok(password),
+    }
+}
+
+fn bundle_response(
+    format: bundle::BundleFormat,
+    items: Vec<bundle::BundleItem>,
+    name: &str,
+    password: Option<String>,
+) -> Response {
+    // Every item of a selection counts in the download statistics, as a folder does.
+    let served = stats::Served(items.iter().map(|item| item.path.clone()).collect());
+    let reader = bundle::stream(format, items, password);
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static(format.mime_type()),
+    );
+    headers.insert(
+        header::CONTENT_DISPOSITION,
+        serve::disposition(false, &format!("{}.{}", name, format.extension())),
+    );
+    let body = axum::body::Body::from_stream(bandwidth::throttle(ReaderStream::new(reader)));
+    let mut response = (StatusCode::OK, headers, body).into_response();
+    response.extensions_mut().insert(served);
+    response
+}
+
+// --- sign_handler ---
+// Signed, expiring download links for one or more files, for scripts that hand out
+// short-lived links in bulk. Nothing is stored: the link carries its path and expiry and
+// is checked against the server's key, so it lasts as long as the key and can't be
+// revoked early. Takes `path` (repeatable) and `ttl` in seconds.
+const DEFAULT_SIGNED_TTL_SECS: i64 = 60 * 60;
+const MAX_SIGNED_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+async fn sign_handler(
+    State(state): State<SharedState>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Response {
+    if let Err(response) = require_creator(&state, &state.share_roles, "create share links") {
+        return response;
+    }
+    let mut ttl = DEFAULT_SIGNED_TTL_SECS;
+    let mut paths = Vec::new();
+    for (key, value) in fields {
+        match key.as_str() {
+            "ttl" => match value.parse::<i64>() {
+                Ok(secs) if (1..=MAX_SIGNED_TTL_SECS).contains(&secs) => ttl = secs,
+                _ => {
+                    return error_response(
+                        StatusCode::BAD_REQUEST,
+                        "ttl must be between 1 second and 7 days.",
+                    );
+                }
+            },
+            "path" => paths.push(value),
+            _ => {}
+        }
+    }
+    if paths.is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "No path given.");
+    }
+
+    let expires = chrono::Utc::now().timestamp() + ttl;
+    let mut links = Vec::new();
+    for path in paths {
+        let full_path = match resolve_and_validate_path(&state.mounts, &sanitize_path(&path)) {
+            Ok(full_path) => full_path,
+            Err(response) => return response,
+        };
+        if !full_path.is_file() {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                "Signed links are only supported for files.",
+            );
+        }
+        if !policy::is_listed(&state.mounts.root_of(&full_path).dir, &full_path) {
+            return error_response(StatusCode::FORBIDDEN, "This file is excluded from sharing.");
+        }
+        let url = format!(
+            "/signed?path={}&expires={}&sig={}",
+            urlencoding::encode(&path),
+            expires,
+            state.signer.sign_download(&path, expires)
+        );
+        links.push(serde_json::json!({ "path": path, "url": url, "expires": expires }));
+    }
+    info!("Signed {} download links valid for {}s", links.len(), ttl);
+    axum::Json(serde_json::json!({ "links": links })).into_response()
+}
+
+// --- signed_download_handler ---
+async fn signed_download_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<SignedDownloadQuery>,
+    headers: HeaderMap,
+) -> Response {
+    if !state
+        .signer
+        .verify_download(&query.path, query.expires, &query.sig)
+    {
+        return error_response(StatusCode::FORBIDDEN, "Invalid download link.");
+    }
+    if chrono::Utc::now().timestamp() > query.expires {
+        return error_response(StatusCode::GONE, "This download link has expired.");
+    }
+    // Checked again: the file may have moved out of reach since the link was made.
+    let full_path = match resolve_and_validate_path(&state.mounts, &sanitize_path(&query.path)) {
+        Ok(full_path) => full_path,
+        Err(response) => return response,
+    };
+    if !full_path.is_file() || !policy::is_listed(&state.mounts.root_of(&full_path).dir, &full_path)
+    {
+        return error_response(StatusCode::NOT_FOUND, "File not found.");
+    }
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "download".to_string());
+    let mut extra_headers = HeaderMap::new();
+    extra_headers.insert(
+        header::CONTENT_DISPOSITION,
+        serve::disposition(false, &filename),
+    );
+    serve::file_response(&full_path, &headers, extra_headers).await
+}
+
+// --- download_handler ---
+async fn download_handler(
+    State(state): State<SharedState>,
+    AxumPath(uuid): AxumPath<Uuid>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Response {
+    info!("Download requested for UUID: {}", uuid);
+
+    let (path_to_serve, share_inline) = match state.shares.get(&uuid) {
+        Some(share) => (share.path.clone(), share.inline),
+        None => {
+            info!("Share link not found: {}", uuid);
+            return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
+        }
+    };
+
+    info!("Attempting to serve file: {}", path_to_serve.display());
+
+    match path_to_serve.canonicalize() {
+        Ok(canonical_path_now) => {
+            if state.mounts.containing(&canonical_path_now).is_none() {
+                error!(
+                    "Shared path {} resolved to {} outside the served directories during download (UUID: {}).",
+                    path_to_serve.display(),
+                    canonical_path_now.display(),
+                    uuid
+                );
+                return error_response(StatusCode::FORBIDDEN, "Access denied.");
+            }
+            if !canonical_path_now.is_file() {
+                error!(
+                    "Shared path {} is no longer a file (UUID: {}).",
+                    canonical_path_now.display(),
+                    uuid
+                );
+                return error_response(
+                    StatusCode::NOT_FOUND,
+                    "Shared item is no longer accessible as a file.",
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "Failed to re-validate/canonicalize shared path {} for download (UUID: {}): {}",
+                path_to_serve.display(),
+                uuid,
+                e
+            );
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return error_response(StatusCode::NOT_FOUND, "Shared file not found.");
+            } else {
+                return error_response(StatusCode::FORBIDDEN, "Cannot access shared file.");
+            }
+        }
+    }
+
+    let filename = path_to_serve
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "download".to_string());
+    let inline = query.inline.unwrap_or(share_inline);
+    let mut extra_headers = HeaderMap::new();
+    if let Ok(metadata) = tokio::fs::metadata(&path_to_serve).await {
+        match state.file_hashes.cached(&path_to_serve, &metadata) {
+            Some(hash) => serve::insert_digest(&mut extra_headers, &hash),
+            // The file changed since it was hashed; later downloads get the new digest.
+            None => {
+                let hash_state = state.clone();
+                let hash_path = path_to_serve.clone();
+                tokio::spawn(async move {
+                    let _ = hash_state.file_hashes.sha256(&hash_path).await;
+                });
+            }
+        }
+    }
+    extra_headers.insert(
+        header::CONTENT_DISPOSITION,
+        serve::disposition(inline, &filename),
+    );
+    let mime_type = mime_guess::from_path(&path_to_serve)
+        .first_or_octet_stream()
+        .to_string();
+    if inline && serve::is_scriptable(&mime_type) {
+        extra_headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(serve::SANDBOX_CSP),
+        );
+    }
+    // Ranges let interrupted downloads resume and media players seek.
+    serve::file_response(&path_to_serve, &headers, extra_headers).await
+}
+
+// --- trash_handler ---
+async fn trash_handler(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Form(payload): Form<PathPayload>,
+) -> Result<Markup, Response> {
+    info!("Trash requested for path: {}", payload.path);
+
+    let sanitized_req_path = sanitize_path(&payload.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+
+    if state.mounts.is_mount_dir(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "The root directory cannot be moved to the trash.",
+        ));
+    }
+
+    let relative_path = rawnames::encode(&sanitized_req_path);
+    match trash::move_to_trash(&state.trash, &full_path, &relative_path).await {
+        Ok(trashed_path) => info!(
+            "Moved {} to trash at {}",
+            full_path.display(),
+            trashed_path.display()
+        ),
+        Err(e) => {
+            error!("Failed to move {} to trash: {}", full_path.display(), e);
+            return Err(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not move item to the trash.",
+            ));
+        }
+    }
+
+    // Re-render the directory the item was removed from
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    browse_handler(
+        state,
+        &headers,
+        BrowseQuery {
+            path: Some(parent_path),
+            view: None,
+            checksums: false,
+            format: None,
+            filter: None,
+            sort: None,
+            order: None,
+            folders: None,
+            hidden: None,
+        },
+    )
+    .await
+}
+
+// --- add_favorite_handler ---
+async fn add_favorite_handler(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Form(payload): Form<FavoritePayload>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&payload.path);
+    resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    let relative_path = rawnames::encode(&sanitized_req_path);
+    if let Err(e) = state.favorites.add(&relative_path).await {
+        error!("Failed to save favorites: {}", e);
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not save favorites.",
+        ));
+    }
+    favorites_back(state, &headers, &sanitized_req_path, payload.back).await
+}
+
+// --- remove_favorite_handler ---
+// Pins are removed by name, so ones whose file has since gone away can be removed too.
+async fn remove_favorite_handler(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    Form(payload): Form<FavoritePayload>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&payload.path);
+    if let Err(response) = resolve_and_validate_path(&state.mounts, &sanitized_req_path)
+        && !pin_missing(&state.mounts, &sanitized_req_path)
+    {
+        return Err(response);
+    }
+    let relative_path = rawnames::encode(&sanitized_req_path);
+    if let Err(e) = state.favorites.remove(&relative_path).await {
+        error!("Failed to save favorites: {}", e);
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not save favorites.",
+        ));
+    }
+    favorites_back(state, &headers, &sanitized_req_path, payload.back).await
+}
+
+// A pin whose file or folder is gone, but which the current request could use if it were
+// there, so it can still be shown as missing and removed.
+fn pin_missing(mounts: &mounts::Mounts, pin: &Path) -> bool {
+    mounts.split(pin).is_some_and(|(mount, inner)| {
+        let path = mount.dir.join(inner);
+        path.symlink_metadata()
+            .is_err_and(|e| e.kind() == std::io::ErrorKind::NotFound)
+            && policy::is_accessible(&mount.dir, &path)
+            && auth::allows(&rawnames::encode(pin))
+    })
+}
+
+async fn favorites_back(
+    state: SharedState,
+    headers: &HeaderMap,
+    item_path: &Path,
+    back: Option<String>,
+) -> Result<Markup, Response> {
+    let back = back.unwrap_or_else(|| {
+        item_path
+            .parent()
+            .map(rawnames::encode)
+            .unwrap_or_else(|| ".".to_string())
+    });
+    browse_handler(
+        state,
+        headers,
+        BrowseQuery {
+            path: Some(back),
+            view: None,
+            checksums: false,
+            format: None,
+            filter: None,
+            sort: None,
+            order: None,
+            folders: None,
+            hidden: None,
+        },
+    )
+    .await
+}
+
+// --- upload_handler ---
+async fn upload_handler(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Markup, Response> {
+    require_creator(&state, &state.upload_roles, "upload")?;
+    // The form sends the target directory before the files, so it is known by the
+    // time the first file field arrives. A drop zone pins the target and ignores it.
+    let mut target_dir: Option<(PathBuf, PathBuf)> = state
+        .drop_zone
+        .as_ref()
+        .map(|zone| (zone.relative_path.clone(), zone.full_path.clone()));
+    let mut stored_names = Vec::new();
+
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read upload form: {}", e);
+                return Err(error_response(
+                    StatusCode::BAD_REQUEST,
+                    "Malformed upload request.",
+                ));
+            }
+        };
+
+        match field.name() {
+            Some("path") if state.drop_zone.is_none() => {
+                let requested = field.text().await.map_err(|e| {
+                    error!("Failed to read upload target path: {}", e);
+                    error_response(StatusCode::BAD_REQUEST, "Malformed upload request.")
+                })?;
+                let sanitized_req_path = sanitize_path(&requested);
+                let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+                if !full_path.is_dir() {
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Uploads must target a directory.",
+                    ));
+                }
+                target_dir = Some((sanitized_req_path, full_path));
+            }
+            Some("file") => {
+                let Some((_, dir_full_path)) = &target_dir else {
+                    return Err(error_response(
+                        StatusCode::BAD_REQUEST,
+                        "Upload target directory must be sent before files.",
+                    ));
+                };
+                let stored_path = receive_upload(&state, dir_full_path, field).await?;
+                stored_names.push(
+                    stored_path
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default(),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    let Some((sanitized_req_path, _)) = target_dir else {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "No upload target directory given.",
+        ));
+    };
+    info!(
+        "Uploaded {} file(s) into {}",
+        stored_names.len(),
+        sanitized_req_path.display()
+    );
+    audit::note(
+        stored_names
+            .iter()
+            .map(|name| rawnames::encode(&sanitized_req_path.join(name)))
+            .collect::<Vec<_>>()
+            .join(", "),
     );
 
-    // --- Construct RELATIVE URL path to the landing page ---
-    // The link will be relative to the current domain, e.g., "/share/uuid-goes-here"
-    let share_link_path = format!("/share/{}", uuid);
-    info!("Relative share link path generated: {}", share_link_path);
-    // --- End Construct URL ---
+    // Drop-zone visitors only get to see what they themselves sent.
+    if state.drop_zone.is_some() {
+        return Ok(html! {
+            div class="drop-zone-result" {
+                @if stored_names.is_empty() {
+                    p { "No files were received." }
+                } @else {
+                    p { "Thank you! Received " (stored_names.len()) " file(s):" }
+                    ul {
+                        @for name in &stored_names { li { (name) } }
+                    }
+                }
+            }
+        });
+    }
 
-    // --- Determine Target Placeholder ID (same as before) ---
-    let item_id_base = payload
-        .path
-        .replace(|c: char| !c.is_alphanumeric() && c != '-', "_");
-    let target_placeholder_id = format!("share-placeholder-{}", item_id_base);
-    let input_id = format!("share-link-input-{}", uuid);
+    browse_handler(
+        state,
+        &headers,
+        BrowseQuery {
+            path: Some(rawnames::encode(&sanitized_req_path)),
+            view: None,
+            checksums: false,
+            format: None,
+            filter: None,
+            sort: None,
+            order: None,
+            folders: None,
+            hidden: None,
+        },
+    )
+    .await
+}
+
+async fn receive_upload(
+    state: &AppState,
+    dir_full_path: &Path,
+    mut field: axum::extract::multipart::Field<'_>,
+) -> Result<PathBuf, Response> {
+    // Only the final component of the client-supplied name is used, so uploads can't
+    // escape the target directory.
+    let file_name = match field
+        .file_name()
+        .and_then(|name| Path::new(name).file_name())
+        .and_then(|name| name.to_str())
+    {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "Uploaded file has no usable name.",
+            ));
+        }
+    };
+
+    let mut target_path = upload_target(state, dir_full_path, &file_name).await?;
+
+    // Staged in the target's own mount, so moving it into place stays on one filesystem.
+    let staging_dir = state
+        .mounts
+        .root_of(&target_path)
+        .dir
+        .join(UPLOADS_DIR_NAME);
+    let staging_path = staging_dir.join(Uuid::new_v4().to_string());
+    // The first bytes are kept to tell what the file really is.
+    let mut head = Vec::with_capacity(sniff::HEAD_LEN);
+    let write_result = async {
+        fs::create_dir_all(&staging_dir).await?;
+        let mut file = fs::File::create(&staging_path).await?;
+        while let Some(chunk) = field.chunk().await.map_err(std::io::Error::other)? {
+            let wanted = (sniff::HEAD_LEN - head.len()).min(chunk.len());
+            head.extend_from_slice(&chunk[..wanted]);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await
+    }
+    .await;
+
+    if let Err(e) = write_result {
+        error!("Failed to receive upload '{}': {}", file_name, e);
+        let _ = fs::remove_file(&staging_path).await;
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not store uploaded file.",
+        ));
+    }
+
+    match state.upload_types.judge(&file_name, &head) {
+        sniff::Verdict::Accept => {}
+        sniff::Verdict::Rename(fixed) => {
+            info!(
+                "Storing upload '{}' as '{}' to match its contents",
+                file_name, fixed
+            );
+            target_path = match upload_target(state, dir_full_path, &fixed).await {
+                Ok(path) => path,
+                Err(response) => {
+                    let _ = fs::remove_file(&staging_path).await;
+                    return Err(response);
+                }
+            };
+        }
+        sniff::Verdict::Refuse(reason) => {
+            info!("Refused upload '{}': {}", file_name, reason);
+            let _ = fs::remove_file(&staging_path).await;
+            return Err(error_response(StatusCode::UNSUPPORTED_MEDIA_TYPE, &reason));
+        }
+    }
+
+    if let Some(clamd) = &state.clamd {
+        scan_upload(state, clamd, &staging_path, &file_name).await?;
+    }
+
+    // Anonymous drop-zone uploads never replace an existing submission, so there is no
+    // previous version to keep.
+    if state.drop_zone.is_some() {
+        target_path = match reserve_upload_path(&target_path).await {
+            Ok(path) => path,
+            Err(e) => {
+                error!(
+                    "Failed to reserve a name for upload at {}: {}",
+                    target_path.display(),
+                    e
+                );
+                let _ = fs::remove_file(&staging_path).await;
+                return Err(error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not store uploaded file.",
+                ));
+            }
+        };
+    } else {
+        let relative_path = PathBuf::from(state.mounts.relative(&target_path));
+        if let Err(e) = versions::snapshot(&state.versions, &target_path, &relative_path).await {
+            error!(
+                "Failed to keep previous version of {}: {}",
+                target_path.display(),
+                e
+            );
+            let _ = fs::remove_file(&staging_path).await;
+            return Err(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not keep the previous version of the file; upload aborted.",
+            ));
+        }
+    }
+
+    if let Err(e) = fs::rename(&staging_path, &target_path).await {
+        error!(
+            "Failed to move upload into place at {}: {}",
+            target_path.display(),
+            e
+        );
+        let _ = fs::remove_file(&staging_path).await;
+        if state.drop_zone.is_some() {
+            let _ = fs::remove_file(&target_path).await;
+        }
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not store uploaded file.",
+        ));
+    }
+
+    info!("Stored upload at {}", target_path.display());
+    Ok(target_path)
+}
+
+async fn upload_target(
+    state: &AppState,
+    dir_full_path: &Path,
+    file_name: &str,
+) -> Result<PathBuf, Response> {
+    if is_reserved_name(file_name) {
+        info!("Refused upload with reserved name '{}'", file_name);
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "That name is reserved for kiv's own files.",
+        ));
+    }
+    let target_path = dir_full_path.join(file_name);
+    // In the drop zone a folder's name just makes the upload take the next free one.
+    if state.drop_zone.is_none() && target_path.is_dir() {
+        return Err(error_response(
+            StatusCode::CONFLICT,
+            "A directory with that name already exists.",
+        ));
+    }
+    Ok(target_path)
+}
+
+// Claims `path`, or `name (1).ext`, `name (2).ext` and so on when it's taken, by creating
+// it empty. Each name is taken in one step, so two uploads of the same name at once can't
+// both end up with it; the upload is then moved over the empty file.
+async fn reserve_upload_path(path: &Path) -> std::io::Result<PathBuf> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = path
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+    let mut candidate = path.to_path_buf();
+    let mut counter = 0;
+    loop {
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+            .await
+        {
+            Ok(_) => return Ok(candidate),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+        counter += 1;
+        candidate = dir.join(format!("{} ({}){}", stem, counter, extension));
+    }
+}
+
+// Scans a staged upload with clamd. Infected files are quarantined, and uploads are
+// rejected outright if the scanner can't be reached rather than stored unscanned.
+async fn scan_upload(
+    state: &AppState,
+    clamd: &clamav::ClamdAddr,
+    staging_path: &Path,
+    file_name: &str,
+) -> Result<(), Response> {
+    match clamav::scan_file(clamd, staging_path).await {
+        Ok(clamav::ScanVerdict::Clean) => Ok(()),
+        Ok(clamav::ScanVerdict::Infected(signature)) => {
+            let quarantine_dir = state.root_dir.join(clamav::QUARANTINE_DIR_NAME);
+            let quarantine_path = quarantine_dir.join(format!("{}-{}", Uuid::new_v4(), file_name));
+            let moved = async {
+                fs::create_dir_all(&quarantine_dir).await?;
+                fileops::move_path(staging_path, &quarantine_path).await
+            }
+            .await;
+            match moved {
+                Ok(()) => error!(
+                    "Upload '{}' is infected ({}); quarantined at {}",
+                    file_name,
+                    signature,
+                    quarantine_path.display()
+                ),
+                Err(e) => {
+                    error!(
+                        "Upload '{}' is infected ({}) and could not be quarantined, deleting: {}",
+                        file_name, signature, e
+                    );
+                    let _ = fs::remove_file(staging_path).await;
+                }
+            }
+            Err(error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                &format!(
+                    "Upload of '{}' was rejected: malware detected ({}).",
+                    file_name, signature
+                ),
+            ))
+        }
+        Err(e) => {
+            error!("Virus scan of upload '{}' failed: {}", file_name, e);
+            let _ = fs::remove_file(staging_path).await;
+            Err(error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &format!(
+                    "Upload of '{}' was rejected: the virus scanner is unavailable.",
+                    file_name
+                ),
+            ))
+        }
+    }
+}
+
+// --- versions_handler ---
+async fn versions_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    render_versions(&state, &sanitized_req_path, &full_path).await
+}
+
+async fn restore_version_handler(
+    State(state): State<SharedState>,
+    Form(payload): Form<RestoreVersionPayload>,
+) -> Result<Markup, Response> {
+    info!(
+        "Restore of version {} requested for path: {}",
+        payload.version, payload.path
+    );
+
+    let sanitized_req_path = sanitize_path(&payload.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    let relative_path = PathBuf::from(state.mounts.relative(&full_path));
+
+    if let Err(e) = versions::restore(
+        &state.versions,
+        &full_path,
+        &relative_path,
+        &payload.version,
+    )
+    .await
+    {
+        error!(
+            "Failed to restore version {} of {}: {}",
+            payload.version,
+            full_path.display(),
+            e
+        );
+        return Err(match e.kind() {
+            std::io::ErrorKind::NotFound | std::io::ErrorKind::InvalidInput => {
+                error_response(StatusCode::NOT_FOUND, "Version not found.")
+            }
+            _ => error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not restore version.",
+            ),
+        });
+    }
+
+    render_versions(&state, &sanitized_req_path, &full_path).await
+}
+
+async fn render_versions(
+    state: &AppState,
+    sanitized_req_path: &Path,
+    full_path: &Path,
+) -> Result<Markup, Response> {
+    if !full_path.is_file() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Versions are only kept for files.",
+        ));
+    }
+
+    let relative_path = PathBuf::from(state.mounts.relative(full_path));
+    let versions = versions::list(&state.versions, &relative_path)
+        .await
+        .map_err(|e| {
+            error!("Failed to list versions of {}: {}", full_path.display(), e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not list versions.",
+            )
+        })?;
+
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+    let request_path = rawnames::encode(sanitized_req_path);
+
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
 
-    // --- Create OOB Swap Response Targeting Placeholder ---
     Ok(html! {
-        // 1. Default Swap Content (targets #context-share-button-wrapper)
-        { "" } // Clears the "Share File" button from the context menu span
+        div class="preview-container" {
+            div class="preview-header" {
+                h1 { "Versions: " (filename) }
+                div class="preview-actions" {
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="versions-content" {
+                @if !state.versions.enabled() {
+                    p class="versions-note" { "Versioning is disabled on this server (see --keep-versions)." }
+                }
+                @if versions.is_empty() {
+                    p { "No previous versions are stored for this file." }
+                } @else {
+                    table class="versions-table" {
+                        thead { tr { th { "Saved" } th { "Size" } th {} } }
+                        tbody {
+                            @for version in &versions {
+                                @let saved = version.saved_at
+                                    .map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                                    .unwrap_or_else(|| version.id.clone());
+                                @let vals = serde_json::json!({ "path": request_path, "version": version.id }).to_string();
+                                tr {
+                                    td { (saved) }
+                                    td { (format_size(version.size, BINARY)) }
+                                    td {
+                                        button class="restore-button"
+                                               hx-get=(format!("/diff?left={}&version={}", urlencoding::encode(&request_path), urlencoding::encode(&version.id)))
+                                               hx-target="#file-browser"
+                                               hx-swap="innerHTML"
+                                               { "Compare" }
+                                        @if !state.read_only {
+                                            " "
+                                            button class="restore-button"
+                                                   hx-post="/versions/restore"
+                                                   hx-vals=(vals)
+                                                   hx-target="#file-browser"
+                                                   hx-swap="innerHTML"
+                                                   hx-confirm="Replace the current file with this version?"
+                                                   { "Restore" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
 
-        // 2. Out-of-Band Swap Content - The Share Link Box
-        div class="share-link-inline-box"
-            hx-swap-oob={"innerHTML:#"(target_placeholder_id)} // Target the specific placeholder
+// --- diff_pick_handler ---
+// Second step of "Compare…": choose what to compare the file with.
+async fn diff_pick_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_req_path = sanitize_path(&query.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if !full_path.is_file() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Only files can be compared.",
+        ));
+    }
+
+    let request_path = rawnames::encode(&sanitized_req_path);
+    let filename = full_path
+        .file_name()
+        .map(rawnames::display)
+        .unwrap_or_else(|| "Unknown file".to_string());
+    let mount = state.mounts.root_of(&full_path);
+    let parent_dir = full_path.parent().unwrap_or(&mount.dir).to_path_buf();
+    let parent_path = sanitized_req_path
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+
+    let mut siblings = Vec::new();
+    if let Ok(mut entries) = tokio::fs::read_dir(&parent_dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path == full_path
+                || !path.starts_with(&mount.dir)
+                || !policy::is_accessible(&mount.dir, &path)
+                || !path.is_file()
             {
-            span { "Share Link:" }
-            div style="display: flex; align-items: center; gap: 10px;" {
-                input type="text"
-                      id=(input_id)
-                      value=(share_link_path) // Use the relative path initially
-                      readonly;
-                button class="copy-button"
-                       data-copy-target={"#"(input_id)}
-                       type="button" { "Copy" }
-                button class="close-inline-share"
-                        type="button"
-                        onclick={"document.getElementById('"(target_placeholder_id)"').innerHTML = '';"}
-                        { (PreEscaped("×")) } // Close button (cross icon)
+                continue;
             }
+            siblings.push((
+                entry.file_name().to_string_lossy().into_owned(),
+                mount.relative(&path),
+            ));
         }
-        script {
-            (PreEscaped(&format!("
-                // Convert relative URL to absolute URL in the input field
-                (function() {{
-                    const input = document.getElementById('{}');
-                    if (input && input.value.startsWith('/')) {{
-                        const protocol = window.location.protocol;
-                        const host = window.location.host;
-                        input.value = protocol + '//' + host + input.value;
-                    }}
-                }})();
-            ", input_id)))
+    }
+    siblings.sort();
+    let relative_path = PathBuf::from(mount.relative(&full_path));
+    let stored_versions = versions::list(&state.versions, &relative_path)
+        .await
+        .unwrap_or_default();
+    let encoded_left = urlencoding::encode(&request_path);
+
+    Ok(html! {
+        div class="preview-container" {
+            div class="preview-header" {
+                h1 { "Compare " (filename) " with…" }
+                div class="preview-actions" {
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="versions-content" {
+                form class="diff-pick-form"
+                     hx-get="/diff"
+                     hx-target="#file-browser"
+                     hx-swap="innerHTML" {
+                    input type="hidden" name="left" value=(request_path);
+                    input type="text" name="right" placeholder="Path of another file, e.g. config/prod.toml" required;
+                    button type="submit" class="restore-button" { "Compare" }
+                }
+                @if !stored_versions.is_empty() {
+                    h3 { "Previous versions" }
+                    ul class="diff-pick-list" {
+                        @for version in &stored_versions {
+                            li {
+                                a href="#"
+                                  hx-get=(format!("/diff?left={}&version={}", encoded_left, urlencoding::encode(&version.id)))
+                                  hx-target="#file-browser"
+                                  hx-swap="innerHTML" {
+                                    (version.saved_at
+                                        .map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                                        .unwrap_or_else(|| version.id.clone()))
+                                }
+                            }
+                        }
+                    }
+                }
+                @if !siblings.is_empty() {
+                    h3 { "Files in the same folder" }
+                    ul class="diff-pick-list" {
+                        @for (name, path) in &siblings {
+                            li {
+                                a href="#"
+                                  hx-get=(format!("/diff?left={}&right={}", encoded_left, urlencoding::encode(path)))
+                                  hx-target="#file-browser"
+                                  hx-swap="innerHTML" { (name) }
+                            }
+                        }
+                    }
+                }
+            }
         }
     })
 }
 
-// --- share_landing_handler --- (remains the same)
-async fn share_landing_handler(
+// --- diff_handler ---
+async fn diff_handler(
     State(state): State<SharedState>,
-    AxumPath(uuid): AxumPath<Uuid>,
-) -> Response {
-    info!("Share landing page requested for UUID: {}", uuid);
+    Query(query): Query<DiffQuery>,
+) -> Result<Markup, Response> {
+    let sanitized_left = sanitize_path(&query.left);
+    let left_full = resolve_and_validate_path(&state.mounts, &sanitized_left)?;
+    let left_label = sanitized_left.to_string_lossy().replace('\\', "/");
 
-    let path_to_serve = match state.shares.get(&uuid) {
-        Some(path_ref) => path_ref.value().clone(),
-        None => {
-            info!("Share link not found: {}", uuid);
-            return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
+    // Either two browsable files, or a stored version (old) against the live file (new).
+    let (old_path, old_label, new_path, new_label) = match (&query.version, &query.right) {
+        (Some(version), _) => {
+            let relative = PathBuf::from(state.mounts.relative(&left_full));
+            let version_file = versions::version_path(&state.versions, &relative, version)
+                .filter(|path| path.is_file())
+                .ok_or_else(|| error_response(StatusCode::NOT_FOUND, "Version not found."))?;
+            (
+                version_file,
+                format!("{} (version {})", left_label, version),
+                left_full.clone(),
+                format!("{} (current)", left_label),
+            )
+        }
+        (None, Some(right)) => {
+            let sanitized_right = sanitize_path(right);
+            let right_full = resolve_and_validate_path(&state.mounts, &sanitized_right)?;
+            (
+                left_full.clone(),
+                left_label.clone(),
+                right_full,
+                sanitized_right.to_string_lossy().replace('\\', "/"),
+            )
+        }
+        (None, None) => {
+            return Err(error_response(
+                StatusCode::BAD_REQUEST,
+                "Choose a file or version to compare with.",
+            ));
         }
     };
+    if !old_path.is_file() || !new_path.is_file() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Only files can be compared.",
+        ));
+    }
 
-    info!("Showing landing page for: {}", path_to_serve.display());
+    let old_text = diff::read_text(&old_path)
+        .await
+        .map_err(|e| error_response(StatusCode::UNPROCESSABLE_ENTITY, &e))?;
+    let new_text = diff::read_text(&new_path)
+        .await
+        .map_err(|e| error_response(StatusCode::UNPROCESSABLE_ENTITY, &e))?;
+    let mode = diff::DiffMode::from_param(query.mode.as_deref());
+    let rendered =
+        tokio::task::spawn_blocking(move || diff::render_diff(&old_text, &new_text, mode))
+            .await
+            .map_err(|e| {
+                error!("Diff rendering failed: {}", e);
+                error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Could not compare files.",
+                )
+            })?;
 
-    match path_to_serve.canonicalize() {
-        Ok(canonical_path_now) => {
-            if !canonical_path_now.starts_with(&state.root_dir) {
-                error!(
-                    "Shared path {} resolved outside root {} for landing page (UUID: {}).",
-                    path_to_serve.display(),
-                    state.root_dir.display(),
-                    uuid
-                );
-                return error_response(StatusCode::FORBIDDEN, "Access denied.");
-            }
-            if !canonical_path_now.is_file() {
-                error!(
-                    "Shared path {} is no longer a file (UUID: {}).",
-                    canonical_path_now.display(),
-                    uuid
-                );
-                return error_response(
-                    StatusCode::NOT_FOUND,
-                    "Shared item is no longer accessible as a file.",
-                );
-            }
+    let parent_path = sanitized_left
+        .parent()
+        .map(rawnames::encode)
+        .unwrap_or_else(|| ".".to_string());
+    let back_url = format!("/browse?path={}", urlencoding::encode(&parent_path));
+    let mode_url = |mode: diff::DiffMode| {
+        let mut url = format!(
+            "/diff?left={}&mode={}",
+            urlencoding::encode(&query.left),
+            mode.param()
+        );
+        if let Some(version) = &query.version {
+            url.push_str(&format!("&version={}", urlencoding::encode(version)));
+        } else if let Some(right) = &query.right {
+            url.push_str(&format!("&right={}", urlencoding::encode(right)));
         }
-        Err(e) => {
-            error!(
-                "Failed to validate path {} for landing page (UUID: {}): {}",
-                path_to_serve.display(),
-                uuid,
-                e
-            );
-            if e.kind() == std::io::ErrorKind::NotFound {
-                return error_response(StatusCode::NOT_FOUND, "Shared file not found.");
-            } else {
-                return error_response(StatusCode::FORBIDDEN, "Cannot access shared file.");
+        url
+    };
+
+    Ok(html! {
+        div class="preview-container diff-preview" {
+            div class="preview-header" {
+                h1 { "Compare" }
+                div class="preview-actions" {
+                    @for option in [diff::DiffMode::Unified, diff::DiffMode::Split] {
+                        button hx-get=(mode_url(option))
+                               hx-target="#file-browser"
+                               hx-swap="innerHTML"
+                               class="close-button"
+                               disabled[option == mode] {
+                            (if option == diff::DiffMode::Unified { "Unified" } else { "Side by side" })
+                        }
+                    }
+                    button hx-get=(back_url)
+                           hx-target="#file-browser"
+                           hx-swap="innerHTML"
+                           class="close-button" { "Back to Files" }
+                }
+            }
+            div class="diff-files" {
+                div class="diff-old-name" { "− " (old_label) }
+                div class="diff-new-name" { "+ " (new_label) }
             }
+            div class="preview-content" { (rendered) }
         }
+    })
+}
+
+// --- copy_handler ---
+async fn copy_handler(
+    State(state): State<SharedState>,
+    Form(payload): Form<PathPayload>,
+) -> Result<Markup, Response> {
+    info!("Copy requested for path: {}", payload.path);
+
+    let sanitized_req_path = sanitize_path(&payload.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if state.mounts.is_mount_dir(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "The root directory cannot be duplicated.",
+        ));
     }
 
-    let metadata = match tokio::fs::metadata(&path_to_serve).await {
-        Ok(meta) => meta,
+    let Some(destination) = fileops::copy_destination(&full_path).await else {
+        return Err(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Could not pick a name for the copy.",
+        ));
+    };
+
+    let description = format!(
+        "Duplicate {}",
+        sanitized_req_path.to_string_lossy().replace('\\', "/")
+    );
+    let mount = state.mounts.root_of(&full_path).clone();
+    let job = state.jobs.spawn("copy", description, move |job| {
+        fileops::copy_tree(job, mount, full_path, destination)
+    });
+    Ok(jobs::render_job(&job))
+}
+
+// --- delete_handler ---
+async fn delete_handler(
+    State(state): State<SharedState>,
+    Form(payload): Form<PathPayload>,
+) -> Result<Markup, Response> {
+    info!("Permanent delete requested for path: {}", payload.path);
+
+    let sanitized_req_path = sanitize_path(&payload.path);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if state.mounts.is_mount_dir(&full_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "The root directory cannot be deleted.",
+        ));
+    }
+
+    let description = format!(
+        "Delete {}",
+        sanitized_req_path.to_string_lossy().replace('\\', "/")
+    );
+    let mount = state.mounts.root_of(&full_path).clone();
+    let job = state.jobs.spawn("delete", description, move |job| {
+        fileops::delete_tree(job, mount, full_path)
+    });
+    Ok(jobs::render_job(&job))
+}
+
+// --- Checksum manifest handlers ---
+async fn generate_checksums_handler(
+    State(state): State<SharedState>,
+    Form(payload): Form<PathPayload>,
+) -> Result<Markup, Response> {
+    let (relative_path, full_path) = resolve_directory(&state, &payload.path)?;
+    let mount = state.mounts.root_of(&full_path).clone();
+    let job = state.jobs.spawn(
+        "checksums",
+        format!(
+            "Generate {} for /{}",
+            checksums::MANIFEST_NAME,
+            relative_path
+        ),
+        move |job| checksums::generate_manifest(job, mount, full_path),
+    );
+    Ok(jobs::render_job(&job))
+}
+
+async fn verify_checksums_handler(
+    State(state): State<SharedState>,
+    Form(payload): Form<PathPayload>,
+) -> Result<Markup, Response> {
+    let (relative_path, full_path) = resolve_directory(&state, &payload.path)?;
+    if !full_path.join(checksums::MANIFEST_NAME).is_file() {
+        return Err(error_response(
+            StatusCode::NOT_FOUND,
+            "This directory has no SHA256SUMS manifest to verify.",
+        ));
+    }
+    let mount = state.mounts.root_of(&full_path).clone();
+    let job = state.jobs.spawn(
+        "checksums",
+        format!("Verify {} in /{}", checksums::MANIFEST_NAME, relative_path),
+        move |job| checksums::verify_manifest(job, mount, full_path),
+    );
+    Ok(jobs::render_job(&job))
+}
+
+async fn checksum_manifest_handler(
+    State(state): State<SharedState>,
+    Query(query): Query<PreviewQuery>,
+) -> Response {
+    let (_, full_path) = match resolve_directory(&state, &query.path) {
+        Ok(resolved) => resolved,
+        Err(response) => return response,
+    };
+    match fs::read(full_path.join(checksums::MANIFEST_NAME)).await {
+        Ok(manifest) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            manifest,
+        )
+            .into_response(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => error_response(
+            StatusCode::NOT_FOUND,
+            "This directory has no SHA256SUMS manifest.",
+        ),
         Err(e) => {
             error!(
-                "Failed to get metadata for shared file {}: {}",
-                path_to_serve.display(),
+                "Failed to read checksum manifest in {}: {}",
+                full_path.display(),
                 e
             );
-            return error_response(
+            error_response(
                 StatusCode::INTERNAL_SERVER_ERROR,
-                "Could not read file information.",
-            );
+                "Could not read checksum manifest.",
+            )
         }
-    };
+    }
+}
 
-    let filename = path_to_serve
-        .file_name()
-        .and_then(|name| name.to_str())
-        .unwrap_or("Unknown file")
-        .to_string();
+// --- Job handlers ---
+async fn jobs_handler(State(state): State<SharedState>) -> Markup {
+    let jobs = state.jobs.list();
+    html! {
+        @if jobs.is_empty() {
+            p class="jobs-empty" { "No background jobs." }
+        }
+        @for job in &jobs {
+            (jobs::render_job(job))
+        }
+    }
+}
 
-    let extension = path_to_serve
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+async fn job_status_handler(
+    State(state): State<SharedState>,
+    AxumPath(id): AxumPath<Uuid>,
+) -> Result<Markup, Response> {
+    match state.jobs.get(&id) {
+        Some(job) => Ok(jobs::render_job(&job)),
+        None => Err(error_response(StatusCode::NOT_FOUND, "Job not found.")),
+    }
+}
 
-    let file_icon = match extension.as_str() {
-        "pdf" => "📄",
-        "doc" | "docx" => "📝",
-        "xls" | "xlsx" => "📊",
-        "ppt" | "pptx" => "📑",
-        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "svg" => "🖼️",
-        "mp3" | "wav" | "flac" | "ogg" => "🎵",
-        "mp4" | "avi" | "mov" | "mkv" | "webm" => "🎬",
-        "zip" | "rar" | "7z" | "tar" | "gz" => "🗄️",
-        "txt" | "md" | "rst" => "📄",
-        "html" | "htm" | "css" | "js" => "🌐",
-        "exe" | "msi" | "dmg" | "app" => "📦",
-        _ => "📄",
+async fn cancel_job_handler(
+    State(state): State<SharedState>,
+    AxumPath(id): AxumPath<Uuid>,
+) -> Result<Markup, Response> {
+    let Some(job) = state.jobs.get(&id) else {
+        return Err(error_response(StatusCode::NOT_FOUND, "Job not found."));
     };
+    info!("Cancellation requested for {} job {}", job.kind, job.id);
+    job.cancel();
+    Ok(jobs::render_job(&job))
+}
 
-    let (size, modified) = get_metadata_strings(&metadata);
-    let mime_type = mime_guess::from_path(&path_to_serve)
-        .first_or_octet_stream()
-        .to_string();
+// --- stats_handler ---
+// Server-wide figures, refreshing themselves while they are shown.
+async fn stats_handler(State(state): State<SharedState>) -> Markup {
+    const TOP_DOWNLOADS: usize = 20;
 
-    let markup = html! {
+    let bandwidth = bandwidth::get();
+    let downloads = &state.download_stats;
+    let top = downloads.top(TOP_DOWNLOADS);
+    html! {
+        div class="stats" hx-get="/stats" hx-trigger="every 2s" hx-swap="outerHTML" {
+            table {
+                tr {
+                    th { "Downloads running" }
+                    td { (downloads.active()) }
+                }
+                tr {
+                    th { "Downloads since start" }
+                    td { (downloads.requests()) " (" (format_size(downloads.bytes(), BINARY)) ")" }
+                }
+                tr {
+                    th { "Download throughput" }
+                    td { (format_size(bandwidth.current_rate(), BINARY)) "/s" }
+                }
+                tr {
+                    th { "Bandwidth limit" }
+                    td {
+                        @match bandwidth.limit() {
+                            Some(limit) => { (format_size(limit, BINARY)) "/s" },
+                            None => "None",
+                        }
+                    }
+                }
+                tr {
+                    th { "Sent since start" }
+                    td { (format_size(bandwidth.total_sent(), BINARY)) }
+                }
+                @if let Some(slots) = state.download_queue.slots() {
+                    @let (running, waiting) = state.download_queue.counts();
+                    tr {
+                        th { "Download queue" }
+                        td { (running) " of " (slots) " slots in use, " (waiting) " waiting" }
+                    }
+                }
+            }
+            @if !top.is_empty() {
+                h4 { "Most downloaded" }
+                table class="stats-top" {
+                    @for (path, count) in &top {
+                        tr {
+                            td { (count) }
+                            td { code { (path) } }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// --- metrics_handler ---
+async fn metrics_handler(State(state): State<SharedState>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        stats::prometheus(&state.download_stats, bandwidth::get().total_sent()),
+    )
+        .into_response()
+}
+
+// --- admin_handler ---
+// One place to run the server from: its settings, the accounts, the shares, the
+// downloads in progress and the background jobs. Only for users marked `admin` in the
+// users file, so it needs `--users`.
+#[allow(clippy::result_large_err)]
+fn require_admin(state: &AppState) -> Result<(), Response> {
+    if state.users.is_none() {
+        return Err(error_response(
+            StatusCode::NOT_FOUND,
+            "The admin area needs accounts; start kiv with --users.",
+        ));
+    }
+    if !auth::is_admin() {
+        return Err(error_response(
+            StatusCode::FORBIDDEN,
+            "Only administrators can open the admin area.",
+        ));
+    }
+    Ok(())
+}
+
+async fn admin_handler(State(state): State<SharedState>) -> Result<Markup, Response> {
+    require_admin(&state)?;
+    Ok(html! {
         (DOCTYPE)
         html lang="en" {
             head {
                 meta charset="UTF-8";
                 meta name="viewport" content="width=device-width, initial-scale=1.0";
-                title { "Download " (filename) }
-                link rel="stylesheet" href="/static/styles.css"; // Relative path for CSS
+                title { "Administration" }
+                link rel="stylesheet" href="/static/styles.css";
+                script src="/static/htmx.min.js" {}
+            }
+            body class="admin" {
+                h1 {
+                    "Administration"
+                    a class="admin-back" href="/" { "Back to Files" }
+                }
+                section {
+                    h2 { "Configuration" }
+                    (admin_config(&state))
+                }
+                section #admin-users {
+                    (admin_users(&state, None))
+                }
+                section {
+                    h2 { "Shares" }
+                    div #admin-shares { (admin_shares(&state)) }
+                }
+                section {
+                    h2 { "Downloads in Progress" }
+                    div hx-get="/admin/transfers" hx-trigger="load, every 2s" hx-swap="innerHTML" {}
+                }
+                section {
+                    h2 { "Statistics" }
+                    div hx-get="/stats" hx-trigger="load" hx-swap="innerHTML" {}
+                }
+                section {
+                    h2 { "Background Jobs" }
+                    div hx-get="/jobs" hx-trigger="load, every 5s" hx-swap="innerHTML" {}
+                }
+                section {
+                    h2 { "Audit Log" }
+                    @if let Some(file) = state.audit.file() {
+                        p { "Also written to " code { (file.display()) } "." }
+                    }
+                    div hx-get="/admin/audit" hx-trigger="load, every 10s" hx-swap="innerHTML" {}
+                }
+            }
+        }
+    })
+}
+
+fn admin_config(state: &AppState) -> Markup {
+    let enabled = |on: bool| if on { "Yes" } else { "No" };
+    let value_name = |value: Option<clap::builder::PossibleValue>| {
+        value.map_or_else(String::new, |value| value.get_name().to_string())
+    };
+    html! {
+        table class="admin-table admin-config" {
+            @for mount in state.mounts.list() {
+                tr {
+                    th { "Serving" }
+                    td {
+                        @if !mount.name.is_empty() { (mount.name) " → " }
+                        code { (mount.dir.display()) }
+                    }
+                }
+            }
+            tr { th { "State directory" } td { code { (state.root_dir.join(STATE_DIR_NAME).display()) } } }
+            tr { th { "Read-only" } td { (enabled(state.read_only)) } }
+            tr {
+                th { "Client addresses" }
+                td {
+                    @if state.ip_filter.allowed().is_empty() {
+                        "Any"
+                    } @else {
+                        "Only " (state.ip_filter.allowed().iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))
+                    }
+                    @if !state.ip_filter.denied().is_empty() {
+                        ", except " (state.ip_filter.denied().iter().map(ToString::to_string).collect::<Vec<_>>().join(", "))
+                    }
+                }
+            }
+            tr { th { "Sharing" } td { (enabled(state.sharing)) } }
+            tr {
+                th { "Left out" }
+                td {
+                    @if state.features.disabled().is_empty() {
+                        "Nothing"
+                    } @else {
+                        (state.features.disabled().iter().map(|feature| value_name(clap::ValueEnum::to_possible_value(feature))).collect::<Vec<_>>().join(", "))
+                    }
+                }
+            }
+            tr { th { "Signing keys" } td { (state.signer.key_count()) } }
+            tr {
+                th { "Locked folders" }
+                td {
+                    @match locks::get() {
+                        Some(folder_locks) => (folder_locks.len()),
+                        None => "None",
+                    }
+                }
+            }
+            @if let Some(users) = &state.users {
+                tr {
+                    th { "Without a login" }
+                    td {
+                        @let guest_may = users
+                            .guest()
+                            .map(|guest| {
+                                [auth::Capability::Browse, auth::Capability::Preview, auth::Capability::Download]
+                                    .into_iter()
+                                    .filter(|capability| guest.has_capability(*capability))
+                                    .map(auth::Capability::name)
+                                    .collect::<Vec<_>>()
+                            })
+                            .unwrap_or_default();
+                        @if guest_may.is_empty() {
+                            "Share links only"
+                        } @else {
+                            "Share links; " (guest_may.join(", ")) " where allowed"
+                        }
+                    }
+                }
+                tr {
+                    th { "Links created by" }
+                    td {
+                        @if state.share_roles.is_empty() { "Any user" } @else { "Roles " (state.share_roles.join(", ")) }
+                    }
+                }
+                tr {
+                    th { "Uploads by" }
+                    td {
+                        @if state.upload_roles.is_empty() { "Any user" } @else { "Roles " (state.upload_roles.join(", ")) }
+                    }
+                }
+            }
+            tr {
+                th { "Dotfiles" }
+                td { (value_name(clap::ValueEnum::to_possible_value(&policy::hidden_policy()))) }
+            }
+            tr {
+                th { "Symlinks" }
+                td { (value_name(clap::ValueEnum::to_possible_value(&policy::symlink_policy()))) }
+            }
+            tr {
+                th { "Trash" }
+                td {
+                    @match state.trash.retention {
+                        Some(retention) => { "Kept " (retention.as_secs() / (24 * 60 * 60)) " days" },
+                        None => "Kept until emptied",
+                    }
+                    @if let Some(max_size) = state.trash.max_size {
+                        ", at most " (format_size(max_size, BINARY))
+                    }
+                }
+            }
+            tr {
+                th { "Versions kept" }
+                td { @if state.versions.enabled() { (state.versions.keep) } @else { "Off" } }
+            }
+            tr {
+                th { "Virus scanning" }
+                td {
+                    @match &state.clamd {
+                        Some(clamd) => code { (clamd) },
+                        None => "Off",
+                    }
+                }
             }
-            body {
-                div class="download-card" {
-                    div class="file-header" {
-                        div class="file-icon" { (file_icon) }
-                        div class="file-title" { h1 { (filename) } }
+            tr {
+                th { "Upload types" }
+                td {
+                    @if state.upload_types.checked() {
+                        @if state.upload_types.fixes_extensions() {
+                            "Extensions fixed to match contents"
+                        } @else {
+                            "Must match their extension"
+                        }
+                    } @else {
+                        "Not checked"
                     }
-                    div class="file-meta" {
-                        @if let Some(size_str) = &size { div { strong { "Size:" } (size_str) } }
-                        @if let Some(mod_str) = &modified { div { strong { "Modified:" } (mod_str) } }
-                        div { strong { "Type:" } (mime_type) }
+                    @if !state.upload_types.denied().is_empty() {
+                        "; refused: " (state.upload_types.denied().join(", "))
                     }
-                    // The download link is also relative
-                    a href={"/direct-download/"(uuid)} class="download-button" { "Download File" }
-                    div class="footer" {
-                        "This file has been shared with you securely. Click the Download button to save it to your device."
+                }
+            }
+            tr { th { "Video transcoding" } td { (enabled(state.transcoder.is_some())) } }
+            tr { th { "Folder sizes" } td { (enabled(state.show_dir_sizes)) } }
+            tr { th { "Cache limit" } td { (format_size(state.cache.max_size, BINARY)) } }
+            tr {
+                th { "Bandwidth limit" }
+                td {
+                    @match bandwidth::get().limit() {
+                        Some(limit) => { (format_size(limit, BINARY)) "/s" },
+                        None => "None",
+                    }
+                }
+            }
+            tr {
+                th { "Request timeout" }
+                td {
+                    @match state.request_timeout {
+                        Some(timeout) => { (timeout.as_secs()) " seconds" },
+                        None => "None",
+                    }
+                }
+            }
+            tr { th { "Stream buffer" } td { (format_size(serve::stream_buffer_size() as u64, BINARY)) } }
+            tr {
+                th { "Downloads per client" }
+                td {
+                    @match state.client_limits.per_client() {
+                        Some(limit) => (limit),
+                        None => "Unlimited",
+                    }
+                }
+            }
+            tr {
+                th { "Download queue" }
+                td {
+                    @match state.download_queue.slots() {
+                        Some(slots) => { (slots) " slots" },
+                        None => "Off",
                     }
                 }
             }
         }
-    };
-    markup.into_response()
+    }
 }
 
-// --- download_handler --- (remains the same)
-async fn download_handler(
-    State(state): State<SharedState>,
-    AxumPath(uuid): AxumPath<Uuid>,
-) -> Response {
-    info!("Download requested for UUID: {}", uuid);
-
-    let path_to_serve = match state.shares.get(&uuid) {
-        Some(path_ref) => path_ref.value().clone(),
-        None => {
-            info!("Share link not found: {}", uuid);
-            return error_response(StatusCode::NOT_FOUND, "Invalid or expired share link.");
-        }
+fn admin_users(state: &AppState, notice: Option<&str>) -> Markup {
+    let Some(users) = &state.users else {
+        return html! {};
     };
-
-    info!("Attempting to serve file: {}", path_to_serve.display());
-
-    match path_to_serve.canonicalize() {
-        Ok(canonical_path_now) => {
-            if !canonical_path_now.starts_with(&state.root_dir) {
-                error!(
-                    "Shared path {} resolved outside root {} during download (UUID: {}).",
-                    path_to_serve.display(),
-                    state.root_dir.display(),
-                    uuid
-                );
-                return error_response(StatusCode::FORBIDDEN, "Access denied.");
-            }
-            if !canonical_path_now.is_file() {
-                error!(
-                    "Shared path {} is no longer a file (UUID: {}).",
-                    canonical_path_now.display(),
-                    uuid
-                );
-                return error_response(
-                    StatusCode::NOT_FOUND,
-                    "Shared item is no longer accessible as a file.",
-                );
-            }
+    html! {
+        h2 { "Accounts" }
+        p {
+            "Accounts, roles and permissions are read from " code { (users.file().display()) } ". "
+            "Add a password with " code { "kiv --hash-password" } ", then reload the file."
         }
-        Err(e) => {
-            error!(
-                "Failed to re-validate/canonicalize shared path {} for download (UUID: {}): {}",
-                path_to_serve.display(),
-                uuid,
-                e
-            );
-            if e.kind() == std::io::ErrorKind::NotFound {
-                return error_response(StatusCode::NOT_FOUND, "Shared file not found.");
-            } else {
-                return error_response(StatusCode::FORBIDDEN, "Cannot access shared file.");
+        table class="admin-table" {
+            thead { tr { th { "User" } th { "Roles" } th { "Home" } th { "Administrator" } } }
+            tbody {
+                @for user in users.list() {
+                    tr {
+                        td { (user.name) }
+                        td { (user.roles.join(", ")) }
+                        td { @if let Some(home) = &user.home { code { (home) } } }
+                        td { @if user.admin { "Yes" } }
+                    }
+                }
             }
         }
+        p { (state.sessions.count()) " active login sessions." }
+        button hx-post="/admin/users/reload" hx-target="#admin-users" hx-swap="innerHTML" {
+            "🔄 Reload Users File"
+        }
+        @if let Some(notice) = notice {
+            span class="admin-notice" { (notice) }
+        }
     }
+}
 
-    let metadata = match tokio::fs::metadata(&path_to_serve).await {
-        Ok(meta) => meta,
-        Err(e) => {
-            error!(
-                "Failed to get metadata for file {}: {}",
-                path_to_serve.display(),
-                e
-            );
-            return error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Could not read file information for download.",
-            );
+fn admin_shares(state: &AppState) -> Markup {
+    struct Row {
+        uuid: Uuid,
+        path: String,
+        kind: &'static str,
+        created: DateTime<Local>,
+        created_by: Option<String>,
+    }
+    let mut shares: Vec<Row> = state
+        .shares
+        .iter()
+        .map(|entry| {
+            let share = entry.value();
+            Row {
+                uuid: *entry.key(),
+                path: state.mounts.relative(&share.path),
+                kind: match (share.inline, share.torrent, share.high_priority) {
+                    (_, true, _) => "Torrent",
+                    (_, _, true) => "Priority",
+                    (true, _, _) => "Open in browser",
+                    _ => "Download",
+                },
+                created: share.created,
+                created_by: share.created_by.clone(),
+            }
+        })
+        .collect();
+    shares.sort_by_key(|row| std::cmp::Reverse(row.created));
+    html! {
+        @if shares.is_empty() {
+            p { "No shares." }
+        } @else {
+            table class="admin-table" {
+                thead { tr { th { "File" } th { "Kind" } th { "Created" } th { "By" } th {} } }
+                tbody {
+                    @for row in &shares {
+                        tr {
+                            td { a href=(format!("/share/{}", row.uuid)) { (row.path) } }
+                            td { (row.kind) }
+                            td { (row.created.format("%Y-%m-%d %H:%M:%S")) }
+                            td { (row.created_by.as_deref().unwrap_or("")) }
+                            td {
+                                button hx-post="/admin/shares/revoke"
+                                       hx-vals=(serde_json::json!({ "uuid": row.uuid }).to_string())
+                                       hx-target="#admin-shares"
+                                       hx-swap="innerHTML"
+                                       hx-confirm="Revoke this share? Its link stops working."
+                                       { "Revoke" }
+                            }
+                        }
+                    }
+                }
+            }
         }
-    };
+    }
+}
 
-    match tokio::fs::File::open(&path_to_serve).await {
-        Ok(file) => {
-            let filename = path_to_serve
-                .file_name()
-                .and_then(|name| name.to_str())
-                .unwrap_or("download")
-                .to_string();
+async fn admin_transfers_handler(State(state): State<SharedState>) -> Result<Markup, Response> {
+    require_admin(&state)?;
+    let transfers = state.download_stats.transfers();
+    Ok(html! {
+        @if transfers.is_empty() {
+            p { "No downloads running." }
+        } @else {
+            table class="admin-table" {
+                thead { tr { th { "File" } th { "Client" } th { "User" } th { "Running" } th { "Sent" } } }
+                tbody {
+                    @for transfer in &transfers {
+                        tr {
+                            td { code { (transfer.path) } }
+                            td { (transfer.client) }
+                            td { (transfer.user.as_deref().unwrap_or("")) }
+                            td { (media::format_duration(transfer.started.elapsed())) }
+                            td { (format_size(transfer.sent.load(std::sync::atomic::Ordering::Relaxed), BINARY)) }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
 
-            let mime_type = mime_guess::from_path(&path_to_serve)
-                .first_or_octet_stream()
-                .to_string();
+async fn admin_audit_handler(State(state): State<SharedState>) -> Result<Markup, Response> {
+    const SHOWN_EVENTS: usize = 100;
 
-            let stream = ReaderStream::with_capacity(file, 1 << 18); // 256KiB buffer
-            let body = axum::body::Body::from_stream(stream);
+    require_admin(&state)?;
+    let events = state.audit.recent(SHOWN_EVENTS);
+    Ok(html! {
+        @if events.is_empty() {
+            p { "Nothing recorded since the server started." }
+        } @else {
+            table class="admin-table admin-audit" {
+                thead { tr { th { "Time" } th { "Action" } th { "User" } th { "Client" } th { "Path" } th { "Result" } } }
+                tbody {
+                    @for event in &events {
+                        @let time = DateTime::parse_from_rfc3339(&event.time)
+                            .map(|at| at.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+                            .unwrap_or_else(|_| event.time.clone());
+                        tr class={ "audit-" (event.result) } {
+                            td { (time) }
+                            td { (event.action) }
+                            td { (event.actor.as_deref().unwrap_or("")) }
+                            td { @if let Some(ip) = event.ip { (ip) } }
+                            td { code { (event.path) } }
+                            td { (event.result) " (" (event.status) ")" }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
 
-            let mut headers = HeaderMap::new();
-            headers.insert(
-                header::CONTENT_TYPE,
-                HeaderValue::from_str(&mime_type)
-                    .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
-            );
-            headers.insert(
-                header::CONTENT_LENGTH,
-                HeaderValue::from_str(&metadata.len().to_string())
-                    .unwrap_or_else(|_| HeaderValue::from_static("0")),
-            );
-            headers.insert(
-                header::CONTENT_DISPOSITION,
-                HeaderValue::from_str(&format!("attachment; filename=\"{}\"", filename))
-                    .unwrap_or_else(|_| {
-                        HeaderValue::from_static("attachment; filename=\"download\"")
-                    }),
-            );
+#[derive(Deserialize)]
+struct RevokeShareForm {
+    uuid: Uuid,
+}
 
-            (StatusCode::OK, headers, body).into_response()
-        }
-        Err(e) => {
-            error!(
-                "Failed to open file for download {}: {}",
-                path_to_serve.display(),
-                e
-            );
-            error_response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Could not read file for download.",
-            )
-        }
+async fn admin_revoke_share_handler(
+    State(state): State<SharedState>,
+    Form(form): Form<RevokeShareForm>,
+) -> Result<Markup, Response> {
+    require_admin(&state)?;
+    if let Some((uuid, share)) = state.shares.remove(&form.uuid) {
+        audit::note(format!(
+            "{} (share {})",
+            state.mounts.relative(&share.path),
+            uuid
+        ));
+        info!(
+            "Share {} of {} revoked by {}",
+            uuid,
+            share.path.display(),
+            auth::current_user().unwrap_or_default()
+        );
     }
+    Ok(admin_shares(&state))
+}
+
+async fn admin_reload_users_handler(State(state): State<SharedState>) -> Result<Markup, Response> {
+    require_admin(&state)?;
+    let notice = match state.users.as_ref().map(|users| users.reload()) {
+        Some(reload) => match reload.await {
+            Ok(count) => format!("Reloaded {} accounts.", count),
+            Err(message) => {
+                error!("{}", message);
+                format!("Not reloaded: {}", message)
+            }
+        },
+        None => String::new(),
+    };
+    Ok(admin_users(&state, Some(&notice)))
 }
 
 // --- Utility Functions --- (remain the same)
@@ -949,14 +7594,17 @@ fn error_response(status_code: StatusCode, message: &str) -> Response {
 }
 
 fn sanitize_path(path_str: &str) -> PathBuf {
-    let decoded_path =
-        urlencoding::decode(path_str).map_or_else(|_| path_str.into(), |p| p.into_owned());
+    // Names that aren't UTF-8 arrive with their raw bytes percent-encoded; see `rawnames`.
+    let decoded_path = urlencoding::decode(path_str).map_or_else(
+        |_| rawnames::decode(path_str),
+        |p| PathBuf::from(p.into_owned()),
+    );
     let mut clean_path = PathBuf::new();
     for component in Path::new(&decoded_path).components() {
         match component {
             std::path::Component::Normal(comp) => {
-                // Allow all normal path components (including hidden files and directories)
-                // Security is handled by resolve_and_validate_path which ensures we stay within root_dir
+                // Hidden files are kept; resolve_and_validate_path applies the --hidden policy
+                // and ensures we stay within root_dir.
                 if comp == std::ffi::OsStr::new(".") && !clean_path.as_os_str().is_empty() {
                     continue; // Skip redundant "." components
                 }
@@ -980,18 +7628,39 @@ fn sanitize_path(path_str: &str) -> PathBuf {
     }
 }
 
+// Maps a sanitized request path to the file it names inside its mount. The virtual top
+// level holding the mounts isn't a directory on disk, so it is refused here; handlers that
+// can show it check `Mounts::is_top` first.
+#[allow(clippy::result_large_err)]
 fn resolve_and_validate_path(
-    root_dir: &Path,
+    mounts: &mounts::Mounts,
     sanitized_relative_path: &Path,
 ) -> Result<PathBuf, Response> {
+    if mounts.is_top(sanitized_relative_path) {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Choose one of the mounted folders first.",
+        ));
+    }
+    let Some((mount, inner_path)) = mounts.split(sanitized_relative_path) else {
+        info!("No mount for path: {}", sanitized_relative_path.display());
+        return Err(error_response(StatusCode::NOT_FOUND, "Path not found."));
+    };
+    let root_dir = mount.dir.as_path();
     let mut potentially_unsafe_path = root_dir.to_path_buf();
-    potentially_unsafe_path.push(sanitized_relative_path);
+    potentially_unsafe_path.push(&inner_path);
 
     match potentially_unsafe_path.canonicalize() {
         Ok(canonical_path) => {
-            if canonical_path.starts_with(root_dir) {
-                Ok(canonical_path)
-            } else {
+            if !policy::is_accessible(root_dir, &potentially_unsafe_path)
+                || !policy::is_accessible(root_dir, &canonical_path)
+            {
+                info!(
+                    "Denied access to internal path: {}",
+                    canonical_path.display()
+                );
+                Err(error_response(StatusCode::FORBIDDEN, "Access denied."))
+            } else if !canonical_path.starts_with(root_dir) {
                 error!(
                     "Path traversal attempt: Sanitized path '{}' resolved to '{}' which is outside root '{}'",
                     sanitized_relative_path.display(),
@@ -999,6 +7668,34 @@ fn resolve_and_validate_path(
                     root_dir.display()
                 );
                 Err(error_response(StatusCode::FORBIDDEN, "Access denied."))
+            } else if !auth::in_home(&mounts.relative(&canonical_path)) {
+                // Checked on the resolved path, so a link can't lead out of a home.
+                info!(
+                    "Outside {}'s home: {}",
+                    auth::current_user().unwrap_or_default(),
+                    canonical_path.display()
+                );
+                Err(error_response(
+                    StatusCode::FORBIDDEN,
+                    "That's outside your folder.",
+                ))
+            } else if let Some(folder) =
+                locks::get().and_then(|locks| locks.locked(&mounts.relative(&canonical_path)))
+            {
+                info!(
+                    "Locked by the password on {}: {}",
+                    folder,
+                    canonical_path.display()
+                );
+                Err(locked_response(folder, None))
+            } else if !auth::allows(&mounts.relative(&canonical_path)) {
+                info!("Denied by user permissions: {}", canonical_path.display());
+                Err(error_response(
+                    StatusCode::FORBIDDEN,
+                    "You don't have permission to do that here.",
+                ))
+            } else {
+                Ok(canonical_path)
             }
         }
         Err(e) => match e.kind() {
@@ -1024,6 +7721,110 @@ fn resolve_and_validate_path(
     }
 }
 
+// Canonicalizes each `--root` mount, refusing ones that aren't directories or whose names
+// are taken.
+async fn resolve_mounts(args: &[mounts::MountArg]) -> Result<Vec<mounts::Mount>, String> {
+    let mut resolved: Vec<mounts::Mount> = Vec::new();
+    for arg in args {
+        if resolved.iter().any(|mount| mount.name == arg.name) {
+            return Err(format!("Mount name '{}' is used more than once.", arg.name));
+        }
+        let dir = fs::canonicalize(&arg.dir).await.map_err(|e| {
+            format!(
+                "Failed to resolve mount '{}' at '{}': {}.",
+                arg.name,
+                arg.dir.display(),
+                e
+            )
+        })?;
+        if !dir.is_dir() {
+            return Err(format!(
+                "Mount '{}' at '{}' is not a directory.",
+                arg.name,
+                dir.display()
+            ));
+        }
+        resolved.push(mounts::Mount {
+            name: arg.name.clone(),
+            dir,
+        });
+    }
+    Ok(resolved)
+}
+
+async fn prepare_drop_zone(mounts: &mounts::Mounts, dir: &Path) -> Result<DropZone, String> {
+    let relative_path = sanitize_path(&dir.to_string_lossy());
+    let (mount, inner_path) = mounts
+        .split(&relative_path)
+        .filter(|_| !mounts.is_top(&relative_path))
+        .ok_or_else(|| {
+            format!(
+                "Drop-zone directory '{}' is not inside one of the mounts.",
+                dir.display()
+            )
+        })?;
+    let full_path = mount.dir.join(inner_path);
+    fs::create_dir_all(&full_path).await.map_err(|e| {
+        format!(
+            "Failed to create drop-zone directory '{}': {}.",
+            full_path.display(),
+            e
+        )
+    })?;
+    let full_path = resolve_and_validate_path(mounts, &relative_path).map_err(|_| {
+        format!(
+            "Drop-zone directory '{}' is not usable inside the root.",
+            dir.display()
+        )
+    })?;
+    if !full_path.is_dir() {
+        return Err(format!(
+            "Drop-zone path '{}' is not a directory.",
+            full_path.display()
+        ));
+    }
+    Ok(DropZone {
+        relative_path,
+        full_path,
+    })
+}
+
+// Resolves a request path that must name a directory, returning its display form
+// (relative, forward slashes) alongside the canonical path.
+#[allow(clippy::result_large_err)]
+fn resolve_directory(state: &AppState, requested: &str) -> Result<(String, PathBuf), Response> {
+    let sanitized_req_path = sanitize_path(requested);
+    let full_path = resolve_and_validate_path(&state.mounts, &sanitized_req_path)?;
+    if !full_path.is_dir() {
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Requested path is not a directory.",
+        ));
+    }
+    let relative_path = rawnames::encode(&sanitized_req_path);
+    let relative_path = if relative_path == "." {
+        String::new()
+    } else {
+        relative_path
+    };
+    Ok((relative_path, full_path))
+}
+
+// Names kiv reads or writes itself: its folders at the top of a mount, and the files that
+// change how a folder is listed for everyone. Uploads can't take them, wherever they go.
+fn is_reserved_name(name: &str) -> bool {
+    INTERNAL_DIRS.contains(&name)
+        || name == kivignore::IGNORE_FILE_NAME
+        || name == kivmeta::META_FILE_NAME
+}
+
+fn is_internal_path(root_dir: &Path, path: &Path) -> bool {
+    path.strip_prefix(root_dir)
+        .ok()
+        .and_then(|relative| relative.components().next())
+        .is_some_and(|first| INTERNAL_DIRS.iter().any(|dir| first.as_os_str() == *dir))
+}
+
 fn get_metadata_strings(metadata: &Metadata) -> (Option<String>, Option<String>) {
     let size = if metadata.is_file() {
         Some(format_size(metadata.len(), BINARY))
@@ -1049,9 +7850,185 @@ fn is_image_file(path: &Path) -> bool {
     matches!(
         extension.as_str(),
         "jpg" | "jpeg" | "png" | "gif" | "bmp" | "svg" | "webp" | "ico" | "tiff" | "tif" | "avif"
+    ) || convert::source_format(path).is_some()
+}
+
+fn is_audio_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    matches!(
+        extension.as_str(),
+        "mp3" | "flac" | "ogg" | "oga" | "opus" | "wav" | "m4a" | "aac"
+    )
+}
+
+fn is_pdf_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+fn is_font_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    matches!(extension.as_str(), "ttf" | "otf" | "woff" | "woff2")
+}
+
+fn is_video_file(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    matches!(
+        extension.as_str(),
+        "mp4"
+            | "m4v"
+            | "webm"
+            | "ogv"
+            | "mov"
+            | "mkv"
+            | "avi"
+            | "wmv"
+            | "flv"
+            | "mpg"
+            | "mpeg"
+            | "m2ts"
+            | "mts"
+            | "3gp"
+            | "vob"
     )
 }
 
+// How a file is previewed when clicked in a listing, if at all.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum PreviewKind {
+    Text,
+    Image,
+    Video,
+    Audio,
+    Pdf,
+    Archive,
+    Document,
+    Font,
+    Email,
+    Epub,
+    Comic,
+    #[cfg(feature = "data-preview")]
+    Data,
+}
+
+impl PreviewKind {
+    fn endpoint(self) -> &'static str {
+        match self {
+            PreviewKind::Text => "/preview",
+            PreviewKind::Image => "/image-preview",
+            PreviewKind::Video => "/video-preview",
+            PreviewKind::Audio => "/audio-preview",
+            PreviewKind::Pdf => "/pdf-preview",
+            PreviewKind::Archive => "/archive-preview",
+            PreviewKind::Document => "/document-preview",
+            PreviewKind::Font => "/font-preview",
+            PreviewKind::Email => "/email-preview",
+            PreviewKind::Epub => "/epub-preview",
+            PreviewKind::Comic => "/comic-preview",
+            #[cfg(feature = "data-preview")]
+            PreviewKind::Data => "/data-preview",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            PreviewKind::Text => "📄",
+            PreviewKind::Image => "🖼️",
+            PreviewKind::Video => "🎬",
+            PreviewKind::Audio => "🎵",
+            PreviewKind::Pdf => "📕",
+            PreviewKind::Archive => "🗜️",
+            PreviewKind::Document => "📝",
+            PreviewKind::Font => "🔤",
+            PreviewKind::Email => "✉️",
+            PreviewKind::Epub => "📖",
+            PreviewKind::Comic => "💬",
+            #[cfg(feature = "data-preview")]
+            PreviewKind::Data => "📊",
+        }
+    }
+}
+
+// Where clicking a file of `kind` opens it, unless previews are left out.
+fn preview_url(state: &AppState, kind: Option<PreviewKind>, encoded_path: &str) -> Option<String> {
+    kind.filter(|_| state.features.on(Feature::Preview))
+        .map(|kind| format!("{}?path={}", kind.endpoint(), encoded_path))
+}
+
+fn preview_kind(path: &Path) -> Option<PreviewKind> {
+    if is_image_file(path) {
+        Some(PreviewKind::Image)
+    } else if is_video_file(path) {
+        Some(PreviewKind::Video)
+    } else if is_audio_file(path) {
+        Some(PreviewKind::Audio)
+    } else if is_pdf_file(path) {
+        Some(PreviewKind::Pdf)
+    } else if is_font_file(path) {
+        Some(PreviewKind::Font)
+    } else if email::is_email_file(path) {
+        Some(PreviewKind::Email)
+    } else if epub::is_epub_file(path) {
+        Some(PreviewKind::Epub)
+    } else if comics::is_comic_file(path) {
+        Some(PreviewKind::Comic)
+    } else if documents::document_format(path).is_some() {
+        Some(PreviewKind::Document)
+    } else if is_data_file(path) {
+        data_preview_kind()
+    } else if archive::archive_format(path).is_some() {
+        Some(PreviewKind::Archive)
+    } else if is_text_file(path) {
+        Some(PreviewKind::Text)
+    } else {
+        None
+    }
+}
+
+// Parquet/Feather files are recognised even without the `data-preview` feature, so they
+// aren't sniffed as text; they just have no preview then.
+fn is_data_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            ["parquet", "pq", "feather", "arrow", "ipc"]
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
+}
+
+#[cfg(feature = "data-preview")]
+fn data_preview_kind() -> Option<PreviewKind> {
+    Some(PreviewKind::Data)
+}
+
+#[cfg(not(feature = "data-preview"))]
+fn data_preview_kind() -> Option<PreviewKind> {
+    None
+}
+
+// Known text extensions first; anything else is sniffed so that `LICENSE`, `CHANGELOG`
+// and extensionless scripts can be previewed too.
+fn is_text_file(path: &Path) -> bool {
+    is_previewable_file(path) || (path.is_file() && text::looks_like_text(path))
+}
+
 fn is_previewable_file(path: &Path) -> bool {
     let extension = path
         .extension()
@@ -1142,6 +8119,50 @@ fn is_previewable_file(path: &Path) -> bool {
     )
 }
 
+// Choices for the preview's language picker: highlight.js names and display labels.
+const HIGHLIGHT_LANGUAGES: &[(&str, &str)] = &[
+    ("plaintext", "Plain text"),
+    ("apache", "Apache config"),
+    ("bash", "Bash"),
+    ("c", "C"),
+    ("cpp", "C++"),
+    ("csharp", "C#"),
+    ("css", "CSS"),
+    ("diff", "Diff"),
+    ("dockerfile", "Dockerfile"),
+    ("go", "Go"),
+    ("graphql", "GraphQL"),
+    ("html", "HTML"),
+    ("ini", "INI / TOML"),
+    ("java", "Java"),
+    ("javascript", "JavaScript"),
+    ("json", "JSON"),
+    ("kotlin", "Kotlin"),
+    ("less", "Less"),
+    ("lua", "Lua"),
+    ("makefile", "Makefile"),
+    ("markdown", "Markdown"),
+    ("nginx", "Nginx config"),
+    ("objectivec", "Objective-C"),
+    ("perl", "Perl"),
+    ("php", "PHP"),
+    ("powershell", "PowerShell"),
+    ("python", "Python"),
+    ("r", "R"),
+    ("ruby", "Ruby"),
+    ("rust", "Rust"),
+    ("scss", "SCSS"),
+    ("shell", "Shell session"),
+    ("sql", "SQL"),
+    ("swift", "Swift"),
+    ("typescript", "TypeScript"),
+    ("xml", "XML"),
+    ("yaml", "YAML"),
+];
+
+// Languages in HIGHLIGHT_LANGUAGES that are loaded separately from the highlight.js bundle.
+const HIGHLIGHT_EXTRA_LANGUAGES: &[&str] = &["apache", "dockerfile", "nginx", "powershell"];
+
 fn detect_language(path: &Path) -> String {
     let extension = path
         .extension()
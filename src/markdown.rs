@@ -0,0 +1,68 @@
+use ammonia::{UrlRelative, UrlRelativeEvaluate};
+use pulldown_cmark::{Options, Parser};
+use std::borrow::Cow;
+
+// READMEs larger than this are left out of the listing.
+pub const MAX_README_SIZE: u64 = 1024 * 1024;
+
+// Names picked up as a folder's README, in order of preference; matched ignoring case.
+const README_NAMES: &[&str] = &["readme.md", "readme.markdown", "readme.txt", "readme"];
+
+pub fn is_markdown_file(name: &str) -> bool {
+    let name = name.to_lowercase();
+    name.ends_with(".md") || name.ends_with(".markdown")
+}
+
+// Which of `names` is the folder's README, if any.
+pub fn find_readme<'a>(names: impl Iterator<Item = &'a str> + Clone) -> Option<&'a str> {
+    README_NAMES
+        .iter()
+        .find_map(|readme| names.clone().find(|name| name.eq_ignore_ascii_case(readme)))
+}
+
+// --- Rendering ---
+// Markdown as sanitized HTML. Relative links and images are resolved against `base_dir`
+// (relative to the root, forward slashes) and served through `/raw`.
+pub fn to_html(markdown: &str, base_dir: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new_ext(markdown, options));
+
+    ammonia::Builder::default()
+        .url_relative(UrlRelative::Custom(Box::new(RawUrls {
+            base_dir: base_dir.to_string(),
+        })))
+        .clean(&html)
+        .to_string()
+}
+
+struct RawUrls {
+    base_dir: String,
+}
+
+impl UrlRelativeEvaluate<'_> for RawUrls {
+    fn evaluate<'a>(&self, url: &'a str) -> Option<Cow<'a, str>> {
+        // In-page anchors (footnotes, headings) stay as they are.
+        if url.starts_with('#') {
+            return Some(Cow::Borrowed(url));
+        }
+        raw_url(&self.base_dir, url).map(Cow::Owned)
+    }
+}
+
+fn raw_url(base_dir: &str, url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or("");
+    let path = urlencoding::decode(path).ok()?;
+    let joined = if path.starts_with('/') {
+        path.into_owned()
+    } else {
+        format!("{}/{}", base_dir, path)
+    };
+    let resolved = crate::sanitize_path(&joined)
+        .to_string_lossy()
+        .replace('\\', "/");
+    Some(format!("/raw?path={}", urlencoding::encode(&resolved)))
+}
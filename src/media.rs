@@ -0,0 +1,153 @@
+use lofty::prelude::*;
+use std::{path::Path, time::Duration};
+
+// --- Audio tags ---
+#[derive(Default, Debug)]
+pub struct AudioMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub duration: Option<Duration>,
+    pub bitrate_kbps: Option<u32>,
+    pub sample_rate: Option<u32>,
+}
+
+// Reads ID3/Vorbis/MP4/APE tags and stream properties. Files lofty can't parse simply
+// yield no metadata; the player still works.
+pub async fn audio_metadata(path: &Path) -> AudioMetadata {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let Ok(tagged_file) = lofty::read_from_path(&path) else {
+            return AudioMetadata::default();
+        };
+        let properties = tagged_file.properties();
+        let tag = tagged_file
+            .primary_tag()
+            .or_else(|| tagged_file.first_tag());
+        let duration = properties.duration();
+
+        AudioMetadata {
+            title: tag.and_then(|tag| tag.title().map(|v| v.into_owned())),
+            artist: tag.and_then(|tag| tag.artist().map(|v| v.into_owned())),
+            album: tag.and_then(|tag| tag.album().map(|v| v.into_owned())),
+            duration: (!duration.is_zero()).then_some(duration),
+            bitrate_kbps: properties.audio_bitrate(),
+            sample_rate: properties.sample_rate(),
+        }
+    })
+    .await
+    .unwrap_or_default()
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let total = duration.as_secs();
+    let (hours, minutes, seconds) = (total / 3600, (total / 60) % 60, total % 60);
+    if hours > 0 {
+        format!("{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        format!("{}:{:02}", minutes, seconds)
+    }
+}
+
+// --- Image EXIF ---
+#[derive(Default, Debug)]
+pub struct ImageMetadata {
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    pub taken_at: Option<String>,
+    pub exposure_time: Option<String>,
+    pub aperture: Option<String>,
+    pub iso: Option<String>,
+    pub focal_length: Option<String>,
+    // Decimal degrees (latitude, longitude).
+    pub gps: Option<(f64, f64)>,
+}
+
+impl ImageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.camera.is_none()
+            && self.lens.is_none()
+            && self.taken_at.is_none()
+            && self.exposure_time.is_none()
+            && self.aperture.is_none()
+            && self.iso.is_none()
+            && self.focal_length.is_none()
+            && self.gps.is_none()
+    }
+}
+
+// Parses EXIF from JPEG, TIFF, HEIF, PNG and WebP containers. Images without EXIF (or
+// with a corrupt block) simply yield no metadata. GPS is only read when `include_gps`.
+pub async fn image_metadata(path: &Path, include_gps: bool) -> ImageMetadata {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let Ok(file) = std::fs::File::open(&path) else {
+            return ImageMetadata::default();
+        };
+        let Ok(exif) = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file))
+        else {
+            return ImageMetadata::default();
+        };
+
+        let ascii = |tag| {
+            exif.get_field(tag, exif::In::PRIMARY)
+                .and_then(|field| match &field.value {
+                    exif::Value::Ascii(values) => values.first(),
+                    _ => None,
+                })
+                .map(|value| String::from_utf8_lossy(value).trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let display = |tag| {
+            exif.get_field(tag, exif::In::PRIMARY)
+                .map(|field| field.display_value().with_unit(&exif).to_string())
+        };
+
+        let camera = match (ascii(exif::Tag::Make), ascii(exif::Tag::Model)) {
+            // Many models already start with the maker's name ("Canon EOS R5").
+            (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.or(model),
+        };
+
+        ImageMetadata {
+            camera,
+            lens: ascii(exif::Tag::LensModel),
+            taken_at: ascii(exif::Tag::DateTimeOriginal),
+            exposure_time: display(exif::Tag::ExposureTime),
+            aperture: display(exif::Tag::FNumber),
+            iso: display(exif::Tag::PhotographicSensitivity),
+            focal_length: display(exif::Tag::FocalLength),
+            gps: if include_gps {
+                gps_position(&exif)
+            } else {
+                None
+            },
+        }
+    })
+    .await
+    .unwrap_or_default()
+}
+
+fn gps_position(exif: &exif::Exif) -> Option<(f64, f64)> {
+    let coordinate = |value_tag, ref_tag, negative: &[u8]| {
+        let degrees = match &exif.get_field(value_tag, exif::In::PRIMARY)?.value {
+            exif::Value::Rational(parts) if parts.len() >= 3 => {
+                parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0
+            }
+            _ => return None,
+        };
+        let is_negative = match &exif.get_field(ref_tag, exif::In::PRIMARY)?.value {
+            exif::Value::Ascii(values) => values.first().is_some_and(|v| v.as_slice() == negative),
+            _ => false,
+        };
+        degrees
+            .is_finite()
+            .then_some(if is_negative { -degrees } else { degrees })
+    };
+
+    Some((
+        coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, b"S")?,
+        coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, b"W")?,
+    ))
+}
@@ -0,0 +1,149 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+// A `--root name=/path` argument, before the path is resolved.
+#[derive(Clone, Debug)]
+pub struct MountArg {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl FromStr for MountArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, dir) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected name=/path, got '{}'", s))?;
+        let name = name.trim();
+        if name.is_empty()
+            || name.starts_with('.')
+            || name.contains(['/', '\\'])
+            || name.chars().any(char::is_control)
+        {
+            return Err(format!(
+                "'{}' can't be used as a mount name; use a plain folder name",
+                name
+            ));
+        }
+        if dir.is_empty() {
+            return Err(format!("mount '{}' has no path", name));
+        }
+        Ok(MountArg {
+            name: name.to_string(),
+            dir: PathBuf::from(dir),
+        })
+    }
+}
+
+// A served directory tree. `name` is the first component of every request path below it,
+// or empty when the root directory is served on its own.
+#[derive(Clone, Debug)]
+pub struct Mount {
+    pub name: String,
+    pub dir: PathBuf,
+}
+
+impl Mount {
+    // `path`, somewhere inside the mount, as a request path with forward slashes.
+    pub fn relative(&self, path: &Path) -> String {
+        let inner = crate::rawnames::encode(path.strip_prefix(&self.dir).unwrap_or(path));
+        match (self.name.is_empty(), inner.is_empty()) {
+            (true, _) => inner,
+            (false, true) => self.name.clone(),
+            (false, false) => format!("{}/{}", self.name, inner),
+        }
+    }
+}
+
+// --- Mount table ---
+// With named mounts the top level is a virtual folder holding one entry per mount, and
+// every request path starts with a mount name. Without them the root directory is the
+// single, unnamed mount and paths are relative to it, as before.
+pub struct Mounts {
+    mounts: Vec<Mount>,
+}
+
+impl Mounts {
+    pub fn single(root_dir: PathBuf) -> Self {
+        Mounts {
+            mounts: vec![Mount {
+                name: String::new(),
+                dir: root_dir,
+            }],
+        }
+    }
+
+    // `mounts` must have canonical directories and distinct names.
+    pub fn named(mounts: Vec<Mount>) -> Self {
+        Mounts { mounts }
+    }
+
+    pub fn is_named(&self) -> bool {
+        self.mounts.iter().any(|mount| !mount.name.is_empty())
+    }
+
+    pub fn list(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    // Whether a sanitized request path is the virtual top level listing the mounts.
+    pub fn is_top(&self, sanitized: &Path) -> bool {
+        self.is_named() && sanitized == Path::new(".")
+    }
+
+    // The mount a sanitized request path points into, and the rest of the path below it.
+    pub fn split(&self, sanitized: &Path) -> Option<(&Mount, PathBuf)> {
+        if !self.is_named() {
+            return Some((&self.mounts[0], sanitized.to_path_buf()));
+        }
+        let mut components = sanitized.components();
+        let Some(Component::Normal(first)) = components.next() else {
+            return None;
+        };
+        let mount = self
+            .mounts
+            .iter()
+            .find(|mount| first == mount.name.as_str())?;
+        let rest = components.as_path();
+        let rest = if rest.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            rest.to_path_buf()
+        };
+        Some((mount, rest))
+    }
+
+    // Where a request path would be on disk, without resolving links or checking the
+    // policies; for looking at entries that were already listed.
+    pub fn join(&self, sanitized: &Path) -> Option<PathBuf> {
+        self.split(sanitized)
+            .map(|(mount, rest)| mount.dir.join(rest))
+    }
+
+    // The mount a resolved path lies in; the innermost one when mounts are nested.
+    pub fn containing(&self, path: &Path) -> Option<&Mount> {
+        self.mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.dir))
+            .max_by_key(|mount| mount.dir.components().count())
+    }
+
+    // The mount of a path returned by `resolve_and_validate_path`, which always lies in
+    // one; the first mount for anything else.
+    pub fn root_of(&self, path: &Path) -> &Mount {
+        self.containing(path).unwrap_or(&self.mounts[0])
+    }
+
+    // A resolved path as a request path.
+    pub fn relative(&self, path: &Path) -> String {
+        self.root_of(path).relative(path)
+    }
+
+    // Whether `path` is the top directory of a mount, which can't be moved or deleted.
+    pub fn is_mount_dir(&self, path: &Path) -> bool {
+        self.mounts.iter().any(|mount| mount.dir == path)
+    }
+}
@@ -0,0 +1,37 @@
+use globset::{GlobBuilder, GlobMatcher};
+
+// --- Name filter ---
+// The `filter=` of a listing: a glob when it has `*`, `?` or `[` in it, otherwise a
+// substring to look for. Both ignore case and only look at entry names.
+pub enum NameFilter {
+    Substring(String),
+    Glob(GlobMatcher),
+}
+
+impl NameFilter {
+    // `None` for a blank filter, which keeps everything.
+    pub fn parse(filter: &str) -> Option<Self> {
+        let filter = filter.trim();
+        if filter.is_empty() {
+            return None;
+        }
+        if filter.contains(['*', '?', '[']) {
+            // A pattern that doesn't parse, such as an unclosed `[`, is taken literally.
+            if let Ok(glob) = GlobBuilder::new(filter)
+                .case_insensitive(true)
+                .literal_separator(true)
+                .build()
+            {
+                return Some(NameFilter::Glob(glob.compile_matcher()));
+            }
+        }
+        Some(NameFilter::Substring(filter.to_lowercase()))
+    }
+
+    pub fn matches(&self, name: &str) -> bool {
+        match self {
+            NameFilter::Substring(needle) => name.to_lowercase().contains(needle.as_str()),
+            NameFilter::Glob(glob) => glob.is_match(name),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use serde::Serialize;
+use std::fs::Metadata;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Ownership {
+    // Permission bits as `ls -l` shows them, e.g. `rwxr-xr-x`.
+    pub mode: String,
+    pub owner: String,
+    pub group: String,
+}
+
+// Only Unix has owners and permission bits in this form; elsewhere there is nothing to show.
+#[cfg(not(unix))]
+pub fn of(_metadata: &Metadata) -> Option<Ownership> {
+    None
+}
+
+#[cfg(unix)]
+pub fn of(metadata: &Metadata) -> Option<Ownership> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(Ownership {
+        mode: unix::mode_string(metadata.mode()),
+        owner: unix::user_name(metadata.uid()),
+        group: unix::group_name(metadata.gid()),
+    })
+}
+
+#[cfg(unix)]
+mod unix {
+    use dashmap::DashMap;
+    use std::sync::LazyLock;
+
+    // User and group names by id. Listings ask for the same handful of ids over and over,
+    // and each lookup reads the password or group database.
+    static USER_NAMES: LazyLock<DashMap<u32, String>> = LazyLock::new(DashMap::new);
+    static GROUP_NAMES: LazyLock<DashMap<u32, String>> = LazyLock::new(DashMap::new);
+
+    // Ids without a name, such as files from another machine, are shown as the number.
+    pub fn user_name(uid: u32) -> String {
+        USER_NAMES
+            .entry(uid)
+            .or_insert_with(|| {
+                uzers::get_user_by_uid(uid)
+                    .map(|user| user.name().to_string_lossy().into_owned())
+                    .unwrap_or_else(|| uid.to_string())
+            })
+            .clone()
+    }
+
+    pub fn group_name(gid: u32) -> String {
+        GROUP_NAMES
+            .entry(gid)
+            .or_insert_with(|| {
+                uzers::get_group_by_gid(gid)
+                    .map(|group| group.name().to_string_lossy().into_owned())
+                    .unwrap_or_else(|| gid.to_string())
+            })
+            .clone()
+    }
+
+    // setuid, setgid and sticky replace the execute letter of their triplet: lowercase
+    // when execute is also set, uppercase when it isn't.
+    pub fn mode_string(mode: u32) -> String {
+        let triplet = |shift: u32, special: bool, letter: char| {
+            let bits = (mode >> shift) & 0o7;
+            let execute = match (special, bits & 0o1 != 0) {
+                (true, true) => letter,
+                (true, false) => letter.to_ascii_uppercase(),
+                (false, true) => 'x',
+                (false, false) => '-',
+            };
+            [
+                if bits & 0o4 != 0 { 'r' } else { '-' },
+                if bits & 0o2 != 0 { 'w' } else { '-' },
+                execute,
+            ]
+        };
+        triplet(6, mode & 0o4000 != 0, 's')
+            .into_iter()
+            .chain(triplet(3, mode & 0o2000 != 0, 's'))
+            .chain(triplet(0, mode & 0o1000 != 0, 't'))
+            .collect()
+    }
+}
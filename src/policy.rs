@@ -0,0 +1,93 @@
+use clap::ValueEnum;
+use std::{path::Path, sync::OnceLock};
+
+use crate::kivignore::IgnoreRules;
+
+// What happens to files and folders whose names start with a dot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum HiddenPolicy {
+    // Neither listed nor reachable.
+    Deny,
+    // Treated like any other file.
+    #[default]
+    Show,
+    // Listed, but opening, previewing or downloading them is refused.
+    ListOnly,
+}
+
+// What happens to symbolic links.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum SymlinkPolicy {
+    // Neither listed nor followed; any path through a link is refused.
+    Deny,
+    // Links are transparent as long as they resolve inside the root; ones pointing
+    // elsewhere are left out of listings.
+    #[default]
+    Follow,
+    // Listed as links with their target shown, and followed when it is inside the root.
+    Show,
+}
+
+// Set once at startup. Every listing and every path lookup consults the policies, so they
+// live here rather than being threaded through each of them.
+static HIDDEN: OnceLock<HiddenPolicy> = OnceLock::new();
+static SYMLINKS: OnceLock<SymlinkPolicy> = OnceLock::new();
+static IGNORED: OnceLock<IgnoreRules> = OnceLock::new();
+
+pub fn init(hidden: HiddenPolicy, symlinks: SymlinkPolicy, ignored: IgnoreRules) {
+    let _ = HIDDEN.set(hidden);
+    let _ = SYMLINKS.set(symlinks);
+    let _ = IGNORED.set(ignored);
+}
+
+pub fn hidden_policy() -> HiddenPolicy {
+    HIDDEN.get().copied().unwrap_or_default()
+}
+
+pub fn symlink_policy() -> SymlinkPolicy {
+    SYMLINKS.get().copied().unwrap_or_default()
+}
+
+// Whether any component of `path` below the root starts with a dot.
+fn is_hidden(root_dir: &Path, path: &Path) -> bool {
+    path.strip_prefix(root_dir).is_ok_and(|relative| {
+        relative
+            .components()
+            .any(|component| component.as_os_str().as_encoded_bytes().starts_with(b"."))
+    })
+}
+
+// Whether any component of `path` below the root is a symbolic link.
+fn passes_symlink(root_dir: &Path, path: &Path) -> bool {
+    let Ok(relative) = path.strip_prefix(root_dir) else {
+        return false;
+    };
+    let mut current = root_dir.to_path_buf();
+    relative.components().any(|component| {
+        current.push(component);
+        current.is_symlink()
+    })
+}
+
+// --- Listing ---
+// Whether `path` shows up in listings, search results, the folder tree and folder sizes,
+// and whether it can be shared. Walks over the tree never follow links, whatever the
+// policy.
+pub fn is_listed(root_dir: &Path, path: &Path) -> bool {
+    !crate::is_internal_path(root_dir, path)
+        && (hidden_policy() != HiddenPolicy::Deny || !is_hidden(root_dir, path))
+        && (symlink_policy() != SymlinkPolicy::Deny || !path.is_symlink())
+        && !IGNORED
+            .get()
+            .is_some_and(|rules| rules.is_ignored(root_dir, path))
+}
+
+// --- Access ---
+// Whether `path` may be opened: previewed, downloaded, shared or modified. Called with
+// both the requested and the resolved path, so a link can't lead somewhere the policies
+// refuse.
+pub fn is_accessible(root_dir: &Path, path: &Path) -> bool {
+    !crate::is_internal_path(root_dir, path)
+        && (hidden_policy() == HiddenPolicy::Show || !is_hidden(root_dir, path))
+        && (symlink_policy() != SymlinkPolicy::Deny || !passes_symlink(root_dir, path))
+}
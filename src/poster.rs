@@ -0,0 +1,75 @@
+use crate::{
+    cache::{self, CacheConfig},
+    ffmpeg,
+};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+use tracing::error;
+
+const CACHE_KIND: &str = "posters";
+const POSTER_WIDTH: u32 = 640;
+// Far enough in to skip black intro frames and fade-ins; shorter clips fall back to the
+// first frame.
+const SEEK_SECONDS: u32 = 3;
+
+// --- Poster frames ---
+// A JPEG frame from `source`, extracted with ffmpeg and cached by path, size and mtime.
+// Failures are logged here; callers just show no poster.
+pub async fn poster(cache: &CacheConfig, ffmpeg: &Path, source: &Path) -> Result<PathBuf, ()> {
+    let metadata = tokio::fs::metadata(source).await.map_err(|_| ())?;
+    let key = cache::key(source, &metadata, &format!("poster w={}", POSTER_WIDTH));
+    if let Some(path) = cache.get(CACHE_KIND, &key, "jpg").await {
+        return Ok(path);
+    }
+    if ffmpeg::is_missing() {
+        return Err(());
+    }
+    extract(cache, ffmpeg, source, &key).await.map_err(|e| {
+        if !ffmpeg::is_missing() {
+            error!("No poster frame for {}: {}", source.display(), e);
+        }
+    })
+}
+
+async fn extract(
+    cache: &CacheConfig,
+    ffmpeg: &Path,
+    source: &Path,
+    key: &str,
+) -> Result<PathBuf, String> {
+    let mut frame = extract_frame(ffmpeg, source, SEEK_SECONDS).await?;
+    if frame.is_empty() {
+        frame = extract_frame(ffmpeg, source, 0).await?;
+    }
+    if frame.is_empty() {
+        return Err("ffmpeg produced no frame.".to_string());
+    }
+    cache
+        .put(CACHE_KIND, key, "jpg", &frame)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// The frame at `seek_seconds` as a JPEG; empty when the video is shorter than that.
+async fn extract_frame(ffmpeg: &Path, source: &Path, seek_seconds: u32) -> Result<Vec<u8>, String> {
+    let args: Vec<OsString> = vec![
+        "-ss".into(),
+        seek_seconds.to_string().into(),
+        "-i".into(),
+        source.into(),
+        "-frames:v".into(),
+        "1".into(),
+        "-vf".into(),
+        format!("scale='min({},iw)':-2", POSTER_WIDTH).into(),
+        "-f".into(),
+        "image2".into(),
+        "-c:v".into(),
+        "mjpeg".into(),
+        "-q:v".into(),
+        "4".into(),
+        "pipe:1".into(),
+    ];
+    ffmpeg::run(ffmpeg, args).await
+}
@@ -0,0 +1,153 @@
+use axum::http::{HeaderMap, HeaderValue, header};
+
+use crate::signing::Signer;
+
+const PREFS_COOKIE: &str = "kiv_prefs";
+// A year; the cookie is renewed whenever a preference changes.
+const PREFS_MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortKey {
+    Name,
+    Modified,
+    Size,
+}
+
+impl SortKey {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "name" => Some(SortKey::Name),
+            "modified" => Some(SortKey::Modified),
+            "size" => Some(SortKey::Size),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Modified => "modified",
+            SortKey::Size => "size",
+        }
+    }
+}
+
+// --- Listing preferences ---
+// How a browser wants folders listed. Kept in a signed cookie, so each browser keeps its
+// own without any server-side store, and applied to every listing it asks for.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct ListingPrefs {
+    pub sort: SortKey,
+    pub descending: bool,
+    pub folders_first: bool,
+    // Dotfiles, as far as the `--hidden` policy lists them at all.
+    pub show_hidden: bool,
+    pub grid: bool,
+}
+
+impl Default for ListingPrefs {
+    fn default() -> Self {
+        ListingPrefs {
+            sort: SortKey::Name,
+            descending: false,
+            folders_first: true,
+            show_hidden: true,
+            grid: false,
+        }
+    }
+}
+
+// Preferences given in a request's query, each overriding the stored one.
+#[derive(Default)]
+pub struct PrefChanges<'a> {
+    pub sort: Option<&'a str>,
+    pub order: Option<&'a str>,
+    pub folders: Option<&'a str>,
+    pub hidden: Option<&'a str>,
+    pub view: Option<&'a str>,
+}
+
+impl ListingPrefs {
+    // The stored preferences; the defaults when there are none or the signature is wrong.
+    pub fn from_headers(headers: &HeaderMap, signer: &Signer) -> Self {
+        headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(name, _)| *name == PREFS_COOKIE)
+            .and_then(|(_, value)| value.rsplit_once('|'))
+            .filter(|(payload, signature)| signer.verify(payload, signature))
+            .map(|(payload, _)| Self::decode(payload))
+            .unwrap_or_default()
+    }
+
+    // Applies the changes; unknown values are ignored. Returns whether anything changed.
+    pub fn apply(&mut self, changes: &PrefChanges) -> bool {
+        let before = *self;
+        let flag = |value: Option<&str>, on: &str, off: &str| match value {
+            Some(value) if value == on => Some(true),
+            Some(value) if value == off => Some(false),
+            _ => None,
+        };
+        if let Some(sort) = changes.sort.and_then(SortKey::parse) {
+            self.sort = sort;
+        }
+        if let Some(descending) = flag(changes.order, "desc", "asc") {
+            self.descending = descending;
+        }
+        if let Some(folders_first) = flag(changes.folders, "first", "mixed") {
+            self.folders_first = folders_first;
+        }
+        if let Some(show_hidden) = flag(changes.hidden, "show", "hide") {
+            self.show_hidden = show_hidden;
+        }
+        if let Some(grid) = flag(changes.view, "grid", "list") {
+            self.grid = grid;
+        }
+        *self != before
+    }
+
+    pub fn set_cookie(&self, signer: &Signer) -> HeaderValue {
+        let payload = self.encode();
+        let cookie = format!(
+            "{}={}|{}; Path=/; Max-Age={}; SameSite=Lax; HttpOnly",
+            PREFS_COOKIE,
+            payload,
+            signer.sign(&payload),
+            PREFS_MAX_AGE_SECS
+        );
+        HeaderValue::from_str(&cookie).expect("preference cookies are plain ASCII")
+    }
+
+    fn encode(&self) -> String {
+        format!(
+            "sort={}&order={}&folders={}&hidden={}&view={}",
+            self.sort.as_str(),
+            if self.descending { "desc" } else { "asc" },
+            if self.folders_first { "first" } else { "mixed" },
+            if self.show_hidden { "show" } else { "hide" },
+            if self.grid { "grid" } else { "list" }
+        )
+    }
+
+    fn decode(payload: &str) -> Self {
+        let value = |key: &str| {
+            payload
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value)
+        };
+        let mut prefs = ListingPrefs::default();
+        prefs.apply(&PrefChanges {
+            sort: value("sort"),
+            order: value("order"),
+            folders: value("folders"),
+            hidden: value("hidden"),
+            view: value("view"),
+        });
+        prefs
+    }
+}
@@ -0,0 +1,205 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::{
+    collections::VecDeque,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+use tokio_stream::StreamExt;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{SharedState, error_response, ipfilter::ClientIp};
+
+// --- Queue ---
+// Server-wide cap on downloads streaming at once, for links too slow to share between
+// many. The rest wait in line, first come first served, except that downloads of
+// high-priority shares go ahead of everything else that's waiting.
+pub struct DownloadQueue {
+    slots: Option<usize>,
+    timeout: Duration,
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    running: usize,
+    next_id: u64,
+    waiting: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    id: u64,
+    high: bool,
+    // Who is waiting for what, so a landing page can find its visitor's place in line.
+    client: IpAddr,
+    path: String,
+    wake: oneshot::Sender<Slot>,
+}
+
+// A running download's place; freeing it hands it straight to the next in line.
+pub struct Slot(Arc<DownloadQueue>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.release();
+    }
+}
+
+// Takes a waiter out of line if its request is dropped before its turn comes.
+struct WaitGuard<'a> {
+    queue: &'a DownloadQueue,
+    id: u64,
+}
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.queue.lock();
+        inner.waiting.retain(|waiter| waiter.id != self.id);
+    }
+}
+
+impl DownloadQueue {
+    pub fn new(slots: Option<usize>, timeout: Duration) -> Self {
+        DownloadQueue {
+            slots: slots.filter(|slots| *slots > 0),
+            timeout,
+            inner: Mutex::new(Inner {
+                running: 0,
+                next_id: 0,
+                waiting: VecDeque::new(),
+            }),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.slots.is_some()
+    }
+
+    pub fn slots(&self) -> Option<usize> {
+        self.slots
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // `None` when the wait timed out.
+    async fn acquire(self: &Arc<Self>, high: bool, client: IpAddr, path: String) -> Option<Slot> {
+        let Some(slots) = self.slots else {
+            return Some(Slot(self.clone()));
+        };
+        let (wake, woken) = oneshot::channel();
+        let id = {
+            let mut inner = self.lock();
+            if inner.running < slots && inner.waiting.is_empty() {
+                inner.running += 1;
+                return Some(Slot(self.clone()));
+            }
+            let id = inner.next_id;
+            inner.next_id += 1;
+            let waiter = Waiter {
+                id,
+                high,
+                client,
+                path,
+                wake,
+            };
+            // Behind the high-priority downloads already waiting, ahead of the rest.
+            let at = if high {
+                inner
+                    .waiting
+                    .iter()
+                    .position(|waiter| !waiter.high)
+                    .unwrap_or(inner.waiting.len())
+            } else {
+                inner.waiting.len()
+            };
+            inner.waiting.insert(at, waiter);
+            id
+        };
+        let _guard = WaitGuard { queue: self, id };
+        tokio::time::timeout(self.timeout, woken).await.ok()?.ok()
+    }
+
+    fn release(self: &Arc<Self>) {
+        let mut inner = self.lock();
+        // The slot passes on without `running` changing. A waiter that has gone away
+        // drops the slot it was sent, which comes back here for the next one.
+        if let Some(waiter) = inner.waiting.pop_front() {
+            drop(inner);
+            let _ = waiter.wake.send(Slot(self.clone()));
+        } else {
+            inner.running = inner.running.saturating_sub(1);
+        }
+    }
+
+    // Where the client's download of `path` is in line (1 is next) and how many wait in
+    // all, or `None` if it isn't waiting.
+    pub fn position(&self, client: IpAddr, path: &str) -> Option<(usize, usize)> {
+        let inner = self.lock();
+        inner
+            .waiting
+            .iter()
+            .position(|waiter| waiter.client == client && waiter.path == path)
+            .map(|at| (at + 1, inner.waiting.len()))
+    }
+
+    // Downloads streaming now and waiting to start.
+    pub fn counts(&self) -> (usize, usize) {
+        let inner = self.lock();
+        (inner.running, inner.waiting.len())
+    }
+}
+
+// --- Middleware ---
+// Holds a slot from the request until the response body is done, like the per-client
+// limit. Shares marked high priority are recognised by their download path.
+pub async fn limit(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let queue = &state.download_queue;
+    if !queue.enabled() {
+        return next.run(request).await;
+    }
+    let path = request.uri().path().to_string();
+    let high = path
+        .strip_prefix("/direct-download/")
+        .and_then(|uuid| Uuid::parse_str(uuid).ok())
+        .and_then(|uuid| state.shares.get(&uuid).map(|share| share.high_priority))
+        .unwrap_or(false);
+
+    let (running, waiting) = queue.counts();
+    if waiting > 0 || Some(running) >= queue.slots {
+        info!(
+            "{} downloads running and {} waiting; queueing {} for {}",
+            running, waiting, path, client
+        );
+    }
+    let Some(slot) = queue.acquire(high, client, path).await else {
+        let mut response = error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The server is busy with other downloads. Try again in a little while.",
+        );
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from_static("60"));
+        return response;
+    };
+
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _held = &slot;
+        chunk
+    });
+    (parts, Body::from_stream(body)).into_response()
+}
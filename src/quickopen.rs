@@ -0,0 +1,362 @@
+use notify::{RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        RwLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{SharedState, mounts::Mount};
+
+// The index stops growing past this many entries, so a huge tree can't take all memory.
+const MAX_INDEXED: usize = 1_000_000;
+// Changes arriving within this window are applied together, as for listings' watchers.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+// A batch touching more paths than this is cheaper to handle by walking everything again.
+const REBUILD_THRESHOLD: usize = 10_000;
+// Without a working watcher, the index is rebuilt from scratch this often instead.
+const REBUILD_INTERVAL: Duration = Duration::from_secs(60);
+// Ranked matches returned for a query.
+pub const MAX_MATCHES: usize = 50;
+
+// --- Index ---
+// Every listed file and folder of every mount by request path, kept up to date by a
+// recursive watcher so quick-open never has to walk the disk.
+pub struct PathIndex {
+    // Request path → whether it is a folder.
+    paths: RwLock<BTreeMap<String, bool>>,
+    // The first full walk has finished.
+    ready: AtomicBool,
+}
+
+pub struct QuickOpenHit {
+    pub path: String,
+    pub is_dir: bool,
+    // Character offsets into `path` that matched the query, for highlighting.
+    pub positions: Vec<usize>,
+}
+
+impl PathIndex {
+    pub fn new() -> Self {
+        PathIndex {
+            paths: RwLock::new(BTreeMap::new()),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    // Entries containing the query's characters in order, best first. Matches at the
+    // start of words and inside the file name rank higher, as do runs of consecutive
+    // characters and shorter paths. Whitespace in the query is ignored.
+    pub fn find(&self, query: &str, limit: usize) -> Vec<QuickOpenHit> {
+        let needle: Vec<char> = query
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .flat_map(char::to_lowercase)
+            .collect();
+        if needle.is_empty() {
+            return Vec::new();
+        }
+
+        let paths = self.paths.read().unwrap_or_else(|e| e.into_inner());
+        let mut scored: Vec<(i64, &String, bool, Vec<usize>)> = paths
+            .iter()
+            .filter(|(path, _)| is_subsequence(&needle, path))
+            .filter_map(|(path, is_dir)| {
+                let (score, positions) = score(&needle, path)?;
+                Some((score, path, *is_dir, positions))
+            })
+            .collect();
+        let by_rank = |a: &(i64, &String, bool, Vec<usize>),
+                       b: &(i64, &String, bool, Vec<usize>)| {
+            b.0.cmp(&a.0)
+                .then_with(|| a.1.len().cmp(&b.1.len()))
+                .then_with(|| crate::natsort::natural_cmp(a.1, b.1))
+        };
+        if scored.len() > limit {
+            scored.select_nth_unstable_by(limit, by_rank);
+            scored.truncate(limit);
+        }
+        scored.sort_by(by_rank);
+        scored
+            .into_iter()
+            .map(|(_, path, is_dir, positions)| QuickOpenHit {
+                path: path.clone(),
+                is_dir,
+                positions,
+            })
+            .collect()
+    }
+
+    fn replace_all(&self, fresh: BTreeMap<String, bool>) {
+        *self.paths.write().unwrap_or_else(|e| e.into_inner()) = fresh;
+        self.ready.store(true, Ordering::Relaxed);
+    }
+}
+
+// --- Maintenance ---
+// Builds the index, then applies the changes reported by a recursive watcher on every
+// mount. Runs for the life of the server.
+pub async fn maintain_loop(state: SharedState) {
+    if state.drop_zone.is_some() {
+        return;
+    }
+
+    let (changed_tx, mut changed_rx) = mpsc::unbounded_channel::<Option<PathBuf>>();
+    let mut watcher =
+        match notify::recommended_watcher(move |result: notify::Result<notify::Event>| {
+            match result {
+                // The backend lost track of what changed; `None` asks for a full rebuild.
+                Ok(event) if event.need_rescan() => {
+                    let _ = changed_tx.send(None);
+                }
+                Ok(event) => {
+                    for path in event.paths {
+                        let _ = changed_tx.send(Some(path));
+                    }
+                }
+                Err(_) => {
+                    let _ = changed_tx.send(None);
+                }
+            }
+        }) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn!("Could not start the quick-open watcher: {}", e);
+                None
+            }
+        };
+    for mount in state.mounts.list() {
+        if let Some(active) = &mut watcher
+            && let Err(e) = active.watch(&mount.dir, RecursiveMode::Recursive)
+        {
+            warn!(
+                "Could not watch {} for quick-open; rebuilding the index every {}s instead: {}",
+                mount.dir.display(),
+                REBUILD_INTERVAL.as_secs(),
+                e
+            );
+            watcher = None;
+        }
+    }
+
+    rebuild(&state).await;
+    info!(
+        "Quick-open index holds {} entries.",
+        state.quick_open.paths.read().map_or(0, |paths| paths.len())
+    );
+
+    if watcher.is_none() {
+        let mut interval = tokio::time::interval(REBUILD_INTERVAL);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            rebuild(&state).await;
+        }
+    }
+
+    while let Some(first) = changed_rx.recv().await {
+        tokio::time::sleep(SETTLE_TIME).await;
+        let mut changed = HashSet::new();
+        let mut full = first.is_none();
+        changed.extend(first);
+        while let Ok(next) = changed_rx.try_recv() {
+            match next {
+                Some(path) => {
+                    changed.insert(path);
+                }
+                None => full = true,
+            }
+        }
+
+        if full || changed.len() > REBUILD_THRESHOLD {
+            rebuild(&state).await;
+            continue;
+        }
+        let task_state = state.clone();
+        let _ = tokio::task::spawn_blocking(move || apply_changes(&task_state, changed)).await;
+    }
+}
+
+async fn rebuild(state: &SharedState) {
+    let mounts = state.mounts.list().to_vec();
+    let Ok(fresh) = tokio::task::spawn_blocking(move || {
+        let mut fresh = BTreeMap::new();
+        for mount in &mounts {
+            walk(mount, &mount.dir, &mut fresh);
+        }
+        fresh
+    })
+    .await
+    else {
+        return;
+    };
+    state.quick_open.replace_all(fresh);
+}
+
+// Re-reads each changed path: whatever was indexed at or below it is dropped, and it is
+// added back (with everything inside, for a folder) if it still exists and is listed.
+fn apply_changes(state: &SharedState, changed: HashSet<PathBuf>) {
+    let mut removed = Vec::new();
+    let mut added = BTreeMap::new();
+    for path in &changed {
+        let Some(mount) = state.mounts.containing(path) else {
+            continue;
+        };
+        if path == &mount.dir {
+            continue;
+        }
+        removed.push(mount.relative(path));
+        let Ok(metadata) = std::fs::symlink_metadata(path) else {
+            continue;
+        };
+        if !crate::policy::is_listed(&mount.dir, path) {
+            continue;
+        }
+        added.insert(mount.relative(path), metadata.is_dir());
+        if metadata.is_dir() {
+            walk(mount, path, &mut added);
+        }
+    }
+
+    let mut paths = state
+        .quick_open
+        .paths
+        .write()
+        .unwrap_or_else(|e| e.into_inner());
+    for path in removed {
+        paths.remove(&path);
+        let below = format!("{}/", path);
+        let stale: Vec<String> = paths
+            .range(below.clone()..)
+            .take_while(|(key, _)| key.starts_with(&below))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale {
+            paths.remove(&key);
+        }
+    }
+    // Entries whose folder isn't indexed are below something hidden or ignored.
+    for (path, is_dir) in added {
+        if paths.len() >= MAX_INDEXED {
+            break;
+        }
+        let parent_indexed = match path.rsplit_once('/') {
+            Some((parent, _)) => {
+                paths.contains_key(parent)
+                    || state.mounts.list().iter().any(|mount| mount.name == parent)
+            }
+            None => true,
+        };
+        if parent_indexed {
+            paths.insert(path, is_dir);
+        }
+    }
+}
+
+// Adds everything listed below `dir` to `into`, without following links.
+fn walk(mount: &Mount, dir: &Path, into: &mut BTreeMap<String, bool>) {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if into.len() >= MAX_INDEXED {
+                warn!(
+                    "Quick-open index is full at {} entries; the rest of {} is left out.",
+                    MAX_INDEXED,
+                    mount.dir.display()
+                );
+                return;
+            }
+            let path = entry.path();
+            if !crate::policy::is_listed(&mount.dir, &path) {
+                continue;
+            }
+            let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+            into.insert(mount.relative(&path), is_dir);
+            if is_dir {
+                pending.push(path);
+            }
+        }
+    }
+}
+
+// --- Matching ---
+// A cheap check, without allocating, that rules out most paths before scoring.
+fn is_subsequence(needle: &[char], haystack: &str) -> bool {
+    let mut remaining = needle.iter().peekable();
+    for c in haystack.chars().flat_map(char::to_lowercase) {
+        if remaining.peek() == Some(&&c) {
+            remaining.next();
+        }
+    }
+    remaining.peek().is_none()
+}
+
+// Finds the shortest span ending at the earliest complete match (searching forward, then
+// back from its end), scores the characters matched in it, and returns their positions.
+fn score(needle: &[char], path: &str) -> Option<(i64, Vec<usize>)> {
+    let chars: Vec<char> = path.chars().collect();
+    let lower: Vec<char> = chars
+        .iter()
+        .map(|c| c.to_lowercase().next().unwrap_or(*c))
+        .collect();
+
+    let mut end = 0;
+    let mut matched = 0;
+    for (i, c) in lower.iter().enumerate() {
+        if *c == needle[matched] {
+            matched += 1;
+            if matched == needle.len() {
+                end = i;
+                break;
+            }
+        }
+    }
+    if matched < needle.len() {
+        return None;
+    }
+    let mut positions = Vec::with_capacity(needle.len());
+    let mut remaining = needle.len();
+    for i in (0..=end).rev() {
+        if lower[i] == needle[remaining - 1] {
+            positions.push(i);
+            remaining -= 1;
+            if remaining == 0 {
+                break;
+            }
+        }
+    }
+    positions.reverse();
+
+    let name_start = chars.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+    let mut total: i64 = 0;
+    for (n, &i) in positions.iter().enumerate() {
+        total += 16;
+        if n > 0 && positions[n - 1] + 1 == i {
+            total += 12;
+        }
+        let at_boundary = i == 0
+            || matches!(chars[i - 1], '/' | '_' | '-' | '.' | ' ')
+            || (chars[i - 1].is_lowercase() && chars[i].is_uppercase());
+        if at_boundary {
+            total += 10;
+        }
+        if i >= name_start {
+            total += 6;
+        }
+    }
+    let span = positions[positions.len() - 1] - positions[0] + 1;
+    total -= (span - positions.len()).min(40) as i64;
+    Some((total, positions))
+}
@@ -0,0 +1,249 @@
+use axum::{
+    extract::{Extension, Request, State},
+    http::{HeaderValue, StatusCode, header},
+    middleware::Next,
+    response::Response,
+};
+use dashmap::DashMap;
+use std::{
+    hash::Hash,
+    net::{IpAddr, Ipv6Addr},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+use crate::{SharedState, auth, error_response, ipfilter::ClientIp};
+
+// Past this many clients, ones that have caught up with their allowance are swept out on
+// the next request.
+const SWEEP_THRESHOLD: usize = 4096;
+
+// Password checks a client may make in quick succession before `--password-attempts`
+// paces them, enough for a couple of typos.
+pub const PASSWORD_ATTEMPTS_BURST: u32 = 5;
+
+// Lockouts start at a minute and double with every further wrong password, up to a day.
+const LOCKOUT_BASE: Duration = Duration::from_secs(60);
+const LOCKOUT_MAX: Duration = Duration::from_secs(24 * 60 * 60);
+// Failures are forgotten once nothing has been tried for this long.
+const LOCKOUT_FORGET: Duration = Duration::from_secs(24 * 60 * 60);
+// Wrong folder passwords before a client, or a folder for everyone, is locked out. The
+// folder's is higher so one client can't lock others out as easily.
+pub const UNLOCK_CLIENT_LOCKOUT: u32 = 10;
+pub const UNLOCK_FOLDER_LOCKOUT: u32 = 50;
+
+// --- Limiter ---
+// Requests per client address, as a generic cell rate (GCRA) limiter like governor's:
+// each client may make `burst` requests at once and then one every `interval`. Only the
+// time its allowance is next free is kept per client.
+pub struct RateLimiter {
+    // `None` when unlimited.
+    interval: Option<Duration>,
+    burst: u32,
+    // Client (see `client_key`) → when its allowance is back to full ("theoretical arrival
+    // time").
+    clients: DashMap<IpAddr, Instant>,
+}
+
+impl RateLimiter {
+    // `per_second` requests on average, up to `burst` at once.
+    pub fn new(per_second: Option<f64>, burst: u32) -> Self {
+        RateLimiter {
+            interval: per_second
+                .filter(|rate| rate.is_finite() && *rate > 0.0)
+                .map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            burst: burst.max(1),
+            clients: DashMap::new(),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.interval.is_some()
+    }
+
+    // Takes one request from the client's allowance, or says how long until there is one.
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        let Some(interval) = self.interval else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if self.clients.len() >= SWEEP_THRESHOLD {
+            self.clients.retain(|_, free_at| *free_at > now);
+        }
+        let window = interval * self.burst;
+        let mut free_at = self.clients.entry(client_key(client)).or_insert(now);
+        let next = (*free_at).max(now) + interval;
+        if next > now + window {
+            return Err(next - window - now);
+        }
+        *free_at = next;
+        Ok(())
+    }
+}
+
+// --- Lockout ---
+// Wrong passwords per key (a client or a locked folder). After `threshold` of them in a
+// row the key is locked out, for longer with every wrong password after that, until a
+// right one clears it. Unlike the limiter this doesn't forgive a patient guesser.
+pub struct Lockout<K: Hash + Eq> {
+    threshold: u32,
+    keys: DashMap<K, Failures>,
+}
+
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl<K: Hash + Eq + Clone + std::fmt::Display> Lockout<K> {
+    pub fn new(threshold: u32) -> Self {
+        Lockout {
+            threshold: threshold.max(1),
+            keys: DashMap::new(),
+        }
+    }
+
+    // How long the key is still locked out for, if it is.
+    pub fn check(&self, key: &K) -> Result<(), Duration> {
+        let now = Instant::now();
+        match self
+            .keys
+            .get(key)
+            .and_then(|failures| failures.locked_until)
+        {
+            Some(until) if until > now => Err(until - now),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn failed(&self, key: &K) {
+        let now = Instant::now();
+        if self.keys.len() >= SWEEP_THRESHOLD {
+            self.keys
+                .retain(|_, failures| now.duration_since(failures.last) < LOCKOUT_FORGET);
+        }
+        let mut failures = self.keys.entry(key.clone()).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+        if now.duration_since(failures.last) >= LOCKOUT_FORGET {
+            failures.count = 0;
+        }
+        failures.count += 1;
+        failures.last = now;
+        if let Some(over) = failures.count.checked_sub(self.threshold) {
+            let lockout = LOCKOUT_BASE
+                .saturating_mul(2u32.saturating_pow(over.min(31)))
+                .min(LOCKOUT_MAX);
+            failures.locked_until = Some(now + lockout);
+            warn!(
+                "{} wrong passwords in a row for {}, locked out for {}s",
+                failures.count,
+                key,
+                lockout.as_secs()
+            );
+        }
+    }
+
+    pub fn succeeded(&self, key: &K) {
+        self.keys.remove(key);
+    }
+}
+
+// Who an allowance belongs to. An IPv6 client usually has a whole /64 to pick addresses
+// from, so it is one client; IPv4 clients, also when mapped into IPv6, are one address.
+pub fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => IpAddr::V6(Ipv6Addr::from_bits(
+                address.to_bits() & !u128::from(u64::MAX),
+            )),
+        },
+        v4 => v4,
+    }
+}
+
+// A 429 telling the client when to come back.
+pub fn too_many_requests(wait: Duration, message: &str) -> Response {
+    let mut response = error_response(StatusCode::TOO_MANY_REQUESTS, message);
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    if let Ok(value) = HeaderValue::from_str(&seconds.max(1).to_string()) {
+        response.headers_mut().insert(header::RETRY_AFTER, value);
+    }
+    response
+}
+
+// --- Middleware ---
+// Pages, previews and the other endpoints count against `--rate-limit`; downloads, which
+// have limits of their own, and static files don't.
+pub async fn limit(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.request_limiter.enabled() {
+        return next.run(request).await;
+    }
+    let path = request.uri().path();
+    let exempt = path.starts_with("/static/")
+        || path.starts_with("/direct-download/")
+        || path == "/signed"
+        || auth::capability_for(path, request.uri().query().unwrap_or(""))
+            == Some(auth::Capability::Download);
+    if !exempt && let Err(wait) = state.request_limiter.check(client) {
+        info!("Rate limited {} {} from {}", request.method(), path, client);
+        return too_many_requests(wait, "Too many requests. Slow down a little.");
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn ipv6_clients_share_their_64() {
+        let limiter = RateLimiter::new(Some(1.0), 2);
+        assert!(limiter.check(ip("2001:db8:1:2::1")).is_ok());
+        assert!(limiter.check(ip("2001:db8:1:2:ffff::9")).is_ok());
+        assert!(limiter.check(ip("2001:db8:1:2::abcd")).is_err());
+        // The next /64 over, and IPv4 addresses, are other clients.
+        assert!(limiter.check(ip("2001:db8:1:3::1")).is_ok());
+        assert!(limiter.check(ip("192.0.2.1")).is_ok());
+        assert!(limiter.check(ip("192.0.2.2")).is_ok());
+        assert!(limiter.check(ip("::ffff:192.0.2.1")).is_ok());
+        assert!(limiter.check(ip("192.0.2.1")).is_err());
+    }
+
+    #[test]
+    fn lockouts_grow_until_the_right_password() {
+        let lockout = Lockout::new(2);
+        let folder = "private".to_string();
+        lockout.failed(&folder);
+        assert!(lockout.check(&folder).is_ok());
+        lockout.failed(&folder);
+        let first = lockout.check(&folder).unwrap_err();
+        assert!(first > Duration::from_secs(50) && first <= LOCKOUT_BASE);
+        lockout.failed(&folder);
+        assert!(lockout.check(&folder).unwrap_err() > LOCKOUT_BASE);
+        assert!(lockout.check(&"other".to_string()).is_ok());
+        lockout.succeeded(&folder);
+        assert!(lockout.check(&folder).is_ok());
+    }
+
+    #[test]
+    fn unlimited_without_a_rate() {
+        let limiter = RateLimiter::new(None, 1);
+        for _ in 0..10 {
+            assert!(limiter.check(ip("192.0.2.1")).is_ok());
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
+
+// --- Request paths for any file name ---
+// Request paths are strings, but on Unix a file name can be any bytes. A path that isn't
+// valid UTF-8 is written with its stray bytes (and any `%`) percent-encoded, so it can be
+// turned back into the exact bytes; valid paths are written as they are, as before.
+pub fn encode(path: &Path) -> String {
+    if let Some(path) = path.to_str() {
+        return path.replace('\\', "/");
+    }
+    encode_bytes(path.as_os_str())
+}
+
+#[cfg(unix)]
+fn encode_bytes(path: &OsStr) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut encoded = String::new();
+    for chunk in path.as_bytes().utf8_chunks() {
+        encoded.push_str(&chunk.valid().replace('%', "%25"));
+        for byte in chunk.invalid() {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+// Other platforms' file names are always valid Unicode, give or take a lone surrogate.
+#[cfg(not(unix))]
+fn encode_bytes(path: &OsStr) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+// The inverse of `encode`, for a request path whose percent escapes don't decode to UTF-8.
+#[cfg(unix)]
+pub fn decode(path: &str) -> PathBuf {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+    PathBuf::from(OsString::from_vec(
+        urlencoding::decode_binary(path.as_bytes()).into_owned(),
+    ))
+}
+
+#[cfg(not(unix))]
+pub fn decode(path: &str) -> PathBuf {
+    PathBuf::from(path)
+}
+
+// A name for showing on a page; invalid bytes become U+FFFD.
+pub fn display(name: &OsStr) -> String {
+    name.to_string_lossy().into_owned()
+}
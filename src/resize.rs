@@ -0,0 +1,119 @@
+use crate::cache::{self, CacheConfig};
+use image::{DynamicImage, ImageDecoder, ImageReader, codecs::jpeg::JpegEncoder};
+use std::{io::Cursor, path::Path};
+use tokio::sync::Semaphore;
+
+const CACHE_KIND: &str = "resized";
+// Requested widths are rounded up to one of these, so arbitrary `w=` values can't fill
+// the cache with near-identical copies.
+const WIDTHS: &[u32] = &[
+    64, 128, 200, 256, 320, 400, 480, 640, 800, 1024, 1280, 1600, 1920, 2560, 3200, 4096,
+];
+const JPEG_QUALITY: u8 = 85;
+// Images decoded at once. A grid of thumbnails for a folder of large photos queues up
+// here instead of decoding them all in parallel.
+static DECODE_WORKERS: Semaphore = Semaphore::const_new(4);
+
+pub fn snap_width(requested: u32) -> u32 {
+    WIDTHS
+        .iter()
+        .copied()
+        .find(|width| *width >= requested)
+        .unwrap_or(WIDTHS[WIDTHS.len() - 1])
+}
+
+// Formats the image crate can decode here; others (SVG, AVIF, …) are served as they are.
+pub fn is_resizable(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    matches!(
+        extension.as_str(),
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "webp" | "tiff" | "tif" | "ico"
+    )
+}
+
+pub enum Resized {
+    // A scaled-down copy in the cache.
+    Cached(std::path::PathBuf),
+    // The original is already no wider than requested.
+    Original,
+}
+
+// --- Resizing ---
+// Scales `source` down to `width` (snapped, aspect ratio kept, EXIF orientation applied)
+// and caches the result: JPEG for opaque images, PNG when there is transparency.
+pub async fn resized(cache: &CacheConfig, source: &Path, width: u32) -> Result<Resized, String> {
+    let width = snap_width(width);
+    let metadata = tokio::fs::metadata(source)
+        .await
+        .map_err(|e| e.to_string())?;
+    let key = cache::key(source, &metadata, &format!("w={}", width));
+    for extension in ["jpg", "png"] {
+        if let Some(path) = cache.get(CACHE_KIND, &key, extension).await {
+            return Ok(Resized::Cached(path));
+        }
+    }
+
+    let _worker = DECODE_WORKERS.acquire().await.map_err(|e| e.to_string())?;
+    // Another request may have produced it while this one waited for a worker.
+    for extension in ["jpg", "png"] {
+        if let Some(path) = cache.get(CACHE_KIND, &key, extension).await {
+            return Ok(Resized::Cached(path));
+        }
+    }
+    let source_path = source.to_path_buf();
+    let encoded = tokio::task::spawn_blocking(move || encode_resized(&source_path, width))
+        .await
+        .map_err(|e| e.to_string())??;
+    match encoded {
+        Some((bytes, extension)) => cache
+            .put(CACHE_KIND, &key, extension, &bytes)
+            .await
+            .map(Resized::Cached)
+            .map_err(|e| e.to_string()),
+        None => Ok(Resized::Original),
+    }
+}
+
+pub fn decode_oriented(source: &Path) -> Result<DynamicImage, String> {
+    let mut decoder = ImageReader::open(source)
+        .map_err(|e| e.to_string())?
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_decoder()
+        .map_err(|e| e.to_string())?;
+    let orientation = decoder.orientation().map_err(|e| e.to_string())?;
+    let mut image = DynamicImage::from_decoder(decoder).map_err(|e| e.to_string())?;
+    image.apply_orientation(orientation);
+    Ok(image)
+}
+
+fn encode_resized(source: &Path, width: u32) -> Result<Option<(Vec<u8>, &'static str)>, String> {
+    let image = decode_oriented(source)?;
+    if image.width() <= width {
+        return Ok(None);
+    }
+    // `thumbnail` keeps the aspect ratio within the bounding box.
+    let scaled = image.thumbnail(width, u32::MAX);
+    encode(&scaled).map(Some)
+}
+
+// JPEG, or PNG when the image has an alpha channel.
+pub fn encode(image: &DynamicImage) -> Result<(Vec<u8>, &'static str), String> {
+    let mut bytes = Vec::new();
+    if image.color().has_alpha() {
+        image
+            .write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .map_err(|e| e.to_string())?;
+        Ok((bytes, "png"))
+    } else {
+        image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, JPEG_QUALITY))
+            .map_err(|e| e.to_string())?;
+        Ok((bytes, "jpg"))
+    }
+}
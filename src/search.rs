@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+use tokio::task::JoinSet;
+
+use crate::mounts::Mount;
+
+// Directories read at the same time while walking the tree.
+const SEARCH_CONCURRENCY: usize = 8;
+// The walk stops once this many matches have been found…
+pub const MAX_RESULTS: usize = 500;
+// …or this many entries have been looked at, so a search of a huge tree stays bounded.
+const MAX_VISITED: usize = 500_000;
+
+pub struct SearchHit {
+    pub name: String,
+    // The request path, with forward slashes.
+    pub path: String,
+    pub is_dir: bool,
+}
+
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    // The walk stopped early because of `MAX_RESULTS` or `MAX_VISITED`.
+    pub truncated: bool,
+}
+
+// --- Search ---
+// Files and folders under `start_dir` whose names contain every word of `query`, ignoring
+// case. kiv's own directories are skipped and symlinks are not followed.
+pub async fn search(mount: &Mount, start_dir: &Path, query: &str) -> SearchResults {
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    let mut results = SearchResults {
+        hits: Vec::new(),
+        truncated: false,
+    };
+    if terms.is_empty() {
+        return results;
+    }
+
+    let mut pending: Vec<PathBuf> = vec![start_dir.to_path_buf()];
+    let mut reads: JoinSet<Vec<(String, PathBuf, bool)>> = JoinSet::new();
+    let mut visited = 0;
+    loop {
+        while reads.len() < SEARCH_CONCURRENCY
+            && let Some(dir) = pending.pop()
+        {
+            reads.spawn_blocking(move || read_dir(&dir));
+        }
+        let Some(Ok(entries)) = reads.join_next().await else {
+            if reads.is_empty() && pending.is_empty() {
+                break;
+            }
+            continue;
+        };
+
+        for (name, path, is_dir) in entries {
+            visited += 1;
+            if !crate::policy::is_listed(&mount.dir, &path)
+                || !crate::auth::visible(&mount.relative(&path))
+            {
+                continue;
+            }
+            let lower_name = name.to_lowercase();
+            if terms.iter().all(|term| lower_name.contains(term.as_str())) {
+                results.hits.push(SearchHit {
+                    name,
+                    path: mount.relative(&path),
+                    is_dir,
+                });
+            }
+            if is_dir {
+                pending.push(path);
+            }
+        }
+        if results.hits.len() >= MAX_RESULTS || visited >= MAX_VISITED {
+            results.truncated = true;
+            results.hits.truncate(MAX_RESULTS);
+            reads.abort_all();
+            break;
+        }
+    }
+
+    results.hits.sort_by(|a, b| {
+        b.is_dir
+            .cmp(&a.is_dir)
+            .then_with(|| a.path.to_lowercase().cmp(&b.path.to_lowercase()))
+    });
+    results
+}
+
+// Searches every mount from its top, for a search of the virtual top level. The results
+// are bounded as for a single search.
+pub async fn search_mounts(mounts: &[Mount], query: &str) -> SearchResults {
+    let mut results = SearchResults {
+        hits: Vec::new(),
+        truncated: false,
+    };
+    for mount in mounts {
+        let found = search(mount, &mount.dir, query).await;
+        results.hits.extend(found.hits);
+        results.truncated |= found.truncated;
+        if results.hits.len() >= MAX_RESULTS {
+            results.truncated = true;
+            results.hits.truncate(MAX_RESULTS);
+            break;
+        }
+    }
+    results
+}
+
+// (name, path, is_dir) for each entry; unreadable directories are skipped.
+fn read_dir(dir: &Path) -> Vec<(String, PathBuf, bool)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = crate::rawnames::display(&entry.file_name());
+            let is_dir = entry.file_type().ok()?.is_dir();
+            Some((name, entry.path(), is_dir))
+        })
+        .collect()
+}
@@ -0,0 +1,55 @@
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// --- Secret files ---
+// Secrets read from files of their own rather than written into the users file or onto
+// the command line, where they'd show up in process listings and version control.
+// Under systemd, `LoadCredential=` puts them in $CREDENTIALS_DIRECTORY, and relative
+// paths are looked up there.
+
+// The credential `--signing-key-file` defaults to when systemd passes one.
+pub const SIGNING_KEY_CREDENTIAL: &str = "signing-key";
+
+pub fn credentials_dir() -> Option<PathBuf> {
+    std::env::var_os("CREDENTIALS_DIRECTORY")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+}
+
+// `path` as given when absolute; otherwise in the credentials directory, or next to
+// the file that names it (`relative_to`) without one.
+pub fn resolve(path: &Path, relative_to: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.to_path_buf();
+    }
+    match credentials_dir() {
+        Some(dir) => dir.join(path),
+        None => relative_to
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(path),
+    }
+}
+
+// The file's contents without surrounding whitespace, so a trailing newline from an
+// editor or `echo` doesn't become part of the secret.
+pub async fn read(path: &Path) -> Result<String, String> {
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| format!("Failed to read secret {}: {}", path.display(), e))?;
+    #[cfg(unix)]
+    if let Ok(metadata) = tokio::fs::metadata(path).await {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o077 != 0 {
+            warn!(
+                "Secret {} can be read by other users; consider chmod 600",
+                path.display()
+            );
+        }
+    }
+    let secret = contents.trim();
+    if secret.is_empty() {
+        return Err(format!("Secret {} is empty", path.display()));
+    }
+    Ok(secret.to_string())
+}
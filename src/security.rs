@@ -0,0 +1,149 @@
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderName, HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use std::str::FromStr;
+
+use crate::SharedState;
+
+// What kiv's own pages load: its scripts and styles plus highlight.js and hls.js from
+// their CDNs, inline scripts and `onerror`/`onclick` handlers, thumbnails and media from
+// this origin (hls.js plays through blob: URLs and a blob: worker), and PDFs embedded
+// with <object>.
+pub const DEFAULT_CSP: &str = "default-src 'self'; \
+    script-src 'self' 'unsafe-inline' https://cdnjs.cloudflare.com https://cdn.jsdelivr.net; \
+    style-src 'self' 'unsafe-inline' https://cdnjs.cloudflare.com; \
+    img-src 'self' data: blob:; \
+    media-src 'self' blob:; \
+    font-src 'self' data:; \
+    worker-src 'self' blob:; \
+    connect-src 'self'; \
+    object-src 'self'; \
+    frame-src 'self'; \
+    frame-ancestors 'self'; \
+    base-uri 'self'; \
+    form-action 'self'";
+
+// A `--csp-allow directive=source` argument: one more source for one directive.
+#[derive(Clone, Debug)]
+pub struct CspSource {
+    directive: String,
+    source: String,
+}
+
+impl FromStr for CspSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (directive, source) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected directive=source, got '{}'", s))?;
+        let (directive, source) = (directive.trim(), source.trim());
+        if directive.is_empty()
+            || !directive
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c == '-')
+        {
+            return Err(format!("'{}' is not a CSP directive", directive));
+        }
+        if source.is_empty() || source.contains([';', ',']) || source.chars().any(char::is_control)
+        {
+            return Err(format!("'{}' is not a CSP source", source));
+        }
+        Ok(CspSource {
+            directive: directive.to_string(),
+            source: source.to_string(),
+        })
+    }
+}
+
+// The policy with `extra` sources added to their directives, appending directives it
+// doesn't have yet.
+pub fn extend_csp(policy: &str, extra: &[CspSource]) -> String {
+    let mut directives: Vec<String> = policy
+        .split(';')
+        .map(str::trim)
+        .filter(|directive| !directive.is_empty())
+        .map(str::to_string)
+        .collect();
+    for CspSource { directive, source } in extra {
+        let existing = directives
+            .iter_mut()
+            .find(|existing| existing.split_whitespace().next() == Some(directive.as_str()));
+        match existing {
+            Some(existing) => {
+                existing.push(' ');
+                existing.push_str(source);
+            }
+            None => directives.push(format!("{} {}", directive, source)),
+        }
+    }
+    directives.join("; ")
+}
+
+// --- Headers ---
+// Sent with every response. Files served for display bring a sandboxing policy of their
+// own (see `serve::SANDBOX_CSP`), which is left alone.
+pub struct SecurityHeaders {
+    headers: HeaderMap,
+}
+
+impl SecurityHeaders {
+    // `csp` of `None` sends no Content-Security-Policy; `hsts_max_age` of `None` no
+    // Strict-Transport-Security.
+    pub fn new(csp: Option<&str>, hsts_max_age: Option<u64>) -> Result<Self, String> {
+        let mut headers = HeaderMap::new();
+        if let Some(csp) = csp {
+            let value = HeaderValue::from_str(csp)
+                .map_err(|_| format!("Invalid Content-Security-Policy: {}", csp))?;
+            headers.insert(header::CONTENT_SECURITY_POLICY, value);
+        }
+        headers.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+        // Share and signed links in the address bar shouldn't travel to other sites.
+        headers.insert(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("same-origin"),
+        );
+        // Same origin rather than deny: PDF previews embed the file.
+        headers.insert(
+            header::X_FRAME_OPTIONS,
+            HeaderValue::from_static("SAMEORIGIN"),
+        );
+        if let Some(max_age) = hsts_max_age {
+            headers.insert(
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_str(&format!("max-age={}", max_age)).expect("max-age is ASCII"),
+            );
+        }
+        Ok(SecurityHeaders { headers })
+    }
+
+    // Whether HSTS is on, which means the server is reached over HTTPS.
+    pub fn hsts(&self) -> bool {
+        self.headers.contains_key(header::STRICT_TRANSPORT_SECURITY)
+    }
+
+    fn apply(&self, headers: &mut HeaderMap) {
+        for (name, value) in &self.headers {
+            if !headers.contains_key(name) {
+                headers.insert(HeaderName::clone(name), value.clone());
+            }
+        }
+    }
+}
+
+// --- Middleware ---
+pub async fn add_headers(
+    State(state): State<SharedState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let mut response = next.run(request).await;
+    state.security_headers.apply(response.headers_mut());
+    response
+}
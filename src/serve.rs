@@ -0,0 +1,781 @@
+use axum::{
+    body::Body,
+    http::{Extensions, HeaderMap, HeaderName, HeaderValue, StatusCode, Version, header},
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::OnceLock,
+    task::{Context, Poll, ready},
+    time::SystemTime,
+};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::io::ReaderStream;
+use tower_http::compression::{CompressionLayer, Predicate};
+use tracing::error;
+
+use crate::error_response;
+
+// Bytes read from a file per chunk sent. Bigger chunks mean fewer reads, wakeups and
+// socket writes per gigabyte, which is what limits throughput on fast links; smaller ones
+// keep memory down when many downloads run at once. Set once at startup.
+pub const DEFAULT_STREAM_BUFFER_KIB: usize = 256;
+const MIN_STREAM_BUFFER: usize = 16 << 10;
+const MAX_STREAM_BUFFER: usize = 16 << 20;
+static STREAM_BUFFER_SIZE: OnceLock<usize> = OnceLock::new();
+
+// Returns the size actually used, after clamping.
+pub fn init_stream_buffer(kib: usize) -> usize {
+    let size = kib
+        .saturating_mul(1024)
+        .clamp(MIN_STREAM_BUFFER, MAX_STREAM_BUFFER);
+    let _ = STREAM_BUFFER_SIZE.set(size);
+    stream_buffer_size()
+}
+
+pub fn stream_buffer_size() -> usize {
+    *STREAM_BUFFER_SIZE.get_or_init(|| DEFAULT_STREAM_BUFFER_KIB * 1024)
+}
+
+// Streams `bytes` from the file's current position in chunks of the configured size.
+// tokio reads files through a buffer of its own, capped at 2 MiB unless raised, which
+// would otherwise split every larger chunk.
+fn file_stream(
+    mut file: tokio::fs::File,
+    bytes: u64,
+) -> ReaderStream<tokio::io::Take<tokio::fs::File>> {
+    let size = stream_buffer_size();
+    file.set_max_buf_size(size);
+    ReaderStream::with_capacity(file.take(bytes), size)
+}
+
+// Sent with documents that could run script if opened directly (HTML, SVG, XML), so
+// they render without access to this origin.
+pub const SANDBOX_CSP: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; img-src data:; sandbox";
+
+pub fn is_scriptable(mime_type: &str) -> bool {
+    matches!(
+        mime_type,
+        "text/html"
+            | "application/xhtml+xml"
+            | "image/svg+xml"
+            | "text/xml"
+            | "application/xml"
+            | "text/xsl"
+    )
+}
+
+// --- Content-Disposition ---
+// `filename` as RFC 6266 has it: a quoted ASCII fallback with quotes, backslashes, control
+// characters and anything non-ASCII replaced, plus the exact name percent-encoded as UTF-8
+// in `filename*` (RFC 5987) whenever the fallback had to change it.
+pub fn disposition(inline: bool, filename: &str) -> HeaderValue {
+    let kind = if inline { "inline" } else { "attachment" };
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            c if c == ' ' || c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    let fallback = if fallback.trim().is_empty() {
+        "download".to_string()
+    } else {
+        fallback
+    };
+    let value = if fallback == filename {
+        format!("{}; filename=\"{}\"", kind, fallback)
+    } else {
+        format!(
+            "{}; filename=\"{}\"; filename*=UTF-8''{}",
+            kind,
+            fallback,
+            urlencoding::encode(filename)
+        )
+    };
+    HeaderValue::from_str(&value).expect("disposition is ASCII")
+}
+
+// --- Compression ---
+// Text-like types worth compressing on the fly: logs, JSON dumps, source. Media and
+// archives are already compressed and are sent as they are.
+fn is_compressible(mime_type: &str) -> bool {
+    let essence = mime_type.split(';').next().unwrap_or("").trim();
+    essence.starts_with("text/")
+        || matches!(
+            essence,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-javascript"
+                | "application/x-ndjson"
+                | "application/x-yaml"
+                | "application/yaml"
+                | "application/toml"
+                | "application/sql"
+                | "application/x-sh"
+                | "application/xhtml+xml"
+                | "image/svg+xml"
+        )
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+}
+
+// gzip or zstd, as the client prefers, for text-like responses over a couple of KiB.
+// Ranges and already-encoded bodies are left alone (tower-http skips them itself).
+pub fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().gzip(true).zstd(true).compress_when(
+        |status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+            status == StatusCode::OK
+                && headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .is_some_and(is_compressible)
+                && headers
+                    .get(header::CONTENT_LENGTH)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .is_none_or(|len| len >= 2048)
+        },
+    )
+}
+
+// A compressed body is a different representation, so it gets its own ETag: the file's
+// tag with the coding appended. `not_modified` accepts either.
+pub async fn tag_encoded_response<B>(
+    mut response: axum::http::Response<B>,
+) -> axum::http::Response<B> {
+    let encoding = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let etag = response
+        .headers()
+        .get(header::ETAG)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    if let (Some(encoding), Some(etag)) = (encoding, etag)
+        && let Some(tag) = etag.strip_suffix('"')
+        && let Ok(value) = HeaderValue::from_str(&format!("{}-{}\"", tag, encoding))
+    {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+    response
+}
+
+// --- Digests ---
+pub const REPR_DIGEST: HeaderName = HeaderName::from_static("repr-digest");
+pub const X_CHECKSUM_SHA256: HeaderName = HeaderName::from_static("x-checksum-sha256");
+
+// The file's SHA-256 as `Repr-Digest` (RFC 9530, base64) and as the hex that checksum
+// tools print. Both describe the whole file, so they hold for range responses too.
+pub fn insert_digest(headers: &mut HeaderMap, sha256_hex: &str) {
+    let bytes: Option<Vec<u8>> = (0..sha256_hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(sha256_hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    let Some(bytes) = bytes else {
+        return;
+    };
+    if let Ok(value) = HeaderValue::from_str(&format!("sha-256=:{}:", STANDARD.encode(bytes))) {
+        headers.insert(REPR_DIGEST, value);
+    }
+    if let Ok(value) = HeaderValue::from_str(sha256_hex) {
+        headers.insert(X_CHECKSUM_SHA256, value);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    // No (usable) Range header: send the whole file.
+    Full,
+    // Inclusive byte offsets.
+    Partial(u64, u64),
+    // Several inclusive ranges, sent as `multipart/byteranges`.
+    Multiple(Vec<(u64, u64)>),
+    Unsatisfiable,
+}
+
+// More ranges than this in one request are answered with the whole file, so a request
+// can't make the server seek around a file thousands of times.
+const MAX_RANGES: usize = 16;
+
+// Parses a `Range: bytes=...` header. Ranges that lie outside the file are dropped; when
+// none is left the request can't be satisfied. Malformed headers fall back to a full
+// response, which RFC 9110 allows servers to do.
+pub fn parse_range(value: Option<&HeaderValue>, len: u64) -> ByteRange {
+    let Some(specs) = value
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().strip_prefix("bytes="))
+    else {
+        return ByteRange::Full;
+    };
+    let specs: Vec<&str> = specs
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return ByteRange::Full;
+    }
+
+    let mut ranges = Vec::new();
+    for spec in specs {
+        match parse_one(spec, len) {
+            Some(Some(range)) => ranges.push(range),
+            Some(None) => {}
+            None => return ByteRange::Full,
+        }
+    }
+    match ranges.as_slice() {
+        [] => ByteRange::Unsatisfiable,
+        [(start, end)] => ByteRange::Partial(*start, *end),
+        _ => ByteRange::Multiple(ranges),
+    }
+}
+
+// One `start-end`, `start-` or `-suffix` spec: `None` when malformed, `Some(None)` when it
+// lies outside the file.
+fn parse_one(spec: &str, len: u64) -> Option<Option<(u64, u64)>> {
+    let (start, end) = spec.split_once('-')?;
+    match (start.trim(), end.trim()) {
+        ("", "") => None,
+        ("", suffix) => match suffix.parse::<u64>().ok()? {
+            0 => Some(None),
+            _ if len == 0 => Some(None),
+            suffix => Some(Some((len.saturating_sub(suffix), len - 1))),
+        },
+        (start, end) => {
+            let start = start.parse::<u64>().ok()?;
+            let end = if end.is_empty() {
+                len.saturating_sub(1)
+            } else {
+                end.parse::<u64>().ok()?.min(len.saturating_sub(1))
+            };
+            if start >= len || start > end {
+                Some(None)
+            } else {
+                Some(Some((start, end)))
+            }
+        }
+    }
+}
+
+// A validator for the file's current contents, from its path, size and modification time,
+// so it changes whenever the file is rewritten without having to read it. Nothing in it
+// comes from the running process, so a download manager resuming after a restart still
+// gets the tag it saw before.
+pub fn etag(path: &Path, len: u64, modified: Option<SystemTime>) -> String {
+    let nanos = modified
+        .and_then(|at| at.duration_since(SystemTime::UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_nanos());
+    let path_hash = Sha256::digest(path.as_os_str().as_encoded_bytes());
+    format!(
+        "\"{:x}-{:x}-{:x}\"",
+        u64::from_be_bytes(path_hash[..8].try_into().expect("digest is 32 bytes")),
+        len,
+        nanos
+    )
+}
+
+fn http_date(at: SystemTime) -> String {
+    DateTime::<Utc>::from(at)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+// Whether the client's cached copy is still current. If-None-Match wins over
+// If-Modified-Since when both are sent (RFC 9110 §13.2.2); dates compare to the second,
+// the precision of the header.
+fn not_modified(request_headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(tags) = request_headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    {
+        let encoded_prefix = format!("{}-", etag.trim_end_matches('"'));
+        return tags.split(',').map(str::trim).any(|tag| {
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag == "*" || tag == etag || tag.starts_with(&encoded_prefix)
+        });
+    }
+    let (Some(since), Some(modified)) = (
+        request_headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok()),
+        modified,
+    ) else {
+        return false;
+    };
+    DateTime::<Utc>::from(modified).timestamp() <= since.timestamp()
+}
+
+// Whether a Range header should be honoured: only if the If-Range validator sent with it,
+// if any, still matches. Tags compare strongly and dates exactly (RFC 9110 §13.1.5), so a
+// client resuming a file that has since changed gets the whole new file instead of a
+// piece of it spliced onto the old one.
+fn range_applies(request_headers: &HeaderMap, etag: &str, modified: Option<SystemTime>) -> bool {
+    let Some(validator) = request_headers
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+    else {
+        return true;
+    };
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        return validator == etag;
+    }
+    match (DateTime::parse_from_rfc2822(validator), modified) {
+        (Ok(date), Some(modified)) => {
+            DateTime::<Utc>::from(modified).timestamp() == date.timestamp()
+        }
+        _ => false,
+    }
+}
+
+// Streams a file, honouring byte ranges from the request. `extra_headers` (e.g.
+// Content-Disposition) are added to every successful response.
+pub async fn file_response(
+    path: &Path,
+    request_headers: &HeaderMap,
+    extra_headers: HeaderMap,
+) -> Response {
+    let mut file = match tokio::fs::File::open(path).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!("Failed to open file {}: {}", path.display(), e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file.");
+        }
+    };
+    let (len, modified) = match file.metadata().await {
+        Ok(metadata) => (metadata.len(), metadata.modified().ok()),
+        Err(e) => {
+            error!("Failed to get metadata for {}: {}", path.display(), e);
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Could not read file information.",
+            );
+        }
+    };
+
+    let tag = etag(path, len, modified);
+    let mut headers = extra_headers;
+    headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&tag).expect("etag is ASCII"),
+    );
+    if let Some(modified) = modified {
+        headers.insert(
+            header::LAST_MODIFIED,
+            HeaderValue::from_str(&http_date(modified)).expect("date is ASCII"),
+        );
+    }
+    if not_modified(request_headers, &tag, modified) {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    let mime_type = mime_guess::from_path(path)
+        .first_or_octet_stream()
+        .to_string();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&mime_type)
+            .unwrap_or_else(|_| HeaderValue::from_static("application/octet-stream")),
+    );
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    let range = if range_applies(request_headers, &tag, modified) {
+        parse_range(request_headers.get(header::RANGE), len)
+    } else {
+        ByteRange::Full
+    };
+    let mut response = match range {
+        ByteRange::Full => {
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+            let stream = file_stream(file, len);
+            let body = Body::from_stream(crate::bandwidth::throttle(Unchanged::new(
+                stream, path, len, modified,
+            )));
+            (StatusCode::OK, headers, body).into_response()
+        }
+        ByteRange::Partial(start, end) => {
+            if let Err(e) = file.seek(SeekFrom::Start(start)).await {
+                error!("Failed to seek in {}: {}", path.display(), e);
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file.");
+            }
+            let length = end - start + 1;
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(length));
+            headers.insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes {}-{}/{}", start, end, len))
+                    .expect("content range is ASCII"),
+            );
+            let stream =
+                Unchanged::new(file_stream(file, length), path, len, modified).expecting(length);
+            (
+                StatusCode::PARTIAL_CONTENT,
+                headers,
+                Body::from_stream(crate::bandwidth::throttle(stream)),
+            )
+                .into_response()
+        }
+        ByteRange::Multiple(ranges) => {
+            multipart_response(path, file, (len, modified), &mime_type, &ranges, headers).await
+        }
+        ByteRange::Unsatisfiable => (
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            [(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{}", len)).expect("content range is ASCII"),
+            )],
+        )
+            .into_response(),
+    };
+    response
+        .extensions_mut()
+        .insert(crate::stats::Served(vec![path.to_path_buf()]));
+    response
+}
+
+// A `multipart/byteranges` body: each range as a part with its own Content-Range, read
+// from the file as the body is sent.
+async fn multipart_response(
+    path: &Path,
+    file: tokio::fs::File,
+    (len, modified): (u64, Option<SystemTime>),
+    mime_type: &str,
+    ranges: &[(u64, u64)],
+    mut headers: HeaderMap,
+) -> Response {
+    let boundary = uuid::Uuid::new_v4().simple().to_string();
+    let part_header = |(start, end): (u64, u64), first: bool| {
+        format!(
+            "{}--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            if first { "" } else { "\r\n" },
+            boundary,
+            mime_type,
+            start,
+            end,
+            len
+        )
+    };
+    let closing = format!("\r\n--{}--\r\n", boundary);
+
+    let mut body: Pin<Box<dyn Stream<Item = std::io::Result<Bytes>> + Send>> =
+        Box::pin(tokio_stream::empty());
+    let mut content_length = closing.len() as u64;
+    let mut file = Some(file);
+    for (i, &(start, end)) in ranges.iter().enumerate() {
+        let part = part_header((start, end), i == 0);
+        content_length += part.len() as u64 + (end - start + 1);
+        // The first part reuses the file that is already open.
+        let opened = match file.take() {
+            Some(file) => Ok(file),
+            None => tokio::fs::File::open(path).await,
+        };
+        let mut part_file = match opened {
+            Ok(part_file) => part_file,
+            Err(e) => {
+                error!("Failed to open file {}: {}", path.display(), e);
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file.");
+            }
+        };
+        if let Err(e) = part_file.seek(SeekFrom::Start(start)).await {
+            error!("Failed to seek in {}: {}", path.display(), e);
+            return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Could not read file.");
+        }
+        let data = Unchanged::new(file_stream(part_file, end - start + 1), path, len, modified)
+            .expecting(end - start + 1);
+        body = Box::pin(
+            body.chain(tokio_stream::once(Ok(Bytes::from(part))))
+                .chain(data),
+        );
+    }
+    let body = body.chain(tokio_stream::once(Ok(Bytes::from(closing))));
+
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))
+            .expect("boundary is ASCII"),
+    );
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    (
+        StatusCode::PARTIAL_CONTENT,
+        headers,
+        Body::from_stream(crate::bandwidth::throttle(body)),
+    )
+        .into_response()
+}
+
+// --- Change detection ---
+// Passes a file's data through and, before the last of it goes out, checks that exactly
+// the promised number of bytes were read and that the file still has the size and mtime
+// it had when the headers were sent. Otherwise the body ends with an error, which aborts
+// the response instead of letting a truncated or mixed-up file pass for a complete one.
+struct Unchanged<S> {
+    inner: S,
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+    expected: u64,
+    sent: u64,
+    // The last chunk, held back while the file's metadata is read.
+    checking: Option<(Option<std::io::Result<Bytes>>, MetadataFuture)>,
+    finished: bool,
+}
+
+type MetadataFuture = Pin<Box<dyn Future<Output = std::io::Result<Metadata>> + Send>>;
+
+impl<S> Unchanged<S> {
+    fn new(inner: S, path: &Path, len: u64, modified: Option<SystemTime>) -> Self {
+        Unchanged {
+            inner,
+            path: path.to_path_buf(),
+            len,
+            modified,
+            expected: len,
+            sent: 0,
+            checking: None,
+            finished: false,
+        }
+    }
+
+    // For a range: how much of the file this stream carries.
+    fn expecting(mut self, bytes: u64) -> Self {
+        self.expected = bytes;
+        self
+    }
+
+    fn check(&self, metadata: std::io::Result<Metadata>) -> Result<(), String> {
+        match metadata {
+            Ok(metadata) if metadata.len() != self.len => Err(format!(
+                "its size went from {} to {} bytes",
+                self.len,
+                metadata.len()
+            )),
+            Ok(metadata) if metadata.modified().ok() != self.modified => {
+                Err("it was modified".to_string())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("it can no longer be read: {}", e)),
+        }
+    }
+
+    fn finish(
+        &mut self,
+        chunk: Option<std::io::Result<Bytes>>,
+        checked: Result<(), String>,
+    ) -> Option<std::io::Result<Bytes>> {
+        self.finished = true;
+        match checked {
+            Ok(()) => chunk,
+            Err(reason) => {
+                error!(
+                    "Aborted sending {}: {} while it was being sent.",
+                    self.path.display(),
+                    reason
+                );
+                Some(Err(std::io::Error::other(format!(
+                    "file changed while being sent: {}",
+                    reason
+                ))))
+            }
+        }
+    }
+}
+
+impl<S> Stream for Unchanged<S>
+where
+    S: Stream<Item = std::io::Result<Bytes>> + Unpin,
+{
+    type Item = std::io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        if let Some((_, metadata)) = &mut self.checking {
+            let metadata = ready!(metadata.as_mut().poll(cx));
+            let checked = self.check(metadata);
+            let (chunk, _) = self.checking.take().expect("checking");
+            return Poll::Ready(self.finish(chunk, checked));
+        }
+        let chunk = ready!(Pin::new(&mut self.inner).poll_next(cx));
+        if let Some(Ok(bytes)) = &chunk {
+            self.sent += bytes.len() as u64;
+            // The server stops reading once Content-Length is reached, so the last chunk
+            // is held back until the file has been checked.
+            if self.sent < self.expected {
+                return Poll::Ready(chunk);
+            }
+        }
+        if matches!(chunk, Some(Err(_))) {
+            return Poll::Ready(chunk);
+        }
+        if self.sent != self.expected {
+            let shrank = format!(
+                "read {} of {} bytes; the file shrank",
+                self.sent, self.expected
+            );
+            return Poll::Ready(self.finish(chunk, Err(shrank)));
+        }
+        // Read off the runtime's worker threads, like the file itself.
+        let metadata = Box::pin(tokio::fs::metadata(self.path.clone()));
+        self.checking = Some((chunk, metadata));
+        self.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(value: &str, len: u64) -> ByteRange {
+        parse_range(Some(&HeaderValue::from_str(value).unwrap()), len)
+    }
+
+    #[test]
+    fn single_ranges() {
+        assert_eq!(range("bytes=0-9", 100), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=90-", 100), ByteRange::Partial(90, 99));
+        assert_eq!(range("bytes=-10", 100), ByteRange::Partial(90, 99));
+        // Ends past the file are cut to its last byte; suffixes longer than it are the file.
+        assert_eq!(range("bytes=50-500", 100), ByteRange::Partial(50, 99));
+        assert_eq!(range("bytes=-500", 100), ByteRange::Partial(0, 99));
+    }
+
+    #[test]
+    fn ranges_outside_the_file() {
+        assert_eq!(range("bytes=100-", 100), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=20-10", 100), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0", 100), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-", 0), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-5", 0), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn malformed_headers_get_the_whole_file() {
+        assert_eq!(parse_range(None, 100), ByteRange::Full);
+        assert_eq!(range("items=0-9", 100), ByteRange::Full);
+        assert_eq!(range("bytes=", 100), ByteRange::Full);
+        assert_eq!(range("bytes=-", 100), ByteRange::Full);
+        assert_eq!(range("bytes=a-b", 100), ByteRange::Full);
+        assert_eq!(range("bytes=0-9,x", 100), ByteRange::Full);
+    }
+
+    #[test]
+    fn multiple_ranges() {
+        assert_eq!(
+            range("bytes=0-9, 20-29,-5", 100),
+            ByteRange::Multiple(vec![(0, 9), (20, 29), (95, 99)])
+        );
+        // Ranges outside the file are dropped from the rest.
+        assert_eq!(range("bytes=0-9,200-300", 100), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=200-,300-", 100), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_whole_file() {
+        let specs: Vec<String> = (0..=MAX_RANGES).map(|i| format!("{}-{}", i, i)).collect();
+        let header = format!("bytes={}", specs.join(","));
+        assert_eq!(range(&header, 100), ByteRange::Full);
+        let header = format!("bytes={}", specs[..MAX_RANGES].join(","));
+        assert!(
+            matches!(range(&header, 100), ByteRange::Multiple(ranges) if ranges.len() == MAX_RANGES)
+        );
+    }
+
+    #[test]
+    fn if_range_needs_a_matching_validator() {
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let mut headers = HeaderMap::new();
+        assert!(range_applies(&headers, "\"abc\"", Some(modified)));
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("\"abc\""));
+        assert!(range_applies(&headers, "\"abc\"", Some(modified)));
+        assert!(!range_applies(&headers, "\"def\"", Some(modified)));
+        headers.insert(
+            header::IF_RANGE,
+            HeaderValue::from_str(&http_date(modified)).unwrap(),
+        );
+        assert!(range_applies(&headers, "\"abc\"", Some(modified)));
+        let later = modified + std::time::Duration::from_secs(1);
+        assert!(!range_applies(&headers, "\"abc\"", Some(later)));
+    }
+
+    async fn served(contents: &[u8], range: &str) -> (StatusCode, HeaderMap, Bytes) {
+        let path = std::env::temp_dir().join(format!("kiv-serve-{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, HeaderValue::from_str(range).unwrap());
+        let response = file_response(&path, &headers, HeaderMap::new()).await;
+        let (parts, body) = response.into_parts();
+        let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (parts.status, parts.headers, body)
+    }
+
+    #[tokio::test]
+    async fn serves_one_range() {
+        let (status, headers, body) = served(b"0123456789", "bytes=2-4").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(headers[header::CONTENT_LENGTH], "3");
+        assert_eq!(&body[..], b"234");
+    }
+
+    #[tokio::test]
+    async fn serves_several_ranges_as_multipart() {
+        let (status, headers, body) = served(b"0123456789", "bytes=0-1,-2").await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        let content_type = headers[header::CONTENT_TYPE].to_str().unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        assert_eq!(
+            headers[header::CONTENT_LENGTH],
+            body.len().to_string().as_str()
+        );
+        let body = std::str::from_utf8(&body).unwrap();
+        let expected = format!(
+            "--{b}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{b}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 8-9/10\r\n\r\n89\
+             \r\n--{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(body, expected);
+    }
+
+    #[tokio::test]
+    async fn refuses_ranges_past_the_end() {
+        let (status, headers, _) = served(b"0123456789", "bytes=10-").await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes */10");
+    }
+
+    #[tokio::test]
+    async fn files_changed_while_sent_end_in_an_error() {
+        let path = std::env::temp_dir().join(format!("kiv-serve-{}.bin", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let response = file_response(&path, &HeaderMap::new(), HeaderMap::new()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        std::fs::write(&path, b"01234567890123456789").unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await;
+        std::fs::remove_file(&path).unwrap();
+        assert!(body.is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use axum::http::{HeaderMap, HeaderValue, header};
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+pub const SESSION_COOKIE: &str = "kiv_session";
+
+// Past this many sessions, expired ones are swept out when the next one is created.
+const SWEEP_THRESHOLD: usize = 1024;
+
+struct Session {
+    user: String,
+    last_seen: Instant,
+}
+
+// --- Store ---
+// Logins made through the login page, by the random token in their cookie. A session
+// names its user rather than holding it, so reloading the users file applies to sessions
+// already open. Sessions end on logout, or once unused for the idle timeout.
+pub struct Sessions {
+    idle_timeout: Duration,
+    sessions: DashMap<String, Session>,
+}
+
+impl Sessions {
+    pub fn new(idle_timeout: Duration) -> Self {
+        Sessions {
+            idle_timeout,
+            sessions: DashMap::new(),
+        }
+    }
+
+    // Starts a session for `user` and returns its token.
+    pub fn create(&self, user: &str) -> String {
+        if self.sessions.len() >= SWEEP_THRESHOLD {
+            self.sessions
+                .retain(|_, session| session.last_seen.elapsed() < self.idle_timeout);
+        }
+        // Two v4 UUIDs from the operating system's random source, as for the signing key:
+        // 244 random bits, the version and variant bits being fixed.
+        let token = format!(
+            "{}{}",
+            uuid::Uuid::new_v4().simple(),
+            uuid::Uuid::new_v4().simple()
+        );
+        self.sessions.insert(
+            token.clone(),
+            Session {
+                user: user.to_string(),
+                last_seen: Instant::now(),
+            },
+        );
+        token
+    }
+
+    // The user of a live session, which counts as activity; expired sessions are dropped.
+    pub fn touch(&self, token: &str) -> Option<String> {
+        let mut session = self.sessions.get_mut(token)?;
+        if session.last_seen.elapsed() >= self.idle_timeout {
+            drop(session);
+            self.sessions.remove(token);
+            return None;
+        }
+        session.last_seen = Instant::now();
+        Some(session.user.clone())
+    }
+
+    // Ends the session, returning whose it was.
+    pub fn remove(&self, token: &str) -> Option<String> {
+        self.sessions.remove(token).map(|(_, session)| session.user)
+    }
+
+    pub fn count(&self) -> usize {
+        self.sessions
+            .iter()
+            .filter(|session| session.last_seen.elapsed() < self.idle_timeout)
+            .count()
+    }
+}
+
+// --- Cookie ---
+pub fn token(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value.to_string())
+}
+
+// No Max-Age, so the browser forgets it when closed. SameSite=Lax keeps other sites from
+// posting forms with it; `secure` when the server is reached over HTTPS.
+pub fn set_cookie(token: &str, secure: bool) -> HeaderValue {
+    let cookie = format!(
+        "{}={}; Path=/; SameSite=Lax; HttpOnly{}",
+        SESSION_COOKIE,
+        token,
+        if secure { "; Secure" } else { "" }
+    );
+    HeaderValue::from_str(&cookie).expect("session tokens are plain ASCII")
+}
+
+pub fn clear_cookie(secure: bool) -> HeaderValue {
+    let cookie = format!(
+        "{}=; Path=/; Max-Age=0; SameSite=Lax; HttpOnly{}",
+        SESSION_COOKIE,
+        if secure { "; Secure" } else { "" }
+    );
+    HeaderValue::from_str(&cookie).expect("session cookies are plain ASCII")
+}
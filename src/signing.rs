@@ -0,0 +1,359 @@
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::{io, path::Path, path::PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info};
+
+use crate::secrets;
+
+// Kept in the state directory, so cookies signed before a restart stay valid. One key
+// per line in hex: the first signs, the ones after it only verify what they signed
+// before being replaced. Lines starting with # are ignored.
+pub const SECRET_FILE_NAME: &str = "secret.key";
+
+type HmacSha256 = Hmac<Sha256>;
+
+// --- Keys ---
+struct Key {
+    // Named in signatures, so the key that made one is found without trying them all.
+    // Derived from the key itself, so the file holds nothing but keys.
+    id: String,
+    secret: Vec<u8>,
+}
+
+impl Key {
+    fn new(secret: Vec<u8>) -> Self {
+        let id = encode_hex(&Sha256::digest(&secret)[..4]);
+        Key { id, secret }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any length")
+    }
+
+    fn verify(&self, value: &str, signature: &[u8]) -> bool {
+        let mut mac = self.mac();
+        mac.update(value.as_bytes());
+        mac.verify_slice(signature).is_ok()
+    }
+}
+
+fn parse_keys(contents: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match decode_hex(line) {
+            Some(secret) if secret.len() >= 32 => keys.push(Key::new(secret)),
+            _ => {
+                return Err(format!(
+                    "line {} isn't a key of at least 32 bytes in hex",
+                    number + 1
+                ));
+            }
+        }
+    }
+    if keys.is_empty() {
+        return Err("no keys".to_string());
+    }
+    Ok(keys)
+}
+
+// --- Signer ---
+// Signs values handed to clients (cookies, signed download links) with a key only the
+// server knows, so they can't be altered or forged.
+pub struct Signer {
+    // The first signs; all of them verify.
+    keys: Vec<Key>,
+}
+
+impl Signer {
+    // Reads the keys from `file`, creating a random one on first start unless `create` is
+    // false. When neither works a throwaway key is used, and anything signed doesn't
+    // survive a restart.
+    pub async fn load_or_create(file: PathBuf, create: bool) -> Self {
+        match tokio::fs::read_to_string(&file).await {
+            Ok(contents) => match parse_keys(&contents) {
+                Ok(keys) => {
+                    if keys.len() > 1 {
+                        info!(
+                            "Signing with key {}; {} older keys still verify",
+                            keys[0].id,
+                            keys.len() - 1
+                        );
+                    }
+                    return Signer { keys };
+                }
+                Err(e) => error!(
+                    "Ignoring malformed signing key {} ({}); using a temporary one",
+                    file.display(),
+                    e
+                ),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !create => {
+                info!(
+                    "No signing key at {}; using a temporary one",
+                    file.display()
+                );
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let key = random_key();
+                match save(&file, &[&key]).await {
+                    Ok(()) => info!("Created signing key {}", file.display()),
+                    Err(e) => error!("Failed to save signing key {}: {}", file.display(), e),
+                }
+                return Signer {
+                    keys: vec![Key::new(key)],
+                };
+            }
+            Err(e) => error!("Failed to read signing key {}: {}", file.display(), e),
+        }
+        Signer {
+            keys: vec![Key::new(random_key())],
+        }
+    }
+
+    // Reads the keys from a file kiv doesn't manage (`--signing-key-file`, a systemd
+    // credential), which has to be there and be right.
+    pub async fn load(file: &Path) -> Result<Self, String> {
+        let contents = secrets::read(file).await?;
+        let keys = parse_keys(&contents)
+            .map_err(|e| format!("Malformed signing key {}: {}", file.display(), e))?;
+        info!("Signing with key {} from {}", keys[0].id, file.display());
+        Ok(Signer { keys })
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    // `<key id>.<HMAC>`, in hex.
+    pub fn sign(&self, value: &str) -> String {
+        let key = &self.keys[0];
+        let mut mac = key.mac();
+        mac.update(value.as_bytes());
+        format!("{}.{}", key.id, encode_hex(&mac.finalize().into_bytes()))
+    }
+
+    // Compares in constant time. Signatures from before keys had IDs are tried against
+    // every key.
+    pub fn verify(&self, value: &str, signature: &str) -> bool {
+        let (id, mac) = match signature.split_once('.') {
+            Some((id, mac)) => (Some(id), mac),
+            None => (None, signature),
+        };
+        let Some(mac) = decode_hex(mac) else {
+            return false;
+        };
+        self.keys
+            .iter()
+            .filter(|key| id.is_none_or(|id| id == key.id))
+            .any(|key| key.verify(value, &mac))
+    }
+
+    // Download links name the path and expiry (seconds since the epoch) they were issued
+    // for; the prefix keeps them from ever verifying as some other signed value.
+    pub fn sign_download(&self, path: &str, expires: i64) -> String {
+        self.sign(&format!("download\n{}\n{}", expires, path))
+    }
+
+    pub fn verify_download(&self, path: &str, expires: i64, signature: &str) -> bool {
+        self.verify(&format!("download\n{}\n{}", expires, path), signature)
+    }
+}
+
+// --- Rotation ---
+// A new random key in hex, for `--generate-signing-key`.
+pub fn generate_key() -> String {
+    encode_hex(&random_key())
+}
+
+// Puts a new key first in `file` for `--rotate-signing-key`. The key it replaces is kept
+// to verify what it signed; any older ones are dropped. kiv picks it up when started.
+pub async fn rotate(file: &Path) -> Result<String, String> {
+    let previous = match tokio::fs::read_to_string(file).await {
+        Ok(contents) => parse_keys(&contents)
+            .map_err(|e| format!("Malformed signing key {}: {}", file.display(), e))?
+            .into_iter()
+            .next(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => {
+            return Err(format!(
+                "Failed to read signing key {}: {}",
+                file.display(),
+                e
+            ));
+        }
+    };
+    let key = Key::new(random_key());
+    let mut secrets = vec![key.secret.as_slice()];
+    secrets.extend(previous.as_ref().map(|previous| previous.secret.as_slice()));
+    save(file, &secrets)
+        .await
+        .map_err(|e| format!("Failed to save signing key {}: {}", file.display(), e))?;
+    Ok(match previous {
+        Some(previous) => format!(
+            "Now signing with key {}; key {} still verifies until the next rotation.",
+            key.id, previous.id
+        ),
+        None => format!("Now signing with key {}.", key.id),
+    })
+}
+
+// 32 bytes from two v4 UUIDs, which come from the operating system's random source.
+fn random_key() -> Vec<u8> {
+    let mut key = uuid::Uuid::new_v4().as_bytes().to_vec();
+    key.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+    key
+}
+
+// Written to a new file that is readable only by its owner from the start, then moved
+// over the old one, so the key is never readable by others or left half written.
+async fn save(file: &Path, secrets: &[&[u8]]) -> io::Result<()> {
+    if let Some(dir) = file.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let contents: String = secrets
+        .iter()
+        .map(|secret| encode_hex(secret) + "\n")
+        .collect();
+    let temp = file.with_extension(format!("{}.tmp", uuid::Uuid::new_v4().simple()));
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let written = async {
+        let mut out = options.open(&temp).await?;
+        out.write_all(contents.as_bytes()).await?;
+        out.sync_all().await
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(e);
+    }
+    tokio::fs::rename(&temp, file).await
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(secrets: &[&[u8]]) -> Signer {
+        Signer {
+            keys: secrets
+                .iter()
+                .map(|secret| Key::new(secret.to_vec()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn verifies_only_what_it_signed() {
+        let signer = signer(&[&[1; 32]]);
+        let signature = signer.sign("value");
+        assert!(signer.verify("value", &signature));
+        assert!(!signer.verify("value2", &signature));
+        assert!(!signer.verify("value", "not hex"));
+        assert!(!signer.verify("value", ""));
+        // Another key doesn't verify it, even under the right ID.
+        let other = self::signer(&[&[2; 32]]);
+        assert!(!other.verify("value", &signature));
+        let (_, mac) = signature.split_once('.').unwrap();
+        let forged = format!("{}.{}", other.keys[0].id, mac);
+        assert!(!other.verify("value", &forged));
+    }
+
+    #[test]
+    fn signatures_without_an_id_try_every_key() {
+        let old = signer(&[&[1; 32]]);
+        let signature = old.sign("value");
+        let (_, mac) = signature.split_once('.').unwrap();
+        let rotated = signer(&[&[2; 32], &[1; 32]]);
+        assert!(rotated.verify("value", mac));
+    }
+
+    #[test]
+    fn download_links_cover_path_and_expiry() {
+        let signer = signer(&[&[1; 32]]);
+        let signature = signer.sign_download("a/b.txt", 100);
+        assert!(signer.verify_download("a/b.txt", 100, &signature));
+        assert!(!signer.verify_download("a/c.txt", 100, &signature));
+        assert!(!signer.verify_download("a/b.txt", 101, &signature));
+        // Nothing else that was signed passes for a download link.
+        assert!(!signer.verify_download("a/b.txt", 100, &signer.sign("a/b.txt")));
+    }
+
+    #[test]
+    fn parses_key_files() {
+        let key = encode_hex(&[7; 32]);
+        let keys =
+            parse_keys(&format!("# current\n{}\n\n{}\n", key, encode_hex(&[8; 40]))).unwrap();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].secret, vec![7; 32]);
+        assert!(parse_keys("").is_err());
+        assert!(parse_keys(&encode_hex(&[7; 31])).is_err());
+        assert!(parse_keys("zz").is_err());
+    }
+
+    #[tokio::test]
+    async fn rotation_keeps_one_previous_key() {
+        let dir = std::env::temp_dir().join(format!("kiv-signing-{}", uuid::Uuid::new_v4()));
+        let file = dir.join(SECRET_FILE_NAME);
+
+        rotate(&file).await.unwrap();
+        let first = Signer::load(&file).await.unwrap();
+        let signed_first = first.sign("value");
+
+        rotate(&file).await.unwrap();
+        let second = Signer::load(&file).await.unwrap();
+        assert_eq!(second.key_count(), 2);
+        assert_ne!(second.sign("value"), signed_first);
+        assert!(second.verify("value", &signed_first));
+
+        rotate(&file).await.unwrap();
+        let third = Signer::load(&file).await.unwrap();
+        assert_eq!(third.key_count(), 2);
+        assert!(!third.verify("value", &signed_first));
+        assert!(third.verify("value", &second.sign("value")));
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn keys_are_saved_for_the_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("kiv-signing-{}", uuid::Uuid::new_v4()));
+        let file = dir.join(SECRET_FILE_NAME);
+        rotate(&file).await.unwrap();
+        rotate(&file).await.unwrap();
+        let mode = tokio::fs::metadata(&file)
+            .await
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+        // Nothing is left beside it.
+        let mut entries = tokio::fs::read_dir(&dir).await.unwrap();
+        assert!(entries.next_entry().await.unwrap().is_some());
+        assert!(entries.next_entry().await.unwrap().is_none());
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
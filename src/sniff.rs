@@ -0,0 +1,364 @@
+use std::path::Path;
+
+// How much of a file `detect` wants to see; tar's marker is the furthest in.
+pub const HEAD_LEN: usize = 4096;
+
+// --- Signatures ---
+// What a file's first bytes say it is. Only formats with a reliable signature are here;
+// text formats (HTML, SVG, source code) have none and are never judged by content.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Kind {
+    Jpeg,
+    Png,
+    Gif,
+    Webp,
+    Bmp,
+    Tiff,
+    Ico,
+    Pdf,
+    Zip,
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    SevenZip,
+    Rar,
+    Tar,
+    OleDocument,
+    Mp3,
+    Flac,
+    Ogg,
+    Wav,
+    Avi,
+    IsoMedia,
+    Matroska,
+    Sqlite,
+    Wasm,
+    Elf,
+    WindowsExecutable,
+    MachO,
+    Script,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Jpeg => "a JPEG image",
+            Kind::Png => "a PNG image",
+            Kind::Gif => "a GIF image",
+            Kind::Webp => "a WebP image",
+            Kind::Bmp => "a BMP image",
+            Kind::Tiff => "a TIFF image or camera raw",
+            Kind::Ico => "an icon",
+            Kind::Pdf => "a PDF document",
+            Kind::Zip => "a ZIP archive",
+            Kind::Gzip => "a gzip file",
+            Kind::Bzip2 => "a bzip2 file",
+            Kind::Xz => "an xz file",
+            Kind::Zstd => "a zstd file",
+            Kind::SevenZip => "a 7z archive",
+            Kind::Rar => "a RAR archive",
+            Kind::Tar => "a tar archive",
+            Kind::OleDocument => "an Office 97–2003 document or installer",
+            Kind::Mp3 => "an MP3",
+            Kind::Flac => "a FLAC file",
+            Kind::Ogg => "an Ogg file",
+            Kind::Wav => "a WAV file",
+            Kind::Avi => "an AVI video",
+            Kind::IsoMedia => "an MP4/QuickTime/HEIF file",
+            Kind::Matroska => "a Matroska/WebM file",
+            Kind::Sqlite => "an SQLite database",
+            Kind::Wasm => "a WebAssembly module",
+            Kind::Elf => "a Linux/Unix executable",
+            Kind::WindowsExecutable => "a Windows executable",
+            Kind::MachO => "a macOS executable",
+            Kind::Script => "a script",
+        }
+    }
+
+    // The extensions files of this kind go by, the usual one first.
+    pub fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Kind::Jpeg => &["jpg", "jpeg", "jpe", "jfif"],
+            Kind::Png => &["png", "apng"],
+            Kind::Gif => &["gif"],
+            Kind::Webp => &["webp"],
+            Kind::Bmp => &["bmp", "dib"],
+            Kind::Tiff => &[
+                "tif", "tiff", "dng", "nef", "nrw", "cr2", "arw", "sr2", "srw", "pef", "orf",
+                "erf", "3fr", "mos", "mef",
+            ],
+            Kind::Ico => &["ico"],
+            Kind::Pdf => &["pdf", "ai"],
+            // Office, OpenDocument, e-books, Java and Android packages are all zips.
+            Kind::Zip => &[
+                "zip", "docx", "docm", "dotx", "xlsx", "xlsm", "xltx", "pptx", "pptm", "potx",
+                "odt", "ods", "odp", "odg", "odf", "ott", "epub", "cbz", "cbr", "jar", "war",
+                "ear", "apk", "aab", "aar", "ipa", "xpi", "kmz", "whl", "nupkg", "vsix", "3mf",
+                "xps", "oxps", "appx", "msix", "usdz", "ora", "sketch", "key", "pages", "numbers",
+                "apkg",
+            ],
+            Kind::Gzip => &["gz", "tgz", "svgz"],
+            Kind::Bzip2 => &["bz2", "tbz2", "tbz"],
+            Kind::Xz => &["xz", "txz"],
+            Kind::Zstd => &["zst", "tzst"],
+            Kind::SevenZip => &["7z"],
+            // Comic archives carry either extension whatever they are.
+            Kind::Rar => &["rar", "cbr", "cbz"],
+            Kind::Tar => &["tar", "ova"],
+            Kind::OleDocument => &[
+                "doc", "dot", "xls", "xlt", "xla", "ppt", "pps", "pot", "msg", "vsd", "pub", "mpp",
+                "msi",
+            ],
+            Kind::Mp3 => &["mp3"],
+            Kind::Flac => &["flac"],
+            Kind::Ogg => &["ogg", "oga", "ogv", "opus", "spx"],
+            Kind::Wav => &["wav"],
+            Kind::Avi => &["avi"],
+            Kind::IsoMedia => &[
+                "mp4", "m4v", "m4a", "m4b", "mov", "heic", "heif", "avif", "3gp", "3g2", "f4v",
+                "cr3",
+            ],
+            Kind::Matroska => &["mkv", "webm", "mka", "mks"],
+            Kind::Sqlite => &["sqlite", "sqlite3"],
+            Kind::Wasm => &["wasm"],
+            Kind::Elf => &["so", "o", "ko", "elf", "axf", "out"],
+            Kind::WindowsExecutable => &[
+                "exe", "dll", "sys", "scr", "com", "ocx", "cpl", "efi", "drv", "mui", "node",
+            ],
+            Kind::MachO => &["dylib", "bundle", "o"],
+            Kind::Script => &[
+                "sh", "bash", "zsh", "ksh", "csh", "fish", "py", "pl", "rb", "php", "js", "mjs",
+                "lua", "tcl", "awk", "r",
+            ],
+        }
+    }
+
+    // Native programs, which run as they are on some system.
+    pub fn is_executable(self) -> bool {
+        matches!(self, Kind::Elf | Kind::WindowsExecutable | Kind::MachO)
+    }
+}
+
+pub fn detect(head: &[u8]) -> Option<Kind> {
+    let at = |offset: usize, magic: &[u8]| head.get(offset..offset + magic.len()) == Some(magic);
+    let kind = if at(0, b"\xFF\xD8\xFF") {
+        Kind::Jpeg
+    } else if at(0, b"\x89PNG\r\n\x1A\n") {
+        Kind::Png
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Kind::Gif
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        Kind::Webp
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        Kind::Wav
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        Kind::Avi
+    } else if at(0, b"BM") && at(6, b"\0\0\0\0") {
+        Kind::Bmp
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        Kind::Tiff
+    } else if at(0, b"%PDF-") {
+        Kind::Pdf
+    } else if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") {
+        Kind::Zip
+    } else if at(0, b"\x1F\x8B") {
+        Kind::Gzip
+    } else if at(0, b"BZh") {
+        Kind::Bzip2
+    } else if at(0, b"\xFD7zXZ\0") {
+        Kind::Xz
+    } else if at(0, b"\x28\xB5\x2F\xFD") {
+        Kind::Zstd
+    } else if at(0, b"7z\xBC\xAF\x27\x1C") {
+        Kind::SevenZip
+    } else if at(0, b"Rar!\x1A\x07") {
+        Kind::Rar
+    } else if at(257, b"ustar") {
+        Kind::Tar
+    } else if at(0, b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1") {
+        Kind::OleDocument
+    } else if at(0, b"ID3") {
+        Kind::Mp3
+    } else if at(0, b"fLaC") {
+        Kind::Flac
+    } else if at(0, b"OggS") {
+        Kind::Ogg
+    } else if at(4, b"ftyp") {
+        Kind::IsoMedia
+    } else if at(0, b"\x1A\x45\xDF\xA3") {
+        Kind::Matroska
+    } else if at(0, b"SQLite format 3\0") {
+        Kind::Sqlite
+    } else if at(0, b"\0asm") {
+        Kind::Wasm
+    } else if at(0, b"\x7FELF") {
+        Kind::Elf
+    } else if at(0, b"MZ") && is_pe(head) {
+        Kind::WindowsExecutable
+    } else if [
+        b"\xFE\xED\xFA\xCE",
+        b"\xCE\xFA\xED\xFE",
+        b"\xFE\xED\xFA\xCF",
+        b"\xCF\xFA\xED\xFE",
+    ]
+    .iter()
+    .any(|magic| at(0, *magic))
+    {
+        Kind::MachO
+    } else if at(0, b"\0\0\x01\0") {
+        Kind::Ico
+    } else if at(0, b"#!") {
+        Kind::Script
+    } else {
+        return None;
+    };
+    Some(kind)
+}
+
+// "MZ" alone starts plenty of text; a Windows program also has its PE header where the
+// DOS header points.
+fn is_pe(head: &[u8]) -> bool {
+    head.get(0x3C..0x40)
+        .map(|offset| u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]) as usize)
+        .and_then(|offset| head.get(offset..offset + 4))
+        == Some(b"PE\0\0")
+}
+
+// --- Upload policy ---
+pub enum Verdict {
+    Accept,
+    // Store it under this name instead, with the extension its contents call for.
+    Rename(String),
+    Refuse(String),
+}
+
+// What `--deny-upload-type`, `--skip-upload-type-check` and `--fix-upload-extensions`
+// make of an upload, judged by its name and first bytes.
+pub struct UploadTypes {
+    // Lowercase extensions without the dot, or "executable" or "script".
+    deny: Vec<String>,
+    check: bool,
+    fix_extensions: bool,
+}
+
+impl UploadTypes {
+    pub fn new(deny: &[String], check: bool, fix_extensions: bool) -> Self {
+        UploadTypes {
+            deny: deny
+                .iter()
+                .map(|name| name.trim().trim_start_matches('.').to_ascii_lowercase())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            check,
+            fix_extensions,
+        }
+    }
+
+    pub fn denied(&self) -> &[String] {
+        &self.deny
+    }
+
+    pub fn checked(&self) -> bool {
+        self.check
+    }
+
+    pub fn fixes_extensions(&self) -> bool {
+        self.fix_extensions
+    }
+
+    pub fn judge(&self, file_name: &str, head: &[u8]) -> Verdict {
+        let extension = Path::new(file_name)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        let kind = detect(head);
+
+        let denied = |name: &str| self.deny.iter().any(|denied| denied == name);
+        if let Some(extension) = &extension
+            && denied(extension)
+        {
+            return Verdict::Refuse(format!(".{} files aren't accepted here.", extension));
+        }
+        if let Some(kind) = kind {
+            let by_category = (kind.is_executable() && denied("executable"))
+                || (kind == Kind::Script && denied("script"));
+            if by_category || kind.extensions().iter().any(|ext| denied(ext)) {
+                return Verdict::Refuse(format!(
+                    "This file is {}, which isn't accepted here.",
+                    kind.name()
+                ));
+            }
+        }
+
+        let (Some(kind), Some(extension)) = (kind, extension) else {
+            return Verdict::Accept;
+        };
+        if kind.extensions().contains(&extension.as_str()) {
+            return Verdict::Accept;
+        }
+        // A program dressed up as something else is refused even with checks off.
+        if kind.is_executable() {
+            return Verdict::Refuse(format!(
+                "This file is {} but is named as a .{} file.",
+                kind.name(),
+                extension
+            ));
+        }
+        // Extensions no known format claims (.txt, .dat, …) may hold anything.
+        if !self.check || !is_known_extension(&extension) {
+            return Verdict::Accept;
+        }
+        if self.fix_extensions && kind != Kind::Script {
+            let stem = Path::new(file_name)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(file_name);
+            return Verdict::Rename(format!("{}.{}", stem, kind.extensions()[0]));
+        }
+        Verdict::Refuse(format!(
+            "This file is {} but is named as a .{} file.",
+            kind.name(),
+            extension
+        ))
+    }
+}
+
+const ALL_KINDS: [Kind; 30] = [
+    Kind::Jpeg,
+    Kind::Png,
+    Kind::Gif,
+    Kind::Webp,
+    Kind::Bmp,
+    Kind::Tiff,
+    Kind::Ico,
+    Kind::Pdf,
+    Kind::Zip,
+    Kind::Gzip,
+    Kind::Bzip2,
+    Kind::Xz,
+    Kind::Zstd,
+    Kind::SevenZip,
+    Kind::Rar,
+    Kind::Tar,
+    Kind::OleDocument,
+    Kind::Mp3,
+    Kind::Flac,
+    Kind::Ogg,
+    Kind::Wav,
+    Kind::Avi,
+    Kind::IsoMedia,
+    Kind::Matroska,
+    Kind::Sqlite,
+    Kind::Wasm,
+    Kind::Elf,
+    Kind::WindowsExecutable,
+    Kind::MachO,
+    Kind::Script,
+];
+
+fn is_known_extension(extension: &str) -> bool {
+    ALL_KINDS
+        .iter()
+        .any(|kind| kind.extensions().contains(&extension))
+}
@@ -0,0 +1,237 @@
+use axum::{
+    body::Body,
+    extract::{Extension, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::DashMap;
+use std::{
+    fmt::Write,
+    net::IpAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+use tokio_stream::StreamExt;
+
+use crate::{SharedState, auth, ipfilter::ClientIp};
+
+// Set on a response by whatever serves files or folders, so the counters know what was
+// fetched: one path for a file, each item for an archive of several.
+#[derive(Clone)]
+pub struct Served(pub Vec<PathBuf>);
+
+impl Served {
+    // The mount-relative paths of what was served, leaving out any outside the mounts.
+    pub fn relative(&self, state: &SharedState) -> Vec<String> {
+        self.0
+            .iter()
+            .filter_map(|path| Some(state.mounts.containing(path)?.relative(path)))
+            .collect()
+    }
+}
+
+// --- Counters ---
+// Downloads since the server started: how many are streaming now, how many bytes they
+// have sent, and how often each file or folder was fetched.
+pub struct DownloadStats {
+    active: AtomicUsize,
+    bytes: AtomicU64,
+    requests: AtomicU64,
+    // Request path → downloads started.
+    counts: DashMap<String, u64>,
+    // The downloads streaming now, by an id of their own.
+    transfers: DashMap<u64, Transfer>,
+    next_transfer: AtomicU64,
+}
+
+// One download in progress, as listed on the admin page.
+#[derive(Clone)]
+pub struct Transfer {
+    // What is being fetched (mount-relative paths, or the request path when unknown).
+    pub path: String,
+    pub client: IpAddr,
+    pub user: Option<String>,
+    pub started: Instant,
+    pub sent: Arc<AtomicU64>,
+}
+
+// Counts `active` down again however the body ends, including the client hanging up.
+struct ActiveGuard(SharedState, u64);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        let stats = &self.0.download_stats;
+        stats.active.fetch_sub(1, Ordering::Relaxed);
+        stats.transfers.remove(&self.1);
+    }
+}
+
+impl DownloadStats {
+    pub fn new() -> Self {
+        DownloadStats {
+            active: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            counts: DashMap::new(),
+            transfers: DashMap::new(),
+            next_transfer: AtomicU64::new(0),
+        }
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn requests(&self) -> u64 {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    // The downloads streaming now, oldest first.
+    pub fn transfers(&self) -> Vec<Transfer> {
+        let mut transfers: Vec<Transfer> = self
+            .transfers
+            .iter()
+            .map(|entry| entry.value().clone())
+            .collect();
+        transfers.sort_by_key(|transfer| transfer.started);
+        transfers
+    }
+
+    // The most fetched paths, most first; only those the current request gets to see, so
+    // the figures don't give away names across homes and rules.
+    pub fn top(&self, limit: usize) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self
+            .counts
+            .iter()
+            .filter(|entry| auth::visible(entry.key()))
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts.truncate(limit);
+        counts
+    }
+}
+
+// --- Middleware ---
+// Only attachments count: files opened in the browser, on the same routes, aren't
+// downloads. Resumed or seeking requests (ranges not starting at the first byte) add to
+// the bytes but aren't counted as another download.
+pub async fn track(
+    State(state): State<SharedState>,
+    Extension(ClientIp(client)): Extension<ClientIp>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let request_path = request.uri().path().to_string();
+    let response = next.run(request).await;
+    let status = response.status();
+    let attachment = response
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("attachment"));
+    if !attachment || (status != StatusCode::OK && status != StatusCode::PARTIAL_CONTENT) {
+        return response;
+    }
+    let stats = &state.download_stats;
+    let from_start = status == StatusCode::OK
+        || response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|range| range.starts_with("bytes 0-"));
+    let served = response
+        .extensions()
+        .get::<Served>()
+        .map(|served| served.relative(&state))
+        .unwrap_or_default();
+    if from_start {
+        stats.requests.fetch_add(1, Ordering::Relaxed);
+        for relative in &served {
+            *stats.counts.entry(relative.clone()).or_insert(0) += 1;
+        }
+    }
+
+    stats.active.fetch_add(1, Ordering::Relaxed);
+    let id = stats.next_transfer.fetch_add(1, Ordering::Relaxed);
+    let sent = Arc::new(AtomicU64::new(0));
+    stats.transfers.insert(
+        id,
+        Transfer {
+            path: if served.is_empty() {
+                request_path
+            } else {
+                served.join(", ")
+            },
+            client,
+            user: auth::current_user(),
+            started: Instant::now(),
+            sent: sent.clone(),
+        },
+    );
+    let guard = ActiveGuard(state.clone(), id);
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        if let Ok(bytes) = &chunk {
+            let len = bytes.len() as u64;
+            guard
+                .0
+                .download_stats
+                .bytes
+                .fetch_add(len, Ordering::Relaxed);
+            sent.fetch_add(len, Ordering::Relaxed);
+        }
+        chunk
+    });
+    (parts, Body::from_stream(body)).into_response()
+}
+
+// --- Metrics ---
+// The counters in Prometheus' text format.
+pub fn prometheus(stats: &DownloadStats, sent: u64) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP kiv_downloads_active Downloads streaming right now."
+    );
+    let _ = writeln!(out, "# TYPE kiv_downloads_active gauge");
+    let _ = writeln!(out, "kiv_downloads_active {}", stats.active());
+    let _ = writeln!(out, "# HELP kiv_downloads_total Downloads started.");
+    let _ = writeln!(out, "# TYPE kiv_downloads_total counter");
+    let _ = writeln!(out, "kiv_downloads_total {}", stats.requests());
+    let _ = writeln!(
+        out,
+        "# HELP kiv_download_bytes_total Bytes sent by downloads."
+    );
+    let _ = writeln!(out, "# TYPE kiv_download_bytes_total counter");
+    let _ = writeln!(out, "kiv_download_bytes_total {}", stats.bytes());
+    let _ = writeln!(
+        out,
+        "# HELP kiv_sent_bytes_total Bytes of files sent, including previews and media."
+    );
+    let _ = writeln!(out, "# TYPE kiv_sent_bytes_total counter");
+    let _ = writeln!(out, "kiv_sent_bytes_total {}", sent);
+    let _ = writeln!(out, "# HELP kiv_file_downloads_total Downloads by path.");
+    let _ = writeln!(out, "# TYPE kiv_file_downloads_total counter");
+    for (path, count) in stats.top(usize::MAX) {
+        let label = path
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        let _ = writeln!(
+            out,
+            "kiv_file_downloads_total{{path=\"{}\"}} {}",
+            label, count
+        );
+    }
+    out
+}
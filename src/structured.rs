@@ -0,0 +1,228 @@
+use maud::{Markup, html};
+use serde_json::{Map, Number, Value};
+use std::path::Path;
+
+// Documents larger than this are only shown as text; the tree is built in memory.
+pub const MAX_STRUCTURED_SIZE: u64 = 32 * 1024 * 1024;
+// Children of one node rendered per request; the rest come in with "Show more".
+const CHILDREN_PAGE: usize = 100;
+// Nodes rendered up front. Small documents are sent whole; in big ones, containers past
+// this budget are fetched when they are first expanded.
+const EAGER_NODES: usize = 2000;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StructuredFormat {
+    Json,
+    Yaml,
+}
+
+pub fn structured_format(path: &Path) -> Option<StructuredFormat> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match extension.as_str() {
+        "json" | "geojson" | "jsonld" => Some(StructuredFormat::Json),
+        "yaml" | "yml" => Some(StructuredFormat::Yaml),
+        _ => None,
+    }
+}
+
+// --- Parsing ---
+// The whole document as JSON values. A YAML stream with several documents becomes an
+// array of them.
+pub async fn parse(path: &Path, format: StructuredFormat) -> Result<Value, String> {
+    let metadata = tokio::fs::metadata(path).await.map_err(|e| e.to_string())?;
+    if metadata.len() > MAX_STRUCTURED_SIZE {
+        return Err(format!(
+            "The file is too large for the tree view (limit {} MiB).",
+            MAX_STRUCTURED_SIZE / 1024 / 1024
+        ));
+    }
+    let contents = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || match format {
+        StructuredFormat::Json => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+        StructuredFormat::Yaml => {
+            let mut documents = Vec::new();
+            for document in serde_yaml::Deserializer::from_str(&contents) {
+                let value: serde_yaml::Value =
+                    serde::Deserialize::deserialize(document).map_err(|e| e.to_string())?;
+                documents.push(from_yaml(value));
+            }
+            Ok(match documents.len() {
+                1 => documents.remove(0),
+                _ => Value::Array(documents),
+            })
+        }
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// YAML allows non-string keys, tags and non-finite floats, none of which JSON has; keys
+// and special floats are shown as strings and tags are dropped.
+fn from_yaml(value: serde_yaml::Value) -> Value {
+    match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::from(i)
+            } else if let Some(u) = n.as_u64() {
+                Value::from(u)
+            } else {
+                n.as_f64()
+                    .and_then(Number::from_f64)
+                    .map_or_else(|| Value::String(n.to_string()), Value::Number)
+            }
+        }
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(items) => {
+            Value::Array(items.into_iter().map(from_yaml).collect())
+        }
+        serde_yaml::Value::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .map(|(key, value)| (yaml_key(key), from_yaml(value)))
+                .collect::<Map<_, _>>(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => from_yaml(tagged.value),
+    }
+}
+
+fn yaml_key(key: serde_yaml::Value) -> String {
+    match from_yaml(key) {
+        Value::String(s) => s,
+        other => other.to_string(),
+    }
+}
+
+// --- Rendering ---
+// `<li>`s for the children of `value` (found at JSON pointer `pointer`), starting at
+// `offset`. `node_url` gives the URL listing a node's children from an offset.
+pub fn render_children(
+    value: &Value,
+    pointer: &str,
+    offset: usize,
+    node_url: &dyn Fn(&str, usize) -> String,
+) -> Markup {
+    let mut budget = EAGER_NODES;
+    children(value, pointer, offset, 0, &mut budget, node_url)
+}
+
+fn children(
+    value: &Value,
+    pointer: &str,
+    offset: usize,
+    depth: usize,
+    budget: &mut usize,
+    node_url: &dyn Fn(&str, usize) -> String,
+) -> Markup {
+    let entries: Vec<(String, &Value)> = match value {
+        Value::Object(map) => map
+            .iter()
+            .skip(offset)
+            .take(CHILDREN_PAGE)
+            .map(|(key, child)| (key.clone(), child))
+            .collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .skip(offset)
+            .take(CHILDREN_PAGE)
+            .map(|(index, child)| (index.to_string(), child))
+            .collect(),
+        _ => Vec::new(),
+    };
+    let total = child_count(value);
+    let next_offset = offset + entries.len();
+    let is_array = value.is_array();
+
+    let mut nodes = Vec::with_capacity(entries.len());
+    for (key, child) in &entries {
+        let child_pointer = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+        let summary = html! { (label(key, is_array)) (container_summary(child)) };
+        let node = if !(child.is_object() || child.is_array()) {
+            html! { span class="json-leaf" { (label(key, is_array)) (scalar(child)) } }
+        } else if *budget > 0 {
+            *budget = budget.saturating_sub(child_count(child).min(CHILDREN_PAGE) + 1);
+            let inner = children(child, &child_pointer, 0, depth + 1, budget, node_url);
+            html! {
+                details open[depth == 0] {
+                    summary { (summary) }
+                    ul class="json-children" { (inner) }
+                }
+            }
+        } else {
+            html! {
+                details hx-get=(node_url(&child_pointer, 0))
+                        hx-trigger="toggle once"
+                        hx-target="find .json-children"
+                        hx-swap="innerHTML" {
+                    summary { (summary) }
+                    ul class="json-children" { li class="json-loading" { "Loading…" } }
+                }
+            }
+        };
+        nodes.push(node);
+    }
+
+    html! {
+        @for node in nodes {
+            li class="json-node" { (node) }
+        }
+        @if next_offset < total {
+            li class="json-more" {
+                button hx-get=(node_url(pointer, next_offset))
+                       hx-target="closest li"
+                       hx-swap="outerHTML" {
+                    "Show " ((total - next_offset).min(CHILDREN_PAGE)) " more of " (total - next_offset) "…"
+                }
+            }
+        }
+    }
+}
+
+fn child_count(value: &Value) -> usize {
+    match value {
+        Value::Object(map) => map.len(),
+        Value::Array(items) => items.len(),
+        _ => 0,
+    }
+}
+
+fn label(key: &str, is_index: bool) -> Markup {
+    html! {
+        @if is_index {
+            span class="json-index" { (key) }
+        } @else {
+            span class="json-key" { (key) }
+        }
+        span class="json-colon" { ": " }
+    }
+}
+
+fn container_summary(value: &Value) -> Markup {
+    let count = child_count(value);
+    html! {
+        @match value {
+            Value::Object(_) => span class="json-summary" { "{…} " (count) @if count == 1 { " key" } @else { " keys" } },
+            _ => span class="json-summary" { "[…] " (count) @if count == 1 { " item" } @else { " items" } },
+        }
+    }
+}
+
+pub fn scalar(value: &Value) -> Markup {
+    html! {
+        @match value {
+            Value::String(s) => span class="json-string" { "\"" (s) "\"" },
+            Value::Number(n) => span class="json-number" { (n) },
+            Value::Bool(b) => span class="json-bool" { (b) },
+            Value::Null => span class="json-null" { "null" },
+            _ => (container_summary(value)),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::{Path, PathBuf};
+
+// Subtitle files larger than this aren't served; real ones are a few hundred KiB at most.
+pub const MAX_SUBTITLE_SIZE: u64 = 10 * 1024 * 1024;
+
+pub struct Sidecar {
+    pub path: PathBuf,
+    pub label: String,
+    // From names like `movie.en.srt`, when the part before the extension looks like a
+    // language code.
+    pub language: Option<String>,
+}
+
+pub fn is_subtitle_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("srt") || ext.eq_ignore_ascii_case("vtt"))
+}
+
+// --- Sidecars ---
+// `.srt`/`.vtt` files next to `video` whose names start with the video's file stem, e.g.
+// `movie.srt`, `movie.en.vtt` or `movie.English.srt` for `movie.mkv`.
+pub async fn sidecars(video: &Path) -> Vec<Sidecar> {
+    let (Some(dir), Some(stem)) = (video.parent(), video.file_stem().and_then(|s| s.to_str()))
+    else {
+        return Vec::new();
+    };
+    let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+        return Vec::new();
+    };
+
+    let mut sidecars = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !is_subtitle_file(&path) || !path.is_file() {
+            continue;
+        }
+        let Some(subtitle_stem) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let tag = if subtitle_stem == stem {
+            None
+        } else if let Some(tag) = subtitle_stem
+            .strip_prefix(stem)
+            .and_then(|rest| rest.strip_prefix('.'))
+        {
+            Some(tag.to_string())
+        } else {
+            continue;
+        };
+        let language = tag
+            .as_ref()
+            .filter(|tag| is_language_code(tag))
+            .map(|tag| tag.replace('_', "-"));
+        sidecars.push(Sidecar {
+            label: tag.unwrap_or_else(|| name.to_string()),
+            path,
+            language,
+        });
+    }
+    sidecars.sort_by(|a, b| a.label.cmp(&b.label));
+    sidecars
+}
+
+// "en", "pt-BR", "chi"; not "English" or "forced".
+fn is_language_code(tag: &str) -> bool {
+    let mut parts = tag.splitn(2, ['-', '_']);
+    let primary = parts.next().unwrap_or("");
+    (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.next().is_none_or(|region| {
+            (2..=4).contains(&region.len()) && region.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+// --- Conversion ---
+// WebVTT for `<track>`. SRT differs mainly in the header and the decimal comma in
+// timestamps; cue numbers are valid VTT cue identifiers and are kept.
+pub fn to_vtt(contents: &str, is_srt: bool) -> String {
+    let contents = contents
+        .trim_start_matches('\u{feff}')
+        .replace("\r\n", "\n");
+    if !is_srt {
+        return contents;
+    }
+    let mut vtt = String::from("WEBVTT\n\n");
+    for line in contents.lines() {
+        if line.contains("-->") {
+            vtt.push_str(&line.replace(',', "."));
+        } else {
+            vtt.push_str(line);
+        }
+        vtt.push('\n');
+    }
+    vtt
+}
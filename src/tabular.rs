@@ -0,0 +1,156 @@
+use arrow::{
+    array::RecordBatch,
+    datatypes::SchemaRef,
+    util::display::{ArrayFormatter, FormatOptions},
+};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::{fs::File, io::BufReader, path::Path};
+
+// Rows rendered in the preview table.
+pub const MAX_PREVIEW_ROWS: usize = 100;
+// Longer cell values are cut off; the preview is for skimming, not reading blobs.
+const MAX_CELL_CHARS: usize = 200;
+// Feather files don't record their row count, so counting means reading every batch.
+// Beyond this size that's skipped and only a lower bound is shown.
+const MAX_FEATHER_COUNT_SIZE: u64 = 512 * 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DataFormat {
+    Parquet,
+    Feather,
+}
+
+impl DataFormat {
+    pub fn label(self) -> &'static str {
+        match self {
+            DataFormat::Parquet => "Parquet",
+            DataFormat::Feather => "Feather (Arrow IPC)",
+        }
+    }
+}
+
+pub fn data_format(path: &Path) -> Option<DataFormat> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    match extension.as_str() {
+        "parquet" | "pq" => Some(DataFormat::Parquet),
+        "feather" | "arrow" | "ipc" => Some(DataFormat::Feather),
+        _ => None,
+    }
+}
+
+pub struct Column {
+    pub name: String,
+    pub data_type: String,
+    pub nullable: bool,
+}
+
+pub struct TablePreview {
+    pub columns: Vec<Column>,
+    pub rows: Vec<Vec<String>>,
+    // Total rows in the file; `None` when only `rows.len()` or more is known.
+    pub total_rows: Option<u64>,
+}
+
+// --- Reading ---
+pub async fn preview(path: &Path, format: DataFormat) -> Result<TablePreview, String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || match format {
+        DataFormat::Parquet => preview_parquet(&path),
+        DataFormat::Feather => preview_feather(&path),
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+fn preview_parquet(path: &Path) -> Result<TablePreview, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file).map_err(|e| e.to_string())?;
+    let total_rows = builder.metadata().file_metadata().num_rows();
+    let schema = builder.schema().clone();
+    let reader = builder
+        .with_batch_size(MAX_PREVIEW_ROWS)
+        .with_limit(MAX_PREVIEW_ROWS)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut rows = Vec::new();
+    for batch in reader {
+        append_rows(&mut rows, &batch.map_err(|e| e.to_string())?)?;
+        if rows.len() >= MAX_PREVIEW_ROWS {
+            break;
+        }
+    }
+    Ok(TablePreview {
+        columns: columns(&schema),
+        rows,
+        total_rows: u64::try_from(total_rows).ok(),
+    })
+}
+
+fn preview_feather(path: &Path) -> Result<TablePreview, String> {
+    let file = File::open(path).map_err(|e| e.to_string())?;
+    let file_len = file.metadata().map_err(|e| e.to_string())?.len();
+    let reader = arrow::ipc::reader::FileReader::try_new(BufReader::new(file), None)
+        .map_err(|e| e.to_string())?;
+    let schema = reader.schema();
+
+    let mut rows = Vec::new();
+    let mut counted: u64 = 0;
+    let mut complete = true;
+    for batch in reader {
+        let batch = batch.map_err(|e| e.to_string())?;
+        counted += batch.num_rows() as u64;
+        if rows.len() < MAX_PREVIEW_ROWS {
+            append_rows(&mut rows, &batch)?;
+        } else if file_len > MAX_FEATHER_COUNT_SIZE {
+            complete = false;
+            break;
+        }
+    }
+    Ok(TablePreview {
+        columns: columns(&schema),
+        rows,
+        total_rows: complete.then_some(counted),
+    })
+}
+
+fn columns(schema: &SchemaRef) -> Vec<Column> {
+    schema
+        .fields()
+        .iter()
+        .map(|field| Column {
+            name: field.name().clone(),
+            data_type: field.data_type().to_string(),
+            nullable: field.is_nullable(),
+        })
+        .collect()
+}
+
+fn append_rows(rows: &mut Vec<Vec<String>>, batch: &RecordBatch) -> Result<(), String> {
+    let options = FormatOptions::default().with_null("∅");
+    let formatters = batch
+        .columns()
+        .iter()
+        .map(|array| ArrayFormatter::try_new(array.as_ref(), &options))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let wanted = MAX_PREVIEW_ROWS.saturating_sub(rows.len());
+    for row in 0..batch.num_rows().min(wanted) {
+        rows.push(
+            formatters
+                .iter()
+                .map(|formatter| truncate_cell(formatter.value(row).to_string()))
+                .collect(),
+        );
+    }
+    Ok(())
+}
+
+fn truncate_cell(mut value: String) -> String {
+    if let Some((index, _)) = value.char_indices().nth(MAX_CELL_CHARS) {
+        value.truncate(index);
+        value.push('…');
+    }
+    value
+}
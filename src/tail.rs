@@ -0,0 +1,128 @@
+use axum::response::sse::Event;
+use notify::{RecursiveMode, Watcher};
+use std::{
+    convert::Infallible,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tracing::{error, warn};
+
+// Filesystem events are the fast path; the poll catches writers on filesystems that
+// don't deliver them (network mounts, some containers).
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+// Upper bound on what a single event carries, so a burst of writes is sent in pieces.
+const MAX_EVENT_BYTES: u64 = 256 * 1024;
+
+// --- Follow stream ---
+// Streams everything appended to `path` after byte `from` as SSE `append` events. Each
+// event's id is the file offset it ends at, so a reconnecting browser resumes exactly
+// where it left off. A `truncated` event is sent when the file shrinks (log rotation).
+pub fn follow(path: PathBuf, from: u64) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        if let Err(e) = follow_loop(&path, from, &tx).await {
+            error!("Stopped following {}: {}", path.display(), e);
+            let _ = tx
+                .send(Ok(Event::default().event("error").data(e.to_string())))
+                .await;
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+async fn follow_loop(
+    path: &Path,
+    mut position: u64,
+    tx: &mpsc::Sender<Result<Event, Infallible>>,
+) -> std::io::Result<()> {
+    let (changed_tx, mut changed_rx) = mpsc::channel(1);
+    let watcher = notify::recommended_watcher(move |_| {
+        // A full channel already holds a pending wake-up.
+        let _ = changed_tx.try_send(());
+    })
+    .and_then(|mut watcher| {
+        watcher
+            .watch(path, RecursiveMode::NonRecursive)
+            .map(|_| watcher)
+    });
+    if let Err(e) = &watcher {
+        warn!(
+            "Could not watch {}; falling back to polling: {}",
+            path.display(),
+            e
+        );
+    }
+
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = changed_rx.recv() => {}
+            _ = poll.tick() => {}
+            _ = tx.closed() => return Ok(()),
+        }
+
+        loop {
+            let (data, end, truncated) = read_appended(path, position).await?;
+            if truncated {
+                let event = Event::default()
+                    .event("truncated")
+                    .id(end.to_string())
+                    .data("");
+                if tx.send(Ok(event)).await.is_err() {
+                    return Ok(());
+                }
+            }
+            position = end;
+            if data.is_empty() {
+                break;
+            }
+            let event = Event::default()
+                .event("append")
+                .id(end.to_string())
+                .data(data);
+            if tx.send(Ok(event)).await.is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// Returns the complete lines written after `position` (at most `MAX_EVENT_BYTES`), the
+// offset they end at, and whether the file had shrunk so reading restarted from 0.
+async fn read_appended(path: &Path, position: u64) -> std::io::Result<(String, u64, bool)> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let len = file.metadata()?.len();
+        let (start, truncated) = if len < position {
+            (0, true)
+        } else {
+            (position, false)
+        };
+        if len == start {
+            return Ok((String::new(), start, truncated));
+        }
+
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = Vec::new();
+        file.take(MAX_EVENT_BYTES).read_to_end(&mut buf)?;
+        // Hold back a partial last line until its newline arrives, unless it alone fills
+        // the whole window.
+        let consumed = match buf.iter().rposition(|b| *b == b'\n') {
+            Some(newline) => newline + 1,
+            None if buf.len() as u64 == MAX_EVENT_BYTES => buf.len(),
+            None => 0,
+        };
+        // SSE can't carry carriage returns; CRLF logs arrive as plain lines.
+        Ok((
+            String::from_utf8_lossy(&buf[..consumed]).replace('\r', ""),
+            start + consumed as u64,
+            truncated,
+        ))
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
@@ -0,0 +1,247 @@
+use std::{
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+// A window of a text file, aligned to line (or at least UTF-8) boundaries so that paging
+// back and forth never splits a line or a character.
+pub struct TextChunk {
+    pub text: String,
+    // Byte offsets of the chunk in the file; `end` is exclusive.
+    pub start: u64,
+    pub end: u64,
+    pub file_len: u64,
+}
+
+impl TextChunk {
+    pub fn is_complete(&self) -> bool {
+        self.start == 0 && self.end >= self.file_len
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.start > 0
+    }
+
+    pub fn has_next(&self) -> bool {
+        self.end < self.file_len
+    }
+}
+
+// Where a chunk is pinned: paging forward starts exactly where the previous chunk ended,
+// paging back (and "jump to end") ends exactly where the next one starts, so no bytes
+// are skipped or repeated.
+#[derive(Clone, Copy, Debug)]
+pub enum ChunkAnchor {
+    StartAt(u64),
+    EndAt(u64),
+}
+
+// --- Chunked reading ---
+// Reads at most `max_len` bytes around `anchor`. Only that window is read, so previews
+// stay cheap no matter how large the file is.
+pub async fn read_chunk(
+    path: &Path,
+    anchor: ChunkAnchor,
+    max_len: u64,
+) -> std::io::Result<TextChunk> {
+    let path = path.to_path_buf();
+    let max_len = max_len.max(1);
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let file_len = file.metadata()?.len();
+        let (window_start, window_end) = match anchor {
+            ChunkAnchor::StartAt(offset) => {
+                let start = offset.min(file_len);
+                (start, start.saturating_add(max_len).min(file_len))
+            }
+            ChunkAnchor::EndAt(offset) => {
+                let end = offset.min(file_len);
+                (end.saturating_sub(max_len), end)
+            }
+        };
+
+        file.seek(SeekFrom::Start(window_start))?;
+        let mut buf = Vec::new();
+        file.take(window_end - window_start).read_to_end(&mut buf)?;
+
+        let (mut head, mut tail) = (0, buf.len());
+        match anchor {
+            // Trim a partial last line unless the window reaches the end of the file.
+            ChunkAnchor::StartAt(_) => {
+                head = continuation_bytes(&buf);
+                if window_end < file_len {
+                    tail = match buf[head..].iter().rposition(|b| *b == b'\n') {
+                        Some(newline) => head + newline + 1,
+                        // A single line longer than the window: cut at a character boundary.
+                        None => head + complete_utf8_len(&buf[head..]),
+                    };
+                }
+            }
+            // Trim a partial first line unless the window starts at the beginning of the file.
+            ChunkAnchor::EndAt(_) => {
+                if window_start > 0 {
+                    head = match buf.iter().position(|b| *b == b'\n') {
+                        Some(newline) if newline + 1 < buf.len() => newline + 1,
+                        _ => continuation_bytes(&buf),
+                    };
+                }
+            }
+        }
+        let tail = tail.max(head);
+
+        Ok(TextChunk {
+            text: String::from_utf8_lossy(&buf[head..tail]).into_owned(),
+            start: window_start + head as u64,
+            end: window_start + tail as u64,
+            file_len,
+        })
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// --- Line numbers ---
+// Line positions are found by scanning from the start of the file, which is only done this
+// far in; deeper pages of huge files are shown without line numbers.
+pub const MAX_LINE_SCAN: u64 = 64 * 1024 * 1024;
+
+// The 1-based line number that byte `offset` falls on.
+pub async fn line_number_at(path: &Path, offset: u64) -> std::io::Result<Option<u64>> {
+    if offset > MAX_LINE_SCAN {
+        return Ok(None);
+    }
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut reader = std::fs::File::open(&path)?.take(offset);
+        let mut buf = vec![0u8; 1 << 16];
+        let mut newlines = 0;
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                return Ok(Some(newlines + 1));
+            }
+            newlines += buf[..read].iter().filter(|b| **b == b'\n').count() as u64;
+        }
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// The byte offset at which the 1-based `line` starts, if the file has that many lines
+// within the scan limit.
+pub async fn line_start(path: &Path, line: u64) -> std::io::Result<Option<u64>> {
+    if line <= 1 {
+        return Ok(Some(0));
+    }
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut reader = std::fs::File::open(&path)?.take(MAX_LINE_SCAN);
+        let mut buf = vec![0u8; 1 << 16];
+        let (mut position, mut current_line) = (0u64, 1u64);
+        loop {
+            let read = reader.read(&mut buf)?;
+            if read == 0 {
+                return Ok(None);
+            }
+            for (index, byte) in buf[..read].iter().enumerate() {
+                if *byte == b'\n' {
+                    current_line += 1;
+                    if current_line == line {
+                        return Ok(Some(position + index as u64 + 1));
+                    }
+                }
+            }
+            position += read as u64;
+        }
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// Parses `42`, `42-50` or `L42-L50` into an inclusive, 1-based line range.
+pub fn parse_line_range(spec: &str) -> Option<(u64, u64)> {
+    let number = |part: &str| {
+        part.trim()
+            .trim_start_matches(['L', 'l'])
+            .parse::<u64>()
+            .ok()
+            .filter(|n| *n > 0)
+    };
+    match spec.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (number(start)?, number(end)?);
+            Some((start.min(end), start.max(end)))
+        }
+        None => number(spec).map(|line| (line, line)),
+    }
+}
+
+// --- Content sniffing ---
+// How much of a file is inspected when its extension doesn't say whether it's text.
+const SNIFF_LEN: usize = 8 * 1024;
+// Share of suspicious bytes (control characters, invalid UTF-8) tolerated in the sample.
+const MAX_BINARY_RATIO: f64 = 0.05;
+
+// For files like `LICENSE`, `CHANGELOG` or extensionless scripts: samples the start of
+// the file and treats it as text if it has no NUL bytes and is (nearly) all printable UTF-8.
+pub fn looks_like_text(path: &Path) -> bool {
+    let Ok(file) = std::fs::File::open(path) else {
+        return false;
+    };
+    let mut sample = Vec::with_capacity(SNIFF_LEN);
+    if file
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut sample)
+        .is_err()
+    {
+        return false;
+    }
+    if sample.is_empty() {
+        return true;
+    }
+    if sample.contains(&0) {
+        return false;
+    }
+
+    let mut suspicious = 0;
+    let mut rest = &sample[..complete_utf8_len(&sample)];
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                suspicious += count_control_chars(valid);
+                break;
+            }
+            Err(e) => {
+                let (valid, invalid) = rest.split_at(e.valid_up_to());
+                suspicious += count_control_chars(std::str::from_utf8(valid).unwrap_or_default());
+                let skip = e.error_len().unwrap_or(invalid.len());
+                suspicious += skip;
+                rest = &invalid[skip..];
+            }
+        }
+    }
+    (suspicious as f64) / (sample.len() as f64) <= MAX_BINARY_RATIO
+}
+
+fn count_control_chars(text: &str) -> usize {
+    text.chars()
+        .filter(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'))
+        .count()
+}
+
+// Length of `bytes` without a trailing, incomplete UTF-8 sequence.
+fn complete_utf8_len(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        _ => bytes.len(),
+    }
+}
+
+// Number of leading UTF-8 continuation bytes (at most 3, the longest possible run).
+fn continuation_bytes(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take(3)
+        .take_while(|b| (**b & 0xC0) == 0x80)
+        .count()
+}
@@ -0,0 +1,154 @@
+use dashmap::DashMap;
+use sha1::{Digest, Sha1};
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use tokio::sync::Semaphore;
+
+// Pieces are sized for roughly this many per file, within the bounds below: fewer pieces
+// keep the .torrent small, smaller ones let clients verify and resume in finer steps.
+const TARGET_PIECES: u64 = 1500;
+const MIN_PIECE_LENGTH: u64 = 256 << 10;
+const MAX_PIECE_LENGTH: u64 = 16 << 20;
+
+fn piece_length_for(len: u64) -> u64 {
+    (len / TARGET_PIECES)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH, MAX_PIECE_LENGTH)
+}
+
+struct CachedPieces {
+    modified: Option<SystemTime>,
+    len: u64,
+    piece_length: u64,
+    // SHA-1 of each piece, one after another, as the info dictionary wants them.
+    pieces: Vec<u8>,
+}
+
+// --- Piece hashes ---
+// The piece hashes of shared files, cached per (path, size, mtime) since reading a
+// multi-gigabyte file takes a while. One file is hashed at a time.
+pub struct Torrents {
+    cache: DashMap<PathBuf, CachedPieces>,
+    worker: Semaphore,
+}
+
+impl Torrents {
+    pub fn new() -> Self {
+        Torrents {
+            cache: DashMap::new(),
+            worker: Semaphore::new(1),
+        }
+    }
+
+    // Hashes the file's pieces ahead of time, so the .torrent is ready when asked for.
+    pub async fn prepare(&self, path: &Path) -> std::io::Result<()> {
+        self.pieces(path).await.map(|_| ())
+    }
+
+    async fn pieces(&self, path: &Path) -> std::io::Result<(u64, u64, Vec<u8>)> {
+        let metadata = tokio::fs::metadata(path).await?;
+        let (modified, len) = (metadata.modified().ok(), metadata.len());
+        let cached = || {
+            self.cache
+                .get(path)
+                .filter(|cached| cached.modified == modified && cached.len == len)
+                .map(|cached| (len, cached.piece_length, cached.pieces.clone()))
+        };
+        if let Some(found) = cached() {
+            return Ok(found);
+        }
+        let _worker = self.worker.acquire().await.map_err(std::io::Error::other)?;
+        // Another request may have hashed it while this one waited.
+        if let Some(found) = cached() {
+            return Ok(found);
+        }
+        let piece_length = piece_length_for(len);
+        let pieces = hash_pieces(path, piece_length).await?;
+        self.cache.insert(
+            path.to_path_buf(),
+            CachedPieces {
+                modified,
+                len,
+                piece_length,
+                pieces: pieces.clone(),
+            },
+        );
+        Ok((len, piece_length, pieces))
+    }
+
+    // A single-file .torrent for `path`, named `name`, with `web_seed` (the file's
+    // direct-download URL) as a BEP 19 web seed so kiv stays the origin.
+    pub async fn build(&self, path: &Path, name: &str, web_seed: &str) -> std::io::Result<Vec<u8>> {
+        let (len, piece_length, pieces) = self.pieces(path).await?;
+        let mut out = Vec::new();
+        out.push(b'd');
+        bencode_str(&mut out, b"created by");
+        bencode_str(&mut out, b"kiv");
+        bencode_str(&mut out, b"creation date");
+        bencode_int(&mut out, chrono::Utc::now().timestamp());
+        // Keys of the info dictionary in byte order, as bencode requires.
+        bencode_str(&mut out, b"info");
+        out.push(b'd');
+        bencode_str(&mut out, b"length");
+        bencode_int(&mut out, len as i64);
+        bencode_str(&mut out, b"name");
+        bencode_str(&mut out, name.as_bytes());
+        bencode_str(&mut out, b"piece length");
+        bencode_int(&mut out, piece_length as i64);
+        bencode_str(&mut out, b"pieces");
+        bencode_str(&mut out, &pieces);
+        out.push(b'e');
+        bencode_str(&mut out, b"url-list");
+        out.push(b'l');
+        bencode_str(&mut out, web_seed.as_bytes());
+        out.push(b'e');
+        out.push(b'e');
+        Ok(out)
+    }
+}
+
+async fn hash_pieces(path: &Path, piece_length: u64) -> std::io::Result<Vec<u8>> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let file = std::fs::File::open(&path)?;
+        let mut reader = std::io::BufReader::with_capacity(1 << 20, file);
+        let mut pieces = Vec::new();
+        let mut buffer = vec![0u8; piece_length as usize];
+        loop {
+            let mut filled = 0;
+            while filled < buffer.len() {
+                let read = reader.read(&mut buffer[filled..])?;
+                if read == 0 {
+                    break;
+                }
+                filled += read;
+            }
+            if filled == 0 {
+                break;
+            }
+            pieces.extend_from_slice(&Sha1::digest(&buffer[..filled]));
+            if filled < buffer.len() {
+                break;
+            }
+        }
+        Ok(pieces)
+    })
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// --- Bencode ---
+fn bencode_str(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(value.len().to_string().as_bytes());
+    out.push(b':');
+    out.extend_from_slice(value);
+}
+
+fn bencode_int(out: &mut Vec<u8>, value: i64) {
+    out.push(b'i');
+    out.extend_from_slice(value.to_string().as_bytes());
+    out.push(b'e');
+}
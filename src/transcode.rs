@@ -0,0 +1,273 @@
+use crate::{SharedState, auth, cache, ffmpeg};
+use dashmap::DashMap;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader},
+    process::Child,
+};
+use tracing::{error, info};
+
+// Sessions live in `<cache dir>/hls/<id>/`. The id is random, and a session belongs to
+// the user who started it: opening the same video again joins it, but other users get
+// their own.
+const HLS_DIR_NAME: &str = "hls";
+const PLAYLIST_NAME: &str = "index.m3u8";
+const SEGMENT_SECONDS: u32 = 6;
+const MAX_HEIGHT: u32 = 1080;
+// Players refetch the playlist every few seconds while it grows, so a session nobody has
+// asked anything of for this long has been abandoned.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(30);
+// How long the first request waits for ffmpeg to write the first segment.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+// --- Configuration ---
+pub struct Transcoder {
+    dir: PathBuf,
+    ffmpeg: PathBuf,
+    max_sessions: usize,
+    sessions: DashMap<String, Arc<Session>>,
+    // Held while a session is being looked up or started, so two viewers opening the
+    // same video at once don't both start ffmpeg.
+    start_lock: tokio::sync::Mutex<()>,
+}
+
+struct Session {
+    dir: PathBuf,
+    source: PathBuf,
+    // The request path `source` was opened as, checked again on every fetch.
+    path: String,
+    // Which version of `source` it transcodes, from its size and mtime.
+    version: String,
+    // The user who started it; `None` without accounts, or for the guest.
+    owner: Option<String>,
+    // Dropping the child kills ffmpeg.
+    child: Mutex<Child>,
+    last_access: Mutex<Instant>,
+}
+
+impl Session {
+    fn touch(&self) {
+        *self.last_access.lock().unwrap() = Instant::now();
+    }
+
+    // False once ffmpeg has exited with an error.
+    fn is_healthy(&self) -> bool {
+        match self.child.lock().unwrap().try_wait() {
+            Ok(Some(status)) => status.success(),
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
+}
+
+pub enum StartError {
+    // `max_sessions` other videos are already being transcoded.
+    Busy,
+    Failed(String),
+}
+
+impl Transcoder {
+    pub fn new(cache_dir: &Path, ffmpeg: PathBuf, max_sessions: usize) -> Self {
+        let dir = cache_dir.join(HLS_DIR_NAME);
+        // Leftovers from a previous run can't be resumed.
+        let _ = std::fs::remove_dir_all(&dir);
+        Transcoder {
+            dir,
+            ffmpeg,
+            max_sessions: max_sessions.max(1),
+            sessions: DashMap::new(),
+            start_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    // --- Sessions ---
+    // The id of a (possibly already running) session transcoding `source`, opened as the
+    // request path `path`, for the current user.
+    pub async fn start(&self, source: &Path, path: &str) -> Result<String, StartError> {
+        let metadata = tokio::fs::metadata(source)
+            .await
+            .map_err(|e| StartError::Failed(e.to_string()))?;
+        let version = cache::key(source, &metadata, "hls");
+        let owner = auth::current_user();
+        let _guard = self.start_lock.lock().await;
+        let running = self
+            .sessions
+            .iter()
+            .find(|entry| entry.version == version && entry.owner == owner)
+            .map(|entry| (entry.key().clone(), entry.value().clone()));
+        if let Some((id, session)) = running {
+            if session.is_healthy() {
+                session.touch();
+                return Ok(id);
+            }
+            self.sessions.remove(&id);
+            let _ = tokio::fs::remove_dir_all(&session.dir).await;
+        }
+        if self.sessions.len() >= self.max_sessions {
+            return Err(StartError::Busy);
+        }
+
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let dir = self.dir.join(&id);
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| StartError::Failed(e.to_string()))?;
+        let mut child = ffmpeg::spawn(&self.ffmpeg, hls_args(source, &dir), Stdio::null())
+            .map_err(StartError::Failed)?;
+        if let Some(stderr) = child.stderr.take() {
+            let source = source.to_path_buf();
+            tokio::spawn(async move {
+                let mut lines = BufReader::new(stderr).lines();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    error!("ffmpeg ({}): {}", source.display(), line);
+                }
+            });
+        }
+        info!("Started transcoding {} (session {}).", source.display(), id);
+        self.sessions.insert(
+            id.clone(),
+            Arc::new(Session {
+                dir,
+                source: source.to_path_buf(),
+                path: path.to_string(),
+                version,
+                owner,
+                child: Mutex::new(child),
+                last_access: Mutex::new(Instant::now()),
+            }),
+        );
+        Ok(id)
+    }
+
+    // The session's playlist once it lists at least one segment.
+    pub async fn playlist(&self, id: &str) -> Result<String, String> {
+        let session = self.session(id)?;
+        let deadline = Instant::now() + STARTUP_TIMEOUT;
+        loop {
+            session.touch();
+            if let Ok(playlist) = tokio::fs::read_to_string(session.dir.join(PLAYLIST_NAME)).await
+                && playlist.contains("#EXTINF")
+            {
+                return Ok(playlist);
+            }
+            if !session.is_healthy() {
+                return Err("ffmpeg could not transcode this video.".to_string());
+            }
+            if Instant::now() > deadline {
+                return Err("Transcoding did not start in time.".to_string());
+            }
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+    }
+
+    pub async fn segment(&self, id: &str, name: &str) -> Result<Vec<u8>, String> {
+        if !is_segment_name(name) {
+            return Err("Invalid segment name.".to_string());
+        }
+        let session = self.session(id)?;
+        session.touch();
+        tokio::fs::read(session.dir.join(name))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    // The request path the session's video was opened as, for checking it again.
+    pub fn path(&self, id: &str) -> Result<String, String> {
+        self.session(id).map(|session| session.path.clone())
+    }
+
+    // Only the user who started a session may fetch from it.
+    fn session(&self, id: &str) -> Result<Arc<Session>, String> {
+        self.sessions
+            .get(id)
+            .map(|session| session.clone())
+            .filter(|session| session.owner == auth::current_user())
+            .ok_or_else(|| "Unknown or expired transcoding session.".to_string())
+    }
+
+    async fn remove_idle(&self) {
+        let idle: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|entry| entry.last_access.lock().unwrap().elapsed() > IDLE_TIMEOUT)
+            .map(|entry| entry.key().clone())
+            .collect();
+        for id in idle {
+            if let Some((_, session)) = self.sessions.remove(&id) {
+                info!(
+                    "Stopped transcoding {} (session {}).",
+                    session.source.display(),
+                    id
+                );
+                let _ = session.child.lock().unwrap().start_kill();
+                let _ = tokio::fs::remove_dir_all(&session.dir).await;
+            }
+        }
+    }
+}
+
+// --- Cleanup ---
+pub async fn cleanup_loop(state: SharedState) {
+    let Some(transcoder) = &state.transcoder else {
+        return;
+    };
+    let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+    loop {
+        interval.tick().await;
+        transcoder.remove_idle().await;
+    }
+}
+
+// H.264/AAC in MPEG-TS segments plays everywhere HLS does. The playlist is an "event"
+// playlist so playback can start while the rest is still being transcoded.
+fn hls_args(source: &Path, dir: &Path) -> Vec<OsString> {
+    vec![
+        "-i".into(),
+        source.into(),
+        "-map".into(),
+        "0:v:0".into(),
+        "-map".into(),
+        "0:a:0?".into(),
+        "-sn".into(),
+        "-vf".into(),
+        format!("scale=-2:'min({},ih)'", MAX_HEIGHT).into(),
+        "-c:v".into(),
+        "libx264".into(),
+        "-preset".into(),
+        "veryfast".into(),
+        "-crf".into(),
+        "23".into(),
+        "-pix_fmt".into(),
+        "yuv420p".into(),
+        "-c:a".into(),
+        "aac".into(),
+        "-ac".into(),
+        "2".into(),
+        "-b:a".into(),
+        "160k".into(),
+        "-f".into(),
+        "hls".into(),
+        "-hls_time".into(),
+        SEGMENT_SECONDS.to_string().into(),
+        "-hls_playlist_type".into(),
+        "event".into(),
+        "-hls_flags".into(),
+        "temp_file".into(),
+        "-hls_segment_filename".into(),
+        dir.join("seg%05d.ts").into(),
+        dir.join(PLAYLIST_NAME).into(),
+    ]
+}
+
+fn is_segment_name(name: &str) -> bool {
+    name.strip_prefix("seg")
+        .and_then(|rest| rest.strip_suffix(".ts"))
+        .is_some_and(|number| !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()))
+}
@@ -0,0 +1,216 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::SharedState;
+
+// Trashed items live in `<root>/.kiv-trash/<uuid>/`, which holds the item itself
+// (under its original name) next to a small `meta.json` describing where it came from.
+pub const TRASH_DIR_NAME: &str = ".kiv-trash";
+const META_FILE_NAME: &str = "meta.json";
+
+// --- Configuration ---
+pub struct TrashConfig {
+    pub dir: PathBuf,
+    pub retention: Option<Duration>,
+    pub max_size: Option<u64>,
+    pub purge_interval: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TrashMeta {
+    original_path: String,
+    deleted_at: DateTime<Utc>,
+}
+
+struct TrashEntry {
+    dir: PathBuf,
+    original_path: String,
+    deleted_at: DateTime<Utc>,
+    size: u64,
+}
+
+// --- Moving items into the trash ---
+pub async fn move_to_trash(
+    config: &TrashConfig,
+    full_path: &Path,
+    relative_path: &str,
+) -> std::io::Result<PathBuf> {
+    let name = full_path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no file name"))?;
+
+    let entry_dir = config.dir.join(Uuid::new_v4().to_string());
+    tokio::fs::create_dir_all(&entry_dir).await?;
+
+    let meta = TrashMeta {
+        original_path: relative_path.to_string(),
+        deleted_at: Utc::now(),
+    };
+    let meta_json = serde_json::to_vec_pretty(&meta).map_err(std::io::Error::other)?;
+    tokio::fs::write(entry_dir.join(META_FILE_NAME), meta_json).await?;
+
+    let target = entry_dir.join(name);
+    if let Err(e) = crate::fileops::move_path(full_path, &target).await {
+        let _ = tokio::fs::remove_dir_all(&entry_dir).await;
+        return Err(e);
+    }
+    Ok(target)
+}
+
+// --- Auto-purge ---
+pub async fn purge_loop(state: SharedState) {
+    let config = &state.trash;
+    if state.read_only {
+        info!("Trash auto-purge disabled in read-only mode.");
+        return;
+    }
+    if config.retention.is_none() && config.max_size.is_none() {
+        info!("Trash auto-purge disabled (no retention period or size cap configured).");
+        return;
+    }
+
+    let mut interval = tokio::time::interval(config.purge_interval);
+    loop {
+        interval.tick().await;
+        let dir = config.dir.clone();
+        let retention = config.retention;
+        let max_size = config.max_size;
+        match tokio::task::spawn_blocking(move || purge_once(&dir, retention, max_size)).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Trash purge removed {} item(s).", purged),
+            Err(e) => error!("Trash purge task failed: {}", e),
+        }
+    }
+}
+
+fn purge_once(trash_dir: &Path, retention: Option<Duration>, max_size: Option<u64>) -> usize {
+    let mut entries = match read_entries(trash_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return 0,
+        Err(e) => {
+            error!(
+                "Failed to read trash directory {}: {}",
+                trash_dir.display(),
+                e
+            );
+            return 0;
+        }
+    };
+
+    // Oldest first, so the size cap evicts the longest-trashed items.
+    entries.sort_by_key(|entry| entry.deleted_at);
+
+    let now = Utc::now();
+    let mut purged = 0;
+    let mut kept = Vec::new();
+    for entry in entries {
+        let expired = retention.is_some_and(|retention| {
+            (now - entry.deleted_at).to_std().unwrap_or_default() > retention
+        });
+        if expired {
+            if purge_entry(&entry, "retention period elapsed") {
+                purged += 1;
+            }
+        } else {
+            kept.push(entry);
+        }
+    }
+
+    if let Some(max_size) = max_size {
+        let mut total: u64 = kept.iter().map(|entry| entry.size).sum();
+        for entry in &kept {
+            if total <= max_size {
+                break;
+            }
+            if purge_entry(entry, "trash size cap exceeded") {
+                total -= entry.size;
+                purged += 1;
+            }
+        }
+    }
+
+    purged
+}
+
+fn purge_entry(entry: &TrashEntry, reason: &str) -> bool {
+    match fs::remove_dir_all(&entry.dir) {
+        Ok(()) => {
+            info!(
+                "Purged trashed item '{}' (deleted {}, {} bytes): {}",
+                entry.original_path,
+                entry.deleted_at.format("%Y-%m-%d %H:%M"),
+                entry.size,
+                reason
+            );
+            true
+        }
+        Err(e) => {
+            error!(
+                "Failed to purge trashed item '{}' at {}: {}",
+                entry.original_path,
+                entry.dir.display(),
+                e
+            );
+            false
+        }
+    }
+}
+
+fn read_entries(trash_dir: &Path) -> std::io::Result<Vec<TrashEntry>> {
+    let mut entries = Vec::new();
+    for dir_entry in fs::read_dir(trash_dir)? {
+        let dir_entry = dir_entry?;
+        let dir = dir_entry.path();
+        if !dir.is_dir() {
+            continue;
+        }
+
+        // Fall back to the entry directory's mtime if the metadata file is missing or corrupt.
+        let meta = fs::read(dir.join(META_FILE_NAME))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<TrashMeta>(&bytes).ok());
+        let (original_path, deleted_at) = match meta {
+            Some(meta) => (meta.original_path, meta.deleted_at),
+            None => {
+                let modified = dir_entry
+                    .metadata()
+                    .and_then(|m| m.modified())
+                    .map(DateTime::<Utc>::from)
+                    .unwrap_or_else(|_| Utc::now());
+                (dir.display().to_string(), modified)
+            }
+        };
+
+        entries.push(TrashEntry {
+            size: dir_size(&dir),
+            dir,
+            original_path,
+            deleted_at,
+        });
+    }
+    Ok(entries)
+}
+
+fn dir_size(path: &Path) -> u64 {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    fs::read_dir(path)
+        .map(|reader| {
+            reader
+                .filter_map(Result::ok)
+                .map(|entry| dir_size(&entry.path()))
+                .sum()
+        })
+        .unwrap_or(0)
+}
@@ -0,0 +1,134 @@
+use maud::{Markup, html};
+use std::path::Path;
+
+use crate::mounts::Mount;
+
+// Deepest `depth=` honoured; anything below is loaded as folders are expanded.
+pub const MAX_DEPTH: usize = 5;
+
+pub struct TreeNode {
+    pub name: String,
+    // The request path, with forward slashes.
+    pub path: String,
+    // `None` when the folder lies below the requested depth and hasn't been read.
+    pub children: Option<Vec<TreeNode>>,
+    pub has_children: bool,
+}
+
+// --- Reading ---
+// The folders under `dir`, `depth` levels deep. Files and kiv's own directories are left
+// out; symlinks to folders are not followed.
+pub async fn read_tree(mount: &Mount, dir: &Path, depth: usize) -> std::io::Result<Vec<TreeNode>> {
+    let (mount, dir) = (mount.clone(), dir.to_path_buf());
+    tokio::task::spawn_blocking(crate::auth::propagate(move || {
+        read_level(&mount, &dir, depth.clamp(1, MAX_DEPTH))
+    }))
+    .await
+    .map_err(std::io::Error::other)?
+}
+
+// The virtual top level with named mounts: one node per mount, with the folders below
+// each read as for `read_tree`.
+pub async fn read_mounts(mounts: &[Mount], depth: usize) -> std::io::Result<Vec<TreeNode>> {
+    let mounts = mounts.to_vec();
+    tokio::task::spawn_blocking(crate::auth::propagate(move || {
+        let depth = depth.clamp(1, MAX_DEPTH);
+        mounts
+            .iter()
+            .filter(|mount| crate::auth::visible(&mount.name))
+            .map(|mount| {
+                let children = (depth > 1)
+                    .then(|| read_level(mount, &mount.dir, depth - 1).ok())
+                    .flatten();
+                let has_children = match &children {
+                    Some(children) => !children.is_empty(),
+                    None => has_subdirectory(&mount.dir, &mount.dir),
+                };
+                TreeNode {
+                    name: mount.name.clone(),
+                    path: mount.name.clone(),
+                    children,
+                    has_children,
+                }
+            })
+            .collect()
+    }))
+    .await
+    .map_err(std::io::Error::other)
+}
+
+fn read_level(mount: &Mount, dir: &Path, depth: usize) -> std::io::Result<Vec<TreeNode>> {
+    let mut nodes = Vec::new();
+    for entry in std::fs::read_dir(dir)?.flatten() {
+        let path = entry.path();
+        if !entry.file_type().is_ok_and(|t| t.is_dir())
+            || !crate::policy::is_listed(&mount.dir, &path)
+            || !crate::auth::visible(&mount.relative(&path))
+        {
+            continue;
+        }
+        let name = crate::rawnames::display(&entry.file_name());
+        let children = if depth > 1 {
+            read_level(mount, &path, depth - 1).ok()
+        } else {
+            None
+        };
+        let has_children = match &children {
+            Some(children) => !children.is_empty(),
+            None => has_subdirectory(&mount.dir, &path),
+        };
+        nodes.push(TreeNode {
+            name,
+            path: mount.relative(&path),
+            children,
+            has_children,
+        });
+    }
+    nodes.sort_by(|a, b| crate::natsort::natural_cmp(&a.name, &b.name));
+    Ok(nodes)
+}
+
+// Stops at the first folder found, so a huge directory of files costs one pass at most.
+fn has_subdirectory(root_dir: &Path, dir: &Path) -> bool {
+    std::fs::read_dir(dir).is_ok_and(|entries| {
+        entries.flatten().any(|entry| {
+            entry.file_type().is_ok_and(|t| t.is_dir())
+                && crate::policy::is_listed(root_dir, &entry.path())
+        })
+    })
+}
+
+// --- Rendering ---
+// Folders link to their listing; ones with subfolders expand in place, fetching their
+// children from `/tree` the first time when they weren't sent along.
+pub fn render_tree(nodes: &[TreeNode]) -> Markup {
+    html! {
+        @for node in nodes {
+            @let encoded_path = urlencoding::encode(&node.path);
+            @let link = html! {
+                a class="tree-link" data-path=(node.path)
+                  hx-get=(format!("/browse?path={}", encoded_path))
+                  hx-target="#file-browser"
+                  hx-swap="innerHTML" { "📁 " (node.name) }
+            };
+            li {
+                @if !node.has_children {
+                    span class="tree-leaf" { (link) }
+                } @else if let Some(children) = &node.children {
+                    details {
+                        summary { (link) }
+                        ul class="tree-children" { (render_tree(children)) }
+                    }
+                } @else {
+                    details hx-get=(format!("/tree?path={}&depth=1", encoded_path))
+                            hx-trigger="toggle once"
+                            hx-target="find .tree-children"
+                            hx-swap="innerHTML" {
+                        summary { (link) }
+                        ul class="tree-children" { li class="tree-loading" { "Loading…" } }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::prelude::*;
+use std::path::{Path, PathBuf};
+use tracing::{error, info};
+
+// Previous versions of a file `<root>/a/b.txt` are kept as
+// `<root>/.kiv-versions/a/b.txt/<timestamp>`, newest having the largest timestamp.
+pub const VERSIONS_DIR_NAME: &str = ".kiv-versions";
+const VERSION_ID_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+// --- Configuration ---
+pub struct VersionsConfig {
+    pub dir: PathBuf,
+    pub keep: usize,
+}
+
+impl VersionsConfig {
+    pub fn enabled(&self) -> bool {
+        self.keep > 0
+    }
+
+    fn store_for(&self, relative_path: &Path) -> PathBuf {
+        self.dir.join(relative_path)
+    }
+}
+
+pub struct VersionInfo {
+    pub id: String,
+    pub saved_at: Option<DateTime<Utc>>,
+    pub size: u64,
+}
+
+// --- Snapshots ---
+// Copies the current contents of `full_path` into the version store before it gets
+// overwritten. Does nothing when versioning is disabled or there is no regular file there.
+pub async fn snapshot(
+    config: &VersionsConfig,
+    full_path: &Path,
+    relative_path: &Path,
+) -> std::io::Result<Option<PathBuf>> {
+    let saved = save_copy(config, full_path, relative_path).await?;
+    if saved.is_some() {
+        prune(config, relative_path).await;
+    }
+    Ok(saved)
+}
+
+async fn save_copy(
+    config: &VersionsConfig,
+    full_path: &Path,
+    relative_path: &Path,
+) -> std::io::Result<Option<PathBuf>> {
+    // Only a regular file is kept: copying a link would save whatever it points to,
+    // which may be outside the root.
+    if !config.enabled()
+        || !tokio::fs::symlink_metadata(full_path)
+            .await
+            .is_ok_and(|m| m.is_file())
+    {
+        return Ok(None);
+    }
+
+    let store = config.store_for(relative_path);
+    tokio::fs::create_dir_all(&store).await?;
+    let version_path = store.join(Utc::now().format(VERSION_ID_FORMAT).to_string());
+    tokio::fs::copy(full_path, &version_path).await?;
+    info!(
+        "Saved previous version of {} as {}",
+        full_path.display(),
+        version_path.display()
+    );
+    Ok(Some(version_path))
+}
+
+async fn prune(config: &VersionsConfig, relative_path: &Path) {
+    let versions = match list(config, relative_path).await {
+        Ok(versions) => versions,
+        Err(e) => {
+            error!(
+                "Failed to list versions of {} for pruning: {}",
+                relative_path.display(),
+                e
+            );
+            return;
+        }
+    };
+
+    let store = config.store_for(relative_path);
+    for version in versions.iter().skip(config.keep) {
+        let path = store.join(&version.id);
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => info!("Pruned old version {}", path.display()),
+            Err(e) => error!("Failed to prune old version {}: {}", path.display(), e),
+        }
+    }
+}
+
+// --- Listing and restore ---
+// Lists stored versions, newest first.
+pub async fn list(
+    config: &VersionsConfig,
+    relative_path: &Path,
+) -> std::io::Result<Vec<VersionInfo>> {
+    let mut reader = match tokio::fs::read_dir(config.store_for(relative_path)).await {
+        Ok(reader) => reader,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut versions = Vec::new();
+    while let Some(entry) = reader.next_entry().await? {
+        let Ok(id) = entry.file_name().into_string() else {
+            continue;
+        };
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let saved_at = NaiveDateTime::parse_from_str(&id, VERSION_ID_FORMAT)
+            .ok()
+            .map(|naive| naive.and_utc());
+        versions.push(VersionInfo {
+            id,
+            saved_at,
+            size: metadata.len(),
+        });
+    }
+    versions.sort_by(|a, b| b.id.cmp(&a.id));
+    Ok(versions)
+}
+
+// Resolves a version id to its stored file, rejecting anything that isn't a bare file name.
+pub fn version_path(config: &VersionsConfig, relative_path: &Path, id: &str) -> Option<PathBuf> {
+    let is_plain_name = !id.is_empty()
+        && Path::new(id).file_name().and_then(|name| name.to_str()) == Some(id)
+        && id != "."
+        && id != "..";
+    is_plain_name.then(|| config.store_for(relative_path).join(id))
+}
+
+// Restores a stored version over the live file, snapshotting the live file first so the
+// restore itself can be undone. Pruning waits until the copy is done so that the version
+// being restored can't be evicted by the snapshot taken just before it.
+pub async fn restore(
+    config: &VersionsConfig,
+    full_path: &Path,
+    relative_path: &Path,
+    id: &str,
+) -> std::io::Result<()> {
+    let source = version_path(config, relative_path, id).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid version id")
+    })?;
+    if !tokio::fs::symlink_metadata(&source).await?.is_file() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "version not found",
+        ));
+    }
+
+    save_copy(config, full_path, relative_path).await?;
+    tokio::fs::copy(&source, full_path).await?;
+    info!("Restored {} from version {}", full_path.display(), id);
+    prune(config, relative_path).await;
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use axum::response::sse::Event;
+use notify::{RecursiveMode, Watcher};
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+use tokio::sync::mpsc;
+use tokio_stream::{Stream, wrappers::ReceiverStream};
+use tracing::warn;
+
+// Like tail's follow stream: filesystem events are the fast path, and comparing the
+// directory's mtime catches changes on filesystems that don't deliver them.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// Changes arriving within this window are sent as one event, so a build writing hundreds
+// of files causes a handful of refreshes rather than hundreds.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+// --- Change stream ---
+// Sends a `changed` SSE event whenever an entry of `dir` appears, changes or is removed.
+// Nothing below `dir`'s direct entries is watched.
+pub fn changes(dir: PathBuf) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move { watch_loop(&dir, &tx).await });
+    ReceiverStream::new(rx)
+}
+
+async fn watch_loop(dir: &Path, tx: &mpsc::Sender<Result<Event, Infallible>>) {
+    let (changed_tx, mut changed_rx) = mpsc::channel(1);
+    let watcher = notify::recommended_watcher(move |_| {
+        // A full channel already holds a pending wake-up.
+        let _ = changed_tx.try_send(());
+    })
+    .and_then(|mut watcher| {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map(|_| watcher)
+    });
+    if let Err(e) = &watcher {
+        warn!(
+            "Could not watch {}; falling back to polling: {}",
+            dir.display(),
+            e
+        );
+    }
+
+    let mut modified = dir_modified(dir).await;
+    let mut poll = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = changed_rx.recv() => {}
+            _ = poll.tick() => {
+                let now = dir_modified(dir).await;
+                if now == modified {
+                    continue;
+                }
+            }
+            _ = tx.closed() => return,
+        }
+
+        tokio::time::sleep(SETTLE_TIME).await;
+        while changed_rx.try_recv().is_ok() {}
+        modified = dir_modified(dir).await;
+        if tx
+            .send(Ok(Event::default().event("changed").data("")))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn dir_modified(dir: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(dir)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
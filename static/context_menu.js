@@ -23,12 +23,13 @@ document.addEventListener('DOMContentLoaded', () => {
 
     // --- Show Context Menu on Right-Click ---
     fileBrowser.addEventListener('contextmenu', (event) => {
-        const targetLi = event.target.closest('li[data-path][id]'); // File/Dir List Item
+        const targetLi = event.target.closest('li[data-path]'); // File/Dir List Item
         if (targetLi) {
             event.preventDefault();
 
             const path = targetLi.getAttribute('data-path');
             const isDir = targetLi.getAttribute('data-is-dir') === 'true';
+            const isPinned = targetLi.getAttribute('data-pinned') === 'true';
             const shareTargetLi = document.getElementById('context-share-target'); // The parent LI
             const shareButtonWrapper = document.getElementById('context-share-button-wrapper'); // The inner SPAN
 
@@ -82,6 +83,20 @@ document.addEventListener('DOMContentLoaded', () => {
                 shareButtonWrapper.innerHTML = ''; // Clear any button remnants
             }
 
+            // --- Generic actions: point them at the clicked item ---
+            // Buttons marked .context-action post the item's path; data-files-only /
+            // data-dirs-only restrict which kind of item they are offered for, and
+            // data-pinned-only / data-unpinned-only whether it is in Favorites.
+            contextMenu.querySelectorAll('.context-action').forEach(button => {
+                const li = button.closest('li');
+                const hidden = (button.hasAttribute('data-files-only') && isDir) ||
+                    (button.hasAttribute('data-dirs-only') && !isDir) ||
+                    (button.hasAttribute('data-pinned-only') && !isPinned) ||
+                    (button.hasAttribute('data-unpinned-only') && isPinned);
+                li.style.display = hidden ? 'none' : '';
+                button.setAttribute('hx-vals', JSON.stringify({ path: path }));
+            });
+
             // --- Position and show context menu ---
             // Calculate position relative to the document, including scroll offsets
             const menuTop = event.clientY + window.scrollY;
@@ -123,7 +138,7 @@ document.addEventListener('DOMContentLoaded', () => {
     // Attach listener directly to the context menu element for reliability
     contextMenu.addEventListener('click', function(event) {
         // Check if the actual clicked element or its parent is the share button
-        const shareButtonClicked = event.target.closest('#context-share');
+        const shareButtonClicked = event.target.closest('#context-share, .context-action');
         if (shareButtonClicked) {
             // console.log("Share button clicked inside context menu, hiding menu."); // Uncomment for debugging
             hideContextMenu(); // Hide immediately, no timeout needed
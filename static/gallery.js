@@ -0,0 +1,93 @@
+// static/gallery.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    let overlay = null;
+    let items = [];
+    let current = 0;
+
+    function createOverlay() {
+        const element = document.createElement('div');
+        element.className = 'slideshow-overlay';
+        element.innerHTML = `
+            <button class="slideshow-close" title="Close (Esc)">✕</button>
+            <button class="slideshow-prev" title="Previous (←)">‹</button>
+            <img class="slideshow-image" alt="">
+            <button class="slideshow-next" title="Next (→)">›</button>
+            <div class="slideshow-caption">
+                <span class="slideshow-name"></span>
+                <span class="slideshow-counter"></span>
+                <a class="slideshow-open" href="#">Details</a>
+            </div>
+        `;
+        document.body.appendChild(element);
+
+        element.querySelector('.slideshow-close').addEventListener('click', close);
+        element.querySelector('.slideshow-prev').addEventListener('click', () => show(current - 1));
+        element.querySelector('.slideshow-next').addEventListener('click', () => show(current + 1));
+        element.querySelector('.slideshow-open').addEventListener('click', (event) => {
+            event.preventDefault();
+            const url = items[current].getAttribute('data-preview-url');
+            close();
+            htmx.ajax('GET', url, '#file-browser');
+        });
+        // Clicking the backdrop (not the image or controls) closes the slideshow.
+        element.addEventListener('click', (event) => {
+            if (event.target === element) close();
+        });
+        return element;
+    }
+
+    // Wraps around at both ends, and preloads the next image so advancing is instant.
+    function show(index) {
+        current = (index + items.length) % items.length;
+        const item = items[current];
+        overlay.querySelector('.slideshow-image').src = item.getAttribute('data-full-src');
+        overlay.querySelector('.slideshow-image').alt = item.getAttribute('data-name');
+        overlay.querySelector('.slideshow-name').textContent = item.getAttribute('data-name');
+        overlay.querySelector('.slideshow-counter').textContent = `${current + 1} / ${items.length}`;
+
+        const next = items[(current + 1) % items.length];
+        new Image().src = next.getAttribute('data-full-src');
+    }
+
+    function open(index) {
+        items = Array.from(document.querySelectorAll('.gallery-item'));
+        if (items.length === 0) return;
+        if (!overlay) overlay = createOverlay();
+        overlay.classList.add('open');
+        document.body.classList.add('slideshow-active');
+        show(index);
+    }
+
+    function close() {
+        if (!overlay) return;
+        overlay.classList.remove('open');
+        overlay.querySelector('.slideshow-image').removeAttribute('src');
+        document.body.classList.remove('slideshow-active');
+    }
+
+    document.body.addEventListener('click', (event) => {
+        const item = event.target.closest('.gallery-item');
+        if (item) {
+            open(Number(item.getAttribute('data-index')));
+        } else if (event.target.closest('.gallery-slideshow-start')) {
+            open(0);
+        }
+    });
+
+    document.addEventListener('keydown', (event) => {
+        if (!overlay || !overlay.classList.contains('open')) return;
+        if (event.key === 'Escape') {
+            close();
+        } else if (event.key === 'ArrowRight' || event.key === ' ') {
+            event.preventDefault();
+            show(current + 1);
+        } else if (event.key === 'ArrowLeft') {
+            event.preventDefault();
+            show(current - 1);
+        }
+    });
+
+    // Leaving the gallery (e.g. via the back button) shouldn't leave the slideshow up.
+    document.body.addEventListener('htmx:beforeSwap', close);
+});
@@ -0,0 +1,78 @@
+// static/hls_player.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    const HLS_JS_URL = 'https://cdn.jsdelivr.net/npm/hls.js@1/dist/hls.min.js';
+    let hlsJsLoading = null;
+    let activeHls = null;
+
+    // hls.js is only fetched the first time a video actually needs transcoding.
+    function loadHlsJs() {
+        if (!hlsJsLoading) {
+            hlsJsLoading = new Promise((resolve, reject) => {
+                const script = document.createElement('script');
+                script.src = HLS_JS_URL;
+                script.onload = resolve;
+                script.onerror = reject;
+                document.head.appendChild(script);
+            });
+        }
+        return hlsJsLoading;
+    }
+
+    function setStatus(video, text) {
+        const status = video.closest('.media-preview-content')?.querySelector('.transcode-status');
+        if (status) status.textContent = text;
+    }
+
+    // Safari plays HLS natively; elsewhere hls.js feeds it through Media Source Extensions.
+    function playTranscoded(video) {
+        if (video.dataset.transcoding) return;
+        video.dataset.transcoding = 'true';
+        const url = video.getAttribute('data-hls-src');
+        setStatus(video, 'Transcoding… playback starts in a few seconds.');
+
+        if (video.canPlayType('application/vnd.apple.mpegurl')) {
+            video.src = url;
+            video.play().catch(() => {});
+            return;
+        }
+        loadHlsJs().then(() => {
+            if (!window.Hls || !Hls.isSupported()) {
+                setStatus(video, 'This browser cannot play transcoded video.');
+                return;
+            }
+            activeHls = new Hls();
+            activeHls.on(Hls.Events.MANIFEST_PARSED, () => {
+                setStatus(video, 'Playing a transcoded copy.');
+                video.play().catch(() => {});
+            });
+            activeHls.on(Hls.Events.ERROR, (event, data) => {
+                if (data.fatal) setStatus(video, 'Transcoding failed.');
+            });
+            activeHls.loadSource(url);
+            activeHls.attachMedia(video);
+        }).catch(() => setStatus(video, 'Could not load the HLS player.'));
+    }
+
+    // With <source> children, an unplayable file fires `error` on the source, not the video.
+    document.body.addEventListener('error', (event) => {
+        if (event.target.tagName !== 'SOURCE') return;
+        const video = event.target.closest('video[data-hls-src]');
+        if (video) playTranscoded(video);
+    }, true);
+
+    document.body.addEventListener('click', (event) => {
+        const button = event.target.closest('.transcode-button');
+        if (!button) return;
+        const video = button.closest('.media-preview-content')?.querySelector('video[data-hls-src]');
+        if (video) playTranscoded(video);
+    });
+
+    // Stop fetching segments once the preview is gone.
+    document.body.addEventListener('htmx:beforeSwap', () => {
+        if (activeHls) {
+            activeHls.destroy();
+            activeHls = null;
+        }
+    });
+});
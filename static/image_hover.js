@@ -13,6 +13,8 @@ document.addEventListener('DOMContentLoaded', () => {
             <div class="image-name"></div>
         `;
         document.body.appendChild(preview);
+        // Video posters can be missing (no ffmpeg, unreadable file); show nothing then.
+        preview.querySelector('img').addEventListener('error', hideHoverPreview);
         return preview;
     }
     
@@ -0,0 +1,95 @@
+// static/line_links.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    let selectionStart = null;
+    let hashHandled = false;
+
+    function parseRange(text) {
+        const match = /^#?L?(\d+)(?:-L?(\d+))?$/.exec(text || '');
+        if (!match) return null;
+        const a = Number(match[1]);
+        const b = match[2] ? Number(match[2]) : a;
+        return [Math.min(a, b), Math.max(a, b)];
+    }
+
+    function firstLineNumber(container) {
+        const first = container.querySelector('.line-numbers a');
+        return first ? Number(first.id.slice(1)) : null;
+    }
+
+    // Positions the highlight overlay over lines start..end (inclusive) of the current page.
+    function highlight(container, start, end) {
+        const first = firstLineNumber(container);
+        if (first === null) return;
+        let overlay = container.querySelector('.line-highlight');
+        if (!overlay) {
+            overlay = document.createElement('div');
+            overlay.className = 'line-highlight';
+            container.insertBefore(overlay, container.querySelector('pre:not(.line-numbers)'));
+        }
+        overlay.style.setProperty('--hl-start', start - first);
+        overlay.style.setProperty('--hl-count', end - start + 1);
+    }
+
+    // Keeps a language chosen in the preview's picker in shared links.
+    function langParam(container) {
+        const lang = container.getAttribute('data-preview-lang');
+        return lang ? `&lang=${encodeURIComponent(lang)}` : '';
+    }
+
+    function scrollToLine(line) {
+        const anchor = document.getElementById(`L${line}`);
+        if (anchor) {
+            anchor.scrollIntoView({ block: 'center' });
+        }
+    }
+
+    // Clicking a line number selects it (shift-click extends the selection) and puts a
+    // shareable link in the address bar.
+    document.body.addEventListener('click', (event) => {
+        const anchor = event.target.closest('.line-numbers a');
+        if (!anchor) return;
+        event.preventDefault();
+
+        const container = anchor.closest('.code-with-lines');
+        const line = Number(anchor.id.slice(1));
+        let start = line;
+        let end = line;
+        if (event.shiftKey && selectionStart !== null) {
+            start = Math.min(selectionStart, line);
+            end = Math.max(selectionStart, line);
+        } else {
+            selectionStart = line;
+        }
+        highlight(container, start, end);
+
+        const spec = start === end ? `${start}` : `${start}-${end}`;
+        const path = encodeURIComponent(container.getAttribute('data-preview-path'));
+        history.replaceState(null, '', `/?preview=${path}&line=${spec}${langParam(container)}#L${start}`);
+    });
+
+    document.body.addEventListener('htmx:afterSwap', () => {
+        const container = document.querySelector('.code-with-lines');
+        if (!container) return;
+        selectionStart = null;
+
+        const serverHighlight = container.querySelector('.line-highlight');
+        if (serverHighlight) {
+            serverHighlight.scrollIntoView({ block: 'center' });
+            return;
+        }
+        const range = parseRange(window.location.hash);
+        if (!range || hashHandled) return;
+
+        // A bare `#L42` fragment: highlight it if it is on this page, otherwise ask the
+        // server for the page that contains it.
+        hashHandled = true;
+        if (document.getElementById(`L${range[0]}`)) {
+            highlight(container, range[0], range[1]);
+            scrollToLine(range[0]);
+        } else {
+            const path = encodeURIComponent(container.getAttribute('data-preview-path'));
+            htmx.ajax('GET', `/preview?path=${path}&line=${range[0]}-${range[1]}${langParam(container)}`, '#file-browser');
+        }
+    });
+});
@@ -0,0 +1,63 @@
+// static/live_listing.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    let active = null;
+
+    function stopWatching() {
+        if (active) {
+            active.source.close();
+        }
+        active = null;
+    }
+
+    // Reloads the listing in place. It waits while the context menu or an inline share
+    // link is open, so a refresh doesn't pull the item out from under the user.
+    function refresh() {
+        if (!active) return;
+        const menu = document.getElementById('context-menu');
+        const busy = (menu && menu.style.display === 'block') ||
+            document.querySelector('.share-link-inline-box');
+        if (busy) {
+            active.pending = true;
+            return;
+        }
+        active.pending = false;
+        // Keep whatever the filter box is narrowing the listing to.
+        const filter = document.getElementById('filter-input');
+        const filterParam = filter && filter.value ? `&filter=${encodeURIComponent(filter.value)}` : '';
+        htmx.ajax('GET', `/browse?path=${encodeURIComponent(active.path)}${filterParam}`, {
+            target: '#file-browser',
+            swap: 'innerHTML',
+        });
+    }
+
+    function watch(container) {
+        const url = container.getAttribute('data-events-url');
+        // The listing we just refreshed ourselves keeps its stream.
+        if (active && active.url === url) return;
+        stopWatching();
+        const source = new EventSource(url);
+        active = { url, path: container.getAttribute('data-path'), source, pending: false };
+        source.addEventListener('changed', refresh);
+    }
+
+    document.body.addEventListener('htmx:afterSwap', () => {
+        const container = document.querySelector('#current-path-container[data-events-url]');
+        if (container) {
+            watch(container);
+        } else {
+            stopWatching();
+        }
+    });
+
+    // Catch up on a change that arrived while the menu was open, unless the click
+    // navigated away from the listing.
+    document.addEventListener('click', () => {
+        if (!active || !active.pending) return;
+        setTimeout(() => {
+            if (document.querySelector('.htmx-request')) return;
+            const container = document.querySelector('#current-path-container[data-events-url]');
+            if (container && container.getAttribute('data-events-url') === active.url) refresh();
+        }, 300);
+    });
+});
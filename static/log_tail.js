@@ -0,0 +1,104 @@
+// static/log_tail.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    // Keep the follow view from growing without bound; older output is dropped.
+    const MAX_OUTPUT_CHARS = 2 * 1024 * 1024;
+    let active = null;
+
+    function stopFollowing() {
+        if (active && active.source) {
+            active.source.close();
+        }
+        active = null;
+    }
+
+    function setStatus(text) {
+        const status = document.querySelector('.tail-status');
+        if (status) {
+            status.textContent = text;
+        }
+    }
+
+    function connect(state) {
+        const url = `${state.baseUrl}&from=${state.offset}`;
+        const source = new EventSource(url);
+        state.source = source;
+
+        source.addEventListener('open', () => setStatus('Following — new lines appear below.'));
+
+        source.addEventListener('append', (event) => {
+            if (!state.output.isConnected) {
+                stopFollowing();
+                return;
+            }
+            const nearBottom = state.output.scrollTop + state.output.clientHeight
+                >= state.output.scrollHeight - 20;
+
+            state.pre.appendChild(document.createTextNode(event.data));
+            state.offset = Number(event.lastEventId) || state.offset;
+            if (state.pre.textContent.length > MAX_OUTPUT_CHARS) {
+                state.pre.textContent = state.pre.textContent.slice(-MAX_OUTPUT_CHARS / 2);
+            }
+            if (nearBottom) {
+                state.output.scrollTop = state.output.scrollHeight;
+            }
+        });
+
+        source.addEventListener('truncated', (event) => {
+            state.offset = Number(event.lastEventId) || 0;
+            state.pre.appendChild(document.createTextNode('\n--- file truncated ---\n'));
+        });
+
+        source.addEventListener('error', () => {
+            if (source.readyState === EventSource.CLOSED) {
+                setStatus('Connection lost.');
+            } else {
+                setStatus('Reconnecting…');
+            }
+        });
+    }
+
+    function startFollowing(output) {
+        stopFollowing();
+        const state = {
+            output: output,
+            pre: output.querySelector('pre'),
+            baseUrl: output.getAttribute('data-tail-url'),
+            offset: Number(output.getAttribute('data-tail-offset')) || 0,
+            paused: false,
+            source: null,
+        };
+        active = state;
+        output.scrollTop = output.scrollHeight;
+        connect(state);
+    }
+
+    // Pause closes the stream; resume reconnects from the last offset received, so
+    // nothing written in between is lost.
+    document.body.addEventListener('click', (event) => {
+        if (!event.target.matches('.tail-toggle') || !active) return;
+        if (active.paused) {
+            active.paused = false;
+            event.target.textContent = 'Pause';
+            connect(active);
+        } else {
+            active.paused = true;
+            active.source.close();
+            event.target.textContent = 'Resume';
+            setStatus('Paused.');
+        }
+    });
+
+    document.body.addEventListener('htmx:beforeSwap', (event) => {
+        if (active && event.detail.target.contains(active.output)) {
+            stopFollowing();
+        }
+    });
+
+    document.body.addEventListener('htmx:afterSwap', () => {
+        const output = document.querySelector('.tail-output[data-tail-url]');
+        if (output && (!active || active.output !== output)) {
+            startFollowing(output);
+        }
+    });
+});
@@ -0,0 +1,70 @@
+// static/quick_open.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    const overlay = document.getElementById('quick-open');
+    const input = document.getElementById('quick-open-input');
+    const results = document.getElementById('quick-open-results');
+    if (!overlay || !input || !results) return;
+
+    function open() {
+        overlay.classList.add('open');
+        input.select();
+        input.focus();
+    }
+
+    function close() {
+        overlay.classList.remove('open');
+    }
+
+    function hits() {
+        return Array.from(results.querySelectorAll('.quick-open-hit'));
+    }
+
+    function select(index) {
+        const all = hits();
+        if (all.length === 0) return;
+        const wrapped = (index + all.length) % all.length;
+        all.forEach((hit, i) => hit.classList.toggle('selected', i === wrapped));
+        all[wrapped].scrollIntoView({ block: 'nearest' });
+    }
+
+    function selectedIndex() {
+        return hits().findIndex((hit) => hit.classList.contains('selected'));
+    }
+
+    document.getElementById('quick-open-button')?.addEventListener('click', open);
+
+    // Ctrl+P (Cmd+P on macOS) instead of the browser's print dialog.
+    document.addEventListener('keydown', (event) => {
+        if ((event.ctrlKey || event.metaKey) && event.key.toLowerCase() === 'p') {
+            event.preventDefault();
+            open();
+            return;
+        }
+        if (!overlay.classList.contains('open')) return;
+        if (event.key === 'Escape') {
+            close();
+        } else if (event.key === 'ArrowDown') {
+            event.preventDefault();
+            select(selectedIndex() + 1);
+        } else if (event.key === 'ArrowUp') {
+            event.preventDefault();
+            select(selectedIndex() - 1);
+        } else if (event.key === 'Enter') {
+            event.preventDefault();
+            const hit = hits()[Math.max(selectedIndex(), 0)];
+            if (hit) hit.click();
+        }
+    });
+
+    // The best match is preselected, so Enter opens it straight away.
+    results.addEventListener('htmx:afterSwap', () => select(0));
+
+    results.addEventListener('click', (event) => {
+        if (event.target.closest('.quick-open-hit')) close();
+    });
+
+    overlay.addEventListener('click', (event) => {
+        if (event.target === overlay) close();
+    });
+});
@@ -0,0 +1,15 @@
+// static/tree.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    // Marks the folder being listed in the sidebar, whichever way it was reached.
+    function markCurrentFolder() {
+        const container = document.querySelector('#current-path-container[data-path]');
+        if (!container) return;
+        const current = container.getAttribute('data-path') || '.';
+        document.querySelectorAll('#tree-sidebar .tree-link').forEach((link) => {
+            link.classList.toggle('active', link.getAttribute('data-path') === current);
+        });
+    }
+
+    document.body.addEventListener('htmx:afterSwap', markCurrentFolder);
+});
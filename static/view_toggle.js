@@ -0,0 +1,22 @@
+// static/view_toggle.js
+
+document.addEventListener('DOMContentLoaded', () => {
+    const CHECKSUMS_KEY = 'kiv-checksums';
+
+    // Remember whether checksums are shown (list/grid and sorting are remembered by the
+    // server)…
+    document.body.addEventListener('click', (event) => {
+        const checksums = event.target.closest('.view-toggle button[data-checksums]');
+        if (checksums) localStorage.setItem(CHECKSUMS_KEY, checksums.getAttribute('data-checksums'));
+    });
+
+    // …and apply it to every listing that doesn't ask for it itself (folder links, the
+    // sidebar, "Back to Files").
+    document.body.addEventListener('htmx:configRequest', (event) => {
+        if (!event.detail.path.startsWith('/browse')) return;
+        const checksums = localStorage.getItem(CHECKSUMS_KEY);
+        if (checksums && !event.detail.path.includes('checksums=') && !('checksums' in event.detail.parameters)) {
+            event.detail.parameters.checksums = checksums;
+        }
+    });
+});